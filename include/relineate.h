@@ -0,0 +1,51 @@
+#ifndef RELINEATE_H
+#define RELINEATE_H
+
+/* Generated with cbindgen (see cbindgen.toml) from src/ffi.rs */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct RelineateDocument RelineateDocument;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Parses a .rm page of `length` bytes. Returns NULL if it can't be
+ * parsed, with a message in `*error` unless `error` is NULL.
+ *
+ * # Safety
+ * `data` must point to `length` readable bytes.
+ */
+RelineateDocument *relineate_parse(const uint8_t *data, uintptr_t length, char **error);
+
+/**
+ * Renders a parsed page as SVG with the default options. Returns NULL if
+ * it can't be rendered, with a message in `*error` unless `error` is NULL.
+ *
+ * # Safety
+ * `document` must come from relineate_parse and not have been freed.
+ */
+char *relineate_render_svg(const RelineateDocument *document, char **error);
+
+/**
+ * # Safety
+ * `document` must come from relineate_parse, or be NULL.
+ */
+void relineate_free_document(RelineateDocument *document);
+
+/**
+ * # Safety
+ * `string` must come from this library, or be NULL.
+ */
+void relineate_free_string(char *string);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RELINEATE_H */
@@ -0,0 +1,52 @@
+use svg::node::element::Style as SVGStyle;
+use svg::node::element::path::{Command, Data as SVGData};
+
+use crate::pens::Stroke;
+
+// Pen speed for the replay in canvas pixels per second. .rm files have no
+// timestamps, so this is a constant rather than the original pace.
+const DRAW_SPEED: f32 = 900.0;
+const MIN_DURATION: f32 = 0.01;
+// pause between strokes, like lifting the pen
+const STROKE_GAP: f32 = 0.05;
+
+// Each animated path is normalized to a length of 1 (pathLength), fully
+// dashed off, then un-dashed over its duration. The stylesheet's dasharray
+// overrides textures drawn with stroke-dasharray while animating.
+const ANIMATION_CSS: &str = "\
+@keyframes relineate-draw { to { stroke-dashoffset: 0; } }
+.relineate-draw { stroke-dasharray: 1; stroke-dashoffset: 1; animation-name: relineate-draw; \
+animation-timing-function: linear; animation-fill-mode: forwards; }";
+
+pub fn animation_style() -> SVGStyle {
+  return SVGStyle::new(ANIMATION_CSS);
+}
+
+// Length of a path made of absolute moves and lines
+fn data_length(data: &SVGData) -> f32 {
+  return data.iter().fold((0.0, (0.0, 0.0)), |(length, (x, y)), command| {
+    return match command {
+      Command::Move(_, p) if p.len() >= 2 => (length, (p[0], p[1])),
+      Command::Line(_, p) if p.len() >= 2 => (length + ((p[0] - x).powi(2) + (p[1] - y).powi(2)).sqrt(), (p[0], p[1])),
+      _ => (length, (x, y))
+    };
+  }).0
+}
+
+// Schedules every stroke (per layer, per line, in drawing order) to draw
+// itself after the ones before it. Returns the total running time.
+pub fn animate_strokes(layers: &mut [Vec<Vec<Stroke>>]) -> f32 {
+  return layers.iter_mut()
+    .flat_map(|layer| layer.iter_mut())
+    .fold(0.0, |start, line_strokes| {
+      // a line's strokes are all parts of one pen movement, drawn back to back
+      let end = line_strokes.iter_mut().fold(start, |stroke_start, stroke| {
+        let duration = (data_length(&stroke.data) / DRAW_SPEED).max(MIN_DURATION);
+        stroke.attributes.push(("pathLength", String::from("1")));
+        stroke.add_class("relineate-draw");
+        stroke.add_style(&format!("animation-delay:{:.3}s;animation-duration:{:.3}s", stroke_start, duration));
+        return stroke_start + duration;
+      });
+      return end + STROKE_GAP;
+    });
+}
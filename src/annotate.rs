@@ -0,0 +1,679 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::deflate::inflate;
+use crate::pdf::{number, page_contents, PT_PER_PX};
+use crate::scene::Scene;
+
+// How deep references are followed, and the page tree descended, before a
+// PDF is taken to loop back on itself
+const MAX_DEPTH: usize = 64;
+// The name the ink is drawn under in each annotated page's resources
+const INK_NAME: &str = "RelineateInk";
+
+// A PDF object as read from a file. Names keep their escapes as written,
+// so they go back out the same.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+  Null,
+  Boolean(bool),
+  Number(f64),
+  Name(String),
+  Text(Vec<u8>),
+  Array(Vec<Object>),
+  Dictionary(Vec<(String, Object)>),
+  Reference(u32, u16)
+}
+
+impl Object {
+  fn get(&self, key: &str) -> Option<&Object> {
+    return match self {
+      Object::Dictionary(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+      _ => None
+    };
+  }
+
+  fn as_number(&self) -> Option<f64> {
+    return match self {
+      Object::Number(value) => Some(*value),
+      _ => None
+    };
+  }
+
+  fn as_name(&self) -> Option<&str> {
+    return match self {
+      Object::Name(name) => Some(name),
+      _ => None
+    };
+  }
+
+  // The dictionary with `key` set to `value`, replacing any it had
+  fn with(&self, key: &str, value: Object) -> Object {
+    let mut entries = match self {
+      Object::Dictionary(entries) => entries.iter().filter(|(name, _)| name != key).cloned().collect(),
+      _ => Vec::new()
+    };
+    entries.push((String::from(key), value));
+    return Object::Dictionary(entries);
+  }
+
+  fn write(&self) -> String {
+    return match self {
+      Object::Null => String::from("null"),
+      Object::Boolean(value) => value.to_string(),
+      Object::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => format!("{}", *value as i64),
+      Object::Number(value) => number(*value as f32),
+      Object::Name(name) => format!("/{}", name),
+      Object::Text(bytes) => format!("<{}>", bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+      Object::Array(items) => format!("[{}]", items.iter().map(Object::write).collect::<Vec<_>>().join(" ")),
+      Object::Dictionary(entries) => format!(
+        "<< {} >>",
+        entries.iter().map(|(name, value)| format!("/{} {}", name, value.write())).collect::<Vec<_>>().join(" ")
+      ),
+      Object::Reference(id, generation) => format!("{} {} R", id, generation)
+    };
+  }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+  return matches!(byte, 0 | 9 | 10 | 12 | 13 | 32);
+}
+
+fn is_delimiter(byte: u8) -> bool {
+  return b"()<>[]{}/%".contains(&byte);
+}
+
+// Reads objects and keywords from a PDF, from wherever it's put
+struct Lexer<'a> {
+  bytes: &'a [u8],
+  position: usize
+}
+
+impl<'a> Lexer<'a> {
+  fn at(bytes: &'a [u8], position: usize) -> Lexer<'a> {
+    return Lexer { bytes, position };
+  }
+
+  fn peek(&self) -> Option<u8> {
+    return self.bytes.get(self.position).copied();
+  }
+
+  // past whitespace and comments
+  fn skip(&mut self) {
+    while let Some(byte) = self.peek() {
+      if is_whitespace(byte) {
+        self.position += 1;
+      } else if byte == b'%' {
+        while self.peek().is_some_and(|byte| byte != b'\n' && byte != b'\r') {
+          self.position += 1;
+        }
+      } else {
+        return;
+      }
+    }
+  }
+
+  // a run of regular characters: a keyword or a number
+  fn token(&mut self) -> &'a [u8] {
+    self.skip();
+    let start = self.position;
+    while self.peek().is_some_and(|byte| !is_whitespace(byte) && !is_delimiter(byte)) {
+      self.position += 1;
+    }
+    return &self.bytes[start..self.position];
+  }
+
+  fn expect(&mut self, keyword: &str) -> Result<(), String> {
+    let at = self.position;
+    return match self.token() == keyword.as_bytes() {
+      true => Ok(()),
+      false => Err(format!("expected {} at byte {} of the PDF", keyword, at))
+    };
+  }
+
+  fn integer(&mut self) -> Result<u64, String> {
+    let at = self.position;
+    return std::str::from_utf8(self.token()).ok()
+      .and_then(|token| token.parse::<u64>().ok())
+      .ok_or_else(|| format!("expected a number at byte {} of the PDF", at));
+  }
+
+  fn name(&mut self) -> String {
+    // past the slash
+    self.position += 1;
+    let start = self.position;
+    while self.peek().is_some_and(|byte| !is_whitespace(byte) && !is_delimiter(byte)) {
+      self.position += 1;
+    }
+    return String::from_utf8_lossy(&self.bytes[start..self.position]).into_owned();
+  }
+
+  fn literal_string(&mut self) -> Result<Vec<u8>, String> {
+    // past the parenthesis
+    self.position += 1;
+    let mut text = Vec::new();
+    let mut depth = 0;
+    loop {
+      let byte = self.peek().ok_or_else(|| String::from("unterminated string in the PDF"))?;
+      self.position += 1;
+      match byte {
+        b'(' => {
+          depth += 1;
+          text.push(byte);
+        },
+        b')' if depth == 0 => return Ok(text),
+        b')' => {
+          depth -= 1;
+          text.push(byte);
+        },
+        b'\\' => {
+          let escaped = self.peek().ok_or_else(|| String::from("unterminated string in the PDF"))?;
+          self.position += 1;
+          match escaped {
+            b'n' => text.push(b'\n'),
+            b'r' => text.push(b'\r'),
+            b't' => text.push(b'\t'),
+            b'b' => text.push(8),
+            b'f' => text.push(12),
+            b'0'..=b'7' => {
+              let mut value = (escaped - b'0') as u32;
+              for _ in 0..2 {
+                match self.peek() {
+                  Some(digit @ b'0'..=b'7') => {
+                    value = value * 8 + (digit - b'0') as u32;
+                    self.position += 1;
+                  },
+                  _ => break
+                }
+              }
+              text.push(value as u8);
+            },
+            // a line break after a backslash continues the string
+            b'\r' => if self.peek() == Some(b'\n') { self.position += 1; },
+            b'\n' => {},
+            _ => text.push(escaped)
+          }
+        },
+        _ => text.push(byte)
+      }
+    }
+  }
+
+  fn hex_string(&mut self) -> Result<Vec<u8>, String> {
+    // past the angle bracket
+    self.position += 1;
+    let mut digits = Vec::new();
+    loop {
+      let byte = self.peek().ok_or_else(|| String::from("unterminated hex string in the PDF"))?;
+      self.position += 1;
+      match byte {
+        b'>' => break,
+        _ if byte.is_ascii_hexdigit() => digits.push((byte as char).to_digit(16).unwrap_or(0) as u8),
+        _ => {}
+      }
+    }
+    // an odd last digit is followed by a 0
+    if digits.len() % 2 == 1 { digits.push(0); }
+    return Ok(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect());
+  }
+
+  fn object(&mut self) -> Result<Object, String> {
+    self.skip();
+    let at = self.position;
+    return match self.peek() {
+      None => Err(String::from("the PDF ends in the middle of an object")),
+      Some(b'/') => Ok(Object::Name(self.name())),
+      Some(b'(') => self.literal_string().map(Object::Text),
+      Some(b'<') if self.bytes.get(self.position + 1) == Some(&b'<') => {
+        self.position += 2;
+        let mut entries = Vec::new();
+        loop {
+          self.skip();
+          match self.peek() {
+            Some(b'>') => {
+              self.position += 2;
+              return Ok(Object::Dictionary(entries));
+            },
+            Some(b'/') => {
+              let key = self.name();
+              entries.push((key, self.object()?));
+            },
+            _ => return Err(format!("expected a name in the dictionary at byte {} of the PDF", self.position))
+          }
+        }
+      },
+      Some(b'<') => self.hex_string().map(Object::Text),
+      Some(b'[') => {
+        self.position += 1;
+        let mut items = Vec::new();
+        loop {
+          self.skip();
+          if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Object::Array(items));
+          }
+          items.push(self.object()?);
+        }
+      },
+      Some(_) => {
+        let token = self.token();
+        match token {
+          b"true" => Ok(Object::Boolean(true)),
+          b"false" => Ok(Object::Boolean(false)),
+          b"null" => Ok(Object::Null),
+          _ => {
+            let value = std::str::from_utf8(token).ok().and_then(|token| token.parse::<f64>().ok())
+              .ok_or_else(|| format!("unexpected {} at byte {} of the PDF", String::from_utf8_lossy(token), at))?;
+            // "12 0 R" refers to object 12
+            let after_number = self.position;
+            if let (Ok(generation), b"R") = (self.integer(), self.token()) {
+              if value.fract() == 0.0 && value >= 0.0 {
+                return Ok(Object::Reference(value as u32, generation as u16));
+              }
+            }
+            self.position = after_number;
+            Ok(Object::Number(value))
+          }
+        }
+      }
+    };
+  }
+
+  // "12 0 obj ... endobj" with the stream's bytes, still encoded, if it's
+  // a stream
+  fn indirect(&mut self) -> Result<(Object, Option<&'a [u8]>), String> {
+    self.integer()?;
+    self.integer()?;
+    self.expect("obj")?;
+    let object = self.object()?;
+    let before = self.position;
+    if self.token() != b"stream" {
+      self.position = before;
+      return Ok((object, None));
+    }
+    // the data starts after the end of the line
+    if self.peek() == Some(b'\r') { self.position += 1; }
+    if self.peek() == Some(b'\n') { self.position += 1; }
+    let start = self.position;
+    let length = object.get("Length").and_then(Object::as_number).map(|length| length as usize)
+      .filter(|length| {
+        let mut after = Lexer::at(self.bytes, start + length);
+        return start + length <= self.bytes.len() && after.token() == b"endstream";
+      });
+    let end = match length {
+      Some(length) => start + length,
+      // an indirect or wrong length: up to endstream instead
+      None => find(&self.bytes[start..], b"endstream").map(|end| start + end)
+        .ok_or_else(|| String::from("a stream in the PDF has no end"))?
+    };
+    return Ok((object, Some(&self.bytes[start..end])));
+  }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  return haystack.windows(needle.len()).position(|window| window == needle);
+}
+
+// Undoes the PNG predictors of Flate encoded data, row by row
+fn unpredicted(data: &[u8], parameters: Option<&Object>) -> Result<Vec<u8>, String> {
+  let parameter = |key: &str, default: f64| parameters.and_then(|parameters| parameters.get(key)).and_then(Object::as_number).unwrap_or(default);
+  let predictor = parameter("Predictor", 1.0) as u32;
+  if predictor < 10 {
+    return match predictor {
+      1 => Ok(data.to_vec()),
+      _ => Err(format!("PDF predictor {} isn't supported", predictor))
+    };
+  }
+  let pixel = ((parameter("Colors", 1.0) * parameter("BitsPerComponent", 8.0) / 8.0).ceil() as usize).max(1);
+  let row = ((parameter("Columns", 1.0) * parameter("Colors", 1.0) * parameter("BitsPerComponent", 8.0) / 8.0).ceil() as usize).max(1);
+  let mut output = Vec::<u8>::with_capacity(data.len());
+  let mut previous = vec![0u8; row];
+  data.chunks(row + 1).for_each(|chunk| {
+    let (filter, bytes) = (chunk[0], &chunk[1..]);
+    let mut current = vec![0u8; row];
+    bytes.iter().enumerate().for_each(|(index, byte)| {
+      let left = if index >= pixel { current[index - pixel] } else { 0 };
+      let up = previous[index];
+      let up_left = if index >= pixel { previous[index - pixel] } else { 0 };
+      current[index] = byte.wrapping_add(match filter {
+        1 => left,
+        2 => up,
+        3 => ((left as u16 + up as u16) / 2) as u8,
+        4 => {
+          let estimate = left as i16 + up as i16 - up_left as i16;
+          let (to_left, to_up, to_up_left) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+          if to_left <= to_up && to_left <= to_up_left { left } else if to_up <= to_up_left { up } else { up_left }
+        },
+        _ => 0
+      });
+    });
+    output.extend_from_slice(&current[..bytes.len()]);
+    previous = current;
+  });
+  return Ok(output);
+}
+
+// A stream's data decoded, for the Flate encoded streams that hold parts
+// of the cross-reference table and packed objects
+fn decoded(dictionary: &Object, data: &[u8]) -> Result<Vec<u8>, String> {
+  let (filter, parameters) = match (dictionary.get("Filter"), dictionary.get("DecodeParms")) {
+    (Some(Object::Array(filters)), parameters) => match (filters.as_slice(), parameters) {
+      ([], _) => (None, None),
+      ([filter], Some(Object::Array(parameters))) => (filter.as_name(), parameters.first()),
+      ([filter], parameters) => (filter.as_name(), parameters),
+      _ => return Err(String::from("PDF streams with several filters aren't supported"))
+    },
+    (Some(filter), parameters) => (filter.as_name(), parameters),
+    (None, _) => (None, None)
+  };
+  return match filter {
+    None => Ok(data.to_vec()),
+    Some("FlateDecode") => {
+      // past the zlib header
+      let deflated = match data {
+        [method, flags, rest @ ..] if method & 0x0f == 8 && (*method as u16 * 256 + *flags as u16).is_multiple_of(31) => rest,
+        _ => data
+      };
+      inflate(deflated).and_then(|inflated| unpredicted(&inflated, parameters))
+    },
+    Some(filter) => Err(format!("PDF filter {} isn't supported", filter))
+  };
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Location {
+  Free,
+  Offset(usize),
+  // the object stream it's packed in, and its place there
+  Packed(u32, usize)
+}
+
+// The objects of a PDF, found through its cross-reference table
+struct PdfFile<'a> {
+  bytes: &'a [u8],
+  locations: HashMap<u32, (Location, u16)>,
+  trailer: Object,
+  // whether the newest table is a stream, which the update's must be too
+  xref_stream: bool,
+  startxref: usize
+}
+
+fn big_endian(bytes: &[u8]) -> u64 {
+  return bytes.iter().fold(0, |value, byte| value << 8 | *byte as u64);
+}
+
+impl<'a> PdfFile<'a> {
+  fn read(bytes: &'a [u8]) -> Result<PdfFile<'a>, String> {
+    let tail = &bytes[bytes.len().saturating_sub(1024)..];
+    let marker = tail.windows(9).rposition(|window| window == b"startxref")
+      .ok_or_else(|| String::from("the PDF has no startxref"))?;
+    let startxref = Lexer::at(tail, marker + 9).integer()? as usize;
+    let mut file = PdfFile { bytes, locations: HashMap::new(), trailer: Object::Null, xref_stream: false, startxref };
+    let mut offset = Some(startxref);
+    let mut visited = HashSet::new();
+    while let Some(at) = offset.filter(|at| visited.insert(*at)) {
+      let (trailer, is_stream) = file.read_section(at)?;
+      if file.trailer == Object::Null {
+        file.trailer = trailer.clone();
+        file.xref_stream = is_stream;
+      }
+      // hybrid files keep their newer entries in a stream as well
+      if let Some(stream_at) = trailer.get("XRefStm").and_then(Object::as_number) {
+        file.read_section(stream_at as usize)?;
+      }
+      offset = trailer.get("Prev").and_then(Object::as_number).map(|prev| prev as usize);
+    }
+    if file.trailer.get("Encrypt").is_some() {
+      return Err(String::from("encrypted PDFs can't be annotated"));
+    }
+    return Ok(file);
+  }
+
+  // Entries newer sections have already given are kept
+  fn record(&mut self, id: u32, location: Location, generation: u16) {
+    self.locations.entry(id).or_insert((location, generation));
+  }
+
+  // One section of the cross-reference table, a table or a stream, and
+  // the trailer that goes with it
+  fn read_section(&mut self, at: usize) -> Result<(Object, bool), String> {
+    let mut lexer = Lexer::at(self.bytes, at);
+    if lexer.token() == b"xref" {
+      loop {
+        let before = lexer.position;
+        if lexer.token() == b"trailer" {
+          return lexer.object().map(|trailer| (trailer, false));
+        }
+        lexer.position = before;
+        let (first, count) = (lexer.integer()? as u32, lexer.integer()? as u32);
+        for id in first..first + count {
+          let (offset, generation) = (lexer.integer()? as usize, lexer.integer()? as u16);
+          let location = match lexer.token() {
+            b"n" => Location::Offset(offset),
+            _ => Location::Free
+          };
+          self.record(id, location, generation);
+        }
+      }
+    }
+    let (dictionary, data) = Lexer::at(self.bytes, at).indirect()?;
+    let data = decoded(&dictionary, data.ok_or_else(|| String::from("the PDF's startxref doesn't lead to its cross-references"))?)?;
+    let widths = match dictionary.get("W") {
+      Some(Object::Array(widths)) => widths.iter().map(|width| width.as_number().unwrap_or(0.0) as usize).collect::<Vec<_>>(),
+      _ => return Err(String::from("the PDF's cross-reference stream has no /W"))
+    };
+    if widths.len() != 3 {
+      return Err(String::from("the PDF's cross-reference stream has a bad /W"));
+    }
+    let size = dictionary.get("Size").and_then(Object::as_number).unwrap_or(0.0);
+    let index = match dictionary.get("Index") {
+      Some(Object::Array(index)) => index.iter().filter_map(Object::as_number).collect::<Vec<_>>(),
+      _ => vec![0.0, size]
+    };
+    let ids = index.chunks(2)
+      .flat_map(|range| match range {
+        [first, count] => (*first as u32..(*first + *count) as u32).collect::<Vec<_>>(),
+        _ => Vec::new()
+      })
+      .collect::<Vec<_>>();
+    let entry_width = widths.iter().sum::<usize>().max(1);
+    data.chunks(entry_width).zip(ids).for_each(|(entry, id)| {
+      let (type_bytes, rest) = entry.split_at(widths[0].min(entry.len()));
+      let (second, third) = rest.split_at(widths[1].min(rest.len()));
+      // a missing type field means an object in use
+      let kind = if widths[0] == 0 { 1 } else { big_endian(type_bytes) };
+      let (second, third) = (big_endian(second), big_endian(third));
+      let (location, generation) = match kind {
+        1 => (Location::Offset(second as usize), third as u16),
+        2 => (Location::Packed(second as u32, third as usize), 0),
+        _ => (Location::Free, third as u16)
+      };
+      self.record(id, location, generation);
+    });
+    return Ok((dictionary, true));
+  }
+
+  fn object(&self, id: u32) -> Result<Object, String> {
+    return match self.locations.get(&id) {
+      Some((Location::Offset(offset), _)) => Lexer::at(self.bytes, *offset).indirect().map(|(object, _)| object),
+      Some((Location::Packed(stream_id, index), _)) => {
+        let (dictionary, data) = match self.locations.get(stream_id) {
+          Some((Location::Offset(offset), _)) => Lexer::at(self.bytes, *offset).indirect()?,
+          _ => return Err(format!("object stream {} of the PDF is missing", stream_id))
+        };
+        let data = decoded(&dictionary, data.ok_or_else(|| format!("object {} of the PDF isn't a stream", stream_id))?)?;
+        let first = dictionary.get("First").and_then(Object::as_number).unwrap_or(0.0) as usize;
+        let mut header = Lexer::at(&data, 0);
+        let mut offset = None;
+        for _ in 0..=*index {
+          let (_, at) = (header.integer()?, header.integer()? as usize);
+          offset = Some(at);
+        }
+        offset
+          .ok_or_else(|| format!("object {} of the PDF is missing", id))
+          .and_then(|offset| Lexer::at(&data, first + offset).object())
+      },
+      _ => Ok(Object::Null)
+    };
+  }
+
+  // The object, or what it refers to
+  fn resolve(&self, object: &Object) -> Result<Object, String> {
+    let mut resolved = object.clone();
+    for _ in 0..MAX_DEPTH {
+      match resolved {
+        Object::Reference(id, _) => resolved = self.object(id)?,
+        _ => return Ok(resolved)
+      }
+    }
+    return Err(String::from("the PDF's references go round in circles"));
+  }
+
+  // Every page in order: its reference and its dictionary, given what it
+  // inherits from the page tree
+  fn pages(&self) -> Result<Vec<(Object, Object)>, String> {
+    let root = self.resolve(self.trailer.get("Root").unwrap_or(&Object::Null))?;
+    let tree = root.get("Pages").cloned().ok_or_else(|| String::from("the PDF has no pages"))?;
+    let mut pages = Vec::new();
+    self.collect_pages(&tree, &Object::Dictionary(Vec::new()), 0, &mut pages)?;
+    return Ok(pages);
+  }
+
+  fn collect_pages(&self, node: &Object, inherited: &Object, depth: usize, pages: &mut Vec<(Object, Object)>) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+      return Err(String::from("the PDF's page tree goes round in circles"));
+    }
+    let dictionary = self.resolve(node)?;
+    let inherited = ["Resources", "MediaBox", "CropBox", "Rotate"].iter().fold(inherited.clone(), |inherited, key| {
+      return match dictionary.get(key) {
+        Some(value) => inherited.with(key, value.clone()),
+        None => inherited
+      };
+    });
+    return match dictionary.get("Type").and_then(Object::as_name) {
+      Some("Pages") => {
+        let kids = match self.resolve(dictionary.get("Kids").unwrap_or(&Object::Null))? {
+          Object::Array(kids) => kids,
+          _ => Vec::new()
+        };
+        kids.iter().try_for_each(|kid| self.collect_pages(kid, &inherited, depth + 1, pages))
+      },
+      _ => {
+        let page = match &inherited {
+          Object::Dictionary(entries) => entries.iter().fold(dictionary.clone(), |page, (key, value)| page.with(key, value.clone())),
+          _ => dictionary.clone()
+        };
+        pages.push((node.clone(), page));
+        Ok(())
+      }
+    };
+  }
+}
+
+// The box a page shows, in points: left, bottom, width and height
+fn page_box(file: &PdfFile, page: &Object) -> Result<(f32, f32, f32, f32), String> {
+  let shown = page.get("CropBox").or_else(|| page.get("MediaBox")).unwrap_or(&Object::Null);
+  return match file.resolve(shown)? {
+    Object::Array(corners) => match corners.iter().map(|corner| file.resolve(corner).ok().and_then(|corner| corner.as_number())).collect::<Option<Vec<_>>>().as_deref() {
+      Some([x1, y1, x2, y2]) => Ok((x1.min(*x2) as f32, y1.min(*y2) as f32, (x2 - x1).abs() as f32, (y2 - y1).abs() as f32)),
+      _ => Err(String::from("a page of the PDF has a bad MediaBox"))
+    },
+    _ => Err(String::from("a page of the PDF has no MediaBox"))
+  };
+}
+
+// The source PDF with each page's ink drawn over the page it was written
+// on, the ink's scene for the PDF's first page first, None for pages
+// without any. xochitl fits a PDF page to the tablet's screen, so the ink
+// is its canvas fitted and centered on the page's box. The ink is added as
+// an incremental update, leaving the original file as it was, byte for
+// byte, in front of it.
+pub fn annotate_pdf(source: &[u8], ink: &[Option<Scene>]) -> Result<Vec<u8>, String> {
+  let file = PdfFile::read(source)?;
+  let pages = file.pages()?;
+  if ink.len() > pages.len() {
+    return Err(format!("there's ink for {} pages, but the PDF has {}", ink.len(), pages.len()));
+  }
+  let mut next_id = file.trailer.get("Size").and_then(Object::as_number).unwrap_or(0.0) as u32;
+  let mut objects = Vec::<(u32, u16, String)>::new();
+  let mut new_object = |body: String, objects: &mut Vec<(u32, u16, String)>| {
+    let id = next_id;
+    next_id += 1;
+    objects.push((id, 0, body));
+    return Object::Reference(id, 0);
+  };
+  let stream = |dictionary: &str, contents: &str| format!("<< {} /Length {} >>\nstream\n{}\nendstream", dictionary, contents.len(), contents);
+  for ((reference, page), scene) in pages.iter().zip(ink.iter()) {
+    let (scene, (id, generation)) = match (scene, reference) {
+      (Some(scene), Object::Reference(id, generation)) => (scene, (*id, *generation)),
+      _ => continue
+    };
+    let (left, bottom, width, height) = page_box(&file, page)?;
+    let fitted = Scene { width: width / PT_PER_PX, height: height / PT_PER_PX, ..scene.clone() };
+    let (contents, resources) = page_contents(&fitted);
+    let form = new_object(stream(
+      &format!("/Type /XObject /Subtype /Form /BBox [0 0 {} {}] /Resources {}", number(width), number(height), resources),
+      &contents
+    ), &mut objects);
+    // the page's own drawing is kept from changing how the ink is placed
+    let save = new_object(stream("", "q"), &mut objects);
+    let draw = new_object(stream("", &format!("Q\nq 1 0 0 1 {} {} cm /{} Do Q", number(left), number(bottom), INK_NAME)), &mut objects);
+    let original_contents = match page.get("Contents") {
+      Some(Object::Array(items)) => items.clone(),
+      Some(Object::Null) | None => Vec::new(),
+      Some(contents) => match file.resolve(contents)? {
+        Object::Array(items) => items,
+        _ => vec![contents.clone()]
+      }
+    };
+    let contents = [vec![save], original_contents, vec![draw]].concat();
+    let resources = file.resolve(page.get("Resources").unwrap_or(&Object::Null))?;
+    let forms = match resources.get("XObject") {
+      Some(forms) => file.resolve(forms)?,
+      None => Object::Dictionary(Vec::new())
+    };
+    let resources = resources.with("XObject", forms.with(INK_NAME, form));
+    let page = page.with("Contents", Object::Array(contents)).with("Resources", resources);
+    objects.push((id, generation, page.write()));
+  }
+
+  let mut pdf = source.to_vec();
+  if !pdf.ends_with(b"\n") { pdf.push(b'\n'); }
+  let mut offsets = objects.iter()
+    .map(|(id, generation, body)| {
+      let offset = pdf.len();
+      pdf.extend_from_slice(format!("{} {} obj\n{}\nendobj\n", id, generation, body).as_bytes());
+      return (*id, *generation, offset);
+    })
+    .collect::<Vec<_>>();
+  let kept = ["Root", "Info", "ID"].iter()
+    .filter_map(|key| file.trailer.get(key).map(|value| format!("/{} {}", key, value.write())))
+    .collect::<Vec<_>>()
+    .join(" ");
+  let xref = pdf.len();
+  if file.xref_stream {
+    // the table as a stream of its own, with an entry for itself
+    let id = next_id;
+    offsets.push((id, 0, xref));
+    offsets.sort();
+    let entries = offsets.iter()
+      .flat_map(|(_, generation, offset)| [[1].as_slice(), &(*offset as u32).to_be_bytes(), &generation.to_be_bytes()].concat())
+      .collect::<Vec<_>>();
+    let index = offsets.iter().map(|(id, _, _)| format!("{} 1", id)).collect::<Vec<_>>().join(" ");
+    pdf.extend_from_slice(format!(
+      "{} 0 obj\n<< /Type /XRef /Size {} /W [1 4 2] /Index [{}] /Prev {} {} /Length {} >>\nstream\n",
+      id, id + 1, index, file.startxref, kept, entries.len()
+    ).as_bytes());
+    pdf.extend_from_slice(&entries);
+    pdf.extend_from_slice(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF\n", xref).as_bytes());
+    return Ok(pdf);
+  }
+  offsets.sort();
+  pdf.extend_from_slice(b"xref\n");
+  offsets.iter().for_each(|(id, generation, offset)| {
+    pdf.extend_from_slice(format!("{} 1\n{:010} {:05} n \n", id, offset, generation).as_bytes());
+  });
+  pdf.extend_from_slice(format!(
+    "trailer\n<< /Size {} /Prev {} {} >>\nstartxref\n{}\n%%EOF\n", next_id, file.startxref, kept, xref
+  ).as_bytes());
+  return Ok(pdf);
+}
@@ -0,0 +1,340 @@
+use std::convert::TryFrom;
+
+use svg::Document as SVGDocument;
+use svg::node::element::Rectangle as SVGRectangle;
+
+use crate::brush::BrushColor;
+use crate::json;
+use crate::notebook::{page_ids, read_entries, Notebook};
+use crate::options::RenderOptions;
+use crate::orientation::canvas_group;
+use crate::zip::{read_archive, ZipEntry};
+use crate::{header_version, render_svg, v6, Document, LogLevel};
+
+// How opaque highlights are drawn on page thumbnails, over the ink
+const HIGHLIGHT_OPACITY: f32 = 0.35;
+
+// Text highlighted in the PDF or EPUB under a page: `length` characters
+// from `start` in the page's text, which v6 pages may leave out, in one of
+// the brush colors, with the rectangles on the canvas it's covered by
+#[derive(Debug, Clone, PartialEq)]
+pub struct Highlight {
+  pub start: Option<u32>,
+  pub length: u32,
+  pub color: u32,
+  pub text: String,
+  pub rectangles: Vec<(f32, f32, f32, f32)>
+}
+
+impl Highlight {
+  pub fn color_name(&self) -> &'static str {
+    return BrushColor::try_from(self.color as i32).map_or("yellow", |color| color.name());
+  }
+
+  fn rgb(&self) -> &'static str {
+    return BrushColor::try_from(self.color as i32).map_or(BrushColor::Yellow, |color| match color {
+      // the highlighter's own colors, not the pens'
+      BrushColor::Black | BrushColor::Grey | BrushColor::White | BrushColor::Highlight => BrushColor::Yellow,
+      color => color
+    }).default_rgb();
+  }
+}
+
+// A chapter of an EPUB: its title from the table of contents and its text,
+// as plain words, for finding which chapter a highlight is in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+  pub title: String,
+  text: String
+}
+
+// What was written or highlighted on a page of a book or PDF
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnotatedPage {
+  // 1-based position in the document, as in the notebook's page numbers
+  pub number: usize,
+  pub chapter: Option<String>,
+  pub highlights: Vec<Highlight>,
+  pub strokes: usize
+}
+
+#[derive(Debug)]
+pub struct Annotations {
+  pub notebook: Notebook,
+  pub pages: Vec<AnnotatedPage>
+}
+
+// Text with runs of whitespace as single spaces
+fn words(text: &str) -> String {
+  return text.split_whitespace().collect::<Vec<_>>().join(" ");
+}
+
+// The few entities books use in running text
+fn unescaped(text: &str) -> String {
+  let mut plain = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find('&') {
+    plain.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let end = match rest.find(';').filter(|end| *end <= 10) {
+      Some(end) => end,
+      None => {
+        plain.push('&');
+        rest = &rest[1..];
+        continue;
+      }
+    };
+    let entity = &rest[1..end];
+    let character = match entity {
+      "amp" => Some('&'),
+      "lt" => Some('<'),
+      "gt" => Some('>'),
+      "quot" => Some('"'),
+      "apos" => Some('\''),
+      "nbsp" => Some(' '),
+      _ => entity.strip_prefix("#x").and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .or_else(|| entity.strip_prefix('#').and_then(|decimal| decimal.parse::<u32>().ok()))
+        .and_then(char::from_u32)
+    };
+    match character {
+      Some(character) => plain.push(character),
+      None => plain.push_str(&rest[..=end])
+    }
+    rest = &rest[end + 1..];
+  }
+  plain.push_str(rest);
+  return plain;
+}
+
+// The text of an XHTML document, tags and all markup dropped
+fn plain_text(xhtml: &str) -> String {
+  let body = xhtml.find("<body").map_or(xhtml, |start| &xhtml[start..]);
+  let mut text = String::with_capacity(body.len());
+  let mut in_tag = false;
+  body.chars().for_each(|character| match (character, in_tag) {
+    ('<', _) => {
+      in_tag = true;
+      // tags break words, as blocks and line breaks do
+      text.push(' ');
+    },
+    ('>', true) => in_tag = false,
+    (_, false) => text.push(character),
+    _ => {}
+  });
+  return words(&unescaped(&text));
+}
+
+// The opening tags named `name`, from just after the name to the >
+fn tags<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+  let opening = format!("<{}", name);
+  return xml.match_indices(&opening)
+    .map(|(start, _)| &xml[start + opening.len()..])
+    .filter(|rest| rest.starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/'))
+    .map(|rest| &rest[..rest.find('>').unwrap_or(rest.len())])
+    .collect();
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+  return [format!(" {}=\"", name), format!(" {}='", name)].iter()
+    .find_map(|prefix| {
+      let start = tag.find(prefix.as_str())? + prefix.len();
+      let quote = prefix.chars().last()?;
+      return tag[start..].find(quote).map(|end| unescaped(&tag[start..start + end]));
+    });
+}
+
+// `href` from a document in `directory`, as a path in the archive
+fn resolved(directory: &str, href: &str) -> String {
+  let href = href.split('#').next().unwrap_or_default();
+  let mut parts = directory.split('/').filter(|part| !part.is_empty()).collect::<Vec<_>>();
+  href.split('/').for_each(|part| match part {
+    ".." => { parts.pop(); },
+    "." | "" => {},
+    part => parts.push(part)
+  });
+  return parts.join("/");
+}
+
+fn directory_of(path: &str) -> &str {
+  return path.rfind('/').map_or("", |end| &path[..end]);
+}
+
+// The table of contents as the titles of the documents they start at:
+// from the EPUB 3 navigation document, or else the EPUB 2 NCX
+fn contents_titles(files: &[ZipEntry], manifest: &[(String, String, String)], spine_toc: Option<&str>) -> Vec<(String, String)> {
+  let read = |path: &str| files.iter().find(|file| file.name == path).map(|file| String::from_utf8_lossy(&file.data).into_owned());
+  if let Some((_, nav_path, _)) = manifest.iter().find(|(_, _, properties)| properties.split_whitespace().any(|property| property == "nav")) {
+    if let Some(nav) = read(nav_path) {
+      let directory = directory_of(nav_path);
+      return nav.match_indices("<a").filter_map(|(start, _)| {
+        let rest = &nav[start..];
+        let tag = &rest[..rest.find('>')?];
+        let href = attribute(tag, "href")?;
+        let label = &rest[tag.len() + 1..rest.find("</a>")?];
+        return Some((resolved(directory, &href), plain_text(&format!("<body>{}", label))));
+      }).collect();
+    }
+  }
+  let ncx_path = spine_toc
+    .and_then(|toc| manifest.iter().find(|(id, _, _)| id == toc))
+    .map(|(_, path, _)| path.clone())
+    .or_else(|| manifest.iter().find(|(_, path, _)| path.ends_with(".ncx")).map(|(_, path, _)| path.clone()));
+  return ncx_path.and_then(|path| read(&path).map(|ncx| (directory_of(&path).to_string(), ncx)))
+    .map_or_else(Vec::new, |(directory, ncx)| {
+      return ncx.split("<navPoint").skip(1).filter_map(|point| {
+        let label = point.find("<text>").and_then(|start| point[start + 6..].find("</text>").map(|end| &point[start + 6..start + 6 + end]))?;
+        let source = tags(point, "content").first().and_then(|tag| attribute(&format!(" {}", tag), "src"))?;
+        return Some((resolved(&directory, &source), words(&unescaped(label))));
+      }).collect();
+    });
+}
+
+// The chapters of an EPUB in reading order, each document of its spine
+// titled as the table of contents has it, or as the chapter it continues
+pub fn epub_chapters(epub: &[u8]) -> Result<Vec<Chapter>, String> {
+  let files = read_archive(epub)?;
+  let read = |path: &str| {
+    return files.iter().find(|file| file.name == path)
+      .map(|file| String::from_utf8_lossy(&file.data).into_owned())
+      .ok_or_else(|| format!("the EPUB has no {}", path));
+  };
+  let container = read("META-INF/container.xml")?;
+  let package_path = tags(&container, "rootfile").first()
+    .and_then(|tag| attribute(&format!(" {}", tag), "full-path"))
+    .ok_or_else(|| String::from("the EPUB's container.xml names no package"))?;
+  let package = read(&package_path)?;
+  let directory = directory_of(&package_path);
+  // id, path and properties of everything in the book
+  let manifest = tags(&package, "item").iter()
+    .filter_map(|tag| {
+      let tag = format!(" {}", tag);
+      return attribute(&tag, "id").zip(attribute(&tag, "href"))
+        .map(|(id, href)| (id, resolved(directory, &href), attribute(&tag, "properties").unwrap_or_default()));
+    })
+    .collect::<Vec<_>>();
+  let spine_toc = tags(&package, "spine").first().and_then(|tag| attribute(&format!(" {}", tag), "toc"));
+  let titles = contents_titles(&files, &manifest, spine_toc.as_deref());
+  let mut title = String::new();
+  return Ok(tags(&package, "itemref").iter()
+    .filter_map(|tag| attribute(&format!(" {}", tag), "idref"))
+    .filter_map(|idref| manifest.iter().find(|(id, _, _)| *id == idref).map(|(_, path, _)| path.clone()))
+    .filter_map(|path| {
+      let text = read(&path).ok()?;
+      if let Some((_, label)) = titles.iter().find(|(href, label)| *href == path && !label.is_empty()) {
+        title = label.clone();
+      }
+      return Some(Chapter { title: if title.is_empty() { path.clone() } else { title.clone() }, text: plain_text(&text) });
+    })
+    .collect());
+}
+
+// A page's highlights from its file in UUID.highlights/, as firmware 2
+// writes them
+fn json_highlights(data: &[u8]) -> Result<Vec<Highlight>, String> {
+  let highlights = json::parse(&String::from_utf8_lossy(data))?;
+  let number = |value: &json::Value, key: &str| value.get(key).and_then(json::Value::as_f64).map(|number| number as u32);
+  return Ok(highlights.get("highlights").and_then(json::Value::as_array).into_iter().flatten()
+    .flat_map(|group| group.as_array().cloned().unwrap_or_default())
+    .map(|highlight| Highlight {
+      start: number(&highlight, "start"),
+      length: number(&highlight, "length").unwrap_or(0),
+      color: number(&highlight, "color").unwrap_or(BrushColor::Yellow as u32),
+      text: highlight.get("text").and_then(json::Value::as_str).map(words).unwrap_or_default(),
+      rectangles: Vec::new()
+    })
+    .collect());
+}
+
+// Everything highlighted and written on the pages of an annotated PDF or
+// EPUB, from the files of its notebook. Pages of an EPUB are put in the
+// chapter their highlights are from, or else the one the page before was
+// in.
+pub fn read_annotations(entries: &[ZipEntry], logger: &dyn Fn(LogLevel, String)) -> Result<Annotations, String> {
+  let notebook = read_entries(entries, logger)?;
+  let file = |suffix: &str| entries.iter().find(|entry| entry.name.ends_with(suffix));
+  let chapters = match file(&format!("{}.epub", notebook.id)) {
+    Some(epub) => epub_chapters(&epub.data)?,
+    None => Vec::new()
+  };
+  logger(LogLevel::DEBUG, format!("the book has {} chapters", chapters.len()));
+  let content = file(&format!("{}.content", notebook.id))
+    .and_then(|content| json::parse(&String::from_utf8_lossy(&content.data)).ok());
+  let ids = match content.as_ref().map(page_ids).filter(|ids| !ids.is_empty()) {
+    Some(ids) => ids,
+    None => notebook.pages.iter().map(|page| page.id.clone()).collect()
+  };
+  let mut chapter = None::<String>;
+  let pages = ids.iter().enumerate()
+    .map(|(index, page_id)| {
+      let mut highlights = match file(&format!("{}.highlights/{}.json", notebook.id, page_id)) {
+        Some(entry) => json_highlights(&entry.data).map_err(|why| format!("page {} highlights: {}", index + 1, why))?,
+        None => Vec::new()
+      };
+      if let Some(page) = file(&format!("{}/{}.rm", notebook.id, page_id)).filter(|page| header_version(&page.data) == Some(6)) {
+        highlights.extend(v6::read_page(&page.data).map(|page| page.highlights).unwrap_or_default());
+      }
+      let strokes = notebook.pages.iter()
+        .find(|page| page.number == index + 1)
+        .map_or(0, |page| page.layers.iter().map(|layer| layer.lines.len()).sum());
+      let found = highlights.iter()
+        .filter(|highlight| !highlight.text.is_empty())
+        .find_map(|highlight| chapters.iter().find(|chapter| chapter.text.contains(&words(&highlight.text))));
+      if let Some(found) = found {
+        chapter = Some(found.title.clone());
+      }
+      return Ok(AnnotatedPage { number: index + 1, chapter: chapter.clone(), highlights, strokes });
+    })
+    .collect::<Result<Vec<_>, String>>()?
+    .into_iter()
+    .filter(|page| page.strokes > 0 || !page.highlights.is_empty())
+    .collect();
+  return Ok(Annotations { notebook, pages });
+}
+
+// A page's ink with its highlights shaded over it where the tablet
+// recorded them
+pub fn thumbnail(document: &Document, highlights: &[Highlight], options: &RenderOptions) -> Result<SVGDocument, String> {
+  let svg = render_svg(document, options)?;
+  let shading = highlights.iter()
+    .flat_map(|highlight| highlight.rectangles.iter().map(move |rectangle| (highlight.rgb(), rectangle)))
+    .fold(canvas_group(options).set("class", "highlights"), |group, (rgb, (x, y, width, height))| {
+      return group.add(SVGRectangle::new()
+        .set("x", *x)
+        .set("y", *y)
+        .set("width", *width)
+        .set("height", *height)
+        .set("fill", rgb)
+        .set("fill-opacity", HIGHLIGHT_OPACITY));
+    });
+  return Ok(svg.add(shading));
+}
+
+// The annotations as a Markdown report: a section per chapter, a heading per
+// page with its thumbnail (named by `thumbnail_names`, if it has one), what
+// was highlighted where and how much was written
+pub fn write_report(annotations: &Annotations, thumbnail_names: &dyn Fn(&AnnotatedPage) -> Option<String>) -> String {
+  let title = annotations.notebook.name.clone().unwrap_or_else(|| annotations.notebook.id.clone());
+  let mut report = format!("# {}\n", title);
+  let mut chapter = None::<&String>;
+  annotations.pages.iter().for_each(|page| {
+    if let Some(title) = page.chapter.as_ref().filter(|title| Some(*title) != chapter) {
+      report.push_str(&format!("\n## {}\n", title));
+      chapter = Some(title);
+    }
+    report.push_str(&format!("\n### Page {}\n\n", page.number));
+    if let Some(name) = thumbnail_names(page) {
+      report.push_str(&format!("![Page {}]({})\n\n", page.number, name));
+    }
+    page.highlights.iter().for_each(|highlight| {
+      let position = match highlight.start {
+        Some(start) => format!(", characters {} to {}", start, start + highlight.length),
+        None => String::new()
+      };
+      report.push_str(&format!("- Highlighted in {}{}: \"{}\"\n", highlight.color_name(), position, highlight.text));
+    });
+    if page.strokes > 0 {
+      report.push_str(&format!("- {} {} written\n", page.strokes, if page.strokes == 1 { "stroke" } else { "strokes" }));
+    }
+  });
+  return report;
+}
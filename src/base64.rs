@@ -0,0 +1,53 @@
+// Standard base64 with padding, for embedding files as data: URIs
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+  return data.chunks(3)
+    .flat_map(|chunk| {
+      let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+      let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+      return (0..4).map(move |index| {
+        if index > chunk.len() { return '='; }
+        return ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char;
+      });
+    })
+    .collect();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The other direction, to check encodings round-trip
+  fn decode(text: &str) -> Vec<u8> {
+    let sextets = text.bytes()
+      .filter(|byte| *byte != b'=')
+      .map(|byte| ALPHABET.iter().position(|letter| *letter == byte).unwrap() as u32)
+      .collect::<Vec<_>>();
+    return sextets.chunks(4)
+      .flat_map(|chunk| {
+        let group = chunk.iter().enumerate().fold(0, |group, (index, sextet)| group | sextet << (18 - 6 * index));
+        return (0..chunk.len() - 1).map(move |index| (group >> (16 - 8 * index)) as u8);
+      })
+      .collect();
+  }
+
+  #[test]
+  fn encodes_the_rfc_4648_vectors() {
+    let vectors = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+    vectors.iter().for_each(|(data, encoded)| assert_eq!(encode(data.as_bytes()), *encoded));
+  }
+
+  #[test]
+  fn round_trips_every_byte() {
+    let bytes = (0..=255u8).collect::<Vec<_>>();
+    // lengths 0 to 3 over a multiple of 3, padded with ==, = or nothing
+    (0..4).map(|extra| &bytes[..252 + extra]).chain(std::iter::once(&bytes[..0])).for_each(|data| {
+      let encoded = encode(data);
+      assert_eq!(encoded.len(), data.len().div_ceil(3) * 4);
+      assert_eq!(encoded.matches('=').count(), (3 - data.len() % 3) % 3);
+      assert!(encoded.bytes().all(|byte| byte == b'=' || ALPHABET.contains(&byte)));
+      assert_eq!(decode(&encoded), data, "{} bytes didn't round-trip", data.len());
+    });
+  }
+}
@@ -0,0 +1,430 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use relineate::device::Device;
+use relineate::options::RenderOptions;
+use relineate::output::{FileSink, OutputFormat};
+use relineate::preview::braille_preview;
+use relineate::recognize::slugify;
+use relineate::xochitl::{document_pages, list_documents, read_page, DocumentEntry, DocumentSource};
+use relineate::{render_svg, Document, LogLevel};
+
+use crate::{page_output_path, save_page};
+
+const DOCUMENTS_HELP: &str = "↑↓ move  enter open  / filter  q quit";
+const FILTER_HELP: &str = "type to filter  enter keep  esc clear";
+const PAGES_HELP: &str = "↑↓ move  space pick  a all  c convert  esc back  q quit";
+
+// The list takes up to this many columns, the preview the rest
+const LIST_WIDTH: usize = 40;
+
+// What the list shows
+enum View {
+  Documents,
+  // the document's page ids, and the pages picked to convert
+  Pages(DocumentEntry, Vec<String>, BTreeSet<usize>)
+}
+
+// The terminal in raw mode on the alternate screen, for as long as it's
+// held, and as it was before once dropped
+struct Screen;
+
+impl Screen {
+  fn enter() -> io::Result<Screen> {
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+    return Ok(Screen);
+  }
+}
+
+impl Drop for Screen {
+  fn drop(&mut self) {
+    let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+  }
+}
+
+struct Browser<'a> {
+  source: &'a dyn DocumentSource,
+  documents: Vec<DocumentEntry>,
+  view: View,
+  filter: String,
+  filtering: bool,
+  // the highlighted row of the list, the first row shown, and the row
+  // highlighted among the documents while a document is open
+  cursor: usize,
+  scroll: usize,
+  document_cursor: usize,
+  // each page read so far, by id; None for pages never drawn on
+  pages: HashMap<String, Option<Document>>,
+  status: String,
+  // where converted pages go
+  output: &'a str
+}
+
+impl<'a> Browser<'a> {
+  fn shown_documents(&self) -> Vec<&DocumentEntry> {
+    return self.documents.iter()
+      .filter(|document| document.name.to_lowercase().contains(&self.filter.to_lowercase()))
+      .collect();
+  }
+
+  // The list's rows, whichever it shows
+  fn rows(&self) -> Vec<String> {
+    return match &self.view {
+      View::Documents => self.shown_documents().iter().map(|document| document.name.clone()).collect(),
+      View::Pages(_, pages, picked) => (0..pages.len())
+        .map(|index| format!("[{}] page {}", if picked.contains(&index) { "x" } else { " " }, index + 1))
+        .collect()
+    };
+  }
+
+  fn title(&self) -> String {
+    return match (&self.view, self.filter.is_empty()) {
+      (View::Documents, true) => String::from("Documents"),
+      (View::Documents, false) => format!("Documents matching {}", self.filter),
+      (View::Pages(document, pages, picked), _) => format!("{}: {} pages, {} picked", document.name, pages.len(), picked.len())
+    };
+  }
+
+  fn help(&self) -> &'static str {
+    return match (&self.view, self.filtering) {
+      (View::Documents, true) => FILTER_HELP,
+      (View::Documents, false) => DOCUMENTS_HELP,
+      (View::Pages(..), _) => PAGES_HELP
+    };
+  }
+
+  fn move_cursor(&mut self, by: isize) {
+    let last = self.rows().len().saturating_sub(1);
+    self.cursor = (self.cursor as isize + by).clamp(0, last as isize) as usize;
+  }
+
+  // The highlighted page, read if it hasn't been
+  fn current_page(&mut self, logger: &dyn Fn(LogLevel, String)) -> Option<&Document> {
+    let (id, page_id) = match &self.view {
+      View::Pages(document, pages, _) => (document.id.clone(), pages.get(self.cursor)?.clone()),
+      View::Documents => return None
+    };
+    if !self.pages.contains_key(&page_id) {
+      let page = read_page(self.source, &id, &page_id, logger).unwrap_or_else(|why| {
+        self.status = why;
+        return None;
+      });
+      self.pages.insert(page_id.clone(), page);
+    }
+    return self.pages.get(&page_id).and_then(Option::as_ref);
+  }
+
+  fn open(&mut self) {
+    let document = match self.shown_documents().get(self.cursor) {
+      Some(document) => (*document).clone(),
+      None => return
+    };
+    match document_pages(self.source, &document.id) {
+      Ok(pages) => {
+        self.document_cursor = self.cursor;
+        self.cursor = 0;
+        self.scroll = 0;
+        self.view = View::Pages(document, pages, BTreeSet::new());
+      },
+      Err(why) => self.status = why
+    }
+  }
+
+  fn back(&mut self) {
+    self.view = View::Documents;
+    self.cursor = self.document_cursor;
+    self.scroll = 0;
+  }
+
+  // The picked pages, or the highlighted one if none are, converted to
+  // files named after the document
+  fn convert(&mut self, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) {
+    let (document, pages, picked) = match &self.view {
+      View::Pages(document, pages, picked) => (document.clone(), pages.clone(), picked.clone()),
+      View::Documents => return
+    };
+    let numbers = match picked.is_empty() {
+      true => vec![self.cursor + 1],
+      false => picked.iter().map(|index| index + 1).collect()
+    };
+    let output = Path::new(self.output).join(format!("{}.svg", slugify(&document.name))).to_string_lossy().into_owned();
+    let written = numbers.iter().try_fold(Vec::<String>::new(), |mut written, &number| {
+      let page = match read_page(self.source, &document.id, &pages[number - 1], logger)? {
+        Some(page) => page,
+        None => return Ok(written)
+      };
+      let page_output = page_output_path(&output, number, None, "svg");
+      let svg = render_svg(&page, render_options)?;
+      let format = OutputFormat::from_path(&page_output).unwrap_or(OutputFormat::Svg);
+      save_page(&mut FileSink, &page_output, &page, &svg, render_options, format, None, logger)?;
+      written.push(page_output);
+      return Ok::<Vec<String>, String>(written);
+    });
+    self.status = match written {
+      Ok(written) if written.is_empty() => String::from("nothing to convert, the pages are blank"),
+      Ok(written) if written.len() == 1 => format!("wrote {}", written[0]),
+      Ok(written) => format!("wrote {} pages, {} to {}", written.len(), written[0], written[written.len() - 1]),
+      Err(why) => why
+    };
+  }
+
+  // Handles a key press; false once it's time to quit
+  fn key(&mut self, key: KeyEvent, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> bool {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+      return false;
+    }
+    self.status.clear();
+    if self.filtering {
+      match key.code {
+        KeyCode::Char(c) => self.filter.push(c),
+        KeyCode::Backspace => { self.filter.pop(); },
+        KeyCode::Enter => self.filtering = false,
+        KeyCode::Esc => {
+          self.filter.clear();
+          self.filtering = false;
+        },
+        _ => ()
+      }
+      self.cursor = 0;
+      self.scroll = 0;
+      return true;
+    }
+    match (&mut self.view, key.code) {
+      (_, KeyCode::Char('q')) => return false,
+      (_, KeyCode::Up | KeyCode::Char('k')) => self.move_cursor(-1),
+      (_, KeyCode::Down | KeyCode::Char('j')) => self.move_cursor(1),
+      (_, KeyCode::PageUp) => self.move_cursor(-10),
+      (_, KeyCode::PageDown) => self.move_cursor(10),
+      (_, KeyCode::Home | KeyCode::Char('g')) => self.cursor = 0,
+      (_, KeyCode::End | KeyCode::Char('G')) => self.move_cursor(isize::MAX / 2),
+      (View::Documents, KeyCode::Enter | KeyCode::Right | KeyCode::Char('l')) => self.open(),
+      (View::Documents, KeyCode::Char('/')) => self.filtering = true,
+      (View::Documents, KeyCode::Esc) => self.filter.clear(),
+      (View::Pages(_, _, picked), KeyCode::Char(' ')) => {
+        if !picked.remove(&self.cursor) {
+          picked.insert(self.cursor);
+        }
+        self.move_cursor(1);
+      },
+      (View::Pages(_, pages, picked), KeyCode::Char('a')) => match picked.len() == pages.len() {
+        true => picked.clear(),
+        false => picked.extend(0..pages.len())
+      },
+      (View::Pages(..), KeyCode::Char('c') | KeyCode::Enter) => self.convert(render_options, logger),
+      (View::Pages(..), KeyCode::Esc | KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h')) => self.back(),
+      _ => ()
+    }
+    return true;
+  }
+
+  // The preview pane's lines, `width` columns by at most `height` rows
+  fn preview(&mut self, width: usize, height: usize, logger: &dyn Fn(LogLevel, String)) -> Vec<String> {
+    if let View::Documents = self.view {
+      return match self.shown_documents().get(self.cursor) {
+        Some(document) => vec![document.name.clone(), String::new(), String::from("enter lists its pages")],
+        None => vec![String::from("no documents")]
+      };
+    }
+    return match self.current_page(logger) {
+      Some(page) => {
+        // braille cells are 2 dots wide and 4 high, and terminal cells
+        // about twice as tall as they're wide
+        let (canvas_width, canvas_height) = Device::detect(&page.layers).canvas();
+        let rows = height.min((width as f32 * canvas_height / (canvas_width * 2.0)) as usize).max(1);
+        braille_preview(&page.layers, rows).lines().map(String::from).collect()
+      },
+      None => vec![String::from("never drawn on")]
+    };
+  }
+
+  fn draw(&mut self, out: &mut impl Write, logger: &dyn Fn(LogLevel, String)) -> io::Result<()> {
+    let (columns, lines) = terminal::size()?;
+    let (columns, lines) = (columns as usize, lines as usize);
+    let body = lines.saturating_sub(2);
+    let list_width = LIST_WIDTH.min(columns / 2);
+    if self.cursor < self.scroll {
+      self.scroll = self.cursor;
+    } else if body > 0 && self.cursor >= self.scroll + body {
+      self.scroll = self.cursor + 1 - body;
+    }
+    let rows = self.rows();
+    let preview = self.preview(columns.saturating_sub(list_width + 3), body, logger);
+    let fit = |text: &str, width: usize| format!("{:width$}", text.chars().take(width).collect::<String>(), width = width);
+
+    queue!(out, Clear(ClearType::All), MoveTo(0, 0), SetAttribute(Attribute::Bold), Print(fit(&self.title(), columns)), SetAttribute(Attribute::Reset))?;
+    for line in 0..body {
+      let row = rows.get(self.scroll + line).map_or("", String::as_str);
+      queue!(out, MoveTo(0, line as u16 + 1))?;
+      if self.scroll + line == self.cursor && !rows.is_empty() {
+        queue!(out, SetAttribute(Attribute::Reverse), Print(fit(row, list_width)), SetAttribute(Attribute::Reset))?;
+      } else {
+        queue!(out, Print(fit(row, list_width)))?;
+      }
+      queue!(out, Print(" │ "), Print(preview.get(line).map_or("", String::as_str)))?;
+    }
+    let footer = match (self.status.is_empty(), self.filtering) {
+      (_, true) => format!("/{}  ({})", self.filter, self.help()),
+      (true, false) => String::from(self.help()),
+      (false, false) => self.status.clone()
+    };
+    queue!(out, MoveTo(0, lines.saturating_sub(1) as u16), Print(fit(&footer, columns)))?;
+    return out.flush();
+  }
+}
+
+// Draws the browser and hands it key presses until it's done, showing
+// the latest warning logged as it comes
+fn run(
+  browser: &mut Browser,
+  render_options: &RenderOptions,
+  logger: &dyn Fn(LogLevel, String),
+  latest_warning: &dyn Fn() -> Option<String>
+) -> Result<(), String> {
+  let _screen = Screen::enter().map_err(|why| format!("couldn't set up the terminal, {}", why))?;
+  let mut out = io::stdout();
+  let mut shown_warning = None;
+  loop {
+    browser.draw(&mut out, logger).map_err(|why| format!("couldn't draw the browser, {}", why))?;
+    let event = event::read().map_err(|why| format!("couldn't read the terminal, {}", why))?;
+    if let Event::Key(key) = event {
+      if key.kind == KeyEventKind::Press && !browser.key(key, render_options, logger) {
+        return Ok(());
+      }
+    }
+    let warning = latest_warning();
+    if warning != shown_warning && browser.status.is_empty() {
+      browser.status = warning.clone().unwrap_or_default();
+    }
+    shown_warning = warning;
+  }
+}
+
+// A full-screen browser of the documents in a tree: pick a document to
+// list its pages, previewed as they're highlighted, and convert the pages
+// picked to SVG (or whatever their name's extension says) in `output`.
+pub fn browse(
+  source: &dyn DocumentSource,
+  output: &str,
+  render_options: &RenderOptions,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<(), String> {
+  if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+    return Err(String::from("browse needs a terminal, use pull to convert without one"));
+  }
+  let documents = list_documents(source)?;
+  let mut browser = Browser {
+    source,
+    documents,
+    view: View::Documents,
+    filter: String::new(),
+    filtering: false,
+    cursor: 0,
+    scroll: 0,
+    document_cursor: 0,
+    pages: HashMap::new(),
+    status: String::new(),
+    output
+  };
+  // held until the screen is back as it was, and warnings shown as they
+  // come
+  let logged = RefCell::new(Vec::<(LogLevel, String)>::new());
+  let held = |level: LogLevel, message: String| logged.borrow_mut().push((level, message));
+  let latest_warning = || logged.borrow().iter().rev()
+    .find(|(level, _)| matches!(level, LogLevel::ERROR | LogLevel::WARN))
+    .map(|(_, message)| message.clone());
+
+  let result = run(&mut browser, render_options, &held, &latest_warning);
+  logged.into_inner().into_iter().for_each(|(level, message)| logger(level, message));
+  return result;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use relineate::xochitl::MemoryTree;
+
+  const GOOD: &[u8] = include_bytes!("../test_files/good.rm");
+
+  fn quiet(_: LogLevel, _: String) {}
+
+  fn tree() -> MemoryTree {
+    let notebook = |id: &str, name: &str| vec![
+      (format!("{}.metadata", id), format!(r#"{{"visibleName": "{}", "type": "DocumentType", "parent": ""}}"#, name).into_bytes()),
+      (format!("{}.content", id), br#"{"pages": ["p1", "p2", "p3"]}"#.to_vec())
+    ];
+    let mut files = notebook("a", "Sketches");
+    files.extend(notebook("b", "Meeting notes"));
+    files.push((String::from("a/p1.rm"), GOOD.to_vec()));
+    return MemoryTree { files };
+  }
+
+  fn browser(source: &dyn DocumentSource) -> Browser<'_> {
+    return Browser {
+      source,
+      documents: list_documents(source).unwrap(),
+      view: View::Documents,
+      filter: String::new(),
+      filtering: false,
+      cursor: 0,
+      scroll: 0,
+      document_cursor: 0,
+      pages: HashMap::new(),
+      status: String::new(),
+      output: "."
+    };
+  }
+
+  fn press(browser: &mut Browser, keys: &[KeyCode]) -> bool {
+    return keys.iter().all(|code| browser.key(KeyEvent::from(*code), &RenderOptions::default(), &quiet));
+  }
+
+  #[test]
+  fn filters_and_opens_documents() {
+    let tree = tree();
+    let mut browser = browser(&tree);
+    assert_eq!(browser.rows(), vec!["Meeting notes", "Sketches"]);
+    press(&mut browser, &[KeyCode::Char('/'), KeyCode::Char('s'), KeyCode::Char('k'), KeyCode::Enter]);
+    assert_eq!(browser.rows(), vec!["Sketches"]);
+    press(&mut browser, &[KeyCode::Enter]);
+    assert_eq!(browser.rows(), vec!["[ ] page 1", "[ ] page 2", "[ ] page 3"]);
+    assert_eq!(browser.title(), "Sketches: 3 pages, 0 picked");
+  }
+
+  #[test]
+  fn picks_pages_and_goes_back() {
+    let tree = tree();
+    let mut browser = browser(&tree);
+    press(&mut browser, &[KeyCode::Down, KeyCode::Enter, KeyCode::Char(' '), KeyCode::Down, KeyCode::Char(' ')]);
+    assert_eq!(browser.rows(), vec!["[x] page 1", "[ ] page 2", "[x] page 3"]);
+    assert_eq!(browser.cursor, 2);
+    press(&mut browser, &[KeyCode::Char('a')]);
+    assert_eq!(browser.title(), "Sketches: 3 pages, 3 picked");
+    press(&mut browser, &[KeyCode::Char('a'), KeyCode::Esc]);
+    assert_eq!(browser.cursor, 1);
+    assert_eq!(browser.rows(), vec!["Meeting notes", "Sketches"]);
+    assert!(!press(&mut browser, &[KeyCode::Char('q')]));
+  }
+
+  #[test]
+  fn previews_the_highlighted_page() {
+    let tree = tree();
+    let mut browser = browser(&tree);
+    press(&mut browser, &[KeyCode::Down, KeyCode::Enter]);
+    let preview = browser.preview(30, 20, &quiet);
+    assert_eq!(preview.len(), 20);
+    assert!(preview.iter().all(|line| line.chars().count() <= 30));
+    assert!(preview.iter().any(|line| line.chars().any(|c| c != '\u{2800}')));
+    press(&mut browser, &[KeyCode::Down]);
+    assert_eq!(browser.preview(30, 20, &quiet), vec!["never drawn on"]);
+  }
+}
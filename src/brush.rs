@@ -0,0 +1,271 @@
+use std::convert::TryFrom;
+
+use num_enum::TryFromPrimitive;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(i32)]
+pub enum BrushType {
+  // supports pressure
+  Paintbrush = 0,
+  // supports pressure, tilt
+  PencilTilt = 1,
+  // supports pressure
+  Pen = 2,
+  // supports pressure, tilt
+  Marker = 3,
+  // no supported modifiers
+  Fineliner = 4,
+  // no supported modifiers, always black
+  Highlighter = 5,
+  // no supported modifiers
+  Eraser = 6,
+  // no supported modifiers
+  PencilSharp = 7,
+  // no supported modifiers
+  RubberArea = 8,
+  // no supported modifiers
+  EraseAll = 9,
+  // no supported modifiers
+  SelectionBrush1 = 10,
+  // no supported modifiers
+  SelectionBrush2 = 11,
+  Paintbrush2 = 12,
+  MechanicalPencil = 13,
+  Pencil2 = 14,
+  BallpointPen2 = 15,
+  Marker2 = 16,
+  Fineliner2 = 17,
+  Highlighter2 = 18,
+  Calligraphy = 21,
+  // firmware 3.x: a broad, soft tone for shading, built up in layers
+  Shader = 23
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(i32)]
+pub enum BrushColor {
+  Black = 0,
+  Grey = 1,
+  White = 2,
+  // the colors of firmware 3 and later, and of the Paper Pro
+  Yellow = 3,
+  Green = 4,
+  Pink = 5,
+  Blue = 6,
+  Red = 7,
+  // grey that darkens where strokes overlap
+  GreyOverlap = 8,
+  // highlighted text
+  Highlight = 9,
+  Green2 = 10,
+  Cyan = 11,
+  Magenta = 12,
+  Yellow2 = 13
+}
+
+pub const BRUSH_COLORS: [BrushColor; 14] = [
+  BrushColor::Black, BrushColor::Grey, BrushColor::White, BrushColor::Yellow, BrushColor::Green, BrushColor::Pink,
+  BrushColor::Blue, BrushColor::Red, BrushColor::GreyOverlap, BrushColor::Highlight, BrushColor::Green2,
+  BrushColor::Cyan, BrushColor::Magenta, BrushColor::Yellow2
+];
+
+impl BrushColor {
+  pub fn name(&self) -> &'static str {
+    return match self {
+      BrushColor::Black => "black",
+      BrushColor::Grey => "grey",
+      BrushColor::White => "white",
+      BrushColor::Yellow => "yellow",
+      BrushColor::Green => "green",
+      BrushColor::Pink => "pink",
+      BrushColor::Blue => "blue",
+      BrushColor::Red => "red",
+      BrushColor::GreyOverlap => "grey-overlap",
+      BrushColor::Highlight => "highlight",
+      BrushColor::Green2 => "green-2",
+      BrushColor::Cyan => "cyan",
+      BrushColor::Magenta => "magenta",
+      BrushColor::Yellow2 => "yellow-2"
+    };
+  }
+
+  pub fn by_name(name: &str) -> Option<BrushColor> {
+    let name = name.trim().to_lowercase().replace("gray", "grey");
+    return BRUSH_COLORS.iter().copied().find(|color| color.name() == name);
+  }
+
+  // "black, grey, white, ..." for messages
+  pub fn names() -> String {
+    return BRUSH_COLORS.iter().map(BrushColor::name).collect::<Vec<_>>().join(", ");
+  }
+
+  // as the colors appear on the display; the hues are those of the
+  // Paper Pro's palette
+  pub fn default_rgb(&self) -> &'static str {
+    return match self {
+      BrushColor::Black => "black",
+      BrushColor::Grey => "#7d7d7d",
+      BrushColor::White => "white",
+      BrushColor::Yellow => "#fbf719",
+      BrushColor::Green => "#00ff00",
+      BrushColor::Pink => "#ffc0cb",
+      BrushColor::Blue => "#4e69c9",
+      BrushColor::Red => "#b33e39",
+      BrushColor::GreyOverlap => "#7d7d7d",
+      BrushColor::Highlight => "#ffed75",
+      BrushColor::Green2 => "#a1d87d",
+      BrushColor::Cyan => "#8bd0e5",
+      BrushColor::Magenta => "#b782cd",
+      BrushColor::Yellow2 => "#f7e851"
+    };
+  }
+
+  // inverted palette for dark backgrounds; "white" ink is usually used to
+  // cover things up, so it stays the color of the page. Colors read on
+  // dark backgrounds as they are.
+  pub fn dark_rgb(&self) -> &'static str {
+    return match self {
+      BrushColor::Black => "#e6e6e6",
+      BrushColor::Grey | BrushColor::GreyOverlap => "#9a9a9a",
+      BrushColor::White => "#1e1e1e",
+      color => color.default_rgb()
+    };
+  }
+
+  // Highlighters on dark backgrounds. Inverted greys would just wash out
+  // the page, so they become muted hues that tint it instead; colored
+  // highlighters keep their own.
+  pub fn dark_highlighter_rgb(&self) -> &'static str {
+    return match self {
+      BrushColor::Black => "#c9a227",
+      BrushColor::Grey | BrushColor::GreyOverlap => "#5b8fb9",
+      BrushColor::White => "#1e1e1e",
+      color => color.default_rgb()
+    };
+  }
+}
+
+// Static rendering defaults for a brush. The rendered stroke width is the
+// line's brush_size (1.875, 2.0 or 2.125 for thin, medium and thick on
+// v5 firmware) multiplied by width_scale.
+#[derive(Debug, Copy, Clone)]
+pub struct BrushStyle {
+  pub width_scale: f32,
+  pub opacity: f32,
+  pub linecap: &'static str,
+  pub linejoin: &'static str,
+  // CSS mix-blend-mode, for ink that should combine with what's under it
+  pub blend_mode: Option<&'static str>
+}
+
+const DEFAULT_STYLE: BrushStyle = BrushStyle {
+  width_scale: 1.5,
+  opacity: 1.0,
+  linecap: "round",
+  linejoin: "round",
+  blend_mode: None
+};
+
+impl BrushType {
+  // e.g. "ballpoint-pen-2", for labelling output
+  pub fn name(&self) -> &'static str {
+    return match self {
+      BrushType::Paintbrush => "paintbrush",
+      BrushType::PencilTilt => "pencil-tilt",
+      BrushType::Pen => "pen",
+      BrushType::Marker => "marker",
+      BrushType::Fineliner => "fineliner",
+      BrushType::Highlighter => "highlighter",
+      BrushType::Eraser => "eraser",
+      BrushType::PencilSharp => "pencil-sharp",
+      BrushType::RubberArea => "rubber-area",
+      BrushType::EraseAll => "erase-all",
+      BrushType::SelectionBrush1 => "selection-brush-1",
+      BrushType::SelectionBrush2 => "selection-brush-2",
+      BrushType::Paintbrush2 => "paintbrush-2",
+      BrushType::MechanicalPencil => "mechanical-pencil",
+      BrushType::Pencil2 => "pencil-2",
+      BrushType::BallpointPen2 => "ballpoint-pen-2",
+      BrushType::Marker2 => "marker-2",
+      BrushType::Fineliner2 => "fineliner-2",
+      BrushType::Highlighter2 => "highlighter-2",
+      BrushType::Calligraphy => "calligraphy",
+      BrushType::Shader => "shader"
+    };
+  }
+
+  pub fn by_name(name: &str) -> Option<BrushType> {
+    let name = name.trim().to_lowercase();
+    return (0..=23).filter_map(|raw| BrushType::try_from(raw).ok()).find(|brush_type| brush_type.name() == name);
+  }
+
+  pub fn is_highlighter(&self) -> bool {
+    return matches!(self, BrushType::Highlighter | BrushType::Highlighter2);
+  }
+
+  // brushes that tint the page rather than draw on it
+  pub fn tints(&self) -> bool {
+    return self.is_highlighter() || *self == BrushType::Shader;
+  }
+
+  // erasers and selections only ever show up on the tablet
+  pub fn is_ink(&self) -> bool {
+    return !matches!(self,
+      BrushType::Eraser | BrushType::RubberArea | BrushType::EraseAll | BrushType::SelectionBrush1 | BrushType::SelectionBrush2);
+  }
+
+  // Defaults are eyeballed against on-device screenshots and the tool
+  // previews in the toolbar, not derived from the firmware.
+  pub fn style(&self) -> BrushStyle {
+    match self {
+      BrushType::Paintbrush => BrushStyle { width_scale: 2.75, opacity: 0.9, ..DEFAULT_STYLE },
+      BrushType::PencilTilt => BrushStyle { width_scale: 1.25, opacity: 0.65, ..DEFAULT_STYLE },
+      BrushType::Pen => DEFAULT_STYLE,
+      BrushType::Marker => BrushStyle { width_scale: 3.0, opacity: 0.9, ..DEFAULT_STYLE },
+      BrushType::Fineliner => BrushStyle { width_scale: 1.6, ..DEFAULT_STYLE },
+      BrushType::Highlighter => BrushStyle {
+        width_scale: 15.0,
+        opacity: 0.25,
+        linecap: "square",
+        linejoin: "miter",
+        // darkens like ink on paper instead of greying out what's under it
+        blend_mode: Some("multiply")
+      },
+      BrushType::PencilSharp => BrushStyle { width_scale: 1.0, opacity: 0.7, ..DEFAULT_STYLE },
+      // v2 tools: slightly different nibs than their v1 counterparts
+      BrushType::Paintbrush2 => BrushStyle { width_scale: 2.6, opacity: 0.85, ..DEFAULT_STYLE },
+      // 0.5mm-style lead: thin, light and with a crisp end
+      BrushType::MechanicalPencil => BrushStyle {
+        width_scale: 0.9,
+        opacity: 0.75,
+        linecap: "butt",
+        ..DEFAULT_STYLE
+      },
+      // soft graphite: wider and lighter than the mechanical pencil
+      BrushType::Pencil2 => BrushStyle { width_scale: 1.3, opacity: 0.6, ..DEFAULT_STYLE },
+      BrushType::BallpointPen2 => BrushStyle { width_scale: 1.4, opacity: 0.95, ..DEFAULT_STYLE },
+      // chisel tip
+      BrushType::Marker2 => BrushStyle {
+        width_scale: 3.2,
+        opacity: 0.85,
+        linecap: "square",
+        linejoin: "bevel",
+        ..DEFAULT_STYLE
+      },
+      BrushType::Fineliner2 => BrushStyle { width_scale: 1.7, ..DEFAULT_STYLE },
+      BrushType::Highlighter2 => BrushStyle {
+        width_scale: 14.0,
+        opacity: 0.3,
+        linecap: "square",
+        linejoin: "miter",
+        blend_mode: Some("multiply")
+      },
+      BrushType::Calligraphy => BrushStyle { width_scale: 2.2, ..DEFAULT_STYLE },
+      // a light wash that darkens where strokes are laid over each other
+      BrushType::Shader => BrushStyle { width_scale: 12.0, opacity: 0.2, blend_mode: Some("multiply"), ..DEFAULT_STYLE },
+      _ => DEFAULT_STYLE
+    }
+  }
+}
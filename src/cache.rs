@@ -0,0 +1,38 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Mutex;
+
+// What was last written to each output, as a key made from the page and
+// the options it was rendered with, so that pages which haven't changed
+// since aren't parsed and rendered again. Only kept for as long as the
+// process runs, e.g. while watching a directory. Safe to share between the
+// threads converting pages.
+#[derive(Default)]
+pub struct RenderCache {
+  rendered: Mutex<HashMap<String, u64>>
+}
+
+impl RenderCache {
+  // A page's bytes and whatever else decides what's rendered from them
+  pub fn key(bytes: &[u8], options: &impl Debug) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    format!("{:?}", options).hash(&mut hasher);
+    return hasher.finish();
+  }
+
+  // Whether `output` was last written from the same key, and is still there
+  pub fn is_fresh(&self, output: &str, key: u64) -> bool {
+    let recorded = self.rendered.lock().is_ok_and(|rendered| rendered.get(output) == Some(&key));
+    return recorded && Path::new(output).exists();
+  }
+
+  pub fn record(&self, output: &str, key: u64) {
+    if let Ok(mut rendered) = self.rendered.lock() {
+      rendered.insert(String::from(output), key);
+    }
+  }
+}
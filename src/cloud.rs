@@ -0,0 +1,144 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+
+use crate::json;
+use crate::xochitl::{DocumentSource, MemoryTree};
+
+// Documents synced to the reMarkable cloud, read the way the desktop app
+// and rmapi read them: a device token from a one-time code, exchanged for
+// a short-lived user token, and a tree of files by the hash of their
+// contents. Self-hosted servers such as rmfakecloud serve both halves from
+// one URL.
+pub const AUTH_URL: &str = "https://webapp-prod.cloud.remarkable.engineering";
+pub const SYNC_URL: &str = "https://internal.cloud.remarkable.com";
+
+fn request_error(url: &str, why: ureq::Error) -> String {
+  return match why {
+    ureq::Error::Status(status, response) => {
+      format!("{} answered {} {}", url, status, response.into_string().unwrap_or_default().trim())
+    },
+    why => format!("couldn't reach {}, {}", url, why)
+  };
+}
+
+// Random enough to tell this device apart from others on the account
+fn device_id() -> String {
+  let random = || RandomState::new().build_hasher().finish();
+  let hex = format!("{:016x}{:016x}", random(), random());
+  return format!("{}-{}-4{}-a{}-{}", &hex[0..8], &hex[8..12], &hex[13..16], &hex[17..20], &hex[20..32]);
+}
+
+// Registers this machine with the one-time code from
+// my.remarkable.com/device/desktop/connect, for a token kept from then on
+pub fn register_device(auth_url: &str, code: &str) -> Result<String, String> {
+  let url = format!("{}/token/json/2/device/new", auth_url);
+  let body = json::Value::Object(vec![
+    (String::from("code"), json::Value::String(String::from(code.trim()))),
+    (String::from("deviceDesc"), json::Value::String(String::from("desktop-linux"))),
+    (String::from("deviceID"), json::Value::String(device_id()))
+  ]);
+  return ureq::post(&url)
+    .set("Content-Type", "application/json")
+    .send_string(&body.to_string())
+    .map_err(|why| request_error(&url, why))?
+    .into_string()
+    .map_err(|why| format!("couldn't read the device token, {}", why));
+}
+
+// A token for this session from the device's
+pub fn user_token(auth_url: &str, device_token: &str) -> Result<String, String> {
+  let url = format!("{}/token/json/2/user/new", auth_url);
+  return ureq::post(&url)
+    .set("Authorization", &format!("Bearer {}", device_token.trim()))
+    .send_string("")
+    .map_err(|why| request_error(&url, why))?
+    .into_string()
+    .map_err(|why| format!("couldn't read the user token, {}", why));
+}
+
+// The account's files as a xochitl tree, listed when connecting and read
+// as they're needed
+#[derive(Debug, Clone)]
+pub struct CloudTree {
+  sync_url: String,
+  user_token: String,
+  // every file's path in the tree and the hash it's stored under
+  files: Vec<(String, String)>
+}
+
+// The (hash, name) of each entry of an index, which has a schema version
+// on its first line and then one hash:type:name:subfiles:size per entry
+fn index_entries(index: &str) -> Vec<(String, String)> {
+  return index.lines()
+    .skip(1)
+    .filter_map(|line| {
+      let fields = line.split(':').collect::<Vec<_>>();
+      return match fields.as_slice() {
+        [hash, _, name, _, _] if *name != "." => Some((String::from(*hash), String::from(*name))),
+        _ => None
+      };
+    })
+    .collect();
+}
+
+impl CloudTree {
+  pub fn connect(sync_url: &str, user_token: &str) -> Result<CloudTree, String> {
+    let mut tree = CloudTree { sync_url: String::from(sync_url), user_token: String::from(user_token), files: Vec::new() };
+    let root = tree.get(&format!("{}/sync/v4/root", sync_url))?;
+    let root = json::parse(&String::from_utf8_lossy(&root)).map_err(|why| format!("couldn't parse the sync root, {}", why))?;
+    let root_hash = root.get("hash").and_then(|hash| hash.as_str()).ok_or_else(|| String::from("the sync root has no hash"))?;
+    // each document's index lists its files by path
+    for (hash, _) in index_entries(&String::from_utf8_lossy(&tree.file(root_hash)?)) {
+      let entries = index_entries(&String::from_utf8_lossy(&tree.file(&hash)?));
+      tree.files.extend(entries.into_iter().map(|(hash, path)| (path, hash)));
+    }
+    return Ok(tree);
+  }
+
+  fn get(&self, url: &str) -> Result<Vec<u8>, String> {
+    let response = ureq::get(url)
+      .set("Authorization", &format!("Bearer {}", self.user_token.trim()))
+      .call()
+      .map_err(|why| request_error(url, why))?;
+    let mut bytes = Vec::new();
+    response.into_reader().read_to_end(&mut bytes).map_err(|why| format!("couldn't read {}, {}", url, why))?;
+    return Ok(bytes);
+  }
+
+  fn file(&self, hash: &str) -> Result<Vec<u8>, String> {
+    return self.get(&format!("{}/sync/v3/files/{}", self.sync_url, hash));
+  }
+
+  // The .content, .pagedata, pages and page metadata of each document
+  pub fn fetch_documents(&self, ids: &[String]) -> Result<MemoryTree, String> {
+    let files = self.files.iter()
+      .filter(|(path, _)| {
+        return ids.iter().any(|id| {
+          return *path == format!("{}.content", id) || *path == format!("{}.pagedata", id)
+            || (path.starts_with(&format!("{}/", id)) && (path.ends_with(".rm") || path.ends_with("-metadata.json")));
+        });
+      })
+      .map(|(path, hash)| self.file(hash).map(|contents| (path.clone(), contents)))
+      .collect::<Result<Vec<_>, String>>()?;
+    return Ok(MemoryTree { files });
+  }
+}
+
+impl DocumentSource for CloudTree {
+  fn list_metadata(&self) -> Result<Vec<(String, String)>, String> {
+    return self.files.iter()
+      .filter_map(|(path, hash)| path.strip_suffix(".metadata").map(|id| (id, hash)))
+      .map(|(id, hash)| self.file(hash).map(|metadata| (String::from(id), String::from_utf8_lossy(&metadata).into_owned())))
+      .collect();
+  }
+
+  fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+    let (_, hash) = self.files.iter().find(|(file, _)| file == path).ok_or_else(|| format!("no {} in the cloud", path))?;
+    return self.file(hash);
+  }
+
+  fn contains(&self, path: &str) -> bool {
+    return self.files.iter().any(|(file, _)| file == path);
+  }
+}
@@ -0,0 +1,103 @@
+use crate::raster::{rasterize, Canvas};
+use crate::scene::{Item, Scene, Segment};
+
+// A channel differing by less than this, out of 255, is put down to
+// antialiasing rather than counted as a changed pixel
+const PIXEL_THRESHOLD: u8 = 16;
+
+// How two renderings of a page differ; items are paired up in painting
+// order, which is stroke order for rendered pages
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+  pub old_items: usize,
+  pub new_items: usize,
+  // the furthest any point of a shape is from the outline of its pair, in
+  // canvas pixels
+  pub distance: f32,
+  // pairs painted differently, or of labels with other text or places
+  pub restyled: usize,
+  // with pixels compared, how many differ and how many there are
+  pub pixels: Option<(usize, usize)>
+}
+
+impl Comparison {
+  // Whether the renderings are the same but for shapes moving at most
+  // `tolerance` canvas pixels and, with pixels compared, at most
+  // `pixel_tolerance` of them (as a fraction) changing
+  pub fn is_within(&self, tolerance: f32, pixel_tolerance: f32) -> bool {
+    let pixels_within = self.pixels.is_none_or(|(changed, total)| changed as f32 <= total as f32 * pixel_tolerance);
+    return self.old_items == self.new_items && self.distance <= tolerance && self.restyled == 0 && pixels_within;
+  }
+}
+
+// Each subpath as its points, closed ones back to where they started
+fn polylines(segments: &[Segment]) -> Vec<Vec<(f32, f32)>> {
+  let mut polylines = Vec::<Vec<(f32, f32)>>::new();
+  segments.iter().for_each(|segment| match *segment {
+    Segment::Move(x, y) => polylines.push(vec![(x, y)]),
+    Segment::Line(x, y) => match polylines.last_mut() {
+      Some(polyline) => polyline.push((x, y)),
+      None => polylines.push(vec![(x, y)])
+    },
+    Segment::Close => if let Some(polyline) = polylines.last_mut() {
+      let start = polyline[0];
+      polyline.push(start);
+    }
+  });
+  return polylines;
+}
+
+fn distance_to_segment((x, y): (f32, f32), (x1, y1): (f32, f32), (x2, y2): (f32, f32)) -> f32 {
+  let (dx, dy) = (x2 - x1, y2 - y1);
+  let length = dx * dx + dy * dy;
+  let along = match length > 0.0 {
+    true => (((x - x1) * dx + (y - y1) * dy) / length).clamp(0.0, 1.0),
+    false => 0.0
+  };
+  return (x - (x1 + along * dx)).hypot(y - (y1 + along * dy));
+}
+
+// The furthest any point of `from` is from the outline of `to`
+fn furthest(from: &[Vec<(f32, f32)>], to: &[Vec<(f32, f32)>]) -> f32 {
+  return from.iter().flatten()
+    .map(|point| {
+      return to.iter()
+        .flat_map(|polyline| match polyline.len() {
+          1 => vec![distance_to_segment(*point, polyline[0], polyline[0])],
+          _ => polyline.windows(2).map(|edge| distance_to_segment(*point, edge[0], edge[1])).collect()
+        })
+        .fold(f32::INFINITY, f32::min);
+    })
+    .fold(0.0, f32::max);
+}
+
+fn changed_pixels(old: &Canvas, new: &Canvas) -> (usize, usize) {
+  if (old.width, old.height) != (new.width, new.height) {
+    let total = old.width.max(new.width) * old.height.max(new.height);
+    return (total, total);
+  }
+  let changed = old.pixels.chunks(4).zip(new.pixels.chunks(4))
+    .filter(|(old, new)| old.iter().zip(new.iter()).any(|(old, new)| old.abs_diff(*new) >= PIXEL_THRESHOLD))
+    .count();
+  return (changed, old.width * old.height);
+}
+
+// Compares two renderings of a page shape by shape and, with `pixel_scale`,
+// pixel by pixel at that many device pixels per CSS pixel
+pub fn compare_scenes(old: &Scene, new: &Scene, pixel_scale: Option<f32>) -> Comparison {
+  let mut distance = 0.0f32;
+  let mut restyled = 0;
+  old.items.iter().zip(new.items.iter()).for_each(|pair| match pair {
+    (Item::Shape(old_shape), Item::Shape(new_shape)) => {
+      let (old_lines, new_lines) = (polylines(&old_shape.segments), polylines(&new_shape.segments));
+      distance = distance.max(furthest(&old_lines, &new_lines)).max(furthest(&new_lines, &old_lines));
+      if (&old_shape.fill, &old_shape.stroke, old_shape.blend) != (&new_shape.fill, &new_shape.stroke, new_shape.blend) {
+        restyled += 1;
+      }
+    },
+    (Item::Label(old_label), Item::Label(new_label)) if old_label == new_label => {},
+    _ => restyled += 1
+  });
+  let pixels = pixel_scale.map(|scale| changed_pixels(&rasterize(old, scale), &rasterize(new, scale)));
+  return Comparison { old_items: old.items.len(), new_items: new.items.len(), distance, restyled, pixels };
+}
@@ -0,0 +1,453 @@
+// A small DEFLATE (RFC 1951) decoder along the lines of zlib's puff.c,
+// which is all we need to read notebook archives without pulling in a
+// compression crate, and a simple encoder for the images we write.
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+  35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+  3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+const DISTANCE_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+  257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+  buffer: u32,
+  count: u32
+}
+
+impl<'a> BitReader<'a> {
+  fn bits(&mut self, needed: u32) -> Result<u32, String> {
+    while self.count < needed {
+      let byte = *self.data.get(self.position).ok_or_else(|| String::from(TRUNCATED_STREAM))?;
+      self.position += 1;
+      self.buffer |= (byte as u32) << self.count;
+      self.count += 8;
+    }
+    let value = self.buffer & ((1u64 << needed) - 1) as u32;
+    self.buffer >>= needed;
+    self.count -= needed;
+    return Ok(value);
+  }
+
+  fn align(&mut self) {
+    self.buffer = 0;
+    self.count = 0;
+  }
+}
+
+struct Huffman {
+  counts: [u16; MAX_BITS + 1],
+  symbols: Vec<u16>
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Huffman {
+    let mut counts = [0u16; MAX_BITS + 1];
+    lengths.iter().for_each(|&length| counts[length as usize] += 1);
+    counts[0] = 0;
+    let mut offsets = [0u16; MAX_BITS + 2];
+    (1..=MAX_BITS).for_each(|length| offsets[length + 1] = offsets[length] + counts[length]);
+    let mut symbols = vec![0u16; lengths.len()];
+    lengths.iter().enumerate()
+      .filter(|(_, &length)| length != 0)
+      .for_each(|(symbol, &length)| {
+        symbols[offsets[length as usize] as usize] = symbol as u16;
+        offsets[length as usize] += 1;
+      });
+    return Huffman { counts, symbols };
+  }
+
+  fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+    let mut code: i32 = 0;
+    let mut first: i32 = 0;
+    let mut index: i32 = 0;
+    for length in 1..=MAX_BITS {
+      code |= reader.bits(1)? as i32;
+      let count = self.counts[length] as i32;
+      if code - count < first {
+        return Ok(self.symbols[(index + (code - first)) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    return Err(String::from("invalid huffman code in deflate stream"));
+  }
+}
+
+// What inflating data that stops partway through a stream fails with
+pub const TRUNCATED_STREAM: &str = "unexpected end of deflate stream";
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+  return inflate_prefix(data).map(|(output, _)| output);
+}
+
+// The deflate stream at the start of `data`, inflated, and how many bytes
+// of `data` it took up; whatever follows is left alone
+pub fn inflate_prefix(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+  let mut reader = BitReader { data, position: 0, buffer: 0, count: 0 };
+  let mut output = Vec::<u8>::with_capacity(data.len() * 4);
+  loop {
+    let last = reader.bits(1)? == 1;
+    match reader.bits(2)? {
+      0 => inflate_stored(&mut reader, &mut output)?,
+      1 => {
+        let (literals, distances) = fixed_tables();
+        inflate_block(&mut reader, &mut output, &literals, &distances)?
+      },
+      2 => {
+        let (literals, distances) = dynamic_tables(&mut reader)?;
+        inflate_block(&mut reader, &mut output, &literals, &distances)?
+      },
+      _ => return Err(String::from("invalid deflate block type"))
+    }
+    // bytes are only read as their bits are needed, so the last one read
+    // is the stream's last
+    if last { return Ok((output, reader.position)); }
+  }
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), String> {
+  reader.align();
+  let header = reader.data.get(reader.position..reader.position + 4)
+    .ok_or_else(|| String::from(TRUNCATED_STREAM))?;
+  let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+  let complement = u16::from_le_bytes([header[2], header[3]]) as usize;
+  if length != !complement & 0xffff {
+    return Err(String::from("corrupt stored block in deflate stream"));
+  }
+  reader.position += 4;
+  let block = reader.data.get(reader.position..reader.position + length)
+    .ok_or_else(|| String::from(TRUNCATED_STREAM))?;
+  output.extend_from_slice(block);
+  reader.position += length;
+  return Ok(());
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+  let literal_lengths = (0..288).map(|symbol| match symbol {
+    0..=143 => 8,
+    144..=255 => 9,
+    256..=279 => 7,
+    _ => 8
+  }).collect::<Vec<u8>>();
+  return (Huffman::new(&literal_lengths), Huffman::new(&[5; 30]));
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+  let literal_count = reader.bits(5)? as usize + 257;
+  let distance_count = reader.bits(5)? as usize + 1;
+  let code_count = reader.bits(4)? as usize + 4;
+  let mut code_lengths = [0u8; 19];
+  for &index in CODE_LENGTH_ORDER.iter().take(code_count) {
+    code_lengths[index] = reader.bits(3)? as u8;
+  }
+  let codes = Huffman::new(&code_lengths);
+
+  let mut lengths = Vec::<u8>::with_capacity(literal_count + distance_count);
+  while lengths.len() < literal_count + distance_count {
+    let symbol = codes.decode(reader)?;
+    let (value, repeat) = match symbol {
+      0..=15 => (symbol as u8, 1),
+      16 => (*lengths.last().ok_or_else(|| String::from("repeat with no previous length"))?, 3 + reader.bits(2)?),
+      17 => (0, 3 + reader.bits(3)?),
+      _ => (0, 11 + reader.bits(7)?)
+    };
+    if lengths.len() + repeat as usize > literal_count + distance_count {
+      return Err(String::from("too many code lengths in deflate stream"));
+    }
+    lengths.resize(lengths.len() + repeat as usize, value);
+  }
+  return Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])));
+}
+
+fn inflate_block(
+  reader: &mut BitReader,
+  output: &mut Vec<u8>,
+  literals: &Huffman,
+  distances: &Huffman
+) -> Result<(), String> {
+  loop {
+    let symbol = literals.decode(reader)? as usize;
+    if symbol < 256 {
+      output.push(symbol as u8);
+      continue;
+    }
+    if symbol == 256 { return Ok(()); }
+    let length_index = symbol - 257;
+    if length_index >= LENGTH_BASE.len() {
+      return Err(String::from("invalid length symbol in deflate stream"));
+    }
+    let length = LENGTH_BASE[length_index] as usize + reader.bits(LENGTH_EXTRA[length_index] as u32)? as usize;
+    let distance_index = distances.decode(reader)? as usize;
+    if distance_index >= DISTANCE_BASE.len() {
+      return Err(String::from("invalid distance symbol in deflate stream"));
+    }
+    let distance = DISTANCE_BASE[distance_index] as usize
+      + reader.bits(DISTANCE_EXTRA[distance_index] as u32)? as usize;
+    if distance > output.len() {
+      return Err(String::from("distance too far back in deflate stream"));
+    }
+    let start = output.len() - distance;
+    (0..length).for_each(|offset| {
+      let byte = output[start + offset];
+      output.push(byte);
+    });
+  }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+  return !data.iter().fold(!0u32, |crc, &byte| {
+    return (0..8).fold(crc ^ byte as u32, |crc, _| {
+      if crc & 1 == 1 { return (crc >> 1) ^ 0xedb8_8320; }
+      return crc >> 1;
+    });
+  });
+}
+
+// The other direction, for writing PNGs: LZ77 matching over hash chains of
+// three byte prefixes, coded with the fixed Huffman tables. Not as tight as
+// zlib, but rendered pages are mostly long runs that compress well anyway.
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// how many earlier positions to try per match
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+struct BitWriter {
+  output: Vec<u8>,
+  buffer: u64,
+  count: u32
+}
+
+impl BitWriter {
+  fn bits(&mut self, value: u32, count: u32) {
+    self.buffer |= (value as u64) << self.count;
+    self.count += count;
+    while self.count >= 8 {
+      self.output.push(self.buffer as u8);
+      self.buffer >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  // Huffman codes go most significant bit first
+  fn code(&mut self, code: u32, length: u32) {
+    let reversed = (0..length).fold(0, |acc, bit| (acc << 1) | ((code >> bit) & 1));
+    self.bits(reversed, length);
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.output.push(self.buffer as u8);
+    }
+    return self.output;
+  }
+}
+
+fn literal_code(writer: &mut BitWriter, symbol: u32) {
+  match symbol {
+    0..=143 => writer.code(0x30 + symbol, 8),
+    144..=255 => writer.code(0x190 + symbol - 144, 9),
+    256..=279 => writer.code(symbol - 256, 7),
+    _ => writer.code(0xc0 + symbol - 280, 8)
+  }
+}
+
+fn match_codes(writer: &mut BitWriter, length: usize, distance: usize) {
+  let length_index = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap_or(0);
+  literal_code(writer, 257 + length_index as u32);
+  writer.bits((length - LENGTH_BASE[length_index] as usize) as u32, LENGTH_EXTRA[length_index] as u32);
+  let distance_index = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+  writer.code(distance_index as u32, 5);
+  writer.bits((distance - DISTANCE_BASE[distance_index] as usize) as u32, DISTANCE_EXTRA[distance_index] as u32);
+}
+
+fn prefix_hash(data: &[u8], position: usize) -> usize {
+  let prefix = (data[position] as u32) << 16 | (data[position + 1] as u32) << 8 | data[position + 2] as u32;
+  return (prefix.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+}
+
+// Earlier positions of each three byte prefix, most recent first
+struct Matcher<'a> {
+  data: &'a [u8],
+  // most recent position + 1 for each hash, 0 for none
+  heads: Vec<usize>,
+  // the position + 1 before each position in the window with its hash
+  previous: Vec<usize>
+}
+
+impl<'a> Matcher<'a> {
+  fn insert(&mut self, position: usize) {
+    if position + MIN_MATCH <= self.data.len() {
+      let hash = prefix_hash(self.data, position);
+      self.previous[position % WINDOW_SIZE] = self.heads[hash];
+      self.heads[hash] = position + 1;
+    }
+  }
+
+  // (length, distance) of the longest earlier match, length 0 for none
+  fn longest_match(&self, position: usize) -> (usize, usize) {
+    let data = self.data;
+    if position + MIN_MATCH > data.len() { return (0, 0); }
+    let limit = (data.len() - position).min(MAX_MATCH);
+    let mut candidate = self.heads[prefix_hash(data, position)];
+    let mut best = (0, 0);
+    for _ in 0..MAX_CHAIN {
+      if candidate == 0 || position - (candidate - 1) >= WINDOW_SIZE { break; }
+      let start = candidate - 1;
+      let length = (0..limit).take_while(|&offset| data[start + offset] == data[position + offset]).count();
+      if length > best.0 {
+        best = (length, position - start);
+        if length == limit { break; }
+      }
+      let next = self.previous[start % WINDOW_SIZE];
+      // older entries in the slot have been overwritten
+      if next >= candidate { break; }
+      candidate = next;
+    }
+    return best;
+  }
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+  let mut writer = BitWriter { output: Vec::with_capacity(data.len() / 4), buffer: 0, count: 0 };
+  // a single final block with the fixed tables
+  writer.bits(1, 1);
+  writer.bits(1, 2);
+  let mut matcher = Matcher { data, heads: vec![0; 1 << HASH_BITS], previous: vec![0; WINDOW_SIZE] };
+  let mut position = 0;
+  while position < data.len() {
+    let (length, distance) = matcher.longest_match(position);
+    if length >= MIN_MATCH {
+      match_codes(&mut writer, length, distance);
+      (position..position + length).for_each(|covered| matcher.insert(covered));
+      position += length;
+    } else {
+      literal_code(&mut writer, data[position] as u32);
+      matcher.insert(position);
+      position += 1;
+    }
+  }
+  literal_code(&mut writer, 256);
+  return writer.finish();
+}
+
+// A gzip file (RFC 1952) of the data, without a name or timestamp
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+  // deflated, made on an unknown system
+  let mut gzipped = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+  gzipped.extend(deflate(data));
+  gzipped.extend_from_slice(&crc32(data).to_le_bytes());
+  gzipped.extend_from_slice(&(data.len() as u32).to_le_bytes());
+  return gzipped;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Raw DEFLATE streams from Python's zlib (wbits -15), one per block type
+  const STORED: [u8; 27] = [
+    0x01, 0x16, 0x00, 0xe9, 0xff, 0x72, 0x65, 0x6c, 0x69, 0x6e, 0x65, 0x61, 0x74, 0x65, 0x20, 0x73,
+    0x74, 0x6f, 0x72, 0x65, 0x64, 0x20, 0x62, 0x6c, 0x6f, 0x63, 0x6b
+  ];
+  // with the fixed tables, Z_FIXED
+  const FIXED: [u8; 20] = [
+    0x4b, 0x54, 0x48, 0xcb, 0xac, 0x48, 0x4d, 0x51, 0x48, 0xca, 0xc9, 0x4f, 0xce, 0xd6, 0x51, 0x48,
+    0xc4, 0xc3, 0x05, 0x00
+  ];
+  const DYNAMIC: [u8; 93] = [
+    0xd5, 0x8c, 0xb1, 0x09, 0x85, 0x40, 0x14, 0x04, 0x5b, 0xd9, 0x02, 0xc4, 0x2a, 0x44, 0x23, 0x45,
+    0x10, 0xcc, 0x57, 0x6e, 0xf9, 0x5f, 0xd4, 0x53, 0xde, 0x3d, 0x10, 0xac, 0x5e, 0xad, 0x40, 0x0c,
+    0x9d, 0x60, 0x93, 0x61, 0xc7, 0x34, 0x8f, 0x51, 0x74, 0xc1, 0xc4, 0x90, 0xb0, 0xf1, 0xa7, 0x84,
+    0xdd, 0x46, 0x77, 0x45, 0xac, 0x11, 0xbc, 0x44, 0x4d, 0x9b, 0x38, 0xcc, 0x82, 0xdf, 0xeb, 0x60,
+    0x0c, 0x08, 0xc6, 0x3d, 0xc1, 0xff, 0x5a, 0xc0, 0x84, 0xae, 0xaf, 0x32, 0xb4, 0x45, 0x89, 0xd5,
+    0xd0, 0x36, 0x55, 0x7e, 0x9d, 0xbe, 0xd5, 0x3d, 0x5e, 0x00, 0x3d, 0x70, 0x02
+  ];
+
+  fn dynamic_text() -> Vec<u8> {
+    let mut text = b"relineate reads pages written on a reMarkable tablet and draws them as SVG, PDF or PNG. ".repeat(3);
+    text.extend_from_slice(&[b'z'; 44]);
+    text.push(b' ');
+    text.extend_from_slice(&[b'e'; 32]);
+    return text;
+  }
+
+  // xorshift, for data that doesn't compress
+  fn noise(length: usize, mut state: u32) -> Vec<u8> {
+    return (0..length)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        return state as u8;
+      })
+      .collect();
+  }
+
+  #[test]
+  fn inflates_each_block_type() {
+    assert_eq!((STORED[0] >> 1 & 3, FIXED[0] >> 1 & 3, DYNAMIC[0] >> 1 & 3), (0, 1, 2));
+    assert_eq!(inflate(&STORED).unwrap(), b"relineate stored block");
+    assert_eq!(inflate(&FIXED).unwrap(), b"a fixed block, a fixed block, a fixed block");
+    assert_eq!(inflate(&DYNAMIC).unwrap(), dynamic_text());
+  }
+
+  #[test]
+  fn round_trips() {
+    let mut text = dynamic_text().repeat(200);
+    // matches reaching back across the whole window
+    text.extend(noise(WINDOW_SIZE - 100, 7));
+    text.extend(noise(WINDOW_SIZE - 100, 7));
+    let runs = [vec![0u8; 100_000], vec![0xffu8; 1], dynamic_text()].concat();
+    [Vec::new(), vec![42], text, runs, noise(100_000, 1)].iter().for_each(|data| {
+      let deflated = deflate(data);
+      assert_eq!(inflate(&deflated).unwrap(), *data, "{} bytes didn't round-trip", data.len());
+    });
+    // the fixed tables only ever grow incompressible data by an eighth
+    assert!(deflate(&noise(100_000, 1)).len() < 100_000 * 9 / 8 + 16);
+    assert!(deflate(&[0u8; 100_000]).len() < 1_000);
+  }
+
+  #[test]
+  fn gzips_with_the_checksum_and_length() {
+    let data = dynamic_text();
+    let gzipped = gzip(&data);
+    assert_eq!(gzipped[..4], [0x1f, 0x8b, 8, 0]);
+    assert_eq!(inflate(&gzipped[10..gzipped.len() - 8]).unwrap(), data);
+    assert_eq!(gzipped[gzipped.len() - 8..gzipped.len() - 4], crc32(&data).to_le_bytes());
+    assert_eq!(gzipped[gzipped.len() - 4..], (data.len() as u32).to_le_bytes());
+  }
+
+  #[test]
+  fn checks_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+  }
+
+  #[test]
+  fn rejects_broken_streams() {
+    assert_eq!(inflate(&FIXED[..10]).unwrap_err(), "unexpected end of deflate stream");
+    assert_eq!(inflate(&[0x07]).unwrap_err(), "invalid deflate block type");
+    let mut stored = STORED;
+    stored[3] = 0;
+    assert_eq!(inflate(&stored).unwrap_err(), "corrupt stored block in deflate stream");
+  }
+}
@@ -0,0 +1,54 @@
+use crate::{Layer, X_MAX, Y_MAX};
+
+// The tablets pages are written on, which differ in the size of the canvas
+// their strokes are drawn on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Device {
+  // the reMarkable 1 and 2, 1404 by 1872
+  Remarkable,
+  // 1620 by 2160, its 2160 by 1620 screen held upright
+  PaperPro
+}
+
+pub const DEVICES: [&str; 2] = ["remarkable", "paper-pro"];
+
+// Strokes can run a little past the edge of the canvas they were drawn on
+// without the page being from a bigger one
+pub const EDGE_MARGIN: f32 = 8.0;
+
+impl Device {
+  pub fn by_name(name: &str) -> Option<Device> {
+    return match name.trim().to_lowercase().as_str() {
+      "remarkable" | "rm1" | "rm2" => Some(Device::Remarkable),
+      "paper-pro" | "paperpro" => Some(Device::PaperPro),
+      _ => None
+    };
+  }
+
+  pub fn name(&self) -> &'static str {
+    return match self {
+      Device::Remarkable => "remarkable",
+      Device::PaperPro => "paper-pro"
+    };
+  }
+
+  // The canvas' width and height, portrait, in canvas pixels
+  pub fn canvas(&self) -> (f32, f32) {
+    return match self {
+      Device::Remarkable => (X_MAX as f32, Y_MAX as f32),
+      Device::PaperPro => (1620.0, 2160.0)
+    };
+  }
+
+  // The device a page was written on, as far as its strokes tell: pages
+  // with strokes beyond the reMarkable 1 and 2's canvas are from the Paper
+  // Pro, and any others are taken to be from the smaller tablets
+  pub fn detect(layers: &[Layer]) -> Device {
+    let (width, height) = Device::Remarkable.canvas();
+    let beyond = layers.iter()
+      .flat_map(|layer| layer.lines.iter())
+      .flat_map(|line| line.points.iter())
+      .any(|point| point.x > width + EDGE_MARGIN || point.y > height + EDGE_MARGIN);
+    return if beyond { Device::PaperPro } else { Device::Remarkable };
+  }
+}
@@ -0,0 +1,231 @@
+use svg::Document as SVGDocument;
+use svg::node::element::Group as SVGGroup;
+
+use crate::brush::BRUSH_COLORS;
+use crate::device::Device;
+use crate::json::Value;
+use crate::notebook::Notebook;
+use crate::options::{ColorMap, RenderOptions};
+use crate::orientation::rotate_layers;
+use crate::pens::line_strokes;
+use crate::render::{content_bounds, render_svg};
+use crate::{Layer, Line};
+
+const REMOVED_COLOR: &str = "#d62728";
+const ADDED_COLOR: &str = "#2ca02c";
+// the versions of a page laid over each other by default
+pub const OLD_OVERLAY_COLOR: &str = "#d62728";
+pub const NEW_OVERLAY_COLOR: &str = "#1f77b4";
+const OVERLAY_OPACITY: f32 = 0.6;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PageChange {
+  Added,
+  Removed,
+  Modified,
+  Unchanged
+}
+
+impl std::fmt::Display for PageChange {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    return f.pad(match self {
+      PageChange::Added => "added",
+      PageChange::Removed => "removed",
+      PageChange::Modified => "modified",
+      PageChange::Unchanged => "unchanged"
+    });
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct PageDiff {
+  pub id: String,
+  pub change: PageChange,
+  // page numbers in each snapshot, where the page is in it
+  pub old_number: Option<usize>,
+  pub new_number: Option<usize>
+}
+
+// Pages of two snapshots of a notebook matched up by UUID: the newer
+// snapshot's pages in order, then the pages only the older one has. Pages
+// count as modified when any of their strokes differ, not when they only
+// moved.
+pub fn diff_notebooks(old: &Notebook, new: &Notebook) -> Vec<PageDiff> {
+  let current = new.pages.iter().map(|page| {
+    let old_page = old.pages.iter().find(|old_page| old_page.id == page.id);
+    return PageDiff {
+      id: page.id.clone(),
+      change: match old_page {
+        None => PageChange::Added,
+        Some(old_page) if old_page.layers != page.layers => PageChange::Modified,
+        Some(_) => PageChange::Unchanged
+      },
+      old_number: old_page.map(|old_page| old_page.number),
+      new_number: Some(page.number)
+    };
+  });
+  let removed = old.pages.iter()
+    .filter(|page| new.pages.iter().all(|new_page| new_page.id != page.id))
+    .map(|page| PageDiff { id: page.id.clone(), change: PageChange::Removed, old_number: Some(page.number), new_number: None });
+  return current.chain(removed).collect();
+}
+
+// Lines of `lines` that aren't in `others`, each line of `others` pairing
+// off with at most one identical line
+fn unmatched<'a>(lines: &[&'a Line], others: &[&Line]) -> Vec<&'a Line> {
+  let mut matched = vec![false; others.len()];
+  return lines.iter()
+    .filter(|line| {
+      let found = others.iter().enumerate().position(|(index, other)| !matched[index] && *other == **line);
+      if let Some(index) = found {
+        matched[index] = true;
+      }
+      return found.is_none();
+    })
+    .copied()
+    .collect();
+}
+
+// How the strokes of a layer changed between two versions of a page
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct LayerDiff {
+  pub added: usize,
+  pub removed: usize,
+  // drawn again with another brush, color or size, or moved, keeping their
+  // number of points
+  pub modified: usize,
+  pub unchanged: usize
+}
+
+impl LayerDiff {
+  pub fn total(layers: &[LayerDiff]) -> LayerDiff {
+    return layers.iter().fold(LayerDiff::default(), |total, layer| LayerDiff {
+      added: total.added + layer.added,
+      removed: total.removed + layer.removed,
+      modified: total.modified + layer.modified,
+      unchanged: total.unchanged + layer.unchanged
+    });
+  }
+
+  pub fn to_json(&self) -> Value {
+    let count = |n: usize| Value::Number(n as f64);
+    return Value::Object(vec![
+      (String::from("added"), count(self.added)),
+      (String::from("removed"), count(self.removed)),
+      (String::from("modified"), count(self.modified)),
+      (String::from("unchanged"), count(self.unchanged))
+    ]);
+  }
+}
+
+// The strokes of two versions of a page compared layer by layer, the nth
+// layer of one with the nth of the other. Identical strokes pair off first;
+// of what's left, a stroke that went and one that came with as many
+// points count as one stroke modified.
+pub fn diff_pages(old: &[Layer], new: &[Layer]) -> Vec<LayerDiff> {
+  return (0..old.len().max(new.len()))
+    .map(|index| {
+      let old_lines = old.get(index).map_or_else(Vec::new, |layer| layer.lines.iter().collect::<Vec<_>>());
+      let new_lines = new.get(index).map_or_else(Vec::new, |layer| layer.lines.iter().collect::<Vec<_>>());
+      let removed = unmatched(&old_lines, &new_lines);
+      let added = unmatched(&new_lines, &old_lines);
+      let mut paired = vec![false; added.len()];
+      let modified = removed.iter()
+        .filter(|line| {
+          let found = added.iter().enumerate().position(|(index, other)| !paired[index] && other.points.len() == line.points.len());
+          if let Some(index) = found {
+            paired[index] = true;
+          }
+          return found.is_some();
+        })
+        .count();
+      return LayerDiff {
+        added: added.len() - modified,
+        removed: removed.len() - modified,
+        modified,
+        unchanged: new_lines.len() - added.len()
+      };
+    })
+    .collect();
+}
+
+fn mapped_to(color: &str) -> ColorMap {
+  return BRUSH_COLORS.iter()
+    .map(|brush_color| (*brush_color, String::from(color)))
+    .collect();
+}
+
+// Options for drawing two versions of a page together, cropped to
+// everything either version draws
+fn shared_options(old: &[Layer], new: &[Layer], options: &RenderOptions) -> RenderOptions {
+  let both = old.iter().chain(new.iter()).cloned().collect::<Vec<_>>();
+  let view_box = options.crop_to_content
+    .and_then(|margin| content_bounds(&both, margin))
+    .unwrap_or(options.view_box);
+  let device = options.device.unwrap_or_else(|| Device::detect(&both));
+  return RenderOptions { view_box, crop_to_content: None, device: Some(device), ..options.clone() };
+}
+
+// A group of the strokes of `lines` in a single flat color, without any of
+// the brushes' effects, turned as the page is
+fn colored_strokes(id: &str, lines: &[&Line], color: &str, options: &RenderOptions) -> SVGGroup {
+  let colored = RenderOptions { color_map: mapped_to(color), ..options.clone() };
+  let drawn = lines.iter().filter(|line| options.draws_brush(line.brush_type)).map(|line| (*line).clone()).collect::<Vec<_>>();
+  let layers = vec![Layer { num_lines: drawn.len() as i32, lines: drawn }];
+  let layers = match options.rotation % 360 {
+    0 => layers,
+    rotation => rotate_layers(&layers, options.canvas(), rotation)
+  };
+  return layers[0].lines.iter()
+    .flat_map(|line| line_strokes(line, &colored))
+    .fold(SVGGroup::new().set("id", id), |mut group, stroke| {
+      let mut stroke = stroke.rounded(options.precision);
+      stroke.attributes.retain(|(name, _)| *name != "filter");
+      stroke.append_to(&mut group, options.element);
+      return group;
+    });
+}
+
+// The new version of a page with the strokes it no longer has drawn in
+// red and the strokes it gained in green, over the ones that stayed
+pub fn render_diff(old: &[Layer], new: &[Layer], options: &RenderOptions) -> Result<SVGDocument, String> {
+  let old_lines = old.iter().flat_map(|layer| layer.lines.iter()).collect::<Vec<_>>();
+  let new_lines = new.iter().flat_map(|layer| layer.lines.iter()).collect::<Vec<_>>();
+  let removed = unmatched(&old_lines, &new_lines);
+  let added = unmatched(&new_lines, &old_lines);
+  let kept = new.iter()
+    .map(|layer| {
+      let lines = layer.lines.iter()
+        .filter(|line| !added.iter().any(|added_line| std::ptr::eq(*added_line, *line)))
+        .cloned()
+        .collect::<Vec<_>>();
+      return Layer { num_lines: lines.len() as i32, lines };
+    })
+    .collect::<Vec<_>>();
+  let options = &shared_options(old, new, options);
+  return render_svg(&kept, &[], options).map(|document| {
+    return document
+      .add(colored_strokes("removed", &removed, REMOVED_COLOR, options))
+      .add(colored_strokes("added", &added, ADDED_COLOR, options));
+  });
+}
+
+fn drawn_lines<'a>(layers: &'a [Layer], options: &RenderOptions) -> Vec<&'a Line> {
+  return layers.iter().enumerate()
+    .filter(|(index, _)| options.draws_layer(index + 1))
+    .flat_map(|(_, layer)| layer.lines.iter())
+    .collect();
+}
+
+// Two versions of a page laid over each other, each all in one translucent
+// color, the old one under the new; strokes that didn't change come out
+// in a mix of both colors
+pub fn render_overlay(old: &[Layer], new: &[Layer], old_color: &str, new_color: &str, options: &RenderOptions) -> Result<SVGDocument, String> {
+  let (old_lines, new_lines) = (drawn_lines(old, options), drawn_lines(new, options));
+  let options = &shared_options(old, new, options);
+  return render_svg(&[], &[], options).map(|document| {
+    return document
+      .add(colored_strokes("old", &old_lines, old_color, options).set("opacity", OVERLAY_OPACITY))
+      .add(colored_strokes("new", &new_lines, new_color, options).set("opacity", OVERLAY_OPACITY));
+  });
+}
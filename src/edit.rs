@@ -0,0 +1,73 @@
+use crate::scene::Transform;
+use crate::format::WRITTEN_VERSION;
+use crate::{Document, Layer, X_MAX, Y_MAX};
+
+// Edits that turn pages into other pages, which write_rm can put back on
+// the tablet
+
+// One page with the strokes of all of them, in order: the first layer
+// holds every page's first layer, the second every page's second and so on
+pub fn merge_pages(pages: &[Document]) -> Document {
+  let layer_count = pages.iter().map(|page| page.layers.len()).max().unwrap_or(0);
+  let layers = (0..layer_count)
+    .map(|index| {
+      let lines = pages.iter()
+        .filter_map(|page| page.layers.get(index))
+        .flat_map(|layer| layer.lines.iter().cloned())
+        .collect::<Vec<_>>();
+      return Layer { num_lines: lines.len() as i32, lines };
+    })
+    .collect();
+  let warnings = pages.iter().flat_map(|page| page.warnings.iter().cloned()).collect();
+  let text = pages.iter().flat_map(|page| page.text.iter().cloned()).collect();
+  let version = pages.iter().map(|page| page.version).max().unwrap_or(WRITTEN_VERSION);
+  return Document { version, layers, text, warnings };
+}
+
+// A page per layer, each with just that layer's strokes; typed text isn't
+// on any layer, so it goes with the first
+pub fn split_layers(page: &Document) -> Vec<Document> {
+  return page.layers.iter().enumerate()
+    .map(|(index, layer)| Document {
+      version: page.version,
+      layers: vec![layer.clone()],
+      text: if index == 0 { page.text.clone() } else { Vec::new() },
+      warnings: page.warnings.clone()
+    })
+    .collect();
+}
+
+// Scaling by `scale` and turning clockwise by `rotation` degrees about the
+// middle of the page, then moving by `translation` canvas pixels
+pub fn page_transform(scale: f32, rotation: f32, translation: (f32, f32)) -> Transform {
+  let (center_x, center_y) = (X_MAX as f32 / 2.0, Y_MAX as f32 / 2.0);
+  let (sin, cos) = rotation.to_radians().sin_cos();
+  return Transform([1.0, 0.0, 0.0, 1.0, center_x + translation.0, center_y + translation.1])
+    .then(&Transform([cos * scale, sin * scale, -sin * scale, cos * scale, 0.0, 0.0]))
+    .then(&Transform([1.0, 0.0, 0.0, 1.0, -center_x, -center_y]));
+}
+
+// Every point of the page moved by `transform`, with the pen's direction
+// turned along with it
+pub fn transform_page(page: &Document, transform: &Transform) -> Document {
+  let [a, b, _, _, _, _] = transform.0;
+  let turn = b.atan2(a);
+  let mut transformed = page.clone();
+  transformed.layers.iter_mut()
+    .flat_map(|layer| layer.lines.iter_mut())
+    .flat_map(|line| line.points.iter_mut())
+    .for_each(|point| {
+      let (x, y) = transform.apply(point.x, point.y);
+      point.x = x;
+      point.y = y;
+      point.direction = (point.direction + turn).rem_euclid(std::f32::consts::TAU);
+    });
+  // text stays upright, just moved and sized with the strokes
+  transformed.text.iter_mut().for_each(|block| {
+    let (x, y) = transform.apply(block.x, block.y);
+    block.x = x;
+    block.y = y;
+    block.width *= a.hypot(b);
+  });
+  return transformed;
+}
@@ -0,0 +1,117 @@
+use crate::brush::{BrushColor, BrushType};
+use crate::format::WRITTEN_VERSION;
+use crate::scene::{parse_scene, Item, Segment};
+use crate::{Document, Layer, Line, Point, X_MAX, Y_MAX};
+
+// Brush sizes as the tablet writes them for its thin, medium and thick
+// settings
+pub const BRUSH_SIZES: [(&str, f32); 3] = [("thin", 1.875), ("medium", 2.0), ("thick", 2.125)];
+
+// The brush every encoded stroke is drawn with
+#[derive(Debug, Copy, Clone)]
+pub struct EncodeOptions {
+  pub brush_type: BrushType,
+  pub brush_color: BrushColor,
+  pub brush_size: f32
+}
+
+impl Default for EncodeOptions {
+  fn default() -> EncodeOptions {
+    return EncodeOptions { brush_type: BrushType::Fineliner, brush_color: BrushColor::Black, brush_size: 2.0 };
+  }
+}
+
+// thin, medium, thick or a size of its own
+pub fn parse_brush_size(size: &str) -> Result<f32, String> {
+  return match BRUSH_SIZES.iter().find(|(name, _)| *name == size.trim()) {
+    Some((_, size)) => Ok(*size),
+    None => size.trim().parse::<f32>().ok()
+      .filter(|size| size.is_finite() && *size > 0.0)
+      .ok_or_else(|| format!("invalid brush size {}, expected thin, medium, thick or a number", size))
+  };
+}
+
+// A stroke through `points`, drawn with the options' brush
+pub fn stroke_line(points: &[(f32, f32)], options: &EncodeOptions) -> Line {
+  let points = points.iter().enumerate()
+    .map(|(index, (x, y))| {
+      // heading towards the next point, or on from the last one at the end
+      let (from, to) = match index + 1 < points.len() {
+        true => ((*x, *y), points[index + 1]),
+        false => (points[index.saturating_sub(1)], (*x, *y))
+      };
+      return Point {
+        x: *x,
+        y: *y,
+        speed: 0.0,
+        direction: (to.1 - from.1).atan2(to.0 - from.0).rem_euclid(std::f32::consts::TAU),
+        width: options.brush_size * 2.0,
+        pressure: 0.5
+      };
+    })
+    .collect::<Vec<_>>();
+  return Line {
+    brush_type: options.brush_type,
+    brush_color: options.brush_color,
+    padding: 0,
+    brush_size: options.brush_size,
+    unknown: 0.0,
+    num_points: points.len() as i32,
+    points
+  };
+}
+
+// An SVG drawing as a page of strokes, one per subpath of every stroked
+// or filled shape, fitted and centered on the tablet's canvas. Text and
+// images are left out.
+pub fn encode_svg(source: &str, options: &EncodeOptions) -> Result<Document, String> {
+  let scene = parse_scene(source)?;
+  let view_box = scene.view_box;
+  let (canvas_width, canvas_height) = (X_MAX as f32, Y_MAX as f32);
+  let fitted = view_box.width > 0.0 && view_box.height > 0.0;
+  let scale = match fitted {
+    true => (canvas_width / view_box.width).min(canvas_height / view_box.height),
+    false => 1.0
+  };
+  let (offset_x, offset_y) = match fitted {
+    true => (
+      (canvas_width - view_box.width * scale) / 2.0 - view_box.x * scale,
+      (canvas_height - view_box.height * scale) / 2.0 - view_box.y * scale
+    ),
+    false => (0.0, 0.0)
+  };
+  let on_canvas = |x: f32, y: f32| (x * scale + offset_x, y * scale + offset_y);
+
+  let mut lines = Vec::new();
+  scene.items.iter()
+    .filter_map(|item| match item {
+      Item::Shape(shape) if shape.fill.is_some() || shape.stroke.is_some() => Some(shape),
+      _ => None
+    })
+    .for_each(|shape| {
+      let mut subpath = Vec::<(f32, f32)>::new();
+      let mut finish = |subpath: &mut Vec<(f32, f32)>| {
+        if subpath.len() > 1 {
+          lines.push(stroke_line(subpath, options));
+        }
+        subpath.clear();
+      };
+      shape.segments.iter().for_each(|segment| match *segment {
+        Segment::Move(x, y) => {
+          finish(&mut subpath);
+          subpath.push(on_canvas(x, y));
+        },
+        Segment::Line(x, y) => subpath.push(on_canvas(x, y)),
+        Segment::Close => {
+          let start = subpath.first().copied();
+          subpath.extend(start);
+          finish(&mut subpath);
+          // drawing carries on from where the subpath started
+          subpath.extend(start);
+        }
+      });
+      finish(&mut subpath);
+    });
+  let layers = vec![Layer { num_lines: lines.len() as i32, lines }];
+  return Ok(Document { version: WRITTEN_VERSION, layers, text: Vec::new(), warnings: Vec::new() });
+}
@@ -0,0 +1,88 @@
+use crate::pdf::{label_start, number, pdf_string, PT_PER_PX};
+use crate::scene::{Item, Label, LineCap, LineJoin, Paint, Scene, Segment, Shape};
+
+// The standard fonts re-encoded as Latin-1, to match the strings written
+const PROLOG: &str = "\
+/latin1font { findfont dup length dict begin { 1 index /FID ne { def } { pop pop } ifelse } forall \
+/Encoding ISOLatin1Encoding def currentdict end definefont pop } bind def
+/F1 /Helvetica latin1font
+/F2 /Helvetica-Bold latin1font";
+
+// PostScript has no transparency, so translucent paint is mixed with the
+// white of the paper instead, and blend modes are dropped
+fn color(paint: &Paint) -> String {
+  let mixed = |channel: f32| 1.0 - paint.opacity * (1.0 - channel);
+  return format!(
+    "{} {} {} setrgbcolor",
+    number(mixed(paint.color.red)), number(mixed(paint.color.green)), number(mixed(paint.color.blue))
+  );
+}
+
+fn shape_operators(shape: &Shape) -> String {
+  let mut operators = vec![String::from("gsave"), String::from("newpath")];
+  shape.segments.iter().for_each(|segment| operators.push(match segment {
+    Segment::Move(x, y) => format!("{} {} moveto", number(*x), number(*y)),
+    Segment::Line(x, y) => format!("{} {} lineto", number(*x), number(*y)),
+    Segment::Close => String::from("closepath")
+  }));
+  if let Some(fill) = &shape.fill {
+    // the path is kept to be stroked too
+    operators.push(format!("gsave {} fill grestore", color(fill)));
+  }
+  if let Some(stroke) = &shape.stroke {
+    operators.push(color(&stroke.paint));
+    operators.push(format!("{} setlinewidth", number(stroke.width)));
+    operators.push(format!("{} setlinecap", match stroke.cap { LineCap::Butt => 0, LineCap::Round => 1, LineCap::Square => 2 }));
+    operators.push(format!("{} setlinejoin", match stroke.join { LineJoin::Miter => 0, LineJoin::Round => 1, LineJoin::Bevel => 2 }));
+    if !stroke.dashes.is_empty() {
+      operators.push(format!("[{}] 0 setdash", stroke.dashes.iter().map(|dash| number(*dash)).collect::<Vec<_>>().join(" ")));
+    }
+    operators.push(String::from("stroke"));
+  }
+  operators.push(String::from("grestore"));
+  return operators.join("\n");
+}
+
+fn label_operators(label: &Label) -> String {
+  // glyphs are drawn upright in a page whose y axis points down, as in PDF
+  let [a, b, c, d, _, _] = label.transform.0;
+  let (origin_x, origin_y) = label.transform.apply(label_start(label), label.y);
+  return [
+    String::from("gsave"),
+    color(&label.paint),
+    format!("/{} findfont {} scalefont setfont", if label.bold { "F2" } else { "F1" }, number(label.size)),
+    format!("[{} {} {} {} {} {}] concat", number(a), number(b), number(-c), number(-d), number(origin_x), number(origin_y)),
+    format!("0 0 moveto ({}) show", pdf_string(&label.text)),
+    String::from("grestore")
+  ].join("\n");
+}
+
+// An Encapsulated PostScript file of the scene, its bounding box the page
+pub fn write_eps(scene: &Scene) -> Vec<u8> {
+  let (width, height) = (scene.width * PT_PER_PX, scene.height * PT_PER_PX);
+  let view_box = &scene.view_box;
+  // fitted and centered like the PDF's pages, with y flipped to point down
+  let scale = (width / view_box.width).min(height / view_box.height);
+  let (offset_x, offset_y) = ((width - view_box.width * scale) / 2.0, (height - view_box.height * scale) / 2.0);
+  let mut lines = vec![
+    String::from("%!PS-Adobe-3.0 EPSF-3.0"),
+    format!("%%BoundingBox: 0 0 {} {}", width.ceil() as u32, height.ceil() as u32),
+    format!("%%HiResBoundingBox: 0 0 {} {}", number(width), number(height)),
+    String::from("%%Creator: relineate"),
+    String::from("%%LanguageLevel: 2"),
+    String::from("%%EndComments"),
+    String::from(PROLOG),
+    String::from("gsave"),
+    format!("0 0 {} {} rectclip", number(width), number(height)),
+    format!(
+      "[{} 0 0 {} {} {}] concat",
+      number(scale), number(-scale), number(offset_x - view_box.x * scale), number(height - offset_y + view_box.y * scale)
+    )
+  ];
+  scene.items.iter().for_each(|item| lines.push(match item {
+    Item::Shape(shape) => shape_operators(shape),
+    Item::Label(label) => label_operators(label)
+  }));
+  lines.extend([String::from("grestore"), String::from("showpage"), String::from("%%EOF")]);
+  return format!("{}\n", lines.join("\n")).into_bytes();
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use relineate::json::Value;
+use relineate::warning::Warning;
+use relineate::LogLevel;
+
+pub const LOG_FORMATS: [&str; 2] = ["text", "json"];
+
+// For --log-format json: one object per line on stderr, each with an
+// "event" saying what it's about, for build systems to read back
+
+fn text(value: &str) -> Value {
+  return Value::String(String::from(value));
+}
+
+fn event(name: &str, mut members: Vec<(&str, Value)>) {
+  members.insert(0, ("event", text(name)));
+  let object = Value::Object(members.into_iter().map(|(key, value)| (String::from(key), value)).collect());
+  eprintln!("{}", object);
+}
+
+pub fn log(level: LogLevel, message: &str) {
+  event("log", vec![("level", text(&level.to_string())), ("message", text(message))]);
+}
+
+// One stage of converting a page (parse, render or write) done with, for
+// `file`: the input parsed or the output rendered and written. Carries the
+// warnings the page was read with.
+pub fn stage(file: &str, stage: &str, duration: Duration, warnings: &[Warning]) {
+  let warnings = warnings.iter()
+    .map(|warning| Value::Object(vec![
+      (String::from("code"), text(warning.code.id())),
+      (String::from("message"), text(&warning.message))
+    ]))
+    .collect();
+  event("stage", vec![
+    ("file", text(file)),
+    ("stage", text(stage)),
+    ("duration_ms", Value::Number(duration.as_micros() as f64 / 1000.0)),
+    ("warnings", Value::Array(warnings))
+  ]);
+}
+
+// What stopped the run, and the code it exits with
+pub fn error(message: &str, exit_code: i32) {
+  event("error", vec![("message", text(message)), ("exit_code", Value::Number(exit_code as f64))]);
+}
+
+pub fn failed(file: &str, message: &str) {
+  event("failed", vec![("file", text(file)), ("message", text(message))]);
+}
+
+pub fn summary(succeeded: usize, failed: usize, skipped: usize) {
+  event("summary", vec![
+    ("succeeded", Value::Number(succeeded as f64)),
+    ("failed", Value::Number(failed as f64)),
+    ("skipped", Value::Number(skipped as f64))
+  ]);
+}
+
+// What `run` returned and how long it took
+pub fn timed<T>(run: impl FnOnce() -> T) -> (T, Duration) {
+  let start = Instant::now();
+  let result = run();
+  return (result, start.elapsed());
+}
@@ -0,0 +1,89 @@
+use crate::brush::BrushColor;
+use crate::json::Value;
+use crate::{Layer, Line};
+
+// Excalidraw draws freehand lines about this many times their strokeWidth
+// across
+const FREEDRAW_WIDTH_RATIO: f32 = 4.25;
+
+fn hex_color(color: BrushColor) -> &'static str {
+  return match color {
+    BrushColor::Black => "#000000",
+    BrushColor::Grey => "#7d7d7d",
+    BrushColor::White => "#ffffff",
+    color => color.default_rgb()
+  };
+}
+
+fn text(value: &str) -> Value {
+  return Value::String(String::from(value));
+}
+
+fn number(value: f32) -> Value {
+  return Value::Number(value as f64);
+}
+
+fn freedraw(line: &Line, id: String, seed: usize) -> Value {
+  let style = line.brush_type.style();
+  let (origin_x, origin_y) = (line.points[0].x, line.points[0].y);
+  let (left, top, right, bottom) = line.points.iter().fold((f32::MAX, f32::MAX, f32::MIN, f32::MIN), |(l, t, r, b), point| {
+    return (l.min(point.x), t.min(point.y), r.max(point.x), b.max(point.y));
+  });
+  let points = line.points.iter()
+    .map(|point| Value::Array(vec![number(point.x - origin_x), number(point.y - origin_y)]))
+    .collect();
+  let pressures = line.points.iter().map(|point| number(point.pressure.clamp(0.0, 1.0))).collect();
+  return Value::Object(vec![
+    (String::from("id"), Value::String(id)),
+    (String::from("type"), text("freedraw")),
+    (String::from("x"), number(origin_x)),
+    (String::from("y"), number(origin_y)),
+    (String::from("width"), number(right - left)),
+    (String::from("height"), number(bottom - top)),
+    (String::from("angle"), number(0.0)),
+    (String::from("strokeColor"), text(hex_color(line.brush_color))),
+    (String::from("backgroundColor"), text("transparent")),
+    (String::from("fillStyle"), text("solid")),
+    (String::from("strokeWidth"), number(line.brush_size * style.width_scale / FREEDRAW_WIDTH_RATIO)),
+    (String::from("strokeStyle"), text("solid")),
+    (String::from("roughness"), number(0.0)),
+    (String::from("opacity"), number((style.opacity * 100.0).round())),
+    (String::from("groupIds"), Value::Array(Vec::new())),
+    (String::from("frameId"), Value::Null),
+    (String::from("roundness"), Value::Null),
+    (String::from("seed"), number(seed as f32)),
+    (String::from("version"), number(1.0)),
+    (String::from("versionNonce"), number(seed as f32)),
+    (String::from("isDeleted"), Value::Bool(false)),
+    (String::from("boundElements"), Value::Null),
+    (String::from("updated"), number(0.0)),
+    (String::from("link"), Value::Null),
+    (String::from("locked"), Value::Bool(false)),
+    (String::from("points"), Value::Array(points)),
+    (String::from("pressures"), Value::Array(pressures)),
+    (String::from("simulatePressure"), Value::Bool(false)),
+    (String::from("lastCommittedPoint"), Value::Null)
+  ]);
+}
+
+// An Excalidraw scene with a freehand drawing element per stroke, in the
+// tablet's coordinates and the order the strokes were drawn in
+pub fn write_excalidraw(layers: &[Layer]) -> String {
+  let elements = layers.iter().enumerate()
+    .flat_map(|(layer_index, layer)| layer.lines.iter().enumerate().map(move |(line_index, line)| (layer_index, line_index, line)))
+    .filter(|(_, _, line)| line.brush_type.is_ink() && !line.points.is_empty())
+    .enumerate()
+    .map(|(index, (layer_index, line_index, line))| {
+      return freedraw(line, format!("relineate-{}-{}", layer_index + 1, line_index + 1), index + 1);
+    })
+    .collect();
+  let scene = Value::Object(vec![
+    (String::from("type"), text("excalidraw")),
+    (String::from("version"), number(2.0)),
+    (String::from("source"), text("relineate")),
+    (String::from("elements"), Value::Array(elements)),
+    (String::from("appState"), Value::Object(vec![(String::from("viewBackgroundColor"), text("#ffffff"))])),
+    (String::from("files"), Value::Object(Vec::new()))
+  ]);
+  return format!("{}\n", scene);
+}
@@ -0,0 +1,51 @@
+// What made a run fail, which picks its exit code so that scripts can tell
+// failures apart. Codes are documented in the README; never renumber, only
+// add.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FailureKind {
+  // anything not covered below, e.g. a denied warning
+  Other,
+  // an option's value that doesn't make sense; clap exits with the same
+  // code for arguments it rejects itself
+  BadArguments,
+  // an input that doesn't exist or can't be read
+  MissingInput,
+  // an .rm file of a version this doesn't read
+  UnsupportedVersion,
+  Parse,
+  // rendering or writing an output
+  Write,
+  // some of the pages of a --recursive conversion failed
+  PartialBatch
+}
+
+impl FailureKind {
+  pub fn exit_code(&self) -> i32 {
+    return match self {
+      FailureKind::Other => 1,
+      FailureKind::BadArguments => 2,
+      FailureKind::MissingInput => 3,
+      FailureKind::UnsupportedVersion => 4,
+      FailureKind::Parse => 5,
+      FailureKind::Write => 6,
+      FailureKind::PartialBatch => 7
+    };
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Failure {
+  pub kind: FailureKind,
+  pub message: String
+}
+
+impl From<String> for Failure {
+  fn from(message: String) -> Failure {
+    return Failure { kind: FailureKind::Other, message };
+  }
+}
+
+// For map_err, e.g. .map_err(because(FailureKind::Parse))
+pub fn because(kind: FailureKind) -> impl Fn(String) -> Failure {
+  return move |message| Failure { kind, message };
+}
@@ -0,0 +1,85 @@
+// A C interface for linking the converter into other programs, declared
+// in include/relineate.h. Strings handed out are NUL-terminated UTF-8 and
+// must be given back to relineate_free_string; documents go back to
+// relineate_free_document.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+use crate::options::RenderOptions;
+use crate::{parse_rm, render_svg, Document};
+
+// A parsed page, opaque to C
+pub struct RelineateDocument(Document);
+
+fn to_c_string(text: String) -> *mut c_char {
+  // SVG and error messages don't contain NULs, but just in case
+  return CString::new(text.replace('\0', "")).unwrap_or_default().into_raw();
+}
+
+unsafe fn set_error(error: *mut *mut c_char, why: String) {
+  if !error.is_null() {
+    *error = to_c_string(why);
+  }
+}
+
+/// Parses a .rm page of `length` bytes. Returns NULL if it can't be
+/// parsed, with a message in `*error` unless `error` is NULL.
+///
+/// # Safety
+/// `data` must point to `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn relineate_parse(data: *const u8, length: usize, error: *mut *mut c_char) -> *mut RelineateDocument {
+  if data.is_null() {
+    set_error(error, String::from("no data"));
+    return std::ptr::null_mut();
+  }
+  return match parse_rm(std::slice::from_raw_parts(data, length)) {
+    Ok(document) => Box::into_raw(Box::new(RelineateDocument(document))),
+    Err(why) => {
+      set_error(error, why);
+      std::ptr::null_mut()
+    }
+  };
+}
+
+/// Renders a parsed page as SVG with the default options. Returns NULL if
+/// it can't be rendered, with a message in `*error` unless `error` is NULL.
+///
+/// # Safety
+/// `document` must come from relineate_parse and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn relineate_render_svg(document: *const RelineateDocument, error: *mut *mut c_char) -> *mut c_char {
+  let document = match document.as_ref() {
+    Some(document) => document,
+    None => {
+      set_error(error, String::from("no document"));
+      return std::ptr::null_mut();
+    }
+  };
+  return match render_svg(&document.0, &RenderOptions::default()) {
+    Ok(svg) => to_c_string(svg.to_string()),
+    Err(why) => {
+      set_error(error, why);
+      std::ptr::null_mut()
+    }
+  };
+}
+
+/// # Safety
+/// `document` must come from relineate_parse, or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn relineate_free_document(document: *mut RelineateDocument) {
+  if !document.is_null() {
+    drop(Box::from_raw(document));
+  }
+}
+
+/// # Safety
+/// `string` must come from this library, or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn relineate_free_string(string: *mut c_char) {
+  if !string.is_null() {
+    drop(CString::from_raw(string));
+  }
+}
@@ -0,0 +1,220 @@
+use crate::brush::{BrushColor, BrushType};
+use crate::encode::{parse_brush_size, stroke_line, EncodeOptions};
+use crate::format::WRITTEN_VERSION;
+use crate::{Document, Layer};
+
+pub const SHAPES: [&str; 4] = ["line", "rectangle", "circle", "points"];
+
+// How far apart points are put along a shape's edges, in canvas pixels,
+// about as far as the tablet samples a steady stroke
+const POINT_SPACING: f32 = 4.0;
+const CIRCLE_SEGMENTS: usize = 72;
+
+fn number(value: &toml::Value) -> Option<f32> {
+  return value.as_float().map(|float| float as f32).or_else(|| value.as_integer().map(|integer| integer as f32));
+}
+
+fn position(value: &toml::Value) -> Option<(f32, f32)> {
+  return match value.as_array().map(Vec::as_slice) {
+    Some([x, y]) => Some((number(x)?, number(y)?)),
+    _ => None
+  };
+}
+
+// `corners` with points added along each edge between them
+fn sampled(corners: &[(f32, f32)]) -> Vec<(f32, f32)> {
+  let mut points = corners.iter().take(1).copied().collect::<Vec<_>>();
+  corners.windows(2).for_each(|edge| {
+    let ((x1, y1), (x2, y2)) = (edge[0], edge[1]);
+    let steps = ((x2 - x1).hypot(y2 - y1) / POINT_SPACING).ceil().max(1.0) as usize;
+    points.extend((1..=steps).map(|step| {
+      let along = step as f32 / steps as f32;
+      return (x1 + (x2 - x1) * along, y1 + (y2 - y1) * along);
+    }));
+  });
+  return points;
+}
+
+// The points of one stroke of a spec
+fn shape_points(stroke: &toml::Value) -> Result<Vec<(f32, f32)>, String> {
+  let field = |name: &str| stroke.get(name).ok_or_else(|| format!("missing {}", name));
+  let position_of = |name: &str| field(name).and_then(|value| {
+    return position(value).ok_or_else(|| format!("{} must be [x, y]", name));
+  });
+  let number_of = |name: &str| field(name).and_then(|value| number(value).ok_or_else(|| format!("{} must be a number", name)));
+  let shape = stroke.get("shape").and_then(|shape| shape.as_str()).unwrap_or("points");
+  return match shape {
+    "line" => Ok(sampled(&[position_of("from")?, position_of("to")?])),
+    "rectangle" => {
+      let (x, y) = position_of("corner")?;
+      let (width, height) = (number_of("width")?, number_of("height")?);
+      return Ok(sampled(&[(x, y), (x + width, y), (x + width, y + height), (x, y + height), (x, y)]));
+    },
+    "circle" => {
+      let (x, y) = position_of("center")?;
+      let radius = number_of("radius")?;
+      let corners = (0..=CIRCLE_SEGMENTS)
+        .map(|segment| segment as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU)
+        .map(|angle| (x + radius * angle.cos(), y + radius * angle.sin()))
+        .collect::<Vec<_>>();
+      return Ok(sampled(&corners));
+    },
+    "points" => field("points")?.as_array()
+      .ok_or_else(|| String::from("points must be a list of [x, y]"))?
+      .iter()
+      .map(|point| position(point).ok_or_else(|| String::from("points must be a list of [x, y]")))
+      .collect::<Result<Vec<_>, String>>()
+      .and_then(|points| match points.len() {
+        0 | 1 => Err(String::from("points needs at least two points")),
+        _ => Ok(points)
+      }),
+    _ => Err(format!("unknown shape {}, expected {}", shape, SHAPES.join(", ")))
+  };
+}
+
+fn stroke_options(stroke: &toml::Value) -> Result<EncodeOptions, String> {
+  let text = |name: &str| stroke.get(name).and_then(|value| value.as_str());
+  let defaults = EncodeOptions::default();
+  let brush_type = match text("brush") {
+    Some(brush) => BrushType::by_name(brush).ok_or_else(|| format!("unknown brush {}", brush))?,
+    None => defaults.brush_type
+  };
+  let brush_color = match text("color") {
+    Some(color) => BrushColor::by_name(color).ok_or_else(|| format!("unknown color {}, expected {}", color, BrushColor::names()))?,
+    None => defaults.brush_color
+  };
+  let brush_size = match stroke.get("size") {
+    Some(size) => match (size.as_str(), number(size)) {
+      (Some(size), _) => parse_brush_size(size)?,
+      (None, Some(size)) => parse_brush_size(&size.to_string())?,
+      (None, None) => return Err(String::from("size must be thin, medium, thick or a number"))
+    },
+    None => defaults.brush_size
+  };
+  return Ok(EncodeOptions { brush_type, brush_color, brush_size });
+}
+
+// A page made up from a TOML description of its layers and the strokes on
+// each, for tests of whatever reads .rm files:
+//
+//   [[layers]]
+//   [[layers.strokes]]
+//   brush = "pen"
+//   shape = "circle"
+//   center = [702, 936]
+//   radius = 200
+//
+// Strokes are lines from/to, rectangles from a corner with a width and
+// height, circles or any points, in canvas pixels, each with a brush,
+// color and size that default to a medium black fineliner.
+pub fn generate_fixture(spec: &str) -> Result<Document, String> {
+  let spec = spec.parse::<toml::Value>().map_err(|why| format!("invalid fixture spec, {}", why))?;
+  let layers = spec.get("layers")
+    .ok_or_else(|| String::from("the fixture spec has no [[layers]]"))?
+    .as_array()
+    .ok_or_else(|| String::from("layers must be a list of tables, [[layers]]"))?;
+  return layers.iter().enumerate()
+    .map(|(layer_index, layer)| {
+      let strokes = layer.get("strokes").and_then(|strokes| strokes.as_array()).map_or(&[][..], Vec::as_slice);
+      let lines = strokes.iter().enumerate()
+        .map(|(stroke_index, stroke)| {
+          return stroke_options(stroke)
+            .and_then(|options| shape_points(stroke).map(|points| stroke_line(&points, &options)))
+            .map_err(|why| format!("layer {}, stroke {}: {}", layer_index + 1, stroke_index + 1, why));
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+      return Ok(Layer { num_lines: lines.len() as i32, lines });
+    })
+    .collect::<Result<Vec<_>, String>>()
+    .map(|layers| Document { version: WRITTEN_VERSION, layers, text: Vec::new(), warnings: Vec::new() });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::options::RenderOptions;
+  use crate::{parse_document, render_svg, write_rm};
+
+  const SPEC: &str = r#"
+    [[layers]]
+    [[layers.strokes]]
+    shape = "line"
+    from = [100, 100]
+    to = [140, 100]
+
+    [[layers.strokes]]
+    brush = "pen"
+    color = "blue"
+    size = "thick"
+    shape = "rectangle"
+    corner = [200, 200]
+    width = 40
+    height = 20
+
+    [[layers]]
+    [[layers.strokes]]
+    brush = "marker"
+    color = "red"
+    shape = "circle"
+    center = [702, 936]
+    radius = 200
+
+    [[layers.strokes]]
+    brush = "highlighter"
+    color = "yellow"
+    size = 4
+    points = [[10, 10], [20, 30], [40, 35]]
+  "#;
+
+  // the fixture as it reads back from .rm data
+  fn parsed(spec: &str) -> Document {
+    let mut bytes = Vec::new();
+    write_rm(&generate_fixture(spec).unwrap(), &mut bytes).unwrap();
+    return parse_document(&bytes, &|_, _| {}).unwrap();
+  }
+
+  #[test]
+  fn generates_each_shape() {
+    let document = parsed(SPEC);
+    assert_eq!(document.version, WRITTEN_VERSION);
+    assert_eq!(document.layers.len(), 2);
+    assert_eq!(document.layers.iter().map(|layer| layer.lines.len()).collect::<Vec<_>>(), vec![2, 2]);
+    let lines = document.layers.iter().flat_map(|layer| layer.lines.iter()).collect::<Vec<_>>();
+    // 40 pixels at 4 apart, the rectangle's 120 around, 72 arcs of about
+    // 17 pixels each cut in 5, and the points as given
+    assert_eq!(lines.iter().map(|line| line.points.len()).collect::<Vec<_>>(), vec![11, 31, 361, 3]);
+    assert!(lines.iter().all(|line| line.num_points as usize == line.points.len()));
+    let brushes = lines.iter().map(|line| (line.brush_type, line.brush_color)).collect::<Vec<_>>();
+    assert_eq!(brushes, vec![
+      (BrushType::Fineliner, BrushColor::Black),
+      (BrushType::Pen, BrushColor::Blue),
+      (BrushType::Marker, BrushColor::Red),
+      (BrushType::Highlighter, BrushColor::Yellow)
+    ]);
+    assert_eq!(lines[0].brush_size, EncodeOptions::default().brush_size);
+    assert_eq!(lines[1].brush_size, parse_brush_size("thick").unwrap());
+    assert_eq!(lines[3].brush_size, 4.0);
+    assert_eq!((lines[0].points[0].x, lines[0].points[10].x), (100.0, 140.0));
+    assert_eq!((lines[3].points[2].x, lines[3].points[2].y), (40.0, 35.0));
+    let circle = &lines[2].points;
+    assert!(circle.iter().all(|point| ((point.x - 702.0).hypot(point.y - 936.0) - 200.0).abs() < 0.5));
+  }
+
+  #[test]
+  fn renders_the_strokes() {
+    let svg = render_svg(&parsed(SPEC), &RenderOptions::default()).unwrap().to_string();
+    assert!(svg.matches("<path").count() >= 4);
+    [BrushColor::Black, BrushColor::Blue, BrushColor::Red, BrushColor::Yellow].iter()
+      .for_each(|color| assert!(svg.contains(&format!("stroke=\"{}\"", color.default_rgb())), "no {} stroke in {}", color.name(), svg));
+  }
+
+  #[test]
+  fn rejects_bad_specs() {
+    let error = |spec: &str| generate_fixture(spec).unwrap_err();
+    assert_eq!(error("title = 1"), "the fixture spec has no [[layers]]");
+    assert_eq!(error("[[layers]]\n[[layers.strokes]]\nshape = \"line\"\nfrom = [0, 0]"), "layer 1, stroke 1: missing to");
+    assert_eq!(error("[[layers]]\n[[layers.strokes]]\npoints = [[0, 0]]"), "layer 1, stroke 1: points needs at least two points");
+    assert!(error("[[layers]]\n[[layers.strokes]]\nshape = \"star\"").starts_with("layer 1, stroke 1: unknown shape star"));
+    assert!(error("[[layers]]\n[[layers.strokes]]\ncolor = \"mauve\"\npoints = [[0, 0], [1, 1]]").contains("unknown color mauve"));
+  }
+}
@@ -0,0 +1,63 @@
+use binrw::binrw;
+
+// The layout of .rm files, which binrw both reads and writes. Pages of each
+// version are laid out alike but for what starts a line, so what differs
+// takes the version it's for; points are read straight into crate::Point.
+
+// The versions whose layout this describes, and v6, which crate::v6 reads
+pub const SUPPORTED_VERSIONS: [u32; 3] = [3, 5, 6];
+// The one written
+pub const WRITTEN_VERSION: u32 = 5;
+
+// Why a page whose header says it's of `version` can't be read
+pub fn unsupported_version(version: u32) -> String {
+  let supported = SUPPORTED_VERSIONS.iter().map(|version| version.to_string()).collect::<Vec<_>>().join(", ");
+  return format!("detected unsupported version {}, only versions {} are supported", version, supported);
+}
+
+pub const HEADER_PREFIX: &[u8] = b"reMarkable .lines file, version=";
+pub const HEADER_SIZE: usize = 43;
+pub const LAYER_HEADER_SIZE: usize = 4;
+pub const POINT_SIZE: usize = 24;
+
+// "reMarkable .lines file, version=5" padded with spaces to 43 bytes
+#[binrw]
+#[brw(little, magic = b"reMarkable .lines file, version=")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header {
+  #[br(map = |digit: u8| u32::from(digit.wrapping_sub(b'0')))]
+  #[bw(map = |version: &u32| b'0' + *version as u8)]
+  pub version: u32,
+  #[br(temp)]
+  #[bw(calc = [b' '; 10])]
+  padding: [u8; 10]
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LayerHeader {
+  pub num_lines: i32
+}
+
+// What comes before a line's points. Brushes and colors are left as
+// numbers, for whoever reads them to make sense of.
+#[binrw]
+#[brw(little, import(version: u32))]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LineHeader {
+  pub brush_type: i32,
+  pub brush_color: i32,
+  pub padding: u32,
+  pub brush_size: f32,
+  // added in version 5
+  #[brw(if(version >= 5))]
+  pub unknown: f32,
+  pub num_points: i32
+}
+
+impl LineHeader {
+  pub fn size(version: u32) -> usize {
+    return if version >= 5 { 24 } else { 20 };
+  }
+}
@@ -0,0 +1,56 @@
+use crate::device::Device;
+use crate::Layer;
+
+// Settings for driving a pen plotter or laser from the strokes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GcodeOptions {
+  // drawing speed in mm per minute
+  pub feed_rate: f32,
+  // Z heights in mm with the pen lifted and on the paper
+  pub pen_up: f32,
+  pub pen_down: f32,
+  // mm per tablet pixel
+  pub scale: f32
+}
+
+impl Default for GcodeOptions {
+  fn default() -> GcodeOptions {
+    // true to size at the tablet's 226 DPI
+    return GcodeOptions { feed_rate: 1000.0, pen_up: 5.0, pen_down: 0.0, scale: 0.1124 };
+  }
+}
+
+fn millimeters(value: f32) -> String {
+  let formatted = format!("{:.3}", value);
+  return String::from(formatted.trim_end_matches('0').trim_end_matches('.'));
+}
+
+// Every stroke's path in the order it was drawn: lift, travel to its
+// start, lower, then draw through its points. The origin is the page's
+// bottom left corner with y pointing up, as machines have it.
+pub fn write_gcode(layers: &[Layer], options: &GcodeOptions) -> String {
+  let (_, height) = Device::detect(layers).canvas();
+  let position = |x: f32, y: f32| format!("X{} Y{}", millimeters(x * options.scale), millimeters((height - y) * options.scale));
+  let pen_up = format!("G0 Z{}", millimeters(options.pen_up));
+  let header = vec![
+    String::from("; relineate"),
+    // millimeters, absolute positions
+    String::from("G21"),
+    String::from("G90"),
+    pen_up.clone()
+  ];
+  let strokes = layers.iter()
+    .flat_map(|layer| layer.lines.iter())
+    .filter(|line| line.brush_type.is_ink() && !line.points.is_empty())
+    .flat_map(|line| {
+      let start = &line.points[0];
+      let moves = vec![
+        format!("G0 {}", position(start.x, start.y)),
+        format!("G1 Z{} F{}", millimeters(options.pen_down), millimeters(options.feed_rate))
+      ];
+      let draws = line.points[1..].iter().map(|point| format!("G1 {}", position(point.x, point.y)));
+      return moves.into_iter().chain(draws).chain(std::iter::once(pen_up.clone())).collect::<Vec<_>>();
+    });
+  let footer = vec![String::from("G0 X0 Y0"), String::from("M2")];
+  return header.into_iter().chain(strokes).chain(footer).map(|line| line + "\n").collect();
+}
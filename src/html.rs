@@ -0,0 +1,60 @@
+// A page of a notebook as it goes into the HTML or onto a contact sheet:
+// its rendered SVGs, one per layer with --split-layers
+#[derive(Debug, Clone)]
+pub struct HtmlPage {
+  pub number: usize,
+  pub title: Option<String>,
+  pub svgs: Vec<String>
+}
+
+const STYLE: &str = "\
+body { margin: 0; background: #e8e8e8; font-family: sans-serif; }
+nav { position: sticky; top: 0; padding: 0.5em 1em; background: #fff; border-bottom: 1px solid #ccc; }
+section { margin: 2em auto; max-width: 60em; }
+section h2 { font-size: 1em; font-weight: normal; color: #555; }
+section svg { display: block; width: 100%; height: auto; background: #fff; box-shadow: 0 1px 4px rgba(0, 0, 0, 0.3); }
+section svg + svg { margin-top: 1em; }
+.pager { display: flex; justify-content: space-between; }";
+
+fn escape(text: &str) -> String {
+  return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
+
+fn anchor(page: &HtmlPage) -> String {
+  return format!("page-{}", page.number);
+}
+
+fn heading(page: &HtmlPage) -> String {
+  return match &page.title {
+    Some(title) => format!("Page {}: {}", page.number, escape(title)),
+    None => format!("Page {}", page.number)
+  };
+}
+
+// One self-contained file with every page's SVG inline, in order, each
+// under an anchor (#page-3) with links to the pages either side of it
+pub fn write_html(title: &str, pages: &[HtmlPage]) -> String {
+  let contents = pages.iter()
+    .map(|page| format!("<a href=\"#{}\">{}</a>", anchor(page), page.number))
+    .collect::<Vec<_>>()
+    .join(" ");
+  let sections = pages.iter().enumerate()
+    .map(|(index, page)| {
+      let link = |other: Option<&HtmlPage>, text: &str| match other {
+        Some(other) => format!("<a href=\"#{}\">{}</a>", anchor(other), text),
+        None => String::from("<span></span>")
+      };
+      let previous = link(index.checked_sub(1).map(|previous| &pages[previous]), "&larr; previous");
+      let next = link(pages.get(index + 1), "next &rarr;");
+      return format!(
+        "<section id=\"{}\">\n<h2>{}</h2>\n{}\n<div class=\"pager\">{}{}</div>\n</section>",
+        anchor(page), heading(page), page.svgs.join("\n"), previous, next
+      );
+    })
+    .collect::<Vec<_>>()
+    .join("\n");
+  return format!(
+    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<nav>{} &middot; {}</nav>\n{}\n</body>\n</html>\n",
+    escape(title), STYLE, escape(title), contents, sections
+  );
+}
@@ -0,0 +1,43 @@
+use std::fs::{self, File};
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+// Files at least this big are mapped into memory rather than read into it,
+// which saves copying them; smaller ones are quicker to just read
+const MAP_THRESHOLD: u64 = 1 << 20;
+
+// A file's contents, mapped or read
+pub enum InputBytes {
+  Mapped(Mmap),
+  Read(Vec<u8>)
+}
+
+impl Deref for InputBytes {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    return match self {
+      InputBytes::Mapped(map) => map,
+      InputBytes::Read(bytes) => bytes
+    };
+  }
+}
+
+// A file's contents, mapped into memory when it's big enough for that to
+// pay off. Falls back to reading it where it can't be mapped, e.g. on a
+// platform without mmap.
+pub fn read_file(path: &Path) -> Result<InputBytes, String> {
+  let file = File::open(path).map_err(|why| format!("couldn't open {}, {}", path.display(), why))?;
+  let length = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+  if length >= MAP_THRESHOLD {
+    // Safety: the map is only ever read. A file cut short while it's
+    // mapped ends the process rather than failing to parse, which is why
+    // only files big enough to be worth it are mapped.
+    if let Ok(map) = unsafe { Mmap::map(&file) } {
+      return Ok(InputBytes::Mapped(map));
+    }
+  }
+  return fs::read(path).map(InputBytes::Read).map_err(|why| format!("couldn't read {}, {}", path.display(), why));
+}
@@ -0,0 +1,246 @@
+// Just enough JSON to read the .content and .metadata files xochitl
+// writes next to each notebook, and to write the parsed strokes out.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Value>),
+  // kept in document order
+  Object(Vec<(String, Value)>)
+}
+
+impl Value {
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    return match self {
+      Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => None
+    };
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    return match self {
+      Value::String(s) => Some(s),
+      _ => None
+    };
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    return match self {
+      Value::Number(n) => Some(*n),
+      // xochitl stores some numbers (e.g. timestamps) as strings
+      Value::String(s) => s.parse::<f64>().ok(),
+      _ => None
+    };
+  }
+
+  pub fn as_bool(&self) -> Option<bool> {
+    return match self {
+      Value::Bool(b) => Some(*b),
+      _ => None
+    };
+  }
+
+  pub fn as_array(&self) -> Option<&Vec<Value>> {
+    return match self {
+      Value::Array(values) => Some(values),
+      _ => None
+    };
+  }
+}
+
+// Written compactly. Numbers that fit an f32 exactly, like everything read
+// from a page, are written as the f32 they were rather than with the
+// digits f64 needs.
+impl std::fmt::Display for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    return match self {
+      Value::Null => write!(f, "null"),
+      Value::Bool(b) => write!(f, "{}", b),
+      Value::Number(n) if !n.is_finite() => write!(f, "null"),
+      Value::Number(n) if *n as f32 as f64 == *n => write!(f, "{}", *n as f32),
+      Value::Number(n) => write!(f, "{}", n),
+      Value::String(s) => write_string(f, s),
+      Value::Array(values) => {
+        write!(f, "[")?;
+        values.iter().enumerate().try_for_each(|(index, value)| {
+          return write!(f, "{}{}", if index > 0 { "," } else { "" }, value);
+        })?;
+        write!(f, "]")
+      },
+      Value::Object(members) => {
+        write!(f, "{{")?;
+        members.iter().enumerate().try_for_each(|(index, (key, value))| {
+          if index > 0 { write!(f, ",")?; }
+          write_string(f, key)?;
+          return write!(f, ":{}", value);
+        })?;
+        write!(f, "}}")
+      }
+    };
+  }
+}
+
+fn write_string(f: &mut std::fmt::Formatter, s: &str) -> std::fmt::Result {
+  write!(f, "\"")?;
+  s.chars().try_for_each(|c| match c {
+    '"' => write!(f, "\\\""),
+    '\\' => write!(f, "\\\\"),
+    '\n' => write!(f, "\\n"),
+    '\r' => write!(f, "\\r"),
+    '\t' => write!(f, "\\t"),
+    c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32),
+    c => write!(f, "{}", c)
+  })?;
+  write!(f, "\"")
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+  let mut parser = Parser { chars: text.char_indices().peekable(), text };
+  let value = parser.value()?;
+  parser.whitespace();
+  if let Some((offset, _)) = parser.chars.peek() {
+    return Err(format!("trailing characters at offset {} in JSON", offset));
+  }
+  return Ok(value);
+}
+
+struct Parser<'a> {
+  chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+  text: &'a str
+}
+
+impl<'a> Parser<'a> {
+  fn whitespace(&mut self) {
+    while let Some((_, c)) = self.chars.peek() {
+      if !c.is_whitespace() { return; }
+      self.chars.next();
+    }
+  }
+
+  fn expect(&mut self, expected: char) -> Result<(), String> {
+    return match self.chars.next() {
+      Some((_, c)) if c == expected => Ok(()),
+      Some((offset, c)) => Err(format!("expected {} but found {} at offset {} in JSON", expected, c, offset)),
+      None => Err(format!("expected {} but found end of JSON", expected))
+    };
+  }
+
+  fn literal(&mut self, literal: &str, value: Value) -> Result<Value, String> {
+    return literal.chars().try_for_each(|c| self.expect(c)).map(|_| value);
+  }
+
+  fn value(&mut self) -> Result<Value, String> {
+    self.whitespace();
+    return match self.chars.peek().map(|(_, c)| *c) {
+      Some('n') => self.literal("null", Value::Null),
+      Some('t') => self.literal("true", Value::Bool(true)),
+      Some('f') => self.literal("false", Value::Bool(false)),
+      Some('"') => self.string().map(Value::String),
+      Some('[') => self.array(),
+      Some('{') => self.object(),
+      Some(_) => self.number(),
+      None => Err(String::from("unexpected end of JSON"))
+    };
+  }
+
+  fn number(&mut self) -> Result<Value, String> {
+    let start = self.chars.peek().map_or(self.text.len(), |(offset, _)| *offset);
+    let mut end = start;
+    while let Some((offset, c)) = self.chars.peek() {
+      if !(c.is_ascii_digit() || "+-.eE".contains(*c)) { break; }
+      end = offset + c.len_utf8();
+      self.chars.next();
+    }
+    return self.text[start..end].parse::<f64>()
+      .map(Value::Number)
+      .map_err(|_| format!("invalid value at offset {} in JSON", start));
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    self.expect('"')?;
+    let mut string = String::new();
+    loop {
+      match self.chars.next() {
+        Some((_, '"')) => return Ok(string),
+        Some((_, '\\')) => match self.chars.next() {
+          Some((_, 'n')) => string.push('\n'),
+          Some((_, 't')) => string.push('\t'),
+          Some((_, 'r')) => string.push('\r'),
+          Some((_, 'b')) => string.push('\u{8}'),
+          Some((_, 'f')) => string.push('\u{c}'),
+          Some((_, 'u')) => string.push(self.unicode_escape()?),
+          Some((_, c)) => string.push(c),
+          None => return Err(String::from("unterminated string in JSON"))
+        },
+        Some((_, c)) => string.push(c),
+        None => return Err(String::from("unterminated string in JSON"))
+      }
+    }
+  }
+
+  fn hex4(&mut self) -> Result<u32, String> {
+    return (0..4).try_fold(0u32, |acc, _| {
+      return self.chars.next()
+        .and_then(|(_, c)| c.to_digit(16))
+        .map(|digit| acc * 16 + digit)
+        .ok_or_else(|| String::from("invalid unicode escape in JSON"));
+    });
+  }
+
+  fn unicode_escape(&mut self) -> Result<char, String> {
+    let high = self.hex4()?;
+    if (0xd800..0xdc00).contains(&high) {
+      self.expect('\\')?;
+      self.expect('u')?;
+      let low = self.hex4()?;
+      let combined = 0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+      return Ok(std::char::from_u32(combined).unwrap_or('\u{fffd}'));
+    }
+    return Ok(std::char::from_u32(high).unwrap_or('\u{fffd}'));
+  }
+
+  fn array(&mut self) -> Result<Value, String> {
+    self.expect('[')?;
+    let mut values = Vec::new();
+    self.whitespace();
+    if let Some((_, ']')) = self.chars.peek() {
+      self.chars.next();
+      return Ok(Value::Array(values));
+    }
+    loop {
+      values.push(self.value()?);
+      self.whitespace();
+      match self.chars.next() {
+        Some((_, ',')) => continue,
+        Some((_, ']')) => return Ok(Value::Array(values)),
+        _ => return Err(String::from("expected , or ] in JSON array"))
+      }
+    }
+  }
+
+  fn object(&mut self) -> Result<Value, String> {
+    self.expect('{')?;
+    let mut members = Vec::new();
+    self.whitespace();
+    if let Some((_, '}')) = self.chars.peek() {
+      self.chars.next();
+      return Ok(Value::Object(members));
+    }
+    loop {
+      self.whitespace();
+      let key = self.string()?;
+      self.whitespace();
+      self.expect(':')?;
+      members.push((key, self.value()?));
+      self.whitespace();
+      match self.chars.next() {
+        Some((_, ',')) => continue,
+        Some((_, '}')) => return Ok(Value::Object(members)),
+        _ => return Err(String::from("expected , or } in JSON object"))
+      }
+    }
+  }
+}
@@ -0,0 +1,527 @@
+#![allow(clippy::needless_return)]
+
+mod animate;
+pub mod annotate;
+pub mod annotations;
+mod base64;
+pub mod brush;
+#[cfg(feature = "cloud")]
+pub mod cloud;
+pub mod compare;
+pub mod device;
+mod deflate;
+pub mod diff;
+pub mod edit;
+pub mod encode;
+pub mod eps;
+pub mod excalidraw;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixture;
+pub mod format;
+pub mod gcode;
+pub mod html;
+pub mod input;
+pub mod json;
+pub mod notebook;
+pub mod options;
+pub mod orientation;
+pub mod output;
+pub mod pdf;
+pub mod pens;
+pub mod png;
+pub mod preview;
+pub mod raster;
+pub mod recognize;
+pub mod render;
+pub mod scene;
+pub mod simplify;
+pub mod snap;
+pub mod stats;
+pub mod template;
+pub mod text;
+pub mod tile;
+pub mod timelapse;
+pub mod usb;
+pub mod v6;
+pub mod warning;
+pub mod watermark;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+pub mod xochitl;
+mod zip;
+
+use std::io::{Cursor, Read, Write};
+use binrw::{BinReaderExt, BinWriterExt};
+use binrw::io::NoSeek;
+
+use std::convert::TryFrom;
+
+use brush::{BrushType, BrushColor};
+use options::RenderOptions;
+use svg::Document as SVGDocument;
+use text::TextBlock;
+use warning::{annotate_svg, Warning, WarningCode};
+
+#[allow(dead_code)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone)]
+pub enum LogLevel {
+  SILENT,
+  ERROR,
+  WARN,
+  INFO,
+  DEBUG,
+  TRACE
+}
+
+impl std::fmt::Display for LogLevel {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+pub const X_MAX: u32 = 1404;
+pub const Y_MAX: u32  = 1872;
+
+#[derive(Debug, Clone, PartialEq, binrw::BinRead, binrw::BinWrite)]
+#[brw(little)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Point {
+  pub x: f32,
+  pub y: f32,
+  pub speed: f32,
+  pub direction: f32,
+  pub width: f32,
+  pub pressure: f32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Line {
+  pub brush_type: BrushType,
+  pub brush_color: BrushColor,
+  pub padding: u32,
+  pub brush_size: f32,
+  pub unknown: f32,
+  pub num_points: i32,
+  pub points: Vec<Point>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Layer {
+  pub num_lines: i32,
+  pub lines: Vec<Line>
+}
+
+pub use format::SUPPORTED_VERSIONS;
+
+// A parsed page along with anything odd that was found in it
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Document {
+  // the .rm version it was read from
+  pub version: u32,
+  pub layers: Vec<Layer>,
+  // typed text, which only v6 pages have
+  pub text: Vec<TextBlock>,
+  pub warnings: Vec<Warning>
+}
+
+// Takes a File or any other reader
+pub fn parse_file<R: Read>(reader: R, logger: &dyn Fn(LogLevel, String)) -> Result<Vec<Layer>, String>{
+  return parse_reader(reader, logger).map(|document| document.layers);
+}
+
+pub fn parse_bytes(bytes: &[u8], logger: &dyn Fn(LogLevel, String)) -> Result<Vec<Layer>, String>{
+  return parse_slice(bytes, logger).map(|document| document.layers);
+}
+
+pub fn parse_document(bytes: &[u8], logger: &dyn Fn(LogLevel, String)) -> Result<Document, String>{
+  return parse_slice(bytes, logger);
+}
+
+// The version an .rm file's header says it is, e.g. 3 for
+// "reMarkable .lines file, version=3", whether or not it's one this crate
+// reads
+pub fn header_version(bytes: &[u8]) -> Option<u32> {
+  let rest = bytes.strip_prefix(format::HEADER_PREFIX)?;
+  let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+  return std::str::from_utf8(&rest[..digits]).ok()?.parse().ok();
+}
+
+// A page from an .rm file, mapped into memory if it's big
+pub fn parse_path<P: AsRef<std::path::Path>>(path: P) -> Result<Document, String> {
+  return input::read_file(path.as_ref()).and_then(|bytes| parse_slice(&bytes, &|_, _| {}));
+}
+
+// A page from any reader of .rm data, e.g. a file or a network stream
+pub fn parse_rm<R: Read>(reader: R) -> Result<Document, String> {
+  return parse_reader(reader, &|_, _| {});
+}
+
+// A page as SVG, with its warnings noted in the document
+pub fn render_svg(document: &Document, options: &RenderOptions) -> Result<SVGDocument, String> {
+  return render::render_svg(&document.layers, &document.text, options).map(|svg| annotate_svg(svg, &document.warnings));
+}
+
+// render_svg written straight to a file, socket, buffer or the like
+pub fn render_svg_to<W: Write>(document: &Document, options: &RenderOptions, writer: W) -> Result<(), String> {
+  let svg = render_svg(document, options)?;
+  return svg::write(writer, &svg).map_err(|why| format!("couldn't write SVG, {}", why));
+}
+
+// A page as .rm v5 data, whatever version it was read from, which
+// parse_rm reads back the same. v5 has nowhere for typed text, which is
+// left out.
+pub fn write_rm<W: Write>(document: &Document, writer: W) -> Result<(), String> {
+  return write_layers(&document.layers, writer).map_err(|why| format!("couldn't write .rm data, {}", why));
+}
+
+fn write_layers<W: Write>(layers: &[Layer], writer: W) -> binrw::BinResult<()> {
+  let version = format::WRITTEN_VERSION;
+  let mut writer = NoSeek::new(writer);
+  writer.write_le(&format::Header { version })?;
+  writer.write_le(&(layers.len() as i32))?;
+  for layer in layers {
+    writer.write_le(&format::LayerHeader { num_lines: layer.lines.len() as i32 })?;
+    for line in &layer.lines {
+      let header = format::LineHeader {
+        brush_type: line.brush_type as i32,
+        brush_color: line.brush_color as i32,
+        padding: line.padding,
+        brush_size: line.brush_size,
+        unknown: line.unknown,
+        num_points: line.points.len() as i32
+      };
+      writer.write_le_args(&header, (version,))?;
+      writer.write_le(&line.points)?;
+    }
+  }
+  return Ok(writer.into_inner().flush()?);
+}
+
+// Room for `count` things of `size` bytes, but no more than what's left
+// could hold, whatever the count says
+fn capacity(count: i32, size: usize, page: &Cursor<&[u8]>) -> usize {
+  let remaining = page.get_ref().len().saturating_sub(page.position() as usize);
+  return (count.max(0) as usize).min(remaining / size);
+}
+
+// What went wrong reading `what` from `offset` onwards, e.g. "page ends
+// early, at offset 52314 while reading point 83 of line 12 in layer 2"
+fn read_error(offset: u64, what: impl Fn() -> String) -> impl FnOnce(binrw::Error) -> String {
+  return move |why| {
+    let problem = match why.is_eof() {
+      true => String::from("page ends early"),
+      false => why.root_cause().to_string()
+    };
+    return format!("{}, at offset {} while reading {}", problem, offset, what());
+  };
+}
+
+// A line from its header and points, with brushes and colors this version
+// doesn't know and points that can't be drawn warned about; `at` is the
+// line's layer and its place in it, counting from 0
+fn checked_line(header: &format::LineHeader, mut points: Vec<Point>, at: (usize, usize), warn: &mut dyn FnMut(WarningCode, String)) -> Line {
+  let (layer, line) = (at.0 + 1, at.1 + 1);
+  let brush_type = BrushType::try_from(header.brush_type).unwrap_or_else(|_| {
+    warn(WarningCode::UnknownBrush, format!(
+      "unknown brush type {} on layer {}, line {}; drawn as a fineliner", header.brush_type, layer, line
+    ));
+    return BrushType::Fineliner;
+  });
+  let brush_color = BrushColor::try_from(header.brush_color).unwrap_or_else(|_| {
+    warn(WarningCode::UnknownColor, format!(
+      "unknown color {} on layer {}, line {}; drawn black", header.brush_color, layer, line
+    ));
+    return BrushColor::Black;
+  });
+  let read = points.len();
+  points.retain(|p| p.x.is_finite() && p.y.is_finite());
+  if points.len() < read {
+    warn(WarningCode::DroppedPoints, format!(
+      "dropped {} of {} points with invalid coordinates on layer {}, line {}", read - points.len(), read, layer, line
+    ));
+  }
+  return Line {
+    brush_type,
+    brush_color,
+    padding: header.padding,
+    brush_size: header.brush_size,
+    unknown: header.unknown,
+    num_points: points.len() as i32,
+    points
+  };
+}
+
+// The layers of a v3 or v5 page, read from just after its header
+fn read_layers(page: &mut Cursor<&[u8]>, version: u32, warn: &mut dyn FnMut(WarningCode, String)) -> Result<Vec<Layer>, String> {
+  let num_layers = page.read_le::<i32>().map_err(read_error(format::HEADER_SIZE as u64, || String::from("the number of layers")))?;
+  let mut layers = Vec::with_capacity(capacity(num_layers, format::LAYER_HEADER_SIZE, page));
+  for layer_index in 0..num_layers.max(0) as usize {
+    let offset = page.position();
+    let num_lines = page.read_le::<format::LayerHeader>()
+      .map_err(read_error(offset, || format!("the header of layer {}", layer_index + 1)))?
+      .num_lines;
+    let mut lines = Vec::with_capacity(capacity(num_lines, format::LineHeader::size(version), page));
+    for line_index in 0..num_lines.max(0) as usize {
+      let offset = page.position();
+      let header = page.read_le_args::<format::LineHeader>((version,))
+        .map_err(read_error(offset, || format!("the header of line {} in layer {}", line_index + 1, layer_index + 1)))?;
+      let mut points = Vec::with_capacity(capacity(header.num_points, format::POINT_SIZE, page));
+      for point_index in 0..header.num_points.max(0) as usize {
+        let what = || format!("point {} of line {} in layer {}", point_index + 1, line_index + 1, layer_index + 1);
+        let offset = page.position();
+        points.push(page.read_le::<Point>().map_err(read_error(offset, what))?);
+      }
+      lines.push(checked_line(&header, points, (layer_index, line_index), warn));
+    }
+    layers.push(Layer { num_lines, lines });
+  }
+
+  let rest = page.get_ref().len().saturating_sub(page.position() as usize);
+  if rest > 0 {
+    warn(WarningCode::TrailingBytes, format!("ignored {} bytes after the last layer", rest));
+  }
+  return Ok(layers);
+}
+
+// parse_rm, logging as it goes. The whole page is read in first.
+pub fn parse_reader<R: Read>(mut reader: R, logger: &dyn Fn(LogLevel, String)) -> Result<Document, String>{
+  let mut bytes = Vec::new();
+  reader.read_to_end(&mut bytes).map_err(|why| format!("couldn't read the page, {}", why))?;
+  return parse_slice(&bytes, logger);
+}
+
+// A page straight from its bytes, in one pass
+fn parse_slice(bytes: &[u8], logger: &dyn Fn(LogLevel, String)) -> Result<Document, String>{
+
+  logger(LogLevel::INFO, String::from("parsing file"));
+
+  let mut warnings = Vec::<Warning>::new();
+  let mut warn = |code: WarningCode, message: String| {
+    let warning = Warning::new(code, message);
+    logger(LogLevel::WARN, warning.to_string());
+    warnings.push(warning);
+  };
+  logger(LogLevel::DEBUG, format!("header is: {:?}", String::from_utf8_lossy(&bytes[..bytes.len().min(format::HEADER_SIZE)])));
+
+  let mut page = Cursor::new(bytes);
+  let version = match page.read_le::<format::Header>() {
+    Ok(header) if SUPPORTED_VERSIONS.contains(&header.version) => header.version,
+    // whatever the version, and however many digits it has, it's named
+    _ => return Err(match header_version(bytes) {
+      Some(version) if !SUPPORTED_VERSIONS.contains(&version) => format::unsupported_version(version),
+      _ => String::from("header does not match .rm v3, v5 or v6 file")
+    })
+  };
+  let (layers, text) = match version {
+    6 => {
+      let read = v6::read_page(bytes)?;
+      read.skipped.into_iter().for_each(|why| warn(WarningCode::SkippedBlock, why));
+      let mut layers = Vec::with_capacity(read.layers.len());
+      for (layer_index, layer) in read.layers.into_iter().enumerate() {
+        let lines = layer.into_iter().enumerate()
+          .map(|(line_index, (header, points))| checked_line(&header, points, (layer_index, line_index), &mut warn))
+          .collect::<Vec<_>>();
+        layers.push(Layer { num_lines: lines.len() as i32, lines });
+      }
+      (layers, read.text)
+    },
+    _ => (read_layers(&mut page, version, &mut warn)?, Vec::new())
+  };
+
+  // a dump of every point would cost more than parsing them did
+  layers.iter().enumerate().for_each(|(index, layer)| logger(LogLevel::TRACE, format!(
+    "layer {}: {} lines, {} points", index + 1, layer.lines.len(), layer.lines.iter().map(|line| line.points.len()).sum::<usize>()
+  )));
+
+  return Ok(Document { version, layers, text, warnings });
+}
+
+#[derive(Debug)]
+pub struct Output {
+  pub pages: Vec<OutputPage>
+}
+
+#[derive(Debug)]
+pub struct OutputPage {
+  // "page" for a single .rm file, "page-001" etc. for notebook pages
+  pub name: String,
+  // human readable title, when one was recognized
+  pub title: Option<String>,
+  pub svg: String,
+  pub warnings: Vec<Warning>
+}
+
+// Converts a single .rm page or a zipped notebook entirely in memory.
+pub fn convert_bytes(bytes: &[u8], options: &RenderOptions) -> Result<Output, String> {
+  let logger = |_: LogLevel, _: String| {};
+  if zip::is_zip(bytes) {
+    return notebook::read_zip(bytes, &logger)?.pages.iter()
+      .map(|page| {
+        let document = Document { version: page.version, layers: page.layers.clone(), text: page.text.clone(), warnings: page.warnings.clone() };
+        return render_svg(&document, options).map(|svg| OutputPage {
+          name: format!("page-{:03}", page.number),
+          title: None,
+          svg: svg.to_string(),
+          warnings: page.warnings.clone()
+        });
+      })
+      .collect::<Result<Vec<_>, _>>()
+      .map(|pages| Output { pages });
+  }
+  let document = parse_document(bytes, &logger)?;
+  return render_svg(&document, options).map(|svg| Output {
+    pages: vec![OutputPage {
+      name: String::from("page"),
+      title: None,
+      svg: svg.to_string(),
+      warnings: document.warnings
+    }]
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const GOOD: &[u8] = include_bytes!("../test_files/good.rm");
+
+  fn quiet(_: LogLevel, _: String) {}
+
+  // A v3 page of one layer, with a line of `points` points per entry,
+  // laid out by hand: v3 line headers have no `unknown`
+  fn v3_page(lines: &[(i32, i32, f32, usize)]) -> Vec<u8> {
+    let mut bytes = format!("{:<43}", "reMarkable .lines file, version=3").into_bytes();
+    bytes.extend(1i32.to_le_bytes());
+    bytes.extend((lines.len() as i32).to_le_bytes());
+    lines.iter().for_each(|(brush_type, brush_color, brush_size, points)| {
+      bytes.extend(brush_type.to_le_bytes());
+      bytes.extend(brush_color.to_le_bytes());
+      bytes.extend(0u32.to_le_bytes());
+      bytes.extend(brush_size.to_le_bytes());
+      bytes.extend((*points as i32).to_le_bytes());
+      (0..*points).for_each(|index| {
+        let index = index as f32;
+        [100.0 + index * 3.0, 200.0 + index, 0.5, 1.0, 2.5, 0.75].iter().for_each(|value: &f32| bytes.extend(value.to_le_bytes()));
+      });
+    });
+    return bytes;
+  }
+
+  fn round_trip(document: &Document) -> (Vec<u8>, Document) {
+    let mut bytes = Vec::new();
+    write_rm(document, &mut bytes).unwrap();
+    let read = parse_document(&bytes, &quiet).unwrap();
+    return (bytes, read);
+  }
+
+  #[test]
+  fn v5_round_trips() {
+    let document = parse_document(GOOD, &quiet).unwrap();
+    assert_eq!(document.version, 5);
+    let (bytes, read) = round_trip(&document);
+    assert_eq!(read.version, 5);
+    assert_eq!(read.layers, document.layers);
+    // nothing a v5 page holds is lost, so it's written back as it was
+    assert_eq!(bytes, GOOD);
+  }
+
+  #[test]
+  fn v3_round_trips_as_v5() {
+    let page = v3_page(&[(2, 0, 2.0, 5), (5, 3, 1.875, 2)]);
+    let document = parse_document(&page, &quiet).unwrap();
+    assert_eq!(document.version, 3);
+    assert!(document.warnings.is_empty());
+    assert_eq!(document.layers.len(), 1);
+    let lines = &document.layers[0].lines;
+    assert_eq!(lines.iter().map(|line| (line.brush_type, line.brush_color, line.points.len())).collect::<Vec<_>>(), vec![
+      (BrushType::Pen, BrushColor::Black, 5),
+      (BrushType::Highlighter, BrushColor::Yellow, 2)
+    ]);
+    assert_eq!(lines[0].points[4], Point { x: 112.0, y: 204.0, speed: 0.5, direction: 1.0, width: 2.5, pressure: 0.75 });
+    // v3 has no `unknown`, which reads as 0 and is written as such
+    assert!(lines.iter().all(|line| line.unknown == 0.0));
+    let (bytes, read) = round_trip(&document);
+    assert_eq!(header_version(&bytes), Some(format::WRITTEN_VERSION));
+    assert_eq!(bytes.len(), page.len() + 4 * lines.len());
+    assert_eq!(read.version, 5);
+    assert_eq!(read.layers, document.layers);
+  }
+
+  // Hands over a few bytes at a time, as a socket or pipe might
+  struct Trickle<'a>(&'a [u8]);
+
+  impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      let count = buf.len().min(self.0.len()).min(7);
+      buf[..count].copy_from_slice(&self.0[..count]);
+      self.0 = &self.0[count..];
+      return Ok(count);
+    }
+  }
+
+  #[test]
+  fn parses_from_any_reader() {
+    let layers = parse_bytes(GOOD, &quiet).unwrap();
+    assert_eq!(layers.len(), 1);
+    assert_eq!(layers[0].lines.len(), 8);
+    assert_eq!(layers[0].lines[0].points.len(), 64);
+    assert_eq!(parse_file(Cursor::new(GOOD), &quiet).unwrap(), layers);
+    assert_eq!(parse_file(Trickle(GOOD), &quiet).unwrap(), layers);
+    assert_eq!(parse_rm(Trickle(GOOD)).unwrap().layers, layers);
+  }
+
+  #[test]
+  fn says_where_a_page_ends_early() {
+    let truncated = [
+      (45, "at offset 43 while reading the number of layers"),
+      (49, "at offset 47 while reading the header of layer 1"),
+      (60, "at offset 51 while reading the header of line 1 in layer 1"),
+      (104, "at offset 99 while reading point 2 of line 1 in layer 1")
+    ];
+    truncated.iter().for_each(|(length, at)| {
+      let expected = format!("page ends early, {}", at);
+      assert_eq!(parse_rm(Trickle(&GOOD[..*length])).unwrap_err(), expected);
+      assert_eq!(parse_bytes(&GOOD[..*length], &quiet).unwrap_err(), expected);
+    });
+  }
+
+  #[test]
+  fn names_versions_it_cant_read() {
+    let bad_header = include_bytes!("../test_files/bad_header.rm");
+    let unsupported = String::from("detected unsupported version 4, only versions 3, 5, 6 are supported");
+    assert_eq!(parse_document(bad_header, &quiet).unwrap_err(), unsupported);
+    assert_eq!(parse_rm(&bad_header[..]).unwrap_err(), unsupported);
+    assert_eq!(convert_bytes(bad_header, &RenderOptions::default()).unwrap_err(), unsupported);
+    let mut page = GOOD.to_vec();
+    page[32..34].copy_from_slice(b"12");
+    assert_eq!(parse_document(&page, &quiet).unwrap_err(), format::unsupported_version(12));
+    assert_eq!(parse_document(b"not a page", &quiet).unwrap_err(), "header does not match .rm v3, v5 or v6 file");
+  }
+
+  #[test]
+  fn converts_pages_in_memory() {
+    let document = parse_document(GOOD, &quiet).unwrap();
+    assert!(document.warnings.is_empty());
+    assert!(document.text.is_empty());
+    let output = convert_bytes(GOOD, &RenderOptions::default()).unwrap();
+    assert_eq!(output.pages.len(), 1);
+    assert_eq!(output.pages[0].name, "page");
+    assert!(output.pages[0].warnings.is_empty());
+    assert_eq!(output.pages[0].svg, render_svg(&document, &RenderOptions::default()).unwrap().to_string());
+    assert!(output.pages[0].svg.contains("<path"));
+  }
+
+  #[test]
+  fn converts_notebooks_in_memory() {
+    let output = convert_bytes(include_bytes!("../test_files/notebook.zip"), &RenderOptions::default()).unwrap();
+    // p2 was never drawn on, so has no .rm file to convert
+    assert_eq!(output.pages.iter().map(|page| page.name.as_str()).collect::<Vec<_>>(), vec!["page-001", "page-003"]);
+    let page = convert_bytes(GOOD, &RenderOptions::default()).unwrap().pages.remove(0);
+    assert!(output.pages.iter().all(|notebook_page| notebook_page.svg == page.svg && notebook_page.warnings.is_empty()));
+  }
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use relineate::json;
+use relineate::notebook::{hidden_layers, layer_names};
+use relineate::options::RenderOptions;
+use relineate::xochitl::SshTree;
+use relineate::{parse_document, render_svg, LogLevel};
+
+use crate::cache::RenderCache;
+
+// Shows the latest version of the page and swaps in the next as soon as
+// it's there, without the flicker of reloading the whole page
+const VIEWER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>relineate live</title>
+<style>html, body { margin: 0; height: 100%; background: #fff; } img { display: block; width: 100%; height: 100%; object-fit: contain; }</style>
+</head>
+<body>
+<img id="page" src="page.svg" alt="">
+<script>
+const page = document.getElementById("page");
+setInterval(() => {
+  const next = new Image();
+  next.onload = () => { page.src = next.src; };
+  next.src = "page.svg?" + Date.now();
+}, INTERVAL);
+</script>
+</body>
+</html>
+"#;
+
+fn respond(mut stream: TcpStream, latest: &Mutex<String>, interval: Duration) -> std::io::Result<()> {
+  let mut request = String::new();
+  BufReader::new(&stream).read_line(&mut request)?;
+  let path = request.split(' ').nth(1).unwrap_or("/");
+  let (status, content_type, body) = match path.split('?').next().unwrap_or_default() {
+    "/" => ("200 OK", "text/html", VIEWER.replace("INTERVAL", &interval.as_millis().to_string())),
+    "/page.svg" => ("200 OK", "image/svg+xml", latest.lock().map(|svg| svg.clone()).unwrap_or_default()),
+    _ => ("404 Not Found", "text/plain", String::from("not found"))
+  };
+  return write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+    status, content_type, body.len(), body
+  );
+}
+
+// Serves a page showing the SVG in `latest` as it changes, in the
+// background
+fn serve(address: &str, latest: Arc<Mutex<String>>, interval: Duration) -> Result<(), String> {
+  let listener = TcpListener::bind(address).map_err(|why| format!("couldn't listen on {}, {}", address, why))?;
+  thread::spawn(move || {
+    for stream in listener.incoming().filter_map(|stream| stream.ok()) {
+      let latest = latest.clone();
+      thread::spawn(move || respond(stream, &latest, interval));
+    }
+  });
+  return Ok(());
+}
+
+// Renders the page being drawn on a tablet every time it changes, to
+// `output` and, with `address`, to a page served over HTTP that keeps
+// itself up to date
+pub fn live(
+  tablet: &SshTree,
+  output: &str,
+  address: Option<&str>,
+  interval: Duration,
+  render_options: &RenderOptions,
+  show_hidden_layers: bool,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<(), String> {
+  let latest = Arc::new(Mutex::new(String::new()));
+  if let Some(address) = address {
+    serve(address, latest.clone(), interval)?;
+    logger(LogLevel::INFO, format!("serving the page at http://{}/", address));
+  }
+  let mut shown = String::new();
+  let cache = RenderCache::default();
+  return tablet.follow_current_page(interval, &mut |path, bytes, metadata| {
+    if path != shown {
+      logger(LogLevel::INFO, format!("following {}", path));
+      shown = String::from(path);
+    }
+    let metadata = json::parse(&String::from_utf8_lossy(&metadata)).ok();
+    let page_options = render_options.clone().with_layer_names(metadata.as_ref().map(layer_names).unwrap_or_default());
+    let page_options = match (show_hidden_layers, &metadata) {
+      (false, Some(metadata)) => page_options.without_hidden_layers(&hidden_layers(metadata)),
+      _ => page_options
+    };
+    let render_options = &page_options;
+    // xochitl saves pages it hasn't changed too, e.g. on closing them
+    let key = RenderCache::key(&bytes, render_options);
+    if cache.is_fresh(output, key) {
+      return;
+    }
+    // xochitl may be partway through saving it
+    let svg = match parse_document(&bytes, logger).and_then(|page| render_svg(&page, render_options)) {
+      Ok(svg) => svg.to_string(),
+      Err(why) => return logger(LogLevel::WARN, format!("couldn't render {} yet, {}", path, why))
+    };
+    let temporary = format!("{}.tmp", output);
+    let written = fs::write(&temporary, &svg).and_then(|_| fs::rename(&temporary, output));
+    match written {
+      Ok(()) => cache.record(output, key),
+      Err(why) => logger(LogLevel::WARN, format!("couldn't write {}, {}", output, why))
+    }
+    if let Ok(mut latest) = latest.lock() {
+      *latest = svg;
+    }
+  });
+}
@@ -1,276 +1,2332 @@
-use clap::{Arg, App};
+#![allow(clippy::needless_return)]
 
-use std::path::Path;
-use std::fs::File;
+mod browse;
+mod cache;
+mod events;
+mod failure;
+mod live;
+mod progress;
 
-use std::io::Read;
-use std::io::BufReader;
-use byteorder::{ReadBytesExt, LittleEndian};
+use clap::{Arg, App, AppSettings, ArgMatches};
+use notify::{recommended_watcher, Event, EventKind, RecursiveMode, Watcher};
+use rayon::prelude::*;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::cell::RefCell;
+use std::io::{Cursor, Read};
+use std::sync::Mutex;
+use std::sync::mpsc::channel;
+use std::time::{Duration, SystemTime};
+
+use cache::RenderCache;
+use events::{timed, LOG_FORMATS};
+use failure::{because, Failure, FailureKind};
+use progress::{Outcome, Progress};
+use relineate::{header_version, notebook, parse_document, render_svg, write_rm, Document, Layer, LogLevel, SUPPORTED_VERSIONS};
+use relineate::annotate::annotate_pdf;
+use relineate::annotations::{read_annotations, thumbnail, write_report, AnnotatedPage};
+use relineate::brush::{BrushColor, BrushType};
+use relineate::compare::compare_scenes;
+use relineate::device::{Device, DEVICES};
+use relineate::diff::{diff_notebooks, diff_pages, render_diff, render_overlay, LayerDiff, PageChange, NEW_OVERLAY_COLOR, OLD_OVERLAY_COLOR};
+use relineate::edit::{merge_pages, page_transform, split_layers, transform_page};
+use relineate::encode::{encode_svg, parse_brush_size, EncodeOptions};
+use relineate::fixture::generate_fixture;
+use relineate::format::WRITTEN_VERSION;
+use relineate::gcode::GcodeOptions;
+use relineate::json::Value;
+use relineate::notebook::{Notebook, NotebookInfo, NotebookPage, PageSelection};
+use relineate::options::{parse_color, parse_color_map, parse_color_map_toml, parse_layer_numbers, DARK_BACKGROUND, MAX_PRECISION};
+use relineate::options::{Length, RenderOptions, SpeedStyle, StrokeElement, Stylesheet, ViewBox};
+use relineate::orientation::{auto_rotate, LANDSCAPE_ROTATION};
+use relineate::html::{write_html, HtmlPage};
+use relineate::input::{read_file, InputBytes};
+use relineate::output::{encode, FileSink, MemorySink, OutputFormat, OutputSink, OUTPUT_FORMATS};
+use relineate::template::{infer_template, Template, TEMPLATE_NAMES};
+use relineate::pdf::write_pdf;
+use relineate::png::write_png;
+use relineate::raster::rasterize;
+use relineate::recognize::{page_title, recognize_lines, slugify, transcribe, CommandRecognizer, Ocr};
+use relineate::scene::{parse_scene, scene, Scene};
+use relineate::stats::{page_stats, sidecar_json, PageOrigin};
+use relineate::tile::{tile_pages, Tiling};
+use relineate::warning::{check_denied, parse_denied};
+use relineate::watermark::{Watermark, WatermarkPosition, WATERMARK_POSITIONS};
+use relineate::usb::{UsbTree, USB_URL};
+use relineate::xochitl::{document_landscape, document_modified, document_pages, document_templates, file_name, folder_names, list_documents, list_folders, page_layers};
+use relineate::xochitl::{DocumentEntry, DocumentSource, LocalTree, MemoryTree, SshTree, DEVICE_ROOT};
 
-use num_enum::TryFromPrimitive;
-use std::convert::TryFrom;
-
-use svg::Document as SVGDocument;
-use svg::node::element::Group as SVGGroup;
-use svg::node::element::Path as SVGPath;
-use svg::node::element::path::Data as SVGData;
-
-#[allow(dead_code)]
-#[derive(Debug, Copy, Clone)]
-enum LogLevel {
-  SILENT,
-  ERROR,
-  WARN,
-  INFO,
-  DEBUG,
-  TRACE
-}
-
-impl std::fmt::Display for LogLevel {
-  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-    write!(f, "{:?}", self)
-  }
-}
-
-const X_MAX: u32 = 1404;
-const Y_MAX: u32  = 1872;
-
-#[derive(Debug)]
-struct Point {
-  x: f32,
-  y: f32,
-  speed: f32,
-  direction: f32,
-  width: f32,
-  pressure: f32
-}
-
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
-#[repr(i32)]
-enum BrushType {
-  // supports pressure
-  Paintbrush = 0,
-  // supports pressure, tilt
-  PencilTilt = 1,
-  // supports pressure
-  Pen = 2,
-  // supports pressure, tilt
-  Marker = 3,
-  // no supported modifiers
-  Fineliner = 4,
-  // no supported modifiers, always black
-  Highlighter = 5,
-  // no supported modifiers
-  Eraser = 6,
-  // no supported modifiers
-  PencilSharp = 7,
-  // no supported modifiers
-  RubberArea = 8,
-  // no supported modifiers
-  EraseAll = 9,
-  // no supported modifiers
-  SelectionBrush1 = 10,
-  // no supported modifiers
-  SelectionBrush2 = 11,
-  Paintbrush2 = 12,
-  MechanicalPencil = 13,
-  Pencil2 = 14,
-  BallpointPen2 = 15,
-  Marker2 = 16,
-  Fineliner2 = 17,
-  Highlighter2 = 18,
-  Calligraphy = 21
-
-}
-
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
-#[repr(i32)]
-enum BrushColor {
-  Black = 0,
-  Grey = 1,
-  White = 2
-}
-
-#[derive(Debug)]
-struct Line {
-  brush_type: BrushType,
-  brush_color: BrushColor,
-  padding: u32,
-  unknown: f32,
-  brush_size: f32,
-  num_points: i32,
-  points: Vec<Point>
-}
-
-#[derive(Debug)]
-struct Layer {
-  num_lines: i32,
-  lines: Vec<Line>
-}
-
-fn main() -> Result<(), String> {
-  let opts = App::new("relineate")
+fn main() {
+  let app = App::new("relineate")
     .version("0.1.0")
     .author("Dan Shick <dan.shick@gmail.com>")
-    .about("Render .rm v5 files as SVGs")
-    .arg(Arg::new("input")
+    .about("Render .rm v3, v5 and v6 files as SVGs")
+    .setting(AppSettings::SubcommandsNegateReqs)
+    .args(convert_args().into_iter().map(|arg| arg.hidden(true)))
+    .arg(Arg::new("verbose")
+      .short('v')
+      .multiple_occurrences(true)
+      .global(true)
+      .about("Sets the level of verbosity"))
+    .arg(Arg::new("log-format")
+      .long("log-format")
+      .value_name("FORMAT")
+      .about("Writes logs as text on stdout, or as JSON events on stderr, one per line, including how long each page took to parse, render and write")
+      .possible_values(&LOG_FORMATS)
+      .global(true)
+      .takes_value(true))
+    .subcommand(App::new("convert")
+      .about("Converts an .rm page or a zipped notebook to SVG or another format; the default command")
+      .args(convert_args()))
+    .subcommand(App::new("info")
+      .about("Lists the version, layers, strokes, points, brushes, colors and extent of an .rm page or of each page of a zipped notebook")
+      .arg(Arg::new("input")
+        .value_name("INPUT")
+        .about("The .rm page or zipped notebook")
+        .required(true)
+        .index(1))
+      .arg(Arg::new("json")
+        .long("json")
+        .about("Prints the statistics as JSON")))
+    .subcommand(App::new("validate")
+      .about("Checks that an .rm page or a zipped notebook can be read, listing anything odd found in it")
+      .arg(Arg::new("input")
+        .value_name("INPUT")
+        .about("The .rm page or zipped notebook")
+        .required(true)
+        .index(1))
+      .arg(Arg::new("deny")
+        .long("deny")
+        .value_name("CODES")
+        .about("Fails for the given warning codes, e.g. W001,W003, or all")
+        .takes_value(true)))
+    .subcommand(App::new("transcribe")
+      .about("Writes the recognized text of every notebook page as one Markdown document")
+      .arg(Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("INPUT")
+        .about("Specifies a zipped notebook")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies a Markdown output file instead of stdout")
+        .takes_value(true))
+      .arg(Arg::new("cmd")
+        .long("cmd")
+        .value_name("COMMAND")
+        .about("Recognizes a page's strokes, given on stdin as with --title-cmd")
+        .required(true)
+        .takes_value(true)))
+    .subcommand(App::new("encode")
+      .about("Turns the paths and shapes of an SVG drawing into an .rm v5 page of editable strokes")
+      .arg(Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("INPUT")
+        .about("Specifies an SVG input file")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies an .rm output file")
+        .default_value("image.rm")
+        .takes_value(true))
+      .arg(Arg::new("brush")
+        .long("brush")
+        .value_name("BRUSH")
+        .about("Sets the brush the strokes are drawn with, e.g. fineliner or ballpoint-pen-2")
+        .default_value("fineliner")
+        .takes_value(true))
+      .arg(Arg::new("color")
+        .long("color")
+        .value_name("COLOR")
+        .about("Sets the strokes' color, e.g. black, grey, white, blue or red")
+        .default_value("black")
+        .takes_value(true))
+      .arg(Arg::new("size")
+        .long("size")
+        .value_name("SIZE")
+        .about("Sets the brush size: thin, medium, thick or a number")
+        .default_value("medium")
+        .takes_value(true)))
+    .subcommand(App::new("generate")
+      .about("Makes up an .rm v5 page from a description of its layers and strokes, e.g. as a test fixture")
+      .arg(Arg::new("spec")
+        .long("spec")
+        .value_name("SPEC")
+        .about("Specifies a TOML file of [[layers]] and their [[layers.strokes]]")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies an .rm output file")
+        .default_value("fixture.rm")
+        .takes_value(true)))
+    .subcommand(App::new("merge")
+      .about("Puts the strokes of several .rm pages together on one page, layer by layer")
+      .arg(Arg::new("inputs")
+        .value_name("INPUT")
+        .about("The .rm pages to merge, in order")
+        .required(true)
+        .multiple(true)
+        .index(1))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies an .rm output file")
+        .default_value("merged.rm")
+        .takes_value(true)))
+    .subcommand(App::new("split")
+      .about("Writes each layer of an .rm page to an .rm page of its own, e.g. OUTPUT-layer1.rm")
+      .arg(Arg::new("input")
+        .value_name("INPUT")
+        .about("The .rm page to split")
+        .required(true)
+        .index(1))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Names the output files, which get a -layerN suffix")
+        .default_value("page.rm")
+        .takes_value(true)))
+    .subcommand(App::new("transform")
+      .about("Scales, rotates and moves the strokes of an .rm page, writing an .rm page or any output format")
+      .arg(Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("INPUT")
+        .about("Specifies an .rm input file")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies an output file, an .rm page or e.g. an SVG rendered with the options given before transform")
+        .default_value("transformed.rm")
+        .takes_value(true))
+      .arg(Arg::new("scale")
+        .long("scale")
+        .value_name("FACTOR")
+        .about("Scales the strokes about the middle of the page")
+        .default_value("1")
+        .takes_value(true))
+      .arg(Arg::new("rotate")
+        .long("rotate")
+        .value_name("DEGREES")
+        .about("Turns the strokes clockwise about the middle of the page")
+        .default_value("0")
+        .takes_value(true))
+      .arg(Arg::new("translate")
+        .long("translate")
+        .value_name("X,Y")
+        .about("Moves the strokes by X,Y canvas pixels, after scaling and rotating")
+        .default_value("0,0")
+        .takes_value(true)))
+    .subcommand(App::new("browse")
+      .about("Opens a full-screen browser of the documents on a tablet or in a local xochitl tree, to preview and convert pages")
+      .arg(Arg::new("host")
+        .long("host")
+        .value_name("HOST")
+        .about("Reads from a tablet over SSH, e.g. root@10.11.99.1")
+        .required_unless_present("dir")
+        .conflicts_with("dir")
+        .takes_value(true))
+      .arg(Arg::new("dir")
+        .long("dir")
+        .value_name("DIR")
+        .about("Reads from a copy of the tablet's xochitl directory")
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("DIR")
+        .about("Where converted pages go, named after their document")
+        .default_value(".")
+        .takes_value(true)))
+    .subcommand(App::new("pull")
+      .about("Lists the notebooks on a tablet over SSH, or fetches the given ones and converts them as --recursive does")
+      .arg(Arg::new("host")
+        .long("host")
+        .value_name("HOST")
+        .about("The tablet, e.g. root@10.11.99.1 or a host from your ssh config")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("notebooks")
+        .value_name("NOTEBOOK")
+        .about("Notebooks to convert, by name, by path such as Work/Notes, or by UUID")
+        .multiple(true)
+        .index(1))
+      .args(convert_args().into_iter().filter(|arg| !REMOTE_IGNORED_ARGS.contains(&arg.get_name()))))
+    .subcommand(App::new("live")
+      .about("Renders the page being drawn on a tablet over SSH every time it changes, to live.svg or the -o file")
+      .arg(Arg::new("host")
+        .long("host")
+        .value_name("HOST")
+        .about("The tablet, e.g. root@10.11.99.1 or a host from your ssh config")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("serve")
+        .long("serve")
+        .value_name("ADDRESS")
+        .about("Also serves the page over HTTP at ADDRESS, e.g. 127.0.0.1:8080, refreshing itself as it changes")
+        .takes_value(true))
+      .arg(Arg::new("interval")
+        .long("interval")
+        .value_name("SECONDS")
+        .about("How often to check the tablet for changes")
+        .default_value("1")
+        .takes_value(true))
+      .args(convert_args().into_iter().filter(|arg| !REMOTE_IGNORED_ARGS.contains(&arg.get_name()))))
+    .subcommand(App::new("usb")
+      .about("Lists and converts the notebooks on a tablet plugged in over USB, through its web interface")
+      .setting(AppSettings::SubcommandRequiredElseHelp)
+      .arg(Arg::new("url")
+        .long("url")
+        .value_name("URL")
+        .about("Where the web interface is, if not at http://10.11.99.1")
+        .global(true)
+        .takes_value(true))
+      .subcommand(App::new("list")
+        .about("Lists the notebooks on the tablet by path and UUID"))
+      .subcommand(App::new("fetch")
+        .about("Downloads notebooks from the tablet and converts them as --recursive does")
+        .arg(Arg::new("notebooks")
+          .value_name("NOTEBOOK")
+          .about("Notebooks to convert, by name, by path such as Work/Notes, or by UUID")
+          .required(true)
+          .multiple(true)
+          .index(1))
+        .args(convert_args().into_iter().filter(|arg| !REMOTE_IGNORED_ARGS.contains(&arg.get_name())))))
+    .subcommand(App::new("compare")
+      .about("Checks whether an .rm page renders as it did before, against an earlier SVG or .rm file")
+      .arg(Arg::new("old")
+        .value_name("OLD")
+        .about("The earlier rendering, an SVG, or an .rm page rendered with the options given before compare")
+        .required(true)
+        .index(1))
+      .arg(Arg::new("new")
+        .value_name("NEW")
+        .about("The page to check, an .rm page or an SVG")
+        .required(true)
+        .index(2))
+      .arg(Arg::new("tolerance")
+        .long("tolerance")
+        .value_name("PIXELS")
+        .about("Sets how far, in canvas pixels, any shape may have moved")
+        .default_value("0.5")
+        .takes_value(true))
+      .arg(Arg::new("pixels")
+        .long("pixels")
+        .value_name("PERCENT")
+        .about("Also rasterizes both and compares their pixels, of which up to PERCENT may differ")
+        .takes_value(true)))
+    .subcommand(App::new("diff-notebook")
+      .about("Lists the pages added, removed and modified between two snapshots of a notebook")
+      .arg(Arg::new("old")
+        .value_name("OLD")
+        .about("The earlier snapshot, a zipped notebook or a directory it was unzipped to")
+        .required(true)
+        .index(1))
+      .arg(Arg::new("new")
+        .value_name("NEW")
+        .about("The later snapshot")
+        .required(true)
+        .index(2))
+      .arg(Arg::new("visual")
+        .long("visual")
+        .value_name("OUTPUT")
+        .about("Also writes an SVG of each modified page, with removed strokes in red and added ones in green")
+        .takes_value(true)))
+    .subcommand(App::new("diff")
+      .about("Counts the strokes added, removed and modified between two versions of a page, layer by layer")
+      .arg(Arg::new("old")
+        .value_name("OLD")
+        .about("The earlier version, an .rm page")
+        .required(true)
+        .index(1))
+      .arg(Arg::new("new")
+        .value_name("NEW")
+        .about("The later version")
+        .required(true)
+        .index(2))
+      .arg(Arg::new("json")
+        .long("json")
+        .about("Prints the counts as JSON")))
+    .subcommand(App::new("overlay")
+      .about("Draws two versions of a page over each other in different colors to show what changed")
+      .arg(Arg::new("old")
+        .value_name("OLD")
+        .about("The earlier version, an .rm page")
+        .required(true)
+        .index(1))
+      .arg(Arg::new("new")
+        .value_name("NEW")
+        .about("The later version")
+        .required(true)
+        .index(2))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies the output file, rendered with the options given before overlay")
+        .default_value("overlay.svg")
+        .takes_value(true))
+      .arg(Arg::new("old-color")
+        .long("old-color")
+        .value_name("COLOR")
+        .about("Sets the color of the earlier version [default: red]")
+        .takes_value(true))
+      .arg(Arg::new("new-color")
+        .long("new-color")
+        .value_name("COLOR")
+        .about("Sets the color of the later version [default: blue]")
+        .takes_value(true)))
+    .subcommand(App::new("annotate")
+      .about("Draws the ink of an annotated PDF over the original, writing a merged PDF")
+      .arg(Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("NOTEBOOK")
+        .about("Specifies the annotated document, a notebook directory or a zipped notebook")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("pdf")
+        .long("pdf")
+        .value_name("PDF")
+        .about("Specifies the PDF that was annotated, if it isn't the one in the notebook")
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies the merged PDF, with the ink drawn with the options given before annotate")
+        .default_value("annotated.pdf")
+        .takes_value(true)))
+    .subcommand(App::new("annotations")
+      .about("Reports the highlights and ink on an annotated EPUB or PDF, by chapter, with page thumbnails")
+      .arg(Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("NOTEBOOK")
+        .about("Specifies the annotated document, a notebook directory or a zipped notebook")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("OUTPUT")
+        .about("Specifies the Markdown report; thumbnails drawn with the options given before annotations are written beside it")
+        .default_value("annotations.md")
+        .takes_value(true)))
+    .subcommand(App::new("thumbs")
+      .about("Renders a small PNG of each page of a notebook, with the options given before thumbs")
+      .arg(Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("NOTEBOOK")
+        .about("Specifies the notebook, a notebook directory or a zipped notebook")
+        .required(true)
+        .takes_value(true))
+      .arg(Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("DIRECTORY")
+        .about("Specifies the directory the thumbnails are written to [default: thumbnails, or NOTEBOOK with --xochitl]")
+        .takes_value(true))
+      .arg(Arg::new("size")
+        .long("size")
+        .value_name("PIXELS")
+        .about("Sets how wide thumbnails are, their height following the page's")
+        .default_value("280")
+        .takes_value(true))
+      .arg(Arg::new("xochitl")
+        .long("xochitl")
+        .about("Writes thumbnails as the tablet keeps them, UUID.thumbnails/PAGE.png, with the page's template")));
+  #[cfg(feature = "cloud")]
+  let app = app.subcommand(cloud_app());
+  let matches = app.get_matches();
+
+  let json_log = matches.subcommand().and_then(|(_, sub)| sub.value_of("log-format")).or_else(|| matches.value_of("log-format")) == Some("json");
+  if let Err(failure) = run(&matches, json_log) {
+    match json_log {
+      true => events::error(&failure.message, failure.kind.exit_code()),
+      false => eprintln!("Error: {}", failure.message)
+    }
+    std::process::exit(failure.kind.exit_code());
+  }
+}
+
+fn run(matches: &ArgMatches, json_log: bool) -> Result<(), Failure> {
+  // options given after convert, or straight away without it
+  let convert_matches = matches.subcommand_matches("convert").unwrap_or(matches);
+  let opts = Settings::new(convert_matches);
+  // -v counts wherever it's given
+  let verbosity = matches.occurrences_of("verbose") + matches.subcommand().map_or(0, |(_, sub)| sub.occurrences_of("verbose"));
+  let logger = get_logger(verbosity, json_log);
+  logger(LogLevel::INFO, String::from("logger initialized"));
+
+  if let Some(transcribe_matches) = matches.subcommand_matches("transcribe") {
+    return transcribe_notebook(transcribe_matches, &logger).map_err(Failure::from);
+  }
+
+  if let Some(info_matches) = matches.subcommand_matches("info") {
+    return info(info_matches, &logger).map_err(Failure::from);
+  }
+  if let Some(validate_matches) = matches.subcommand_matches("validate") {
+    return validate(validate_matches, &logger).map_err(Failure::from);
+  }
+  if let Some(encode_matches) = matches.subcommand_matches("encode") {
+    return encode_drawing(encode_matches, &logger).map_err(Failure::from);
+  }
+
+  if let Some(generate_matches) = matches.subcommand_matches("generate") {
+    return generate(generate_matches, &logger).map_err(Failure::from);
+  }
+
+  if let Some(merge_matches) = matches.subcommand_matches("merge") {
+    return merge(merge_matches, &logger).map_err(Failure::from);
+  }
+
+  if let Some(split_matches) = matches.subcommand_matches("split") {
+    return split(split_matches, &logger).map_err(Failure::from);
+  }
+
+  if let Some(pull_matches) = matches.subcommand_matches("pull") {
+    let pull_opts = Settings::new(pull_matches);
+    let render_options = get_render_options(&pull_opts).map_err(because(FailureKind::BadArguments))?;
+    return pull(pull_matches, &pull_opts, &render_options, &logger);
+  }
+
+  if let Some(live_matches) = matches.subcommand_matches("live") {
+    let live_opts = Settings::new(live_matches);
+    let render_options = get_render_options(&live_opts).map_err(because(FailureKind::BadArguments))?;
+    let tablet = SshTree { host: String::from(live_matches.value_of("host").unwrap_or_default()), root: String::from(DEVICE_ROOT) };
+    let interval = live_matches.value_of("interval").unwrap_or("1").parse::<u64>()
+      .map_err(|_| Failure { kind: FailureKind::BadArguments, message: String::from("the interval must be a whole number of seconds") })?;
+    let output = live_opts.value_of("output").unwrap_or("live.svg");
+    let address = live_matches.value_of("serve");
+    let show_hidden_layers = live_opts.is_present("show-hidden-layers");
+    return live::live(&tablet, output, address, Duration::from_secs(interval), &render_options, show_hidden_layers, &logger).map_err(Failure::from);
+  }
+
+  if let Some(usb_matches) = matches.subcommand_matches("usb") {
+    return usb(usb_matches, &logger);
+  }
+
+  let render_options = get_render_options(&opts).map_err(because(FailureKind::BadArguments))?;
+
+  if let Some(transform_matches) = matches.subcommand_matches("transform") {
+    return transform(transform_matches, &render_options, &logger).map_err(Failure::from);
+  }
+  if let Some(browse_matches) = matches.subcommand_matches("browse") {
+    let source: Box<dyn DocumentSource> = match browse_matches.value_of("host") {
+      Some(host) => Box::new(SshTree { host: String::from(host), root: String::from(DEVICE_ROOT) }),
+      None => Box::new(LocalTree { root: browse_matches.value_of("dir").unwrap_or(".").into() })
+    };
+    let output = browse_matches.value_of("output").unwrap_or(".");
+    return browse::browse(source.as_ref(), output, &render_options, &logger).map_err(Failure::from);
+  }
+  #[cfg(feature = "cloud")]
+  if let Some(cloud_matches) = matches.subcommand_matches("cloud") {
+    return cloud(cloud_matches, &logger);
+  }
+  if let Some(compare_matches) = matches.subcommand_matches("compare") {
+    return compare(compare_matches, &render_options, &logger).map_err(Failure::from);
+  }
+  if let Some(diff_matches) = matches.subcommand_matches("diff-notebook") {
+    return diff_snapshots(diff_matches, &render_options, &logger).map_err(Failure::from);
+  }
+  if let Some(diff_matches) = matches.subcommand_matches("diff") {
+    return diff(diff_matches, &logger).map_err(Failure::from);
+  }
+  if let Some(overlay_matches) = matches.subcommand_matches("overlay") {
+    return overlay(overlay_matches, &render_options, &logger).map_err(Failure::from);
+  }
+  if let Some(annotate_matches) = matches.subcommand_matches("annotate") {
+    return annotate(annotate_matches, &render_options, &logger).map_err(Failure::from);
+  }
+  if let Some(annotations_matches) = matches.subcommand_matches("annotations") {
+    return annotations(annotations_matches, &render_options, &logger).map_err(Failure::from);
+  }
+  if let Some(thumbs_matches) = matches.subcommand_matches("thumbs") {
+    return thumbs(thumbs_matches, &render_options, &logger).map_err(Failure::from);
+  }
+  if opts.is_present("watch") {
+    return watch(&opts, &render_options, &logger);
+  }
+  return convert(&opts, &render_options, None, &logger);
+}
+
+// A page, a notebook or a whole tree converted as the options say
+fn convert(
+  opts: &Settings,
+  render_options: &RenderOptions,
+  cache: Option<&RenderCache>,
+  logger: &(dyn Fn(LogLevel, String) + Sync)
+) -> Result<(), Failure> {
+  let denied = opts.value_of("deny").map_or(Ok(Vec::new()), parse_denied).map_err(because(FailureKind::BadArguments))?;
+
+  let selection = opts.value_of("pages").map(PageSelection::parse).transpose().map_err(because(FailureKind::BadArguments))?;
+  // only pages with strokes are numbered, so the last is the last of those
+  let selected = |number: usize, last: usize| selection.as_ref().is_none_or(|selection| selection.contains(number, last));
+
+  if let Some(root) = opts.value_of("recursive") {
+    let format = output_format(opts, "").map_err(because(FailureKind::BadArguments))?;
+    let output = opts.value_of("output").unwrap_or(".");
+    let source = LocalTree { root: root.into() };
+    return convert_tree(&source, output, format, &selected, render_options, opts, cache, logger);
+  }
+
+  let input = opts.value_of("input").ok_or_else(|| Failure { kind: FailureKind::BadArguments, message: String::from("no input provided") })?;
+  let input_format = opts.value_of("input-format").unwrap_or("auto");
+  let default_output = format!("image.{}", output_format(opts, "").map_err(because(FailureKind::BadArguments))?.extension());
+  let output = opts.value_of("output").unwrap_or(&default_output);
+  let format = output_format(opts, output).map_err(because(FailureKind::BadArguments))?;
+  let tiling = opts.value_of("tile").map(Tiling::parse).transpose().map_err(because(FailureKind::BadArguments))?;
+  if tiling.is_some() && !matches!(format, OutputFormat::Svg | OutputFormat::Svgz | OutputFormat::Pdf | OutputFormat::Png { .. } | OutputFormat::Eps) {
+    return Err(Failure { kind: FailureKind::BadArguments, message: String::from("--tile writes svg, svgz, pdf, png or eps") });
+  }
+  // HTML and contact sheets put every page of a notebook in one file,
+  // written once they're all rendered
+  let collects_pages = format == OutputFormat::Html || tiling.is_some();
+  let html_pages = Mutex::new(Vec::<HtmlPage>::new());
+  let input_modified = Some(input).filter(|input| *input != "-").and_then(|input| modified_time(Path::new(input)));
+  let recognizer = opts.value_of("title-cmd").map(|command| CommandRecognizer { command: String::from(command) });
+  let convert_notebook_page = |page: &NotebookPage, notebook: &NotebookInfo| -> Result<(), Failure> {
+    let last_modified = notebook.last_modified;
+    let document = Document { version: page.version, layers: page.layers.clone(), text: page.text.clone(), warnings: page.warnings.clone() };
+    let document = match opts.is_present("no-auto-rotate") {
+      true => document,
+      false => auto_rotate(document, &format!("page {}", page.number), logger)
+    };
+    let title = match &recognizer {
+      Some(recognizer) => page_title(&document.layers, recognizer)?,
+      None => None
+    };
+    logger(LogLevel::INFO, format!("page {} title is {:?}", page.number, title));
+    let page_output = page_output_path(output, page.number, title.as_deref(), format.extension());
+    let page_options = match opts.value_of("template") {
+      Some("auto") => RenderOptions {
+        template: page.template.as_deref().and_then(Template::by_name),
+        ..render_options.clone()
+      },
+      Some("infer") => RenderOptions { template: inferred_template(&document.layers, logger), ..render_options.clone() },
+      _ => render_options.clone()
+    };
+    let page_options = unhidden(oriented(page_options, page.landscape, opts), &page.hidden_layers, opts)
+      .with_layer_names(page.layer_names.clone());
+    let modified = last_modified.filter(|_| !opts.is_present("no-metadata-mtime"));
+    check_denied(&document.warnings, &denied).map_err(|why| format!("page {}: {}", page.number, why))?;
+    if !collects_pages {
+      // the notebook's own time, which outputs are given, where it has one
+      let source_modified = last_modified.or(input_modified);
+      let origin = PageOrigin {
+        notebook_id: notebook.id.clone(),
+        notebook_name: notebook.name.clone(),
+        page_id: Some(page.id.clone()),
+        number: Some(page.number),
+        last_modified,
+        layer_names: page.layer_names.clone()
+      };
+      let destination = Destination { path: &page_output, format, modified, source_modified, origin: Some(&origin) };
+      return convert_page(&document, &destination, &page_options, opts, &mut FileSink, logger)
+        .map(|_| ())
+        .map_err(because(FailureKind::Write));
+    }
+    // the notebook's HTML or sheets are reported instead of its pages
+    if opts.is_present("dry-run") {
+      return Ok(());
+    }
+    let mut rendered = MemorySink::default();
+    let destination = Destination { path: &page_output, format: OutputFormat::Svg, modified: None, source_modified: None, origin: None };
+    convert_page(&document, &destination, &page_options, opts, &mut rendered, logger).map_err(because(FailureKind::Write))?;
+    let svgs = rendered.outputs.iter().map(|(_, svg)| String::from_utf8_lossy(svg).into_owned()).collect();
+    html_pages.lock().map_err(|_| String::from("couldn't collect the rendered pages"))?
+      .push(HtmlPage { number: page.number, title, svgs });
+    return Ok(());
+  };
+  let write_notebook_html = |name: Option<&str>, last_modified: Option<SystemTime>| {
+    if !collects_pages {
+      return Ok(());
+    }
+    if opts.is_present("dry-run") {
+      println!("would write {}", output);
+      return Ok(());
+    }
+    let mut pages = html_pages.lock().map_err(|_| String::from("couldn't collect the rendered pages"))?;
+    // in page order, however they finished rendering
+    pages.sort_by_key(|page| page.number);
+    if let Some(tiling) = tiling {
+      return write_sheets(output, &pages, tiling, format, &mut FileSink, logger).map_err(because(FailureKind::Write));
+    }
+    logger(LogLevel::INFO, format!("Writing {}", output));
+    let html = write_html(name.unwrap_or("Notebook"), &pages);
+    return FileSink.write_output(output, html.as_bytes())
+      .and_then(|_| match last_modified.filter(|_| !opts.is_present("no-metadata-mtime")) {
+        Some(modified) => FileSink.set_modified(output, modified),
+        None => Ok(())
+      })
+      .map_err(because(FailureKind::Write));
+  };
+
+  let bytes = match input {
+    "-" => {
+      logger(LogLevel::INFO, String::from("Input is stdin"));
+      let mut stdin = std::io::stdin().lock();
+      let mut bytes = Vec::new();
+      stdin.by_ref().take(4).read_to_end(&mut bytes)
+        .map_err(|why| Failure { kind: FailureKind::MissingInput, message: format!("couldn't read input, {}", why) })?;
+      let is_stream = input_format == "zip" || (input_format == "auto" && notebook::is_archive(&bytes));
+      // which page is last isn't known until the archive ends
+      if is_stream && !selection.as_ref().is_some_and(PageSelection::uses_last) {
+        // converted entry by entry as the archive arrives
+        let progress = Progress::new(None, opts.is_present("quiet") || opts.is_present("dry-run"), json_log(opts));
+        // what stopped the stream, when it was a page rather than the archive
+        let page_failure = RefCell::new(None);
+        let converted = notebook::stream_zip(Cursor::new(bytes).chain(stdin), logger, &mut |info, page| {
+          let label = format!("page {}", page.number);
+          if !selected(page.number, 0) {
+            progress.record(&label, Outcome::Skipped);
+            return Ok(());
+          }
+          let converted = convert_notebook_page(&page, info);
+          progress.record(&label, Outcome::of(&converted));
+          return converted.map_err(|failure| {
+            let message = failure.message.clone();
+            page_failure.replace(Some(failure));
+            return message;
+          });
+        })
+          .map_err(|why| page_failure.take().unwrap_or(Failure { kind: FailureKind::Parse, message: why }))
+          .and_then(|info| write_notebook_html(info.name.as_deref(), info.last_modified));
+        progress.finish();
+        return converted;
+      }
+      stdin.read_to_end(&mut bytes)
+        .map_err(|why| Failure { kind: FailureKind::MissingInput, message: format!("couldn't read input, {}", why) })?;
+      InputBytes::Read(bytes)
+    },
+    _ => read_input(input, logger).map_err(because(FailureKind::MissingInput))?
+  };
+  let is_archive = match input_format {
+    "zip" => true,
+    "rm" => false,
+    _ => notebook::is_archive(&bytes)
+  };
+  if is_archive {
+    let (notebook, duration) = timed(|| notebook::read_zip(&bytes, logger));
+    if json_log(opts) {
+      let warnings = notebook.iter().flat_map(|notebook| notebook.pages.iter()).flat_map(|page| page.warnings.clone()).collect::<Vec<_>>();
+      events::stage(input, "parse", duration, &warnings);
+    }
+    return notebook.map_err(because(FailureKind::Parse)).and_then(|notebook| {
+      let last = notebook.pages.iter().map(|page| page.number).max().unwrap_or(0);
+      let pages = notebook.pages.iter().filter(|page| selected(page.number, last)).collect::<Vec<_>>();
+      let info = NotebookInfo { id: Some(notebook.id.clone()), name: notebook.name.clone(), last_modified: notebook.last_modified };
+      let progress = Progress::new(Some(pages.len()), opts.is_present("quiet") || opts.is_present("dry-run"), json_log(opts));
+      progress.skip(notebook.pages.len() - pages.len());
+      let converted = pages.par_iter()
+        .try_for_each(|page| {
+          let converted = convert_notebook_page(page, &info);
+          progress.record(&format!("page {}", page.number), Outcome::of(&converted));
+          return converted;
+        })
+        .and_then(|_| write_notebook_html(notebook.name.as_deref(), notebook.last_modified));
+      progress.finish();
+      return converted;
+    });
+  }
+  let (document, duration) = timed(|| parse_document(&bytes, logger));
+  if let (Ok(document), true) = (&document, json_log(opts)) {
+    events::stage(input, "parse", duration, &document.warnings);
+  }
+  return document
+    .map_err(parse_failure(&bytes))
+    .and_then(|document| check_denied(&document.warnings, &denied).map(|_| document).map_err(Failure::from))
+    .and_then(|document| {
+      let document = match opts.is_present("no-auto-rotate") {
+        true => document,
+        false => auto_rotate(document, "page", logger)
+      };
+      let page_options = match opts.value_of("template") {
+        Some("infer") => RenderOptions { template: inferred_template(&document.layers, logger), ..render_options.clone() },
+        _ => render_options.clone()
+      };
+      if tiling.is_some() {
+        // a sheet of the one page
+        let mut rendered = MemorySink::default();
+        let destination = Destination { path: output, format: OutputFormat::Svg, modified: None, source_modified: None, origin: None };
+        if !opts.is_present("dry-run") {
+          convert_page(&document, &destination, &page_options, opts, &mut rendered, logger).map_err(because(FailureKind::Write))?;
+        }
+        let svgs = rendered.outputs.iter().map(|(_, svg)| String::from_utf8_lossy(svg).into_owned()).collect();
+        html_pages.lock().map_err(|_| String::from("couldn't collect the rendered page"))?
+          .push(HtmlPage { number: 1, title: None, svgs });
+        return write_notebook_html(None, None);
+      }
+      // a lone page, from no notebook
+      let destination = Destination { path: output, format, modified: None, source_modified: input_modified, origin: Some(&PageOrigin::default()) };
+      return convert_page(&document, &destination, &page_options, opts, &mut FileSink, logger)
+        .map(|_| ())
+        .map_err(because(FailureKind::Write));
+    });
+}
+
+// Rendered pages laid out on contact sheets: the one OUTPUT, or a PDF page
+// per sheet, or OUTPUT-sheet-001.svg and so on when they take several
+fn write_sheets(
+  output: &str,
+  pages: &[HtmlPage],
+  tiling: Tiling,
+  format: OutputFormat,
+  sink: &mut dyn OutputSink,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<(), String> {
+  let sheets = tile_pages(pages, tiling)?;
+  logger(LogLevel::INFO, format!("tiled {} pages onto {} sheets", pages.len(), sheets.len()));
+  if format == OutputFormat::Pdf {
+    logger(LogLevel::INFO, format!("Writing {}", output));
+    let scenes = sheets.iter().map(scene).collect::<Result<Vec<_>, String>>()?;
+    return sink.write_output(output, &write_pdf(&scenes));
+  }
+  // the sheets are drawn from their SVG alone
+  let no_page = Document { version: WRITTEN_VERSION, layers: Vec::new(), text: Vec::new(), warnings: Vec::new() };
+  return sheets.iter().enumerate().try_for_each(|(index, sheet)| {
+    let sheet_output = match sheets.len() {
+      1 => String::from(output),
+      _ => suffixed_output_path(output, &format!("sheet-{:03}", index + 1), format.extension())
+    };
+    logger(LogLevel::INFO, format!("Writing {}", sheet_output));
+    return sink.write_output(&sheet_output, &encode(&no_page, sheet, &RenderOptions::default(), format)?);
+  });
+}
+
+// A page that didn't parse, which is often one of a version this doesn't
+// read
+fn parse_failure(bytes: &[u8]) -> impl Fn(String) -> Failure + '_ {
+  return move |message| match header_version(bytes) {
+    Some(version) if !SUPPORTED_VERSIONS.contains(&version) => Failure { kind: FailureKind::UnsupportedVersion, message },
+    _ => Failure { kind: FailureKind::Parse, message }
+  };
+}
+
+// How long the input has to stay unchanged before it's converted again, as
+// files are usually written or synced in several goes
+const WATCH_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+// Converts the input, then again whenever it changes, until stopped
+fn watch(opts: &Settings, render_options: &RenderOptions, logger: &(dyn Fn(LogLevel, String) + Sync)) -> Result<(), Failure> {
+  let input = opts.value_of("recursive").or_else(|| opts.value_of("input")).unwrap_or_default();
+  if input == "-" {
+    return Err(Failure { kind: FailureKind::BadArguments, message: String::from("--watch needs an input file or directory, not stdin") });
+  }
+  let input = Path::new(input).canonicalize()
+    .map_err(|why| Failure { kind: FailureKind::MissingInput, message: format!("couldn't watch {}, {}", input, why) })?;
+  // a file is watched through its directory, as files synced or saved by
+  // editors are often replaced rather than written to
+  let (watched, mode) = match input.is_dir() {
+    true => (input.clone(), RecursiveMode::Recursive),
+    false => (input.parent().map(Path::to_path_buf).unwrap_or_default(), RecursiveMode::NonRecursive)
+  };
+  let output = opts.value_of("output").map(|output| {
+    return Path::new(output).canonicalize()
+      .or_else(|_| std::env::current_dir().map(|directory| directory.join(output)))
+      .unwrap_or_else(|_| PathBuf::from(output));
+  });
+  let (sender, events) = channel();
+  let mut watcher = recommended_watcher(sender).map_err(|why| format!("couldn't watch {}, {}", input.display(), why))?;
+  watcher.watch(&watched, mode).map_err(|why| format!("couldn't watch {}, {}", input.display(), why))?;
+
+  // changes to the input, not to what's written from it
+  let is_input_change = |event: &Event| {
+    return !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(|path| {
+      return path.starts_with(&input)
+        && !output.as_ref().is_some_and(|output| path.starts_with(output))
+        && !path.file_name().is_some_and(|name| name.to_string_lossy().ends_with(".tmp"));
+    });
+  };
+  // pages that haven't changed since the last time round are left be
+  let cache = RenderCache::default();
+  let mut converted = convert(opts, render_options, Some(&cache), logger);
+  loop {
+    if let Err(failure) = &converted {
+      logger(LogLevel::ERROR, failure.message.clone());
+    }
+    logger(LogLevel::INFO, format!("watching {} for changes", input.display()));
+    loop {
+      match events.recv() {
+        Ok(Ok(event)) if is_input_change(&event) => break,
+        Ok(Ok(_)) => continue,
+        Ok(Err(why)) => logger(LogLevel::WARN, format!("couldn't watch {}, {}", input.display(), why)),
+        Err(_) => return converted
+      }
+    }
+    // let the rest of a burst of changes arrive
+    while events.recv_timeout(WATCH_SETTLE_TIME).is_ok() {}
+    logger(LogLevel::INFO, String::from("input changed, converting again"));
+    converted = convert(opts, render_options, Some(&cache), logger);
+  }
+}
+
+// Lists the notebooks on a tablet, or fetches the given ones in two round
+// trips and converts them
+fn pull(
+  matches: &ArgMatches,
+  opts: &Settings,
+  render_options: &RenderOptions,
+  logger: &(dyn Fn(LogLevel, String) + Sync)
+) -> Result<(), Failure> {
+  let tablet = SshTree { host: String::from(matches.value_of("host").unwrap_or_default()), root: String::from(DEVICE_ROOT) };
+  let wanted = matches.values_of("notebooks").map_or_else(Vec::new, |values| values.collect::<Vec<_>>());
+  let fetch = |ids: &[String]| tablet.fetch_documents(ids);
+  return convert_remote(&tablet, &tablet.host, &fetch, &wanted, opts, render_options, logger);
+}
+
+// Lists the notebooks in a tree that's far away, or, given some by name,
+// path or UUID, fetches just those with `fetch` and converts them the way
+// --recursive converts a local tree
+fn convert_remote(
+  source: &dyn DocumentSource,
+  place: &str,
+  fetch: &dyn Fn(&[String]) -> Result<MemoryTree, String>,
+  wanted: &[&str],
+  opts: &Settings,
+  render_options: &RenderOptions,
+  logger: &(dyn Fn(LogLevel, String) + Sync)
+) -> Result<(), Failure> {
+  let listing = MemoryTree {
+    files: source.list_metadata().map_err(because(FailureKind::MissingInput))?.into_iter()
+      .map(|(id, metadata)| (format!("{}.metadata", id), metadata.into_bytes()))
+      .collect()
+  };
+  let folders = list_folders(&listing)?;
+  let documents = list_documents(&listing)?;
+  let path = |document: &DocumentEntry| {
+    let mut names = folder_names(document, &folders);
+    names.push(document.name.clone());
+    return names.join("/");
+  };
+  if wanted.is_empty() {
+    documents.iter().for_each(|document| println!("{}  {}", path(document), document.id));
+    return Ok(());
+  }
+
+  let mut ids = Vec::<String>::new();
+  for name in wanted {
+    let matching = documents.iter()
+      .filter(|document| document.id == *name || document.name == *name || path(document) == *name)
+      .collect::<Vec<_>>();
+    if matching.is_empty() {
+      return Err(Failure { kind: FailureKind::MissingInput, message: format!("no notebook {} on {}", name, place) });
+    }
+    for document in matching {
+      if !ids.contains(&document.id) {
+        ids.push(document.id.clone());
+      }
+    }
+  }
+  logger(LogLevel::INFO, format!("fetching {} notebooks from {}", ids.len(), place));
+  let fetched = fetch(&ids).map_err(because(FailureKind::MissingInput))?;
+  // the folders, for the directories pages go in, and just the notebooks
+  // asked for
+  let kept = |path: &str| {
+    let id = path.trim_end_matches(".metadata");
+    return ids.iter().any(|kept| kept == id) || folders.iter().any(|folder| folder.id == id);
+  };
+  let tree = MemoryTree {
+    files: listing.files.into_iter().filter(|(path, _)| kept(path)).chain(fetched.files).collect()
+  };
+
+  let selection = opts.value_of("pages").map(PageSelection::parse).transpose().map_err(because(FailureKind::BadArguments))?;
+  let selected = |number: usize, last: usize| selection.as_ref().is_none_or(|selection| selection.contains(number, last));
+  let format = output_format(opts, "").map_err(because(FailureKind::BadArguments))?;
+  let output = opts.value_of("output").unwrap_or(".");
+  return convert_tree(&tree, output, format, &selected, render_options, opts, None, logger);
+}
+
+// Lists the notebooks the tablet's web interface serves, or downloads the
+// given ones and converts them
+fn usb(matches: &ArgMatches, logger: &(dyn Fn(LogLevel, String) + Sync)) -> Result<(), Failure> {
+  let (command, command_matches) = matches.subcommand().ok_or_else(|| String::from("no usb command given"))?;
+  let url = command_matches.value_of("url").or_else(|| matches.value_of("url")).unwrap_or(USB_URL);
+  let tablet = UsbTree::connect(url).map_err(because(FailureKind::MissingInput))?;
+  let fetch = |ids: &[String]| tablet.fetch_documents(ids);
+  let opts = Settings::new(command_matches);
+  let render_options = match command {
+    "fetch" => get_render_options(&opts).map_err(because(FailureKind::BadArguments))?,
+    _ => RenderOptions::default()
+  };
+  let wanted = command_matches.values_of("notebooks").map_or_else(Vec::new, |values| values.collect::<Vec<_>>());
+  return convert_remote(&tablet, url, &fetch, &wanted, &opts, &render_options, logger);
+}
+
+#[cfg(feature = "cloud")]
+fn cloud_app() -> App<'static> {
+  return App::new("cloud")
+    .about("Lists and converts documents synced to the reMarkable cloud")
+    .setting(AppSettings::SubcommandRequiredElseHelp)
+    .arg(Arg::new("server")
+      .long("server")
+      .value_name("URL")
+      .about("Uses a self-hosted server such as rmfakecloud instead of reMarkable's")
+      .global(true)
+      .takes_value(true))
+    .subcommand(App::new("register")
+      .about("Connects this machine to an account, with a one-time code from my.remarkable.com/device/desktop/connect")
+      .arg(Arg::new("code")
+        .value_name("CODE")
+        .required(true)
+        .index(1)))
+    .subcommand(App::new("list")
+      .about("Lists the notebooks in the cloud by path and UUID"))
+    .subcommand(App::new("convert")
+      .about("Converts notebooks from the cloud as --recursive does")
+      .arg(Arg::new("notebooks")
+        .value_name("NOTEBOOK")
+        .about("Notebooks to convert, by name, by path such as Work/Notes, or by UUID")
+        .required(true)
+        .multiple(true)
+        .index(1))
+      .args(convert_args().into_iter().filter(|arg| !REMOTE_IGNORED_ARGS.contains(&arg.get_name()))));
+}
+
+// Where `cloud register` keeps the device token
+#[cfg(feature = "cloud")]
+fn cloud_token_path() -> PathBuf {
+  let config = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+    .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+    .unwrap_or_default();
+  return config.join("relineate").join("cloud-token");
+}
+
+#[cfg(feature = "cloud")]
+fn cloud(matches: &ArgMatches, logger: &(dyn Fn(LogLevel, String) + Sync)) -> Result<(), Failure> {
+  use relineate::cloud::{register_device, user_token, CloudTree, AUTH_URL, SYNC_URL};
+
+  let (command, command_matches) = matches.subcommand().ok_or_else(|| String::from("no cloud command given"))?;
+  let server = command_matches.value_of("server").or_else(|| matches.value_of("server")).map(|server| server.trim_end_matches('/'));
+  let (auth_url, sync_url) = server.map_or((AUTH_URL, SYNC_URL), |server| (server, server));
+  let token_path = cloud_token_path();
+  if command == "register" {
+    let token = register_device(auth_url, command_matches.value_of("code").unwrap_or_default())?;
+    if let Some(directory) = token_path.parent() {
+      std::fs::create_dir_all(directory).map_err(|why| format!("couldn't create {}, {}", directory.display(), why))?;
+    }
+    std::fs::write(&token_path, token).map_err(|why| format!("couldn't write {}, {}", token_path.display(), why))?;
+    println!("registered, with the device token in {}", token_path.display());
+    return Ok(());
+  }
+
+  let device_token = std::fs::read_to_string(&token_path).map_err(|_| Failure {
+    kind: FailureKind::BadArguments,
+    message: String::from("this machine isn't registered yet, run relineate cloud register CODE first")
+  })?;
+  let tree = CloudTree::connect(sync_url, &user_token(auth_url, &device_token)?).map_err(because(FailureKind::MissingInput))?;
+  let fetch = |ids: &[String]| tree.fetch_documents(ids);
+  let opts = Settings::new(command_matches);
+  let render_options = match command {
+    "convert" => get_render_options(&opts).map_err(because(FailureKind::BadArguments))?,
+    _ => RenderOptions::default()
+  };
+  let wanted = command_matches.values_of("notebooks").map_or_else(Vec::new, |values| values.collect::<Vec<_>>());
+  return convert_remote(&tree, "the cloud", &fetch, &wanted, &opts, &render_options, logger);
+}
+
+// Options of convert that pull, live, usb fetch and cloud convert have no
+// use for, as they read from far away
+const REMOTE_IGNORED_ARGS: [&str; 4] = ["input", "recursive", "watch", "input-format"];
+
+// Everything convert takes, which is also accepted without naming the
+// command
+fn convert_args() -> Vec<Arg<'static>> {
+  return vec![
+    Arg::new("input")
       .short('i')
       .long("input")
       .value_name("INPUT")
-      .about("Specifies an .rm v5 input file")
-      .required(true)
-      .takes_value(true))
-    .arg(Arg::new("output")
+      .about("Specifies an .rm input file or a zipped notebook, or - for stdin")
+      .required_unless_present("recursive")
+      .takes_value(true),
+    Arg::new("pages")
+      .long("pages")
+      .value_name("PAGES")
+      .about("Converts only the given notebook pages, e.g. 1,3,5-9 or last")
+      .takes_value(true),
+    Arg::new("quiet")
+      .short('q')
+      .long("quiet")
+      .about("Hides the progress bar and summary of notebook and directory conversions"),
+    Arg::new("update")
+      .short('u')
+      .long("update")
+      .about("Skips pages whose outputs were written since the page last changed"),
+    Arg::new("dry-run")
+      .long("dry-run")
+      .about("Lists the outputs that would be written, and which --update would skip, without writing anything"),
+    Arg::new("watch")
+      .long("watch")
+      .about("Keeps running, converting the input again whenever it changes"),
+    Arg::new("recursive")
+      .long("recursive")
+      .value_name("DIR")
+      .about("Converts every page of every notebook in a xochitl directory, in parallel, into OUTPUT/FOLDER/NOTEBOOK/page-001.svg and so on")
+      .conflicts_with("input")
+      .takes_value(true),
+    Arg::new("input-format")
+      .long("input-format")
+      .value_name("FORMAT")
+      .about("Reads the input as an .rm file or a zipped notebook instead of detecting which")
+      .possible_values(&["auto", "rm", "zip"])
+      .default_value("auto")
+      .takes_value(true),
+    Arg::new("output")
       .short('o')
       .long("output")
       .value_name("OUTPUT")
-      .about("Specifies an SVG output file")
+      .about("Specifies an output file")
       .required(false)
-      .takes_value(true))
-    .arg(Arg::new("verbose")
-      .short('v')
-      .multiple_occurrences(true)
-      .about("Sets the level of verbosity"))
-    .get_matches();
+      .takes_value(true),
+    Arg::new("format")
+      .long("format")
+      .value_name("FORMAT")
+      .about("Sets the output format, by default going by the output file's extension")
+      .possible_values(&OUTPUT_FORMATS)
+      .takes_value(true),
+    Arg::new("compress")
+      .long("compress")
+      .about("Gzips SVG output, as with a .svgz output file"),
+    Arg::new("dpi")
+      .long("dpi")
+      .value_name("DPI")
+      .about("Sets the resolution of PNG and APNG output [default: 96]")
+      .takes_value(true),
+    Arg::new("feed-rate")
+      .long("feed-rate")
+      .value_name("MM_PER_MIN")
+      .about("Sets the drawing speed of G-code output [default: 1000]")
+      .takes_value(true),
+    Arg::new("pen-up")
+      .long("pen-up")
+      .value_name("Z")
+      .about("Sets the Z height in mm of a lifted pen in G-code output [default: 5]")
+      .takes_value(true),
+    Arg::new("pen-down")
+      .long("pen-down")
+      .value_name("Z")
+      .about("Sets the Z height in mm of a drawing pen in G-code output [default: 0]")
+      .takes_value(true),
+    Arg::new("gcode-scale")
+      .long("gcode-scale")
+      .value_name("MM_PER_PX")
+      .about("Sets the mm per tablet pixel of G-code output [default: 0.1124, true to size]")
+      .takes_value(true),
+    Arg::new("width")
+      .long("width")
+      .value_name("WIDTH")
+      .about("Sets the document width, optionally with a unit (e.g. 210mm)")
+      .takes_value(true),
+    Arg::new("height")
+      .long("height")
+      .value_name("HEIGHT")
+      .about("Sets the document height, optionally with a unit (e.g. 297mm)")
+      .takes_value(true),
+    Arg::new("scale")
+      .long("scale")
+      .value_name("SCALE")
+      .about("Sets the output length per canvas pixel (e.g. 2 or 0.1124mm)")
+      .takes_value(true),
+    Arg::new("viewbox")
+      .long("viewbox")
+      .value_name("VIEWBOX")
+      .about("Sets the rendered canvas region as \"x y width height\"")
+      .takes_value(true),
+    Arg::new("crop-to-content")
+      .long("crop-to-content")
+      .value_name("MARGIN")
+      .about("Crops the viewBox to the drawn strokes, plus an optional margin (default 10)")
+      .takes_value(true)
+      .min_values(0)
+      .max_values(1),
+    Arg::new("background")
+      .long("background")
+      .value_name("COLOR")
+      .about("Sets the page background: white, transparent (default) or #RRGGBB")
+      .takes_value(true),
+    Arg::new("color-map")
+      .long("color-map")
+      .value_name("COLORS")
+      .about("Remaps stroke colors, e.g. black=#1a1a2e,grey=#888, or names a TOML palette file")
+      .takes_value(true),
+    Arg::new("layers")
+      .long("layers")
+      .value_name("LAYERS")
+      .about("Draws only the given layers, e.g. 1,3")
+      .takes_value(true),
+    Arg::new("exclude-layers")
+      .long("exclude-layers")
+      .value_name("LAYERS")
+      .about("Leaves out the given layers, e.g. 2")
+      .takes_value(true),
+    Arg::new("max-output-points")
+      .long("max-output-points")
+      .value_name("N")
+      .about("Simplifies strokes just enough to keep the page under N points")
+      .takes_value(true),
+    Arg::new("tolerance")
+      .long("tolerance")
+      .value_name("PIXELS")
+      .about("Simplifies strokes, letting them stray up to PIXELS canvas pixels from what was drawn")
+      .takes_value(true),
+    Arg::new("smoothing")
+      .long("smoothing")
+      .value_name("N")
+      .about("Smooths strokes, averaging each point with up to N points either side of it")
+      .takes_value(true),
+    Arg::new("snap-shapes")
+      .long("snap-shapes")
+      .about("Draws strokes that are nearly straight lines, rectangles, circles or ellipses as those shapes"),
+    Arg::new("ocr-cmd")
+      .long("ocr-cmd")
+      .value_name("COMMAND")
+      .about("Lays handwriting, as recognized by COMMAND, over the page as invisible, searchable text")
+      .takes_value(true),
+    Arg::new("ocr-sidecar")
+      .long("ocr-sidecar")
+      .requires("ocr-cmd")
+      .about("Writes the recognized handwriting to a .txt file beside each output instead"),
+    Arg::new("dark")
+      .long("dark")
+      .about("Renders light strokes on a dark background"),
+    Arg::new("optimize")
+      .long("optimize")
+      .about("Merges paths with identical styling and strips redundant markup"),
+    Arg::new("animate")
+      .long("animate")
+      .about("Animates the strokes drawing themselves in their original order"),
+    Arg::new("speed-style")
+      .long("speed-style")
+      .value_name("STYLE")
+      .about("Shows the pen's speed along each stroke as its color or opacity")
+      .possible_values(&["color", "opacity"])
+      .takes_value(true),
+    Arg::new("group-strokes")
+      .long("group-strokes")
+      .about("Groups each word or sketch into an element of its own so it can be moved as a whole")
+      .conflicts_with("group-by"),
+    Arg::new("group-by")
+      .long("group-by")
+      .value_name("GROUPING")
+      .about("Groups strokes by brush, in a group classed with its name, or by word like --group-strokes")
+      .possible_values(&["brush", "word"])
+      .takes_value(true),
+    Arg::new("watermark")
+      .long("watermark")
+      .value_name("TEXT")
+      .about("Marks every page with TEXT, or with the image in an .svg or .png file")
+      .takes_value(true),
+    Arg::new("watermark-opacity")
+      .long("watermark-opacity")
+      .value_name("OPACITY")
+      .about("Sets the watermark's opacity, from 0 to 1")
+      .default_value("0.2")
+      .takes_value(true),
+    Arg::new("watermark-position")
+      .long("watermark-position")
+      .value_name("POSITION")
+      .about("Places the watermark across the page or at an edge or corner")
+      .possible_values(&WATERMARK_POSITIONS)
+      .default_value("diagonal")
+      .takes_value(true),
+    Arg::new("watermark-above")
+      .long("watermark-above")
+      .about("Draws the watermark over the strokes instead of under them"),
+    Arg::new("no-auto-rotate")
+      .long("no-auto-rotate")
+      .about("Leaves pages that look like they were written upside down as they are"),
+    Arg::new("device")
+      .long("device")
+      .value_name("DEVICE")
+      .about("Sets the tablet pages were written on, and so the size of their canvas, rather than telling from their strokes")
+      .possible_values(&DEVICES)
+      .takes_value(true),
+    Arg::new("precision")
+      .long("precision")
+      .value_name("DIGITS")
+      .about("Rounds stroke coordinates to DIGITS decimal places, 2 by default, or keeps them as recorded with full")
+      .takes_value(true),
+    Arg::new("rotate")
+      .long("rotate")
+      .value_name("DEGREES")
+      .about("Turns pages clockwise, swapping their width and height for 90 and 270; landscape notebooks are turned 270 otherwise")
+      .possible_values(&["0", "90", "180", "270"])
+      .takes_value(true),
+    Arg::new("segment-widths")
+      .long("segment-widths")
+      .about("Splits strokes into short paths, each as wide as the tablet recorded there"),
+    Arg::new("outline")
+      .long("outline")
+      .about("Draws each stroke as a filled outline, as wide as the tablet recorded at every point")
+      .conflicts_with_all(&["segment-widths", "speed-style"]),
+    Arg::new("data-attributes")
+      .long("data-attributes")
+      .about("Labels every stroke with data-brush, data-color, data-size and data-layer attributes"),
+    Arg::new("css-classes")
+      .long("css-classes")
+      .about("Classes every stroke for its brush, color and layer, e.g. brush-fineliner color-black layer-1"),
+    Arg::new("stylesheet")
+      .long("stylesheet")
+      .value_name("CSS")
+      .about("Links the SVG to a stylesheet, classing strokes as --css-classes does so that its rules apply to them")
+      .takes_value(true),
+    Arg::new("embed-stylesheet")
+      .long("embed-stylesheet")
+      .about("Writes the --stylesheet into the SVG rather than linking to it")
+      .requires("stylesheet"),
+    Arg::new("include-selection")
+      .long("include-selection")
+      .about("Draws the strokes of selections, which the tablet doesn't show"),
+    Arg::new("include-eraser-strokes")
+      .long("include-eraser-strokes")
+      .about("Draws the strokes of erasers, which the tablet doesn't show"),
+    Arg::new("element")
+      .long("element")
+      .value_name("ELEMENT")
+      .about("Sets the SVG element strokes are drawn with")
+      .possible_values(&["path", "polyline"])
+      .default_value("path")
+      .takes_value(true),
+    Arg::new("template")
+      .long("template")
+      .value_name("TEMPLATE")
+      .about("Draws a built-in template (e.g. lines-small, grid-medium) behind the strokes, auto for each notebook page's own, or infer to guess it from the strokes")
+      .takes_value(true),
+    Arg::new("title-cmd")
+      .long("title-cmd")
+      .value_name("COMMAND")
+      .about("Names notebook pages after their first line of writing, as recognized by COMMAND")
+      .takes_value(true),
+    Arg::new("no-metadata-mtime")
+      .long("no-metadata-mtime")
+      .about("Keeps the conversion time as the outputs' modification time instead of the notebook's"),
+    Arg::new("show-hidden-layers")
+      .long("show-hidden-layers")
+      .about("Draws the layers of notebook pages that are hidden on the tablet too"),
+    Arg::new("sidecar")
+      .long("sidecar")
+      .about("Writes OUTPUT.meta.json beside each page with its notebook and page UUIDs, number, last change, layers and brushes"),
+    Arg::new("split-layers")
+      .long("split-layers")
+      .about("Writes each layer to its own SVG file, e.g. OUTPUT-layer1.svg"),
+    Arg::new("tile")
+      .long("tile")
+      .value_name("CxR")
+      .about("Lays the pages out C across and R down on labelled contact sheets, for printing an overview")
+      .conflicts_with("split-layers")
+      .takes_value(true),
+    Arg::new("preset")
+      .long("preset")
+      .value_name("PRESET")
+      .about("Applies a bundle of defaults for a use case; explicit options still win")
+      .possible_values(&["archive", "web", "print", "plotter"])
+      .takes_value(true),
+    Arg::new("deny")
+      .long("deny")
+      .value_name("CODES")
+      .about("Fails instead of warning for the given codes, e.g. W001,W003, or all")
+      .takes_value(true)
+  ];
+}
+
+fn transcribe_notebook(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let recognizer = CommandRecognizer { command: String::from(matches.value_of("cmd").unwrap_or_default()) };
+  let bytes = read_input(matches.value_of("input").unwrap_or_default(), logger)?;
+  if !notebook::is_archive(&bytes) {
+    return Err(String::from("transcribe needs a zipped notebook"));
+  }
+  let transcript = notebook::read_zip(&bytes, logger).and_then(|notebook| transcribe(&notebook, &recognizer))?;
+  return match matches.value_of("output") {
+    Some(output) => {
+      logger(LogLevel::INFO, format!("Writing {}", output));
+      FileSink.write_output(output, transcript.as_bytes())
+    },
+    None => {
+      print!("{}", transcript);
+      Ok(())
+    }
+  };
+}
 
-  let logger = get_logger(opts.occurrences_of("verbose"));
-  logger(LogLevel::INFO, format!("logger initialized"));
+// The pages of an .rm file or a zipped notebook, each with its label
+fn read_pages(input: &str, logger: &dyn Fn(LogLevel, String)) -> Result<Vec<(String, Document)>, String> {
+  let bytes = read_input(input, logger)?;
+  if !notebook::is_archive(&bytes) {
+    return parse_document(&bytes, logger).map(|page| vec![(String::from("page"), page)]);
+  }
+  return notebook::read_zip(&bytes, logger).map(|notebook| {
+    return notebook.pages.iter()
+      .map(|page| (format!("page {}", page.number), Document { version: page.version, layers: page.layers.clone(), text: page.text.clone(), warnings: page.warnings.clone() }))
+      .collect();
+  });
+}
 
-  return opts.value_of("input")
-    .map_or_else(|| Err(String::from("no input provided")), |i| get_input_file(i, &logger))
-    .and_then(|file| {
-      logger(LogLevel::INFO, format!("got file"));
-      return parse_file(file, &logger);
-    })
-    .and_then(|layers| {
-      return render_svg(layers);
-    })
-    .and_then(|svg| {
-      svg::save(
-        opts.value_of("output")
-          .map_or_else(|| "image.svg", |o| o),
-        &svg).unwrap();
-      return Ok(());
+fn info(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let pages = read_pages(matches.value_of("input").unwrap_or_default(), logger)?;
+  if matches.is_present("json") {
+    let pages = pages.iter()
+      .map(|(label, page)| {
+        let mut members = vec![(String::from("page"), Value::String(label.clone()))];
+        if let Value::Object(stats) = page_stats(page).to_json() {
+          members.extend(stats);
+        }
+        members.push((String::from("warnings"), Value::Array(page.warnings.iter()
+          .map(|warning| Value::Object(vec![
+            (String::from("code"), Value::String(String::from(warning.code.id()))),
+            (String::from("message"), Value::String(warning.message.clone()))
+          ]))
+          .collect())));
+        return Value::Object(members);
+      })
+      .collect();
+    println!("{}", Value::Object(vec![(String::from("pages"), Value::Array(pages))]));
+    return Ok(());
+  }
+  let counts = |counts: Vec<(&str, usize)>| {
+    return counts.iter().map(|(name, count)| format!("{} {}", name, count)).collect::<Vec<_>>().join(", ");
+  };
+  pages.iter().for_each(|(label, page)| {
+    let stats = page_stats(page);
+    println!(
+      "{}: .rm v{}, {} layers, {} strokes, {} points, {} warnings",
+      label, stats.version, stats.layers.len(), stats.strokes(), stats.points(), page.warnings.len()
+    );
+    if !stats.brushes.is_empty() {
+      println!("  brushes: {}", counts(stats.brushes.iter().map(|(brush, count)| (brush.name(), *count)).collect()));
+      println!("  colors: {}", counts(stats.colors.iter().map(|(color, count)| (color.name(), *count)).collect()));
+    }
+    if let Some(bounds) = stats.bounds {
+      println!(
+        "  bounds: {:.0},{:.0} to {:.0},{:.0} ({:.0} by {:.0})",
+        bounds.x, bounds.y, bounds.x + bounds.width, bounds.y + bounds.height, bounds.width, bounds.height
+      );
+    }
+    println!("  complexity: {} segments", stats.segments);
+    stats.layers.iter().enumerate().for_each(|(index, layer)| {
+      println!("  layer {}: {} strokes, {} points", index + 1, layer.strokes, layer.points);
     });
+  });
+  return Ok(());
+}
 
+fn validate(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let denied = matches.value_of("deny").map_or(Ok(Vec::new()), parse_denied)?;
+  let pages = read_pages(matches.value_of("input").unwrap_or_default(), logger)?;
+  pages.iter().for_each(|(label, page)| page.warnings.iter().for_each(|warning| println!("{}: {}", label, warning)));
+  pages.iter().try_for_each(|(label, page)| check_denied(&page.warnings, &denied).map_err(|why| format!("{}: {}", label, why)))?;
+  let count = pages.iter().map(|(_, page)| page.warnings.len()).sum::<usize>();
+  println!("valid, {} pages with {} warnings", pages.len(), count);
+  return Ok(());
 }
 
-fn get_input_file(file: &str, logger: &dyn Fn(LogLevel, String)) -> Result<File, String> {
-  logger(LogLevel::INFO, format!("Input is {}", file));
-  let file_path = Path::new(file);
-  if !file_path.is_file() {
-    return Err(String::from("input file does not exist"));
+fn encode_drawing(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let brush = matches.value_of("brush").unwrap_or_default();
+  let options = EncodeOptions {
+    brush_type: BrushType::by_name(brush).ok_or_else(|| format!("unknown brush {}", brush))?,
+    brush_color: match matches.value_of("color") {
+      Some(color) => BrushColor::by_name(color).ok_or_else(|| format!("unknown color {}, expected {}", color, BrushColor::names()))?,
+      None => BrushColor::Black
+    },
+    brush_size: parse_brush_size(matches.value_of("size").unwrap_or_default())?
+  };
+  let bytes = read_input(matches.value_of("input").unwrap_or_default(), logger)?;
+  let source = std::str::from_utf8(&bytes).map_err(|_| String::from("input is not UTF-8 text"))?;
+  let document = encode_svg(source, &options)?;
+  let count = document.layers.iter().map(|layer| layer.lines.len()).sum::<usize>();
+  logger(LogLevel::INFO, format!("encoded {} strokes", count));
+  return write_rm_file(matches.value_of("output").unwrap_or_default(), &document, logger);
+}
+
+fn generate(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let path = matches.value_of("spec").unwrap_or_default();
+  let spec = std::fs::read_to_string(path).map_err(|why| format!("couldn't read {}, {}", path, why))?;
+  let document = generate_fixture(&spec)?;
+  let count = document.layers.iter().map(|layer| layer.lines.len()).sum::<usize>();
+  logger(LogLevel::INFO, format!("generated {} strokes on {} layers", count, document.layers.len()));
+  return write_rm_file(matches.value_of("output").unwrap_or_default(), &document, logger);
+}
+
+fn merge(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let pages = matches.values_of("inputs").into_iter().flatten()
+    .map(|input| read_input(input, logger).and_then(|bytes| parse_document(&bytes, logger)))
+    .collect::<Result<Vec<_>, String>>()?;
+  let merged = merge_pages(&pages);
+  let count = merged.layers.iter().map(|layer| layer.lines.len()).sum::<usize>();
+  logger(LogLevel::INFO, format!("merged {} pages into {} strokes on {} layers", pages.len(), count, merged.layers.len()));
+  return write_rm_file(matches.value_of("output").unwrap_or_default(), &merged, logger);
+}
+
+fn split(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let page = read_input(matches.value_of("input").unwrap_or_default(), logger)
+    .and_then(|bytes| parse_document(&bytes, logger))?;
+  let output = matches.value_of("output").unwrap_or_default();
+  return split_layers(&page).iter().enumerate().try_for_each(|(index, layer_page)| {
+    return write_rm_file(&layer_output_path(output, index + 1, "rm"), layer_page, logger);
+  });
+}
+
+fn transform(matches: &ArgMatches, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let number = |name: &str| {
+    let value = matches.value_of(name).unwrap_or_default();
+    return value.trim().parse::<f32>().ok()
+      .filter(|number| number.is_finite())
+      .ok_or_else(|| format!("invalid {} {}", name, value));
+  };
+  let scale = number("scale").and_then(|scale| match scale > 0.0 {
+    true => Ok(scale),
+    false => Err(format!("invalid scale {}", scale))
+  })?;
+  let translate = matches.value_of("translate").unwrap_or_default();
+  let translation = translate.split_once(',')
+    .and_then(|(x, y)| Some((x.trim().parse::<f32>().ok()?, y.trim().parse::<f32>().ok()?)))
+    .filter(|(x, y)| x.is_finite() && y.is_finite())
+    .ok_or_else(|| format!("invalid translation {}, expected X,Y", translate))?;
+  let page = read_input(matches.value_of("input").unwrap_or_default(), logger)
+    .and_then(|bytes| parse_document(&bytes, logger))?;
+  let page = transform_page(&page, &page_transform(scale, number("rotate")?, translation));
+  let output = matches.value_of("output").unwrap_or_default();
+  if Path::new(output).extension().is_some_and(|extension| extension == "rm") {
+    return write_rm_file(output, &page, logger);
   }
-  match File::open(&file_path) {
-    Err(why) => Err(format!("couldn't open {}, {}", file_path.display(), why)),
-    Ok(file) => Ok(file),
+  let format = OutputFormat::from_path(output).unwrap_or(OutputFormat::Svg);
+  return render_svg(&page, render_options).and_then(|svg| save_page(&mut FileSink, output, &page, &svg, render_options, format, None, logger));
+}
+
+fn write_rm_file(output: &str, document: &Document, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let mut bytes = Vec::new();
+  write_rm(document, &mut bytes)?;
+  logger(LogLevel::INFO, format!("Writing {}", output));
+  return FileSink.write_output(output, &bytes);
+}
+
+fn read_snapshot(path: &str, logger: &dyn Fn(LogLevel, String)) -> Result<Notebook, String> {
+  if Path::new(path).is_dir() {
+    return notebook::read_dir(Path::new(path), logger);
   }
+  return read_input(path, logger).and_then(|bytes| notebook::read_zip(&bytes, logger));
 }
 
-const HEADER: &str = "reMarkable .lines file, version=5          ";
+// A rendering to compare, read back from an SVG or rendered from a page
+fn comparison_scene(input: &str, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<Scene, String> {
+  let bytes = read_input(input, logger)?;
+  if input.to_lowercase().ends_with(".svg") {
+    let source = std::str::from_utf8(&bytes).map_err(|_| format!("{} is not UTF-8 text", input))?;
+    return parse_scene(source).map_err(|why| format!("couldn't read {}, {}", input, why));
+  }
+  let svg = parse_document(&bytes, logger).and_then(|page| render_svg(&page, render_options))?;
+  return parse_scene(&svg.to_string());
+}
 
-fn parse_file(file: File, logger: &dyn Fn(LogLevel, String)) -> Result<Vec<Layer>, String>{
+fn compare(matches: &ArgMatches, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let number = |name: &str| -> Result<Option<f32>, String> {
+    return matches.value_of(name)
+      .map(|value| value.trim().parse::<f32>().ok().filter(|number| number.is_finite() && *number >= 0.0).ok_or_else(|| format!("invalid {} {}", name, value)))
+      .transpose();
+  };
+  let tolerance = number("tolerance")?.unwrap_or_default();
+  let pixel_tolerance = number("pixels")?;
+  let old = comparison_scene(matches.value_of("old").unwrap_or_default(), render_options, logger)?;
+  let new = comparison_scene(matches.value_of("new").unwrap_or_default(), render_options, logger)?;
+  let comparison = compare_scenes(&old, &new, pixel_tolerance.map(|_| 1.0));
+  println!("shapes: {} before, {} now, {} painted differently", comparison.old_items, comparison.new_items, comparison.restyled);
+  println!("moved: up to {:.2} px, tolerance {} px", comparison.distance, tolerance);
+  if let (Some((changed, total)), Some(pixel_tolerance)) = (comparison.pixels, pixel_tolerance) {
+    println!("pixels: {} of {} differ ({:.3}%), tolerance {}%", changed, total, changed as f32 * 100.0 / total as f32, pixel_tolerance);
+  }
+  if !comparison.is_within(tolerance, pixel_tolerance.unwrap_or_default() / 100.0) {
+    return Err(String::from("the page renders differently than it did"));
+  }
+  println!("unchanged");
+  return Ok(());
+}
 
-  logger(LogLevel::INFO, format!("parsing file"));
+fn diff(matches: &ArgMatches, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let page = |name: &str| read_input(matches.value_of(name).unwrap_or_default(), logger).and_then(|bytes| parse_document(&bytes, logger));
+  let (old, new) = (page("old")?, page("new")?);
+  let layers = diff_pages(&old.layers, &new.layers);
+  let total = LayerDiff::total(&layers);
+  if matches.is_present("json") {
+    let mut members = match total.to_json() {
+      Value::Object(members) => members,
+      _ => Vec::new()
+    };
+    members.push((String::from("layers"), Value::Array(layers.iter().map(LayerDiff::to_json).collect())));
+    println!("{}", Value::Object(members));
+    return Ok(());
+  }
+  let summary = |diff: &LayerDiff| {
+    return format!("{} added, {} removed, {} modified, {} unchanged", diff.added, diff.removed, diff.modified, diff.unchanged);
+  };
+  println!("{}", summary(&total));
+  layers.iter().enumerate().for_each(|(index, layer)| println!("  layer {}: {}", index + 1, summary(layer)));
+  return Ok(());
+}
 
-  let mut buffer = Vec::new();
-  let reader = RefCell::new(BufReader::new(file));
-  reader.borrow_mut().by_ref().take(HEADER.len() as u64).read_to_end(&mut buffer).unwrap();
-  logger(LogLevel::DEBUG, format!("actual header is: {:?}", &*buffer));
-  logger(LogLevel::DEBUG, format!("expected header is: {:?}", HEADER.as_bytes()));
+fn overlay(matches: &ArgMatches, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let page = |name: &str| read_input(matches.value_of(name).unwrap_or_default(), logger).and_then(|bytes| parse_document(&bytes, logger));
+  let (old, new) = (page("old")?, page("new")?);
+  let color = |name: &str, default: &str| matches.value_of(name).map_or_else(|| Ok(String::from(default)), parse_color);
+  let (old_color, new_color) = (color("old-color", OLD_OVERLAY_COLOR)?, color("new-color", NEW_OVERLAY_COLOR)?);
+  let output = matches.value_of("output").unwrap_or_default();
+  let format = OutputFormat::from_path(output).unwrap_or(OutputFormat::Svg);
+  return render_overlay(&old.layers, &new.layers, &old_color, &new_color, render_options)
+    .and_then(|svg| save_page(&mut FileSink, output, &new, &svg, render_options, format, None, logger));
+}
 
-  if &*buffer != HEADER.as_bytes() {
-    return Err(format!("header does not match .rm v5 file"));
+// A notebook's ink drawn over the PDF it annotates: the one given with
+// --pdf, or else the one it was exported with
+fn annotate(matches: &ArgMatches, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let input = matches.value_of("input").unwrap_or_default();
+  let (notebook, bundled) = match Path::new(input).is_dir() {
+    true => {
+      let notebook = notebook::read_dir(Path::new(input), logger)?;
+      let pdf = Path::new(input).join(format!("{}.pdf", notebook.id));
+      let bundled = match pdf.is_file() {
+        true => Some(std::fs::read(&pdf).map_err(|why| format!("couldn't read {}, {}", pdf.display(), why))?),
+        false => None
+      };
+      (notebook, bundled)
+    },
+    false => {
+      let bytes = read_input(input, logger)?;
+      if !notebook::is_archive(&bytes) {
+        return Err(String::from("annotate needs a notebook directory or a zipped notebook"));
+      }
+      (notebook::read_zip(&bytes, logger)?, notebook::zip_pdf(&bytes)?)
+    }
+  };
+  let source = match matches.value_of("pdf") {
+    Some(pdf) => read_input(pdf, logger)?.to_vec(),
+    None => bundled.ok_or_else(|| format!("{} has no PDF, give the one it annotates with --pdf", input))?
+  };
+  // just the ink, on the whole canvas, for the PDF page to show through
+  let ink_options = RenderOptions {
+    background: None,
+    template: None,
+    crop_to_content: None,
+    rotation: 0,
+    ..render_options.clone()
+  };
+  let mut ink = vec![None; notebook.pages.iter().map(|page| page.number).max().unwrap_or(0)];
+  for page in &notebook.pages {
+    let document = Document { version: page.version, layers: page.layers.clone(), text: page.text.clone(), warnings: Vec::new() };
+    let svg = render_svg(&document, &ink_options).map_err(|why| format!("page {}: {}", page.number, why))?;
+    ink[page.number - 1] = Some(scene(&svg)?);
   }
+  let annotated = annotate_pdf(&source, &ink)?;
+  logger(LogLevel::INFO, format!("annotated {} pages", notebook.pages.len()));
+  let output = matches.value_of("output").unwrap_or_default();
+  logger(LogLevel::INFO, format!("Writing {}", output));
+  return FileSink.write_output(output, &annotated);
+}
 
-  let num_layers: i32 = reader.borrow_mut().read_i32::<LittleEndian>().unwrap();
+// How big page thumbnails in annotation reports are, against the page
+const THUMBNAIL_SCALE: f32 = 0.25;
 
-  let layers = std::iter::repeat_with(||{
-    Layer {
-      num_lines: reader.borrow_mut().read_i32::<LittleEndian>().unwrap(),
-      lines: Vec::<Line>::new()
+// A report of what was highlighted and written on an annotated EPUB or
+// PDF, with a thumbnail of each page that has ink or highlights on it
+fn annotations(matches: &ArgMatches, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let input = matches.value_of("input").unwrap_or_default();
+  let entries = match Path::new(input).is_dir() {
+    true => notebook::dir_entries(Path::new(input), logger)?,
+    false => {
+      let bytes = read_input(input, logger)?;
+      if !notebook::is_archive(&bytes) {
+        return Err(String::from("annotations needs a notebook directory or a zipped notebook"));
+      }
+      notebook::zip_entries(&bytes)?
     }
-  })
-  .take(num_layers as usize)
-  .map(|layer| {
-      Layer {
-        num_lines: layer.num_lines,
-        lines: std::iter::repeat_with(||{
-          let mut b_reader = reader.borrow_mut();
-          return Line {
-            brush_type: BrushType::try_from(b_reader.read_i32::<LittleEndian>().unwrap()).unwrap(),
-            brush_color: BrushColor::try_from(b_reader.read_i32::<LittleEndian>().unwrap()).unwrap(),
-            padding: b_reader.read_u32::<LittleEndian>().unwrap(),
-            unknown: b_reader.read_f32::<LittleEndian>().unwrap(),
-            brush_size: b_reader.read_f32::<LittleEndian>().unwrap(),
-            num_points: b_reader.read_i32::<LittleEndian>().unwrap(),
-            points: Vec::<Point>::new()
-          };
-        })
-        .take(layer.num_lines as usize)
-        .map(|line| {
-          return Line {
-            points: std::iter::repeat_with(||{
-              let mut b_reader = reader.borrow_mut();
-              return Point{
-                x: b_reader.read_f32::<LittleEndian>().unwrap(),
-                y: b_reader.read_f32::<LittleEndian>().unwrap(),
-                speed: b_reader.read_f32::<LittleEndian>().unwrap(),
-                direction: b_reader.read_f32::<LittleEndian>().unwrap(),
-                width: b_reader.read_f32::<LittleEndian>().unwrap(),
-                pressure: b_reader.read_f32::<LittleEndian>().unwrap()
-              }
-            })
-            .take(line.num_points as usize)
-            .collect::<Vec<_>>(),
-            ..line
-          }
-        })
-        .collect::<Vec<_>>()
+  };
+  let annotations = read_annotations(&entries, logger)?;
+  let output = matches.value_of("output").unwrap_or_default();
+  let stem = Path::new(output).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+  let thumbnail_name = |page: &AnnotatedPage| format!("{}-page-{:03}.svg", stem, page.number);
+  let thumbnail_options = RenderOptions {
+    scale: Length::px(THUMBNAIL_SCALE),
+    background: render_options.background.clone().or_else(|| Some(String::from("white"))),
+    ..render_options.clone()
+  };
+  let mut thumbnails = HashSet::new();
+  for page in &annotations.pages {
+    // highlighted pages that were never drawn on have nothing to show
+    let drawn = match annotations.notebook.pages.iter().find(|drawn| drawn.number == page.number) {
+      Some(drawn) => drawn,
+      None => continue
+    };
+    let document = Document { version: drawn.version, layers: drawn.layers.clone(), text: drawn.text.clone(), warnings: Vec::new() };
+    let path = Path::new(output).with_file_name(thumbnail_name(page));
+    let svg = thumbnail(&document, &page.highlights, &thumbnail_options).map_err(|why| format!("page {}: {}", page.number, why))?;
+    logger(LogLevel::DEBUG, format!("Writing {}", path.display()));
+    FileSink.write_output(&path.to_string_lossy(), svg.to_string().as_bytes())?;
+    thumbnails.insert(page.number);
+  }
+  let report = write_report(&annotations, &|page| thumbnails.contains(&page.number).then(|| thumbnail_name(page)));
+  logger(LogLevel::INFO, format!("reported {} annotated pages", annotations.pages.len()));
+  logger(LogLevel::INFO, format!("Writing {}", output));
+  return FileSink.write_output(output, report.as_bytes());
+}
+
+// A PNG of each page drawn on, `--size` wide: page-NNN.png for galleries,
+// or with --xochitl the tablet's own thumbnails, to replace broken ones
+fn thumbs(matches: &ArgMatches, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let input = matches.value_of("input").unwrap_or_default();
+  let is_dir = Path::new(input).is_dir();
+  let notebook = match is_dir {
+    true => notebook::read_dir(Path::new(input), logger)?,
+    false => {
+      let bytes = read_input(input, logger)?;
+      if !notebook::is_archive(&bytes) {
+        return Err(String::from("thumbs needs a notebook directory or a zipped notebook"));
       }
-  })
-  .collect::<Vec<_>>();
-
-  logger(LogLevel::TRACE, format!("{:#?}", layers));
-
-  return Ok(layers);
-}
-
-fn render_svg(layers: Vec<Layer>) -> Result<SVGDocument, String>{
-  return Ok(layers.iter().fold(
-    SVGDocument::new()
-      .set("width", X_MAX)
-      .set("height", Y_MAX)
-      .set("viewBox", (0, 0, X_MAX, Y_MAX)),
-    |acc_svg, next_layer| {
-      return acc_svg.add(next_layer.lines.iter().fold(
-        SVGGroup::new(),
-        |acc_group, next_line| {
-          return acc_group.add(
-            SVGPath::new()
-              .set("fill", "none")
-              .set("stroke", "black")
-              .set("stroke-width", 3)
-              .set("stroke-linejoin", "round")
-              .set("stroke-linecap", "round")
-              .set("d", next_line.points.iter().enumerate().fold(
-                SVGData::new(),
-              |acc_data, (index, next_point)| {
-                if index == 0 { return acc_data.move_to((next_point.x, next_point.y)); }
-                return acc_data.line_to((next_point.x, next_point.y));
-              })
-            )
-          );
-        })
-      );
-    })
+      notebook::read_zip(&bytes, logger)?
+    }
+  };
+  let size = matches.value_of("size").unwrap_or_default().parse::<f32>().ok()
+    .filter(|size| *size >= 1.0)
+    .ok_or_else(|| String::from("--size must be a number of pixels"))?;
+  let xochitl = matches.is_present("xochitl");
+  let directory = match (matches.value_of("output"), xochitl) {
+    (Some(output), false) => PathBuf::from(output),
+    (Some(output), true) => Path::new(output).join(format!("{}.thumbnails", notebook.id)),
+    (None, false) => PathBuf::from("thumbnails"),
+    (None, true) if is_dir => Path::new(input).join(format!("{}.thumbnails", notebook.id)),
+    (None, true) => return Err(String::from("thumbs --xochitl of a zipped notebook needs --output"))
+  };
+  std::fs::create_dir_all(&directory).map_err(|why| format!("couldn't create {}, {}", directory.display(), why))?;
+  let thumbnail_options = RenderOptions {
+    background: render_options.background.clone().or_else(|| Some(String::from("white"))),
+    ..render_options.clone()
+  };
+  for page in &notebook.pages {
+    let page_options = match (xochitl, &thumbnail_options.template) {
+      (true, None) => RenderOptions { template: page.template.as_deref().and_then(Template::by_name), ..thumbnail_options.clone() },
+      _ => thumbnail_options.clone()
+    };
+    let document = Document { version: page.version, layers: page.layers.clone(), text: page.text.clone(), warnings: Vec::new() };
+    let svg = render_svg(&document, &page_options).map_err(|why| format!("page {}: {}", page.number, why))?;
+    let page_scene = scene(&svg)?;
+    let png = write_png(&rasterize(&page_scene, size / page_scene.width));
+    let name = match xochitl {
+      true => format!("{}.png", page.id),
+      false => format!("page-{:03}.png", page.number)
+    };
+    let path = directory.join(name);
+    logger(LogLevel::INFO, format!("Writing {}", path.display()));
+    FileSink.write_output(&path.to_string_lossy(), &png)?;
+  }
+  return Ok(());
+}
+
+fn diff_snapshots(matches: &ArgMatches, render_options: &RenderOptions, logger: &dyn Fn(LogLevel, String)) -> Result<(), String> {
+  let old = read_snapshot(matches.value_of("old").unwrap_or_default(), logger)?;
+  let new = read_snapshot(matches.value_of("new").unwrap_or_default(), logger)?;
+  let diffs = diff_notebooks(&old, &new);
+  diffs.iter().filter(|diff| diff.change != PageChange::Unchanged).for_each(|diff| {
+    let number = diff.new_number.or(diff.old_number).unwrap_or_default();
+    println!("{:<8} page {} ({})", diff.change, number, diff.id);
+  });
+  let count = |change: PageChange| diffs.iter().filter(|diff| diff.change == change).count();
+  println!(
+    "{} added, {} removed, {} modified, {} unchanged",
+    count(PageChange::Added), count(PageChange::Removed), count(PageChange::Modified), count(PageChange::Unchanged)
   );
+
+  let output = match matches.value_of("visual") {
+    Some(output) => output,
+    None => return Ok(())
+  };
+  return diffs.iter()
+    .filter(|diff| diff.change == PageChange::Modified)
+    .filter_map(|diff| {
+      let old_page = old.pages.iter().find(|page| page.id == diff.id)?;
+      let new_page = new.pages.iter().find(|page| page.id == diff.id)?;
+      return Some((old_page, new_page));
+    })
+    .try_for_each(|(old_page, new_page)| {
+      let page_output = page_output_path(output, new_page.number, None, "svg");
+      return render_diff(&old_page.layers, &new_page.layers, render_options)
+        .and_then(|svg| {
+          let format = OutputFormat::from_path(&page_output).unwrap_or(OutputFormat::Svg);
+          let page = Document { version: new_page.version, layers: new_page.layers.clone(), text: new_page.text.clone(), warnings: new_page.warnings.clone() };
+          return save_page(&mut FileSink, &page_output, &page, &svg, render_options, format, None, logger);
+        });
+    });
+}
+
+// A page of a xochitl tree, and where it's written to
+struct TreePage {
+  document: String,
+  name: String,
+  id: String,
+  number: usize,
+  template: Option<String>,
+  landscape: bool,
+  output: String,
+  // when the page's .rm file and its document's .metadata last changed
+  modified: Option<SystemTime>,
+  last_modified: Option<SystemTime>
+}
+
+// Every page of every document in the tree, under directories named after
+// the folders and documents they're in, and how many pages were left out
+// for having no strokes or not being selected
+fn tree_pages(
+  source: &dyn DocumentSource,
+  output: &str,
+  format: OutputFormat,
+  selected: &dyn Fn(usize, usize) -> bool,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<(Vec<TreePage>, usize), String> {
+  let folders = list_folders(source)?;
+  let mut directories = HashSet::new();
+  let mut pages = Vec::new();
+  let mut skipped = 0;
+  for document in list_documents(source)? {
+    let folder = folder_names(&document, &folders).iter()
+      .fold(PathBuf::from(output), |path, name| path.join(file_name(name)));
+    let mut directory = folder.join(file_name(&document.name));
+    // documents of the same name in the same folder each get their own
+    if !directories.insert(directory.clone()) {
+      directory = folder.join(format!("{} ({})", file_name(&document.name), document.id));
+      directories.insert(directory.clone());
+    }
+    let ids = match document_pages(source, &document.id) {
+      Ok(ids) => ids,
+      Err(why) => {
+        logger(LogLevel::WARN, format!("skipping {}, {}", document.name, why));
+        continue;
+      }
+    };
+    let templates = document_templates(source, &document.id);
+    let landscape = document_landscape(source, &document.id);
+    let last_modified = document_modified(source, &document.id);
+    let count = ids.len();
+    // pages that were never drawn on have nothing to convert
+    let drawn = ids.into_iter().enumerate()
+      .filter(|(_, id)| source.contains(&format!("{}/{}.rm", document.id, id)))
+      .collect::<Vec<_>>();
+    let last = drawn.last().map_or(0, |(index, _)| index + 1);
+    let drawn = drawn.into_iter().filter(|(index, _)| selected(index + 1, last)).collect::<Vec<_>>();
+    skipped += count - drawn.len();
+    pages.extend(drawn.into_iter().map(|(index, id)| TreePage {
+      document: document.id.clone(),
+      name: document.name.clone(),
+      number: index + 1,
+      template: templates.get(index).filter(|template| !template.is_empty()).cloned(),
+      landscape,
+      output: directory.join(format!("page-{:03}.{}", index + 1, format.extension())).to_string_lossy().into_owned(),
+      modified: source.modified(&format!("{}/{}.rm", document.id, id)),
+      last_modified,
+      id
+    }));
+  }
+  return Ok((pages, skipped));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn convert_tree(
+  source: &(dyn DocumentSource + Sync),
+  output: &str,
+  format: OutputFormat,
+  selected: &dyn Fn(usize, usize) -> bool,
+  render_options: &RenderOptions,
+  opts: &Settings,
+  cache: Option<&RenderCache>,
+  logger: &(dyn Fn(LogLevel, String) + Sync)
+) -> Result<(), Failure> {
+  let (pages, skipped) = tree_pages(source, output, format, selected, logger).map_err(because(FailureKind::MissingInput))?;
+  if !opts.is_present("dry-run") {
+    pages.iter()
+      .filter_map(|page| Path::new(&page.output).parent())
+      .collect::<HashSet<_>>()
+      .into_iter()
+      .try_for_each(|directory| {
+        return std::fs::create_dir_all(directory).map_err(|why| format!("couldn't create {}, {}", directory.display(), why));
+      })
+      .map_err(because(FailureKind::Write))?;
+  }
+  logger(LogLevel::INFO, format!("converting {} pages", pages.len()));
+  let denied = opts.value_of("deny").map_or(Ok(Vec::new()), parse_denied)?;
+  let convert_tree_page = |page: &TreePage, label: &str| -> Result<Outcome, String> {
+    let path = format!("{}/{}.rm", page.document, page.id);
+    let bytes = match source.read(&path) {
+      Ok(bytes) => bytes,
+      // in case the page was removed since it was listed
+      Err(why) => {
+        logger(LogLevel::DEBUG, why);
+        return Ok(Outcome::Skipped);
+      }
+    };
+    // the template and orientation the notebook names are the only
+    // options that differ between pages and aren't in their bytes
+    let key = cache.map(|_| RenderCache::key(&bytes, &(&page.template, page.landscape, render_options, format)));
+    if cache.zip(key).is_some_and(|(cache, key)| cache.is_fresh(&page.output, key)) {
+      logger(LogLevel::DEBUG, format!("{} hasn't changed, skipping it", path));
+      return Ok(Outcome::Skipped);
+    }
+    let (document, duration) = timed(|| parse_document(&bytes, logger));
+    let document = document?;
+    if json_log(opts) {
+      events::stage(&path, "parse", duration, &document.warnings);
+    }
+    let document = match opts.is_present("no-auto-rotate") {
+      true => document,
+      false => auto_rotate(document, label, logger)
+    };
+    let page_options = match opts.value_of("template") {
+      Some("auto") => RenderOptions {
+        template: page.template.as_deref().and_then(Template::by_name),
+        ..render_options.clone()
+      },
+      Some("infer") => RenderOptions { template: inferred_template(&document.layers, logger), ..render_options.clone() },
+      _ => render_options.clone()
+    };
+    let (layer_names, hidden_layers) = page_layers(source, &page.document, &page.id);
+    let page_options = unhidden(oriented(page_options, page.landscape, opts), &hidden_layers, opts)
+      .with_layer_names(layer_names.clone());
+    check_denied(&document.warnings, &denied)?;
+    let origin = opts.is_present("sidecar").then(|| PageOrigin {
+      notebook_id: Some(page.document.clone()),
+      notebook_name: Some(page.name.clone()),
+      page_id: Some(page.id.clone()),
+      number: Some(page.number),
+      last_modified: page.last_modified,
+      layer_names
+    });
+    // outputs are given the document's own time, as a single notebook's
+    // are, which --update then goes by too
+    let modified = page.last_modified.filter(|_| !opts.is_present("no-metadata-mtime"));
+    let destination = Destination { path: &page.output, format, modified, source_modified: modified.or(page.modified), origin: origin.as_ref() };
+    let outcome = convert_page(&document, &destination, &page_options, opts, &mut FileSink, logger)?;
+    if let Some((cache, key)) = cache.zip(key).filter(|_| outcome == Outcome::Succeeded && !opts.is_present("dry-run")) {
+      cache.record(&page.output, key);
+    }
+    return Ok(outcome);
+  };
+  let progress = Progress::new(Some(pages.len()), opts.is_present("quiet") || opts.is_present("dry-run"), json_log(opts));
+  progress.skip(skipped);
+  let failures = pages.par_iter()
+    .map(|page| {
+      let label = format!("{} page {}", page.name, page.number);
+      let converted = convert_tree_page(page, &label);
+      if let (Err(why), true) = (&converted, json_log(opts)) {
+        events::failed(&page.output, why);
+      }
+      let converted = converted.map_err(|why| format!("{}: {}", label, why));
+      progress.record(&label, *converted.as_ref().unwrap_or(&Outcome::Failed));
+      return converted;
+    })
+    .filter_map(Result::err)
+    .collect::<Vec<String>>();
+  progress.finish();
+  failures.iter().for_each(|why| logger(LogLevel::ERROR, why.clone()));
+  return match failures.len() {
+    0 => Ok(()),
+    count => Err(Failure { kind: FailureKind::PartialBatch, message: format!("{} of {} pages failed", count, pages.len()) })
+  };
+}
+
+// Where a rendered page goes, and as what
+struct Destination<'a> {
+  path: &'a str,
+  format: OutputFormat,
+  // set on the output once written
+  modified: Option<SystemTime>,
+  // when the page was last changed, for --update
+  source_modified: Option<SystemTime>,
+  // what --sidecar says the page is
+  origin: Option<&'a PageOrigin>
 }
 
-fn get_logger(verbosity: u64) -> impl Fn(LogLevel, String) -> () {
+// Whether an output was written after its page last changed
+fn is_up_to_date(output: &str, source_modified: Option<SystemTime>) -> bool {
+  return source_modified.is_some_and(|source_modified| {
+    return modified_time(Path::new(output)).is_some_and(|modified| modified >= source_modified);
+  });
+}
+
+// A page converted to its output, or to one per layer with --split-layers.
+// Skipped when --update finds every output up to date, and only reported
+// with --dry-run.
+fn convert_page(
+  document: &Document,
+  destination: &Destination,
+  render_options: &RenderOptions,
+  opts: &Settings,
+  sink: &mut dyn OutputSink,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<Outcome, String> {
+  let Destination { path: output, format, modified, source_modified, origin } = *destination;
+  let split = opts.is_present("split-layers");
+  let layer_documents = match split {
+    true => split_layers(document).into_iter().enumerate()
+      .filter(|(index, _)| render_options.draws_layer(index + 1))
+      .map(|(index, layer_document)| (index, layer_output_path(output, index + 1, format.extension()), layer_document))
+      .collect::<Vec<_>>(),
+    false => Vec::new()
+  };
+  let outputs = match split {
+    true => layer_documents.iter().map(|(_, layer_output, _)| layer_output.as_str()).collect::<Vec<_>>(),
+    false => vec![output]
+  };
+  if opts.is_present("update") && outputs.iter().all(|output| is_up_to_date(output, source_modified)) {
+    outputs.iter().for_each(|output| match opts.is_present("dry-run") {
+      true => println!("would skip {}, it's up to date", output),
+      false => logger(LogLevel::INFO, format!("{} is up to date, skipping it", output))
+    });
+    return Ok(Outcome::Skipped);
+  }
+  if opts.is_present("dry-run") {
+    outputs.iter().for_each(|output| println!("would write {}", output));
+    return Ok(Outcome::Succeeded);
+  }
+  // pages collected into HTML or sheets have no file of their own to go beside
+  if let Some(origin) = origin.filter(|_| opts.is_present("sidecar")) {
+    let sidecar_output = Path::new(output).with_extension("meta.json").to_string_lossy().into_owned();
+    let sidecar = sidecar_json(document, origin);
+    logger(LogLevel::INFO, format!("Writing {}", sidecar_output));
+    sink.write_output(&sidecar_output, format!("{}\n", sidecar).as_bytes())?;
+  }
+  if !split {
+    return render_page(document, destination, render_options, opts, sink, logger).map(|_| Outcome::Succeeded);
+  }
+  // each layer is the only one in its own file
+  return layer_documents.iter()
+    .try_for_each(|(index, layer_output, layer_document)| {
+      let layer_options = RenderOptions {
+        layers: None,
+        exclude_layers: Vec::new(),
+        layer_numbers: vec![index + 1],
+        ..render_options.clone()
+      };
+      let destination = Destination { path: layer_output, format, modified, source_modified, origin };
+      return render_page(layer_document, &destination, &layer_options, opts, sink, logger);
+    })
+    .map(|_| Outcome::Succeeded);
+}
+
+// A page rendered and written to one output, reporting how long each took
+// when asked to
+fn render_page(
+  document: &Document,
+  destination: &Destination,
+  render_options: &RenderOptions,
+  opts: &Settings,
+  sink: &mut dyn OutputSink,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<(), String> {
+  let Destination { path: output, format, modified, .. } = *destination;
+  let (svg, duration) = timed(|| render_svg(document, render_options));
+  let svg = svg?;
+  if json_log(opts) {
+    events::stage(output, "render", duration, &document.warnings);
+  }
+  let (saved, duration) = timed(|| save_page(sink, output, document, &svg, render_options, format, modified, logger));
+  if saved.is_ok() && json_log(opts) {
+    events::stage(output, "write", duration, &document.warnings);
+  }
+  return saved;
+}
+
+// Named option sets for common use cases, as (option, value) pairs
+const PRESETS: [(&str, &[(&str, &str)]); 4] = [
+  // the full page as it looks on the device
+  ("archive", &[("format", "pdf"), ("background", "white"), ("template", "auto")]),
+  // just the ink, as small as possible
+  ("web", &[("format", "svg"), ("crop-to-content", "10"), ("optimize", "")]),
+  // true to size at the tablet's 226 DPI
+  ("print", &[("format", "pdf"), ("scale", "0.1124mm"), ("background", "white"), ("template", "auto")]),
+  // bare strokes for pen plotters and cutters
+  ("plotter", &[("format", "gcode"), ("crop-to-content", "0")])
+];
+
+// Resolves options from the command line first and the selected preset
+// second, so presets only ever fill in what wasn't given explicitly.
+struct Settings<'a> {
+  matches: &'a ArgMatches,
+  preset: &'static [(&'static str, &'static str)]
+}
+
+impl<'a> Settings<'a> {
+  fn new(matches: &'a ArgMatches) -> Settings<'a> {
+    let preset = matches.value_of("preset")
+      .and_then(|name| PRESETS.iter().find(|(preset_name, _)| *preset_name == name))
+      .map_or(&[][..], |(_, values)| *values);
+    return Settings { matches, preset };
+  }
+
+  fn preset_value(&self, name: &str) -> Option<&'static str> {
+    return self.preset.iter().find(|(option, _)| *option == name).map(|(_, value)| *value);
+  }
+
+  // Only what the command line gives
+  fn given(&self, name: &str) -> Option<&'a str> {
+    if self.matches.occurrences_of(name) > 0 {
+      return self.matches.value_of(name);
+    }
+    return None;
+  }
+
+  fn value_of(&self, name: &str) -> Option<&'a str> {
+    return self.given(name).or_else(|| self.preset_value(name)).or_else(|| self.matches.value_of(name));
+  }
+
+  fn is_present(&self, name: &str) -> bool {
+    return self.matches.is_present(name) || self.preset_value(name).is_some();
+  }
+}
+
+const DEFAULT_CROP_MARGIN: f32 = 10.0;
+
+// Landscape notebooks are turned to read the way they were written, unless
+// --rotate picks the turn for every page
+fn oriented(page_options: RenderOptions, landscape: bool, opts: &Settings) -> RenderOptions {
+  return match landscape && !opts.is_present("rotate") {
+    true => RenderOptions { rotation: LANDSCAPE_ROTATION, ..page_options },
+    false => page_options
+  };
+}
+
+// Layers hidden on the tablet are left out, as it shows the page, unless
+// --show-hidden-layers or --layers picks which to draw
+fn unhidden(page_options: RenderOptions, hidden_layers: &[usize], opts: &Settings) -> RenderOptions {
+  return match opts.is_present("show-hidden-layers") {
+    true => page_options,
+    false => page_options.without_hidden_layers(hidden_layers)
+  };
+}
+
+fn get_render_options(opts: &Settings) -> Result<RenderOptions, String> {
+  let defaults = RenderOptions::default();
+  return Ok(RenderOptions {
+    view_box: opts.value_of("viewbox").map_or(Ok(defaults.view_box), ViewBox::parse)?,
+    width: opts.value_of("width").map(Length::parse).transpose()?,
+    height: opts.value_of("height").map(Length::parse).transpose()?,
+    scale: opts.value_of("scale").map_or(Ok(defaults.scale), Length::parse)?,
+    crop_to_content: match opts.is_present("crop-to-content") {
+      true => Some(opts.value_of("crop-to-content").map_or(Ok(DEFAULT_CROP_MARGIN), |margin| {
+        return margin.parse::<f32>().map_err(|_| format!("invalid crop margin {}", margin));
+      })?),
+      false => None
+    },
+    background: match opts.value_of("background") {
+      None if opts.is_present("dark") => Some(String::from(DARK_BACKGROUND)),
+      None | Some("transparent") => None,
+      Some(color) => Some(parse_color(color)?)
+    },
+    dark: opts.is_present("dark"),
+    snap_shapes: opts.is_present("snap-shapes"),
+    ocr: opts.value_of("ocr-cmd").map(|command| Ocr {
+      recognizer: CommandRecognizer { command: String::from(command) },
+      sidecar: opts.is_present("ocr-sidecar")
+    }),
+    optimize: opts.is_present("optimize"),
+    animate: opts.is_present("animate"),
+    speed_style: opts.value_of("speed-style").and_then(SpeedStyle::by_name),
+    group_strokes: opts.is_present("group-strokes") || opts.value_of("group-by") == Some("word"),
+    group_by_brush: opts.value_of("group-by") == Some("brush"),
+    segment_widths: opts.is_present("segment-widths"),
+    outline: opts.is_present("outline"),
+    data_attributes: opts.is_present("data-attributes"),
+    css_classes: opts.is_present("css-classes") || opts.is_present("stylesheet"),
+    stylesheet: opts.value_of("stylesheet").map(|stylesheet| match opts.is_present("embed-stylesheet") {
+      true => std::fs::read_to_string(stylesheet)
+        .map(Stylesheet::Embedded)
+        .map_err(|why| format!("couldn't read stylesheet {}, {}", stylesheet, why)),
+      false => Ok(Stylesheet::Linked(String::from(stylesheet)))
+    }).transpose()?,
+    include_selection: opts.is_present("include-selection"),
+    include_erasers: opts.is_present("include-eraser-strokes"),
+    device: opts.value_of("device").and_then(Device::by_name),
+    precision: match opts.value_of("precision") {
+      None => defaults.precision,
+      Some("full") => None,
+      Some(digits) => Some(digits.parse::<u32>().ok().filter(|digits| *digits <= MAX_PRECISION).ok_or_else(|| {
+        return format!("invalid precision {}, expected 0 to {} decimal places or full", digits, MAX_PRECISION);
+      })?)
+    },
+    rotation: opts.value_of("rotate").map_or(Ok(defaults.rotation), |rotation| {
+      return rotation.parse::<u32>().map_err(|_| format!("invalid rotation {}", rotation));
+    })?,
+    element: opts.value_of("element").and_then(StrokeElement::by_name).unwrap_or(StrokeElement::Path),
+    watermark: opts.value_of("watermark").map(|spec| {
+      let opacity = opts.value_of("watermark-opacity").unwrap_or_default();
+      return Watermark::from_spec(
+        spec,
+        opacity.parse::<f32>().map_err(|_| format!("invalid watermark opacity {}", opacity))?,
+        opts.value_of("watermark-position").and_then(WatermarkPosition::by_name).unwrap_or(WatermarkPosition::Diagonal),
+        opts.is_present("watermark-above")
+      );
+    }).transpose()?,
+    color_map: match opts.value_of("color-map") {
+      None => defaults.color_map,
+      Some(spec) if spec.contains('=') => parse_color_map(spec)?,
+      Some(file) => std::fs::read_to_string(file)
+        .map_err(|why| format!("couldn't read palette {}, {}", file, why))
+        .and_then(|text| parse_color_map_toml(&text))?
+    },
+    layers: opts.value_of("layers").map(parse_layer_numbers).transpose()?,
+    exclude_layers: opts.value_of("exclude-layers").map_or(Ok(Vec::new()), parse_layer_numbers)?,
+    max_output_points: opts.value_of("max-output-points")
+      .map(|n| n.parse::<usize>().map_err(|_| format!("invalid point budget {}", n)))
+      .transpose()?,
+    tolerance: opts.value_of("tolerance")
+      .map(|tolerance| match tolerance.parse::<f32>() {
+        Ok(pixels) if pixels.is_finite() && pixels >= 0.0 => Ok(pixels),
+        _ => Err(format!("invalid tolerance {}", tolerance))
+      })
+      .transpose()?,
+    smoothing: opts.value_of("smoothing")
+      .map(|n| n.parse::<usize>().map_err(|_| format!("invalid smoothing {}", n)))
+      .transpose()?,
+    template: match opts.value_of("template") {
+      None | Some("auto") | Some("infer") => None,
+      Some(name) => Some(Template::by_name(name).ok_or_else(|| {
+        return format!("unknown template {}, expected auto, infer or one of {}", name, TEMPLATE_NAMES.join(", "));
+      })?)
+    },
+    ..defaults
+  });
+}
+
+// The template a page was most likely written on, for --template infer
+fn inferred_template(layers: &[Layer], logger: &dyn Fn(LogLevel, String)) -> Option<Template> {
+  return match infer_template(layers) {
+    Some((template, alignment)) => {
+      logger(LogLevel::INFO, format!("inferred template {} (alignment {:.2})", template.name(), alignment));
+      Some(template)
+    },
+    None => {
+      logger(LogLevel::INFO, String::from("too few strokes to infer a template"));
+      None
+    }
+  };
+}
+
+// --format if given, or else going by the output's extension, or else the
+// preset's format
+fn output_format(opts: &Settings, output: &str) -> Result<OutputFormat, String> {
+  let format = opts.given("format")
+    .and_then(OutputFormat::by_name)
+    .or_else(|| OutputFormat::from_path(output))
+    .or_else(|| opts.value_of("format").and_then(OutputFormat::by_name))
+    .unwrap_or(OutputFormat::Svg);
+  return match format {
+    OutputFormat::Svg if opts.is_present("compress") => Ok(OutputFormat::Svgz),
+    OutputFormat::Png { dpi } => Ok(OutputFormat::Png { dpi: number_option(opts, "dpi", dpi, true)? }),
+    OutputFormat::Apng { dpi } => Ok(OutputFormat::Apng { dpi: number_option(opts, "dpi", dpi, true)? }),
+    OutputFormat::Gcode(defaults) => Ok(OutputFormat::Gcode(GcodeOptions {
+      feed_rate: number_option(opts, "feed-rate", defaults.feed_rate, true)?,
+      pen_up: number_option(opts, "pen-up", defaults.pen_up, false)?,
+      pen_down: number_option(opts, "pen-down", defaults.pen_down, false)?,
+      scale: number_option(opts, "gcode-scale", defaults.scale, true)?
+    })),
+    format => Ok(format)
+  };
+}
+
+// A number option's value, or the default when it isn't given
+fn number_option(opts: &Settings, name: &str, default: f32, positive: bool) -> Result<f32, String> {
+  return match opts.value_of(name) {
+    None => Ok(default),
+    Some(value) => match value.parse::<f32>() {
+      Ok(number) if number.is_finite() && (!positive || number > 0.0) => Ok(number),
+      _ => Err(format!("invalid {} {}", name, value))
+    }
+  };
+}
+
+#[allow(clippy::too_many_arguments)]
+fn save_page(
+  sink: &mut dyn OutputSink,
+  output: &str,
+  page: &Document,
+  svg: &svg::Document,
+  render_options: &RenderOptions,
+  format: OutputFormat,
+  modified: Option<SystemTime>,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<(), String> {
+  logger(LogLevel::INFO, format!("Writing {}", output));
+  sink.write_output(output, &encode(page, svg, render_options, format)?)?;
+  if let Some(ocr) = render_options.ocr.as_ref().filter(|ocr| ocr.sidecar) {
+    let text_output = Path::new(output).with_extension("txt").to_string_lossy().into_owned();
+    let text = recognize_lines(&page.layers, &ocr.recognizer)?.into_iter()
+      .map(|line| format!("{}\n", line.text))
+      .collect::<String>();
+    logger(LogLevel::INFO, format!("Writing {}", text_output));
+    sink.write_output(&text_output, text.as_bytes())?;
+  }
+  return modified.map_or(Ok(()), |modified| sink.set_modified(output, modified));
+}
+
+// OUTPUT-SUFFIX.EXT, with EXT the output's own extension if it has one
+fn suffixed_output_path(output: &str, suffix: &str, default_extension: &str) -> String {
+  let output_path = Path::new(output);
+  let stem = output_path.file_stem().map_or_else(|| String::from("image"), |s| s.to_string_lossy().into_owned());
+  let extension = output_path.extension().map_or_else(|| String::from(default_extension), |e| e.to_string_lossy().into_owned());
+  return output_path
+    .with_file_name(format!("{}-{}.{}", stem, suffix, extension))
+    .to_string_lossy()
+    .into_owned();
+}
+
+fn layer_output_path(output: &str, layer_number: usize, default_extension: &str) -> String {
+  return suffixed_output_path(output, &format!("layer{}", layer_number), default_extension);
+}
+
+// OUTPUT-page-017.svg, or OUTPUT-017-meeting-notes.svg for a titled page
+fn page_output_path(output: &str, page_number: usize, title: Option<&str>, default_extension: &str) -> String {
+  return match title.map(slugify).filter(|slug| !slug.is_empty()) {
+    Some(slug) => suffixed_output_path(output, &format!("{:03}-{}", page_number, slug), default_extension),
+    None => suffixed_output_path(output, &format!("page-{:03}", page_number), default_extension)
+  };
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+  return std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+}
+
+fn read_input(input: &str, logger: &dyn Fn(LogLevel, String)) -> Result<InputBytes, String> {
+  logger(LogLevel::INFO, format!("Input is {}", input));
+  if !Path::new(input).is_file() {
+    return Err(String::from("input file does not exist"));
+  }
+  return read_file(Path::new(input));
+}
+
+fn get_logger(verbosity: u64, json: bool) -> impl Fn(LogLevel, String) {
   move |level, message| {
-    if verbosity < level as u64 { return (); }
-    println!("[{}]: {}", level.to_string(), message);
+    if verbosity < level as u64 { return; }
+    match json {
+      true => events::log(level, &message),
+      false => println!("[{}]: {}", level, message)
+    }
   }
 }
+
+// Whether --log-format json asked for structured events
+fn json_log(opts: &Settings) -> bool {
+  return opts.value_of("log-format") == Some("json");
+}
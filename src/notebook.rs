@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::json;
+use crate::text::TextBlock;
+use crate::zip::{is_zip, read_archive, ZipEntry, ZipStream};
+use crate::warning::Warning;
+use crate::{parse_document, Document, Layer, LogLevel};
+
+#[derive(Debug)]
+pub struct Notebook {
+  pub id: String,
+  // the visibleName from .metadata, if there is one
+  pub name: Option<String>,
+  // lastModified from .metadata
+  pub last_modified: Option<SystemTime>,
+  pub pages: Vec<NotebookPage>
+}
+
+#[derive(Debug)]
+pub struct NotebookPage {
+  pub id: String,
+  // 1-based position in the notebook; pages that were never drawn on have
+  // no .rm file, so numbers can skip
+  pub number: usize,
+  // template name from .pagedata, e.g. "P Lines small"
+  pub template: Option<String>,
+  // the notebook was written with the tablet turned on its side, as
+  // .content says
+  pub landscape: bool,
+  // the .rm version of the page
+  pub version: u32,
+  pub layers: Vec<Layer>,
+  // as the tablet names them in PAGE-metadata.json, e.g. "Layer 1"; empty
+  // where the page has none
+  pub layer_names: Vec<String>,
+  // layers hidden on the tablet, by number from 1, as PAGE-metadata.json
+  // marks them
+  pub hidden_layers: Vec<usize>,
+  pub text: Vec<TextBlock>,
+  pub warnings: Vec<Warning>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PageNumber {
+  Number(usize),
+  Last
+}
+
+// Notebook pages picked by number, as in 1,3,5-9 or 10-last. The last page
+// is the last one that has strokes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageSelection {
+  ranges: Vec<(PageNumber, PageNumber)>
+}
+
+impl PageSelection {
+  pub fn parse(spec: &str) -> Result<PageSelection, String> {
+    let number = |value: &str| match value.trim() {
+      "last" => Ok(PageNumber::Last),
+      value => value.parse::<usize>().ok()
+        .filter(|number| *number > 0)
+        .map(PageNumber::Number)
+        .ok_or_else(|| format!("invalid page {} in {}", value, spec))
+    };
+    let ranges = spec.split(',')
+      .map(|part| match part.split_once('-') {
+        Some((first, last)) => Ok((number(first)?, number(last)?)),
+        None => number(part).map(|page| (page, page))
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+    let backwards = ranges.iter().any(|range| match range {
+      (PageNumber::Number(first), PageNumber::Number(last)) => first > last,
+      (PageNumber::Last, PageNumber::Number(_)) => true,
+      _ => false
+    });
+    if backwards {
+      return Err(format!("invalid page range in {}, the first page comes after the last", spec));
+    }
+    return Ok(PageSelection { ranges });
+  }
+
+  // whether telling which pages are picked needs the last page's number
+  pub fn uses_last(&self) -> bool {
+    return self.ranges.iter().any(|(_, last)| *last == PageNumber::Last);
+  }
+
+  pub fn contains(&self, number: usize, last: usize) -> bool {
+    let resolve = |page: PageNumber| match page {
+      PageNumber::Number(number) => number,
+      PageNumber::Last => last
+    };
+    return self.ranges.iter().any(|(first, end)| (resolve(*first)..=resolve(*end)).contains(&number));
+  }
+}
+
+pub fn is_archive(bytes: &[u8]) -> bool {
+  return is_zip(bytes);
+}
+
+// Page order from a .content file: older firmware writes a flat "pages"
+// list, 3.x writes "cPages" with per-page records that may be tombstoned.
+pub fn page_ids(content: &json::Value) -> Vec<String> {
+  if let Some(pages) = content.get("pages").and_then(|p| p.as_array()) {
+    return pages.iter().filter_map(|p| p.as_str()).map(String::from).collect();
+  }
+  return content.get("cPages")
+    .and_then(|c| c.get("pages"))
+    .and_then(|p| p.as_array())
+    .map_or_else(Vec::new, |pages| {
+      return pages.iter()
+        .filter(|p| p.get("deleted").is_none())
+        .filter_map(|p| p.get("id").and_then(|id| id.as_str()))
+        .map(String::from)
+        .collect();
+    });
+}
+
+// xochitl writes "portrait" or "landscape"
+pub fn is_landscape(content: &json::Value) -> bool {
+  return content.get("orientation").and_then(|o| o.as_str()) == Some("landscape");
+}
+
+pub fn visible_name(metadata: &json::Value) -> Option<String> {
+  return metadata.get("visibleName").and_then(|n| n.as_str()).map(String::from);
+}
+
+// xochitl stores milliseconds since the epoch, as a string
+pub fn last_modified(metadata: &json::Value) -> Option<SystemTime> {
+  return metadata.get("lastModified")
+    .and_then(|m| m.as_f64())
+    .filter(|millis| *millis > 0.0)
+    .map(|millis| UNIX_EPOCH + Duration::from_millis(millis as u64));
+}
+
+// The names of a page's layers, in order, from its PAGE-metadata.json
+pub fn layer_names(metadata: &json::Value) -> Vec<String> {
+  return metadata.get("layers").and_then(|l| l.as_array()).map_or_else(Vec::new, |layers| {
+    return layers.iter()
+      .map(|layer| layer.get("name").and_then(|n| n.as_str()).map(String::from).unwrap_or_default())
+      .collect();
+  });
+}
+
+// The numbers, from 1, of the layers a page's PAGE-metadata.json says are
+// hidden, "visible": false
+pub fn hidden_layers(metadata: &json::Value) -> Vec<usize> {
+  return metadata.get("layers").and_then(|l| l.as_array()).map_or_else(Vec::new, |layers| {
+    return layers.iter().enumerate()
+      .filter(|(_, layer)| matches!(layer.get("visible"), Some(json::Value::Bool(false))))
+      .map(|(index, _)| index + 1)
+      .collect();
+  });
+}
+
+// A page's layer names and hidden layers, from its PAGE-metadata.json
+fn parse_page_metadata(data: &[u8]) -> (Vec<String>, Vec<usize>) {
+  return json::parse(&String::from_utf8_lossy(data)).ok()
+    .map_or_else(Default::default, |metadata| (layer_names(&metadata), hidden_layers(&metadata)));
+}
+
+pub fn read_zip(bytes: &[u8], logger: &dyn Fn(LogLevel, String)) -> Result<Notebook, String> {
+  let entries = read_archive(bytes)?;
+  logger(LogLevel::DEBUG, format!("archive has {} entries", entries.len()));
+  return read_entries(&entries, logger);
+}
+
+// The PDF a zipped notebook of annotations was exported with, if any
+pub fn zip_pdf(bytes: &[u8]) -> Result<Option<Vec<u8>>, String> {
+  return read_archive(bytes).map(|entries| entries.into_iter().find(|entry| entry.name.ends_with(".pdf")).map(|entry| entry.data));
+}
+
+// A notebook unpacked into a directory, laid out as in the archive: the
+// UUID.content, UUID.metadata and UUID.pagedata files next to a UUID/
+// directory of pages
+pub fn read_dir(path: &Path, logger: &dyn Fn(LogLevel, String)) -> Result<Notebook, String> {
+  return dir_entries(path, logger).and_then(|entries| read_entries(&entries, logger));
+}
+
+// The files of a notebook unpacked into a directory, named as they are in
+// an archive of it
+pub fn dir_entries(path: &Path, logger: &dyn Fn(LogLevel, String)) -> Result<Vec<ZipEntry>, String> {
+  let list = |dir: &Path| {
+    return fs::read_dir(dir)
+      .map_err(|why| format!("couldn't list {}, {}", dir.display(), why))
+      .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect::<Vec<_>>());
+  };
+  let read = |file: &Path| {
+    let name = file.strip_prefix(path).unwrap_or(file).to_string_lossy().replace('\\', "/");
+    return fs::read(file)
+      .map(|data| ZipEntry { name, data })
+      .map_err(|why| format!("couldn't read {}, {}", file.display(), why));
+  };
+  let files = list(path)?;
+  let notebooks = files.iter().filter(|file| file.extension().is_some_and(|e| e == "content")).count();
+  if notebooks > 1 {
+    return Err(format!("{} holds {} notebooks, expected one", path.display(), notebooks));
+  }
+  let entries = files.iter()
+    .map(|file| {
+      return match file.is_dir() {
+        true => list(file),
+        false => Ok(vec![file.clone()])
+      };
+    })
+    .collect::<Result<Vec<_>, _>>()?
+    .into_iter()
+    .flatten()
+    .filter(|file| file.is_file())
+    .map(|file| read(&file))
+    .collect::<Result<Vec<_>, _>>()?;
+  logger(LogLevel::DEBUG, format!("{} has {} files", path.display(), entries.len()));
+  return Ok(entries);
+}
+
+// The files of a zipped notebook
+pub fn zip_entries(bytes: &[u8]) -> Result<Vec<ZipEntry>, String> {
+  return read_archive(bytes);
+}
+
+pub fn read_entries(entries: &[ZipEntry], logger: &dyn Fn(LogLevel, String)) -> Result<Notebook, String> {
+  let content_entry = entries.iter()
+    .find(|e| e.name.ends_with(".content"))
+    .ok_or_else(|| String::from("notebook has no .content file"))?;
+  let id = content_entry.name.trim_end_matches(".content").rsplit('/').next().unwrap_or_default().to_string();
+  let content = json::parse(&String::from_utf8_lossy(&content_entry.data))
+    .map_err(|why| format!("couldn't parse {}, {}", content_entry.name, why))?;
+
+  let metadata = entries.iter()
+    .find(|e| e.name.ends_with(&format!("{}.metadata", id)))
+    .and_then(|e| json::parse(&String::from_utf8_lossy(&e.data)).ok());
+  let name = metadata.as_ref().and_then(visible_name);
+
+  // one template name per line, in page order
+  let templates = entries.iter()
+    .find(|e| e.name.ends_with(&format!("{}.pagedata", id)))
+    .map_or_else(Vec::new, |e| {
+      return String::from_utf8_lossy(&e.data).lines().map(|l| l.trim().to_string()).collect::<Vec<_>>();
+    });
+
+  let landscape = is_landscape(&content);
+  let mut ids = page_ids(&content);
+  if ids.is_empty() {
+    // no usable page list, fall back to whatever pages are in the archive
+    ids = entries.iter()
+      .filter(|e| e.name.ends_with(".rm"))
+      .filter_map(|e| e.name.rsplit('/').next())
+      .map(|n| n.trim_end_matches(".rm").to_string())
+      .collect();
+    ids.sort();
+  }
+
+  let pages = ids.iter().enumerate()
+    .filter_map(|(index, page_id)| {
+      let suffix = format!("{}/{}.rm", id, page_id);
+      return entries.iter()
+        .find(|e| e.name.ends_with(&suffix))
+        .map(|e| (index, page_id, e));
+    })
+    .map(|(index, page_id, entry)| {
+      logger(LogLevel::INFO, format!("parsing page {} ({})", index + 1, page_id));
+      let metadata_suffix = format!("{}/{}-metadata.json", id, page_id);
+      let (layer_names, hidden_layers) = entries.iter()
+        .find(|e| e.name.ends_with(&metadata_suffix))
+        .map_or_else(Default::default, |e| parse_page_metadata(&e.data));
+      return parse_document(&entry.data, logger)
+        .map_err(|why| format!("page {} ({}): {}", index + 1, page_id, why))
+        .map(|document| NotebookPage {
+          id: page_id.clone(),
+          number: index + 1,
+          template: templates.get(index).filter(|t| !t.is_empty()).cloned(),
+          landscape,
+          version: document.version,
+          layers: document.layers,
+          layer_names,
+          hidden_layers,
+          text: document.text,
+          warnings: document.warnings
+        });
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+  return Ok(Notebook { id, name, last_modified: metadata.as_ref().and_then(last_modified), pages });
+}
+
+// What is known about a streamed notebook by the time a page is handed over
+#[derive(Debug, Default, Clone)]
+pub struct NotebookInfo {
+  // the notebook's UUID, from its .content
+  pub id: Option<String>,
+  pub name: Option<String>,
+  pub last_modified: Option<SystemTime>
+}
+
+fn entry_stem(name: &str, extension: &str) -> String {
+  return name.rsplit('/').next().unwrap_or_default().trim_end_matches(extension).to_string();
+}
+
+// Whether an entry is one of a page's files, e.g. UUID/PAGE-metadata.json
+// or UUID.thumbnails/PAGE.png
+fn is_page_file(name: &str, page_id: &str) -> bool {
+  return name.rsplit('/').next().is_some_and(|file| file.starts_with(page_id));
+}
+
+// Like read_zip, but reads the archive front to back (e.g. from a pipe)
+// and hands each page to `on_page` as soon as it can be numbered, instead
+// of keeping the whole notebook in memory. Page numbers come from the
+// .content file, so pages ahead of it in the archive are held back until it
+// arrives; metadata and templates only apply to the pages after them, and
+// a page's PAGE-metadata.json only if it comes with the page's other files,
+// before the next page's. Archives that list their .content, .metadata and .pagedata first (as
+// the reMarkable apps write them) stream in bounded memory.
+pub fn stream_zip<R: Read>(
+  reader: R,
+  logger: &dyn Fn(LogLevel, String),
+  on_page: &mut dyn FnMut(&NotebookInfo, NotebookPage) -> Result<(), String>
+) -> Result<NotebookInfo, String> {
+  let mut info = NotebookInfo::default();
+  let mut ids: Option<Vec<String>> = None;
+  let mut templates = Vec::<String>::new();
+  let mut landscape = false;
+  // pages wait for the .content, to be numbered, and for the rest of their
+  // files, which may hold their PAGE-metadata.json, less v6 pages, whose
+  // layers are in the page itself
+  let mut held = Vec::<(String, Document)>::new();
+  // layer names and hidden layers of pages whose PAGE-metadata.json has
+  // been seen
+  let mut layers = HashMap::<String, (Vec<String>, Vec<usize>)>::new();
+
+  let page = |ids: &[String], templates: &[String], landscape: bool, layers: &HashMap<String, (Vec<String>, Vec<usize>)>, page_id: String, document: Document| {
+    let (layer_names, hidden_layers) = layers.get(&page_id).cloned().unwrap_or_default();
+    return ids.iter().position(|id| *id == page_id).map(|index| NotebookPage {
+      number: index + 1,
+      template: templates.get(index).filter(|t| !t.is_empty()).cloned(),
+      landscape,
+      version: document.version,
+      layers: document.layers,
+      layer_names,
+      hidden_layers,
+      text: document.text,
+      warnings: document.warnings,
+      id: page_id
+    });
+  };
+
+  for entry in ZipStream::new(reader) {
+    let entry = entry?;
+    logger(LogLevel::DEBUG, format!("streamed {} ({} bytes)", entry.name, entry.data.len()));
+    // past a page's files, its metadata can't come any more
+    if let Some(page_ids) = ids.as_deref().filter(|page_ids| !page_ids.is_empty()) {
+      let (done, waiting) = held.into_iter().partition::<Vec<_>, _>(|(page_id, _)| !is_page_file(&entry.name, page_id));
+      held = waiting;
+      done.into_iter()
+        .filter_map(|(page_id, document)| page(page_ids, &templates, landscape, &layers, page_id, document))
+        .try_for_each(|notebook_page| on_page(&info, notebook_page))?;
+    }
+    if entry.name.ends_with(".content") {
+      let content = json::parse(&String::from_utf8_lossy(&entry.data))
+        .map_err(|why| format!("couldn't parse {}, {}", entry.name, why))?;
+      let page_ids = page_ids(&content);
+      landscape = is_landscape(&content);
+      info.id = Some(entry_stem(&entry.name, ".content"));
+      if !page_ids.is_empty() {
+        std::mem::take(&mut held).into_iter()
+          .filter_map(|(page_id, document)| page(&page_ids, &templates, landscape, &layers, page_id, document))
+          .try_for_each(|notebook_page| on_page(&info, notebook_page))?;
+      }
+      ids = Some(page_ids);
+    } else if entry.name.ends_with(".metadata") {
+      let metadata = json::parse(&String::from_utf8_lossy(&entry.data)).ok();
+      info.name = metadata.as_ref().and_then(visible_name);
+      info.last_modified = metadata.as_ref().and_then(last_modified);
+    } else if entry.name.ends_with("-metadata.json") {
+      let page_id = entry_stem(&entry.name, "-metadata.json");
+      layers.insert(page_id.clone(), parse_page_metadata(&entry.data));
+      if let Some(page_ids) = ids.as_deref().filter(|page_ids| !page_ids.is_empty()) {
+        if let Some(index) = held.iter().position(|(held_id, _)| *held_id == page_id) {
+          let (page_id, document) = held.remove(index);
+          if let Some(notebook_page) = page(page_ids, &templates, landscape, &layers, page_id, document) {
+            on_page(&info, notebook_page)?;
+          }
+        }
+      }
+    } else if entry.name.ends_with(".pagedata") {
+      templates = String::from_utf8_lossy(&entry.data).lines().map(|l| l.trim().to_string()).collect();
+    } else if entry.name.ends_with(".rm") {
+      let page_id = entry_stem(&entry.name, ".rm");
+      let document = parse_document(&entry.data, logger).map_err(|why| format!("page {}: {}", page_id, why))?;
+      match ids.as_deref() {
+        Some(page_ids) if !page_ids.is_empty() && (layers.contains_key(&page_id) || document.version == 6) => {
+          if let Some(notebook_page) = page(page_ids, &templates, landscape, &layers, page_id, document) {
+            on_page(&info, notebook_page)?;
+          }
+        },
+        _ => held.push((page_id, document))
+      }
+    }
+  }
+
+  let page_ids = match ids {
+    Some(page_ids) if !page_ids.is_empty() => page_ids,
+    Some(_) => {
+      // no usable page list, number whatever pages were in the archive
+      held.sort_by(|(a, _), (b, _)| a.cmp(b));
+      held.iter().map(|(page_id, _)| page_id.clone()).collect::<Vec<_>>()
+    },
+    None => return Err(String::from("notebook archive has no .content file"))
+  };
+  held.into_iter()
+    .filter_map(|(page_id, document)| page(&page_ids, &templates, landscape, &layers, page_id, document))
+    .try_for_each(|notebook_page| on_page(&info, notebook_page))?;
+  return Ok(info);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::deflate::crc32;
+  use crate::zip::ZIP_MAGIC;
+
+  const GOOD: &[u8] = include_bytes!("../test_files/good.rm");
+
+  fn quiet(_: LogLevel, _: String) {}
+
+  // A zip of stored entries, as far as ZipStream reads it
+  fn stored_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    for (name, data) in entries {
+      zip.extend_from_slice(ZIP_MAGIC);
+      zip.extend_from_slice(&[0; 10]);
+      zip.extend_from_slice(&crc32(data).to_le_bytes());
+      zip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+      zip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+      zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+      zip.extend_from_slice(&[0; 2]);
+      zip.extend_from_slice(name.as_bytes());
+      zip.extend_from_slice(data);
+    }
+    return zip;
+  }
+
+  const CONTENT: &[u8] = br#"{"pages": ["p1", "p2"]}"#;
+  const PAGE_METADATA: &[u8] = br#"{"layers": [{"name": "Sketch"}, {"name": "Notes", "visible": false}]}"#;
+
+  fn streamed(entries: &[(&str, &[u8])]) -> Vec<NotebookPage> {
+    let mut pages = Vec::new();
+    stream_zip(&stored_zip(entries)[..], &quiet, &mut |_, page| {
+      pages.push(page);
+      return Ok(());
+    }).unwrap();
+    return pages;
+  }
+
+  #[test]
+  fn streamed_pages_wait_for_their_metadata() {
+    let pages = streamed(&[
+      ("n.content", CONTENT),
+      ("n/p1.rm", GOOD),
+      ("n/p1-metadata.json", PAGE_METADATA),
+      ("n/p2.rm", GOOD),
+      // too late for p1, whose files have all come
+      ("n/p1-metadata.json", br#"{"layers": [{"name": "Late"}]}"#)
+    ]);
+    assert_eq!(pages.iter().map(|page| page.number).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(pages[0].layer_names, vec!["Sketch", "Notes"]);
+    assert_eq!(pages[0].hidden_layers, vec![2]);
+    assert!(pages[1].layer_names.is_empty());
+    assert!(pages[1].hidden_layers.is_empty());
+  }
+
+  #[test]
+  fn streamed_pages_wait_for_the_content() {
+    let pages = streamed(&[
+      ("n/p2-metadata.json", PAGE_METADATA),
+      ("n/p2.rm", GOOD),
+      ("n/p1.rm", GOOD),
+      ("n.content", CONTENT)
+    ]);
+    assert_eq!(pages.iter().map(|page| page.number).collect::<Vec<_>>(), vec![2, 1]);
+    assert_eq!(pages[0].layer_names, vec!["Sketch", "Notes"]);
+    assert!(pages[1].layer_names.is_empty());
+  }
+
+  #[test]
+  fn streamed_pages_without_metadata_come_out_as_they_go() {
+    let mut numbers = Vec::new();
+    let archive = stored_zip(&[
+      ("n.content", br#"{"pages": ["p1", "p2", "p3"]}"#),
+      ("n/p1.rm", GOOD),
+      ("n.thumbnails/p1.png", b"not a png"),
+      ("n/p2.rm", GOOD),
+      ("n/p3.rm", b"not a page")
+    ]);
+    let result = stream_zip(&archive[..], &quiet, &mut |_, page| {
+      numbers.push(page.number);
+      return Ok(());
+    });
+    // both pages were handed over before the archive was read to its end
+    assert!(result.unwrap_err().starts_with("page p3: "));
+    assert_eq!(numbers, vec![1, 2]);
+  }
+
+  #[test]
+  fn streams_need_a_content_file() {
+    let result = stream_zip(&stored_zip(&[("n/p1.rm", GOOD)])[..], &quiet, &mut |_, _| Ok(()));
+    assert_eq!(result.unwrap_err(), "notebook archive has no .content file");
+  }
+}
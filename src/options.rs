@@ -0,0 +1,586 @@
+use std::collections::HashMap;
+
+use crate::brush::{BrushColor, BrushStyle, BrushType};
+use crate::device::Device;
+use crate::recognize::Ocr;
+use crate::template::Template;
+use crate::watermark::Watermark;
+
+const UNITS: [&str; 7] = ["", "px", "mm", "cm", "in", "pt", "pc"];
+
+// An SVG length such as "1404", "210mm" or "8.5in"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Length {
+  pub value: f32,
+  pub unit: String
+}
+
+impl Length {
+  pub fn px(value: f32) -> Length {
+    return Length { value, unit: String::new() };
+  }
+
+  pub fn parse(length: &str) -> Result<Length, String> {
+    let trimmed = length.trim();
+    let split = trimmed
+      .find(|c: char| c.is_ascii_alphabetic())
+      .unwrap_or(trimmed.len());
+    let (value, unit) = trimmed.split_at(split);
+    if !UNITS.contains(&unit) {
+      return Err(format!("unsupported unit {} in length {}", unit, length));
+    }
+    return value.parse::<f32>()
+      .map_err(|_| format!("invalid length {}", length))
+      .and_then(|value| {
+        if value <= 0.0 { return Err(format!("length must be positive, got {}", length)); }
+        return Ok(Length { value, unit: String::from(unit) });
+      });
+  }
+
+  // in CSS pixels, 96 to the inch
+  pub fn to_px(&self) -> f32 {
+    return self.value * match self.unit.as_str() {
+      "mm" => 96.0 / 25.4,
+      "cm" => 96.0 / 2.54,
+      "in" => 96.0,
+      "pt" => 96.0 / 72.0,
+      "pc" => 16.0,
+      _ => 1.0
+    };
+  }
+
+  fn times(&self, factor: f32) -> Length {
+    return Length { value: self.value * factor, unit: self.unit.clone() };
+  }
+}
+
+impl std::fmt::Display for Length {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}{}", self.value, self.unit)
+  }
+}
+
+// Accepts #RGB, #RRGGBB and the handful of named colors people actually
+// reach for; returns the value as it should appear in the SVG.
+pub fn parse_color(color: &str) -> Result<String, String> {
+  let trimmed = color.trim().to_ascii_lowercase();
+  let named = ["black", "white", "grey", "gray", "red", "green", "blue", "yellow"];
+  if named.contains(&trimmed.as_str()) {
+    return Ok(trimmed);
+  }
+  let hex = trimmed.strip_prefix('#').unwrap_or("");
+  if (hex.len() == 3 || hex.len() == 6) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    return Ok(trimmed);
+  }
+  return Err(format!("invalid color {}, expected a name or #RRGGBB", color));
+}
+
+// Replacement colors for the device's palette
+pub type ColorMap = HashMap<BrushColor, String>;
+
+// Parses "black=#1a1a2e,grey=#888"
+pub fn parse_color_map(spec: &str) -> Result<ColorMap, String> {
+  return spec.split(',')
+    .filter(|pair| !pair.trim().is_empty())
+    .map(|pair| {
+      let (name, color) = pair.split_once('=')
+        .ok_or_else(|| format!("invalid color mapping {}, expected name=color", pair))?;
+      return color_mapping(name, color);
+    })
+    .collect();
+}
+
+// Parses a TOML palette file with one `name = "color"` entry per color
+pub fn parse_color_map_toml(text: &str) -> Result<ColorMap, String> {
+  let table = text.parse::<toml::Value>().map_err(|why| format!("invalid palette file, {}", why))?;
+  return table.as_table()
+    .ok_or_else(|| String::from("palette file must be a table of colors"))?
+    .iter()
+    .map(|(name, color)| {
+      let color = color.as_str().ok_or_else(|| format!("color for {} must be a string", name))?;
+      return color_mapping(name, color);
+    })
+    .collect();
+}
+
+fn color_mapping(name: &str, color: &str) -> Result<(BrushColor, String), String> {
+  let brush_color = BrushColor::by_name(name)
+    .ok_or_else(|| format!("unknown brush color {}, expected {}", name, BrushColor::names()))?;
+  return parse_color(color).map(|color| (brush_color, color));
+}
+
+// Parses "1,3" into 1-based layer numbers
+pub fn parse_layer_numbers(spec: &str) -> Result<Vec<usize>, String> {
+  return spec.split(',')
+    .map(|number| {
+      return number.trim().parse::<usize>().ok()
+        .filter(|number| *number > 0)
+        .ok_or_else(|| format!("invalid layer {}, expected numbers from 1 such as 1,3", number));
+    })
+    .collect();
+}
+
+// A region of the canvas, in device pixels
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewBox {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32
+}
+
+impl ViewBox {
+  // all of a canvas of the given width and height
+  pub fn whole((width, height): (f32, f32)) -> ViewBox {
+    return ViewBox { x: 0.0, y: 0.0, width, height };
+  }
+
+  // accepts "x y w h" or "x,y,w,h", like the SVG attribute
+  pub fn parse(view_box: &str) -> Result<ViewBox, String> {
+    let values = view_box
+      .split(|c: char| c == ',' || c.is_whitespace())
+      .filter(|v| !v.is_empty())
+      .map(|v| v.parse::<f32>().map_err(|_| format!("invalid viewBox value {}", v)))
+      .collect::<Result<Vec<_>, _>>()?;
+    if values.len() != 4 {
+      return Err(format!("viewBox needs 4 values, got {}", view_box));
+    }
+    if values[2] <= 0.0 || values[3] <= 0.0 {
+      return Err(format!("viewBox width and height must be positive, got {}", view_box));
+    }
+    return Ok(ViewBox { x: values[0], y: values[1], width: values[2], height: values[3] });
+  }
+}
+
+impl std::fmt::Display for ViewBox {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{} {} {} {}", self.x, self.y, self.width, self.height)
+  }
+}
+
+// What a stroke's speed is shown as, for analysing handwriting dynamics
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpeedStyle {
+  // slow strokes blue, fast ones red
+  Color,
+  // fast strokes fade out
+  Opacity
+}
+
+impl SpeedStyle {
+  pub fn by_name(name: &str) -> Option<SpeedStyle> {
+    return match name.trim().to_lowercase().as_str() {
+      "color" | "colour" => Some(SpeedStyle::Color),
+      "opacity" => Some(SpeedStyle::Opacity),
+      _ => None
+    };
+  }
+}
+
+// The SVG element strokes are drawn with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StrokeElement {
+  Path,
+  // for CNC and engraving software that only reads <polyline>
+  Polyline
+}
+
+impl StrokeElement {
+  pub fn by_name(name: &str) -> Option<StrokeElement> {
+    return match name.trim().to_lowercase().as_str() {
+      "path" => Some(StrokeElement::Path),
+      "polyline" => Some(StrokeElement::Polyline),
+      _ => None
+    };
+  }
+}
+
+// CSS for the classes strokes are given, as a stylesheet linked from the
+// SVG or written into it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stylesheet {
+  Linked(String),
+  Embedded(String)
+}
+
+impl Stylesheet {
+  pub fn css(&self) -> String {
+    return match self {
+      Stylesheet::Linked(href) => format!("@import url(\"{}\");", href.replace('"', "%22")),
+      Stylesheet::Embedded(css) => css.clone()
+    };
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+  pub view_box: ViewBox,
+  pub width: Option<Length>,
+  pub height: Option<Length>,
+  // output length per canvas pixel, used for any dimension not given
+  // explicitly; e.g. "0.1124mm" gives the tablet's physical size
+  pub scale: Length,
+  // when set, the view box is shrunk to the strokes' bounding box plus
+  // this margin (in canvas pixels)
+  pub crop_to_content: Option<f32>,
+  // fill color for a rect behind the strokes; None leaves it transparent
+  pub background: Option<String>,
+  // page template drawn between the background and the strokes
+  pub template: Option<Template>,
+  // overrides for the default stroke colors
+  pub color_map: ColorMap,
+  // upper bound on the number of points in the document, met by
+  // simplifying strokes as little as possible
+  pub max_output_points: Option<usize>,
+  // strokes simplified so they stray no further than this (in canvas
+  // pixels) from what was recorded
+  pub tolerance: Option<f32>,
+  // strokes smoothed by averaging each point with this many either side,
+  // before any simplifying
+  pub smoothing: Option<usize>,
+  // strokes drawn nearly as lines, rectangles, circles or ellipses drawn
+  // as those shapes
+  pub snap_shapes: bool,
+  // 1-based numbers of the layers to draw; None draws them all
+  pub layers: Option<Vec<usize>>,
+  // 1-based numbers of layers left out, of those drawn otherwise
+  pub exclude_layers: Vec<usize>,
+  // the layers' names in the notebook, in order, which label their groups
+  // as Inkscape layers; unnamed layers are plain groups
+  pub layer_names: Vec<String>,
+  // the tablet's 1-based numbers for the layers given, when they're only
+  // some of the page's, e.g. one split off with --split-layers; empty when
+  // they're all of them, in order
+  pub layer_numbers: Vec<usize>,
+  // light strokes for dark backgrounds; doesn't set a background itself,
+  // see DARK_BACKGROUND
+  pub dark: bool,
+  // merge same-styled paths and drop redundant markup
+  pub optimize: bool,
+  // CSS animation that draws the strokes in their original order
+  pub animate: bool,
+  // shows the recorded pen speed instead of the brushes' own look
+  pub speed_style: Option<SpeedStyle>,
+  // wraps each word or sketch (strokes drawn in a row, close together)
+  // in a group of its own
+  pub group_strokes: bool,
+  // groups each layer's strokes by brush, classed with the brush's name,
+  // drawing all of a brush's strokes together
+  pub group_by_brush: bool,
+  pub watermark: Option<Watermark>,
+  pub element: StrokeElement,
+  // one sub-path per change in the tablet's recorded stroke width, for
+  // renderers without any variable width tricks
+  pub segment_widths: bool,
+  // each stroke as one filled polygon around its centerline, as wide as
+  // the tablet recorded at every point
+  pub outline: bool,
+  // data-brush, data-color, data-size and data-layer on every stroke, for
+  // scripts working with the output
+  pub data_attributes: bool,
+  // classes on every stroke for its brush, color and layer, e.g.
+  // "brush-fineliner color-black layer-1", to style with CSS
+  pub css_classes: bool,
+  pub stylesheet: Option<Stylesheet>,
+  // degrees the page is turned clockwise, 90, 180 or 270, view box and
+  // all; quarter turns swap its width and height
+  pub rotation: u32,
+  // decimal places stroke coordinates are rounded to; None writes them as
+  // recorded, with every digit an f32 has
+  pub precision: Option<u32>,
+  // selection and eraser strokes, which the tablet doesn't show as lines,
+  // drawn anyway for looking into what a page holds
+  pub include_selection: bool,
+  pub include_erasers: bool,
+  // only this many lines drawn, the first in the order they were, with the
+  // page laid out as for all of them; for the frames of a time-lapse
+  pub drawn_lines: Option<usize>,
+  // handwriting recognized with an external command, for search
+  pub ocr: Option<Ocr>,
+  // the tablet pages were written on, which sets the size of the canvas;
+  // None tells from each page's strokes
+  pub device: Option<Device>
+}
+
+pub const DARK_BACKGROUND: &str = "#1e1e1e";
+
+// Hundredths of a canvas pixel are far finer than the pen is, and more
+// digits than that only make files bigger
+pub const DEFAULT_PRECISION: u32 = 2;
+// f32 coordinates on the canvas have no more digits than this after the
+// point
+pub const MAX_PRECISION: u32 = 6;
+
+// In dark mode highlighters and shaders are screened onto the page, which
+// only ever lightens: light strokes underneath stay readable, but the tint
+// is fainter than multiplying onto white, so it is made more opaque.
+const DARK_HIGHLIGHTER_OPACITY: f32 = 1.6;
+
+impl Default for RenderOptions {
+  fn default() -> RenderOptions {
+    return RenderOptions {
+      view_box: ViewBox::whole(Device::Remarkable.canvas()),
+      width: None,
+      height: None,
+      scale: Length::px(1.0),
+      crop_to_content: None,
+      background: None,
+      template: None,
+      color_map: ColorMap::new(),
+      max_output_points: None,
+      tolerance: None,
+      smoothing: None,
+      snap_shapes: false,
+      layers: None,
+      exclude_layers: Vec::new(),
+      layer_names: Vec::new(),
+      layer_numbers: Vec::new(),
+      dark: false,
+      optimize: false,
+      animate: false,
+      speed_style: None,
+      group_strokes: false,
+      group_by_brush: false,
+      watermark: None,
+      element: StrokeElement::Path,
+      segment_widths: false,
+      outline: false,
+      data_attributes: false,
+      css_classes: false,
+      stylesheet: None,
+      rotation: 0,
+      precision: Some(DEFAULT_PRECISION),
+      include_selection: false,
+      include_erasers: false,
+      drawn_lines: None,
+      ocr: None,
+      device: None
+    };
+  }
+}
+
+// Setters for building options up from the defaults, e.g.
+// RenderOptions::default().with_background("white").with_layers(vec![1])
+impl RenderOptions {
+  pub fn with_view_box(self, view_box: ViewBox) -> RenderOptions {
+    return RenderOptions { view_box, ..self };
+  }
+
+  pub fn with_size(self, width: Length, height: Length) -> RenderOptions {
+    return RenderOptions { width: Some(width), height: Some(height), ..self };
+  }
+
+  pub fn with_scale(self, scale: Length) -> RenderOptions {
+    return RenderOptions { scale, ..self };
+  }
+
+  pub fn with_crop_to_content(self, margin: f32) -> RenderOptions {
+    return RenderOptions { crop_to_content: Some(margin), ..self };
+  }
+
+  pub fn with_background(self, color: &str) -> RenderOptions {
+    return RenderOptions { background: Some(String::from(color)), ..self };
+  }
+
+  pub fn with_template(self, template: Template) -> RenderOptions {
+    return RenderOptions { template: Some(template), ..self };
+  }
+
+  pub fn with_color(mut self, brush_color: BrushColor, color: &str) -> RenderOptions {
+    self.color_map.insert(brush_color, String::from(color));
+    return self;
+  }
+
+  pub fn with_color_map(self, color_map: ColorMap) -> RenderOptions {
+    return RenderOptions { color_map, ..self };
+  }
+
+  pub fn with_max_output_points(self, budget: usize) -> RenderOptions {
+    return RenderOptions { max_output_points: Some(budget), ..self };
+  }
+
+  pub fn with_tolerance(self, tolerance: f32) -> RenderOptions {
+    return RenderOptions { tolerance: Some(tolerance), ..self };
+  }
+
+  pub fn with_smoothing(self, window: usize) -> RenderOptions {
+    return RenderOptions { smoothing: Some(window), ..self };
+  }
+
+  pub fn with_snap_shapes(self, snap_shapes: bool) -> RenderOptions {
+    return RenderOptions { snap_shapes, ..self };
+  }
+
+  pub fn with_ocr(self, ocr: Ocr) -> RenderOptions {
+    return RenderOptions { ocr: Some(ocr), ..self };
+  }
+
+  pub fn with_layers(self, layers: Vec<usize>) -> RenderOptions {
+    return RenderOptions { layers: Some(layers), ..self };
+  }
+
+  pub fn with_exclude_layers(self, exclude_layers: Vec<usize>) -> RenderOptions {
+    return RenderOptions { exclude_layers, ..self };
+  }
+
+  pub fn with_layer_names(self, layer_names: Vec<String>) -> RenderOptions {
+    return RenderOptions { layer_names, ..self };
+  }
+
+  pub fn with_layer_numbers(self, layer_numbers: Vec<usize>) -> RenderOptions {
+    return RenderOptions { layer_numbers, ..self };
+  }
+
+  pub fn with_dark(self, dark: bool) -> RenderOptions {
+    return RenderOptions { dark, ..self };
+  }
+
+  pub fn with_optimize(self, optimize: bool) -> RenderOptions {
+    return RenderOptions { optimize, ..self };
+  }
+
+  pub fn with_animate(self, animate: bool) -> RenderOptions {
+    return RenderOptions { animate, ..self };
+  }
+
+  pub fn with_speed_style(self, speed_style: SpeedStyle) -> RenderOptions {
+    return RenderOptions { speed_style: Some(speed_style), ..self };
+  }
+
+  pub fn with_group_strokes(self, group_strokes: bool) -> RenderOptions {
+    return RenderOptions { group_strokes, ..self };
+  }
+
+  pub fn with_group_by_brush(self, group_by_brush: bool) -> RenderOptions {
+    return RenderOptions { group_by_brush, ..self };
+  }
+
+  pub fn with_watermark(self, watermark: Watermark) -> RenderOptions {
+    return RenderOptions { watermark: Some(watermark), ..self };
+  }
+
+  pub fn with_element(self, element: StrokeElement) -> RenderOptions {
+    return RenderOptions { element, ..self };
+  }
+
+  pub fn with_segment_widths(self, segment_widths: bool) -> RenderOptions {
+    return RenderOptions { segment_widths, ..self };
+  }
+
+  pub fn with_outline(self, outline: bool) -> RenderOptions {
+    return RenderOptions { outline, ..self };
+  }
+
+  pub fn with_data_attributes(self, data_attributes: bool) -> RenderOptions {
+    return RenderOptions { data_attributes, ..self };
+  }
+
+  pub fn with_css_classes(self, css_classes: bool) -> RenderOptions {
+    return RenderOptions { css_classes, ..self };
+  }
+
+  pub fn with_stylesheet(self, stylesheet: Stylesheet) -> RenderOptions {
+    return RenderOptions { stylesheet: Some(stylesheet), ..self };
+  }
+
+  pub fn with_rotation(self, rotation: u32) -> RenderOptions {
+    return RenderOptions { rotation, ..self };
+  }
+
+  pub fn with_precision(self, precision: Option<u32>) -> RenderOptions {
+    return RenderOptions { precision, ..self };
+  }
+
+  pub fn with_include_selection(self, include_selection: bool) -> RenderOptions {
+    return RenderOptions { include_selection, ..self };
+  }
+
+  pub fn with_include_erasers(self, include_erasers: bool) -> RenderOptions {
+    return RenderOptions { include_erasers, ..self };
+  }
+
+  pub fn with_drawn_lines(self, drawn_lines: usize) -> RenderOptions {
+    return RenderOptions { drawn_lines: Some(drawn_lines), ..self };
+  }
+
+  pub fn with_device(self, device: Device) -> RenderOptions {
+    return RenderOptions { device: Some(device), ..self };
+  }
+}
+
+impl RenderOptions {
+  // whether the layer with this 1-based number is drawn
+  pub fn draws_layer(&self, number: usize) -> bool {
+    return self.layers.as_ref().is_none_or(|numbers| numbers.contains(&number)) && !self.exclude_layers.contains(&number);
+  }
+
+  // The layers hidden on the tablet, by number, left out too, as the
+  // tablet shows the page, unless `layers` already picks which to draw
+  pub fn without_hidden_layers(self, hidden_layers: &[usize]) -> RenderOptions {
+    if self.layers.is_some() {
+      return self;
+    }
+    let mut exclude_layers = self.exclude_layers.clone();
+    exclude_layers.extend(hidden_layers.iter().filter(|number| !self.exclude_layers.contains(number)));
+    return RenderOptions { exclude_layers, ..self };
+  }
+
+  // The canvas' width and height, the reMarkable 1 and 2's unless the
+  // device is known to be another
+  pub fn canvas(&self) -> (f32, f32) {
+    return self.device.unwrap_or(Device::Remarkable).canvas();
+  }
+
+  // whether lines of this brush are drawn, selections and erasers only when
+  // asked for
+  pub fn draws_brush(&self, brush: BrushType) -> bool {
+    return match brush {
+      BrushType::SelectionBrush1 | BrushType::SelectionBrush2 => self.include_selection,
+      BrushType::Eraser | BrushType::RubberArea | BrushType::EraseAll => self.include_erasers,
+      _ => true
+    };
+  }
+
+  pub fn stroke_color(&self, color: BrushColor) -> &str {
+    let default = match self.dark {
+      true => color.dark_rgb(),
+      false => color.default_rgb()
+    };
+    return self.color_map.get(&color).map_or(default, |c| c.as_str());
+  }
+
+  // stroke_color, with dark mode's own hues for highlighters
+  pub fn brush_color(&self, brush_type: BrushType, color: BrushColor) -> &str {
+    if self.dark && brush_type.is_highlighter() && !self.color_map.contains_key(&color) {
+      return color.dark_highlighter_rgb();
+    }
+    return self.stroke_color(color);
+  }
+
+  pub fn brush_style(&self, brush_type: BrushType) -> BrushStyle {
+    let style = brush_type.style();
+    if self.dark && brush_type.tints() {
+      return BrushStyle {
+        opacity: (style.opacity * DARK_HIGHLIGHTER_OPACITY).min(1.0),
+        blend_mode: Some("screen"),
+        ..style
+      };
+    }
+    return style;
+  }
+
+  // width and height of the document, keeping the view box aspect ratio
+  // when only one of them is given
+  pub fn dimensions(&self) -> (Length, Length) {
+    let aspect = self.view_box.width / self.view_box.height;
+    return match (&self.width, &self.height) {
+      (Some(width), Some(height)) => (width.clone(), height.clone()),
+      (Some(width), None) => (width.clone(), width.times(1.0 / aspect)),
+      (None, Some(height)) => (height.times(aspect), height.clone()),
+      (None, None) => (
+        self.scale.times(self.view_box.width),
+        self.scale.times(self.view_box.height)
+      )
+    };
+  }
+}
@@ -0,0 +1,228 @@
+use svg::node::element::Group as SVGGroup;
+
+use crate::device::Device;
+use crate::options::{RenderOptions, ViewBox};
+use crate::recognize::is_writing;
+use crate::{Document, Layer, Line, LogLevel};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Orientation {
+  Upright,
+  // written with the tablet turned around
+  UpsideDown
+}
+
+// Pages are only turned around when at least this sure
+pub const MIN_ROTATE_CONFIDENCE: f32 = 0.5;
+// Fewer pairs of strokes than this say little about the writing direction
+const MIN_ORIENTATION_VOTES: usize = 8;
+
+#[derive(Debug, Copy, Clone)]
+struct Extent {
+  left: f32,
+  top: f32,
+  right: f32,
+  bottom: f32
+}
+
+fn extent(line: &Line) -> Extent {
+  return line.points.iter().fold(
+    Extent { left: f32::MAX, top: f32::MAX, right: f32::MIN, bottom: f32::MIN },
+    |extent, point| Extent {
+      left: extent.left.min(point.x),
+      top: extent.top.min(point.y),
+      right: extent.right.max(point.x),
+      bottom: extent.bottom.max(point.y)
+    }
+  );
+}
+
+// Which way up a page was written, from the order its strokes were drawn
+// in: writing moves rightwards along a line of text and down to the next
+// one, so on a page written upside down consecutive strokes mostly move
+// left and up instead. Each pair of consecutive strokes votes by the way
+// it moved; the confidence is the majority's margin, from 0 for a tie to
+// 1 when every vote agrees. None with too few votes to tell.
+pub fn detect_orientation(layers: &[Layer]) -> Option<(Orientation, f32)> {
+  let extents = layers.iter()
+    .flat_map(|layer| layer.lines.iter())
+    .filter(|line| is_writing(line) && !line.points.is_empty())
+    .map(extent)
+    .collect::<Vec<_>>();
+  let (upright, upside_down) = extents.windows(2).fold((0, 0), |(upright, upside_down), pair| {
+    let (a, b) = (pair[0], pair[1]);
+    let height = (a.bottom - a.top).max(b.bottom - b.top).max(1.0);
+    let overlap = a.bottom.min(b.bottom) - a.top.max(b.top);
+    let (dx, dy) = ((b.left + b.right - a.left - a.right) / 2.0, (b.top + b.bottom - a.top - a.bottom) / 2.0);
+    // on the same line of text, the next stroke is further along it
+    let forward = match overlap > height * 0.3 {
+      true if dx.abs() > 2.0 => Some(dx > 0.0),
+      true => None,
+      // a new line of text starts below the last one
+      false if dy.abs() > height => Some(dy > 0.0),
+      false => None
+    };
+    return match forward {
+      Some(true) => (upright + 1, upside_down),
+      Some(false) => (upright, upside_down + 1),
+      None => (upright, upside_down)
+    };
+  });
+  let votes = upright + upside_down;
+  if votes < MIN_ORIENTATION_VOTES { return None; }
+  let confidence = (upright as f32 - upside_down as f32).abs() / votes as f32;
+  return match upside_down > upright {
+    true => Some((Orientation::UpsideDown, confidence)),
+    false => Some((Orientation::Upright, confidence))
+  };
+}
+
+// How far landscape notebooks are turned to be read the way they were
+// written: xochitl keeps their strokes on the portrait canvas, as they
+// land with the tablet turned anticlockwise
+pub const LANDSCAPE_ROTATION: u32 = 270;
+
+// The canvas' width and height once turned `rotation` degrees clockwise
+pub fn turned_canvas((width, height): (f32, f32), rotation: u32) -> (f32, f32) {
+  return match rotation % 360 {
+    90 | 270 => (height, width),
+    _ => (width, height)
+  };
+}
+
+// The page turned `rotation` degrees clockwise, a multiple of 90, onto the
+// canvas turned with it
+pub fn rotate_layers(layers: &[Layer], (width, height): (f32, f32), rotation: u32) -> Vec<Layer> {
+  let rotation = rotation % 360;
+  let angle = rotation as f32 / 180.0 * std::f32::consts::PI;
+  let mut rotated = layers.to_vec();
+  rotated.iter_mut()
+    .flat_map(|layer| layer.lines.iter_mut())
+    .flat_map(|line| line.points.iter_mut())
+    .for_each(|point| {
+      let (x, y) = match rotation {
+        90 => (height - point.y, point.x),
+        180 => (width - point.x, height - point.y),
+        270 => (point.y, width - point.x),
+        _ => (point.x, point.y)
+      };
+      point.x = x;
+      point.y = y;
+      point.direction = (point.direction + angle) % std::f32::consts::TAU;
+    });
+  return rotated;
+}
+
+// A region of the portrait canvas, where it is once turned as
+// rotate_layers turns the strokes
+pub fn rotate_view_box(view_box: ViewBox, (width, height): (f32, f32), rotation: u32) -> ViewBox {
+  let ViewBox { x, y, width: box_width, height: box_height } = view_box;
+  return match rotation % 360 {
+    90 => ViewBox { x: height - y - box_height, y: x, width: box_height, height: box_width },
+    180 => ViewBox { x: width - x - box_width, y: height - y - box_height, ..view_box },
+    270 => ViewBox { x: y, y: width - x - box_width, width: box_height, height: box_width },
+    _ => view_box
+  };
+}
+
+// A group for elements placed in canvas coordinates, turned with the page
+// as rotate_layers turns the strokes
+pub fn canvas_group(options: &RenderOptions) -> SVGGroup {
+  let (width, height) = options.canvas();
+  return match options.rotation % 360 {
+    90 => SVGGroup::new().set("transform", format!("translate({} 0) rotate(90)", height)),
+    180 => SVGGroup::new().set("transform", format!("translate({} {}) rotate(180)", width, height)),
+    270 => SVGGroup::new().set("transform", format!("translate(0 {}) rotate(270)", width)),
+    _ => SVGGroup::new()
+  };
+}
+
+// The page turned by 180 degrees about its center
+pub fn rotate_half_turn(layers: &[Layer]) -> Vec<Layer> {
+  return rotate_layers(layers, Device::detect(layers).canvas(), 180);
+}
+
+// Turns a page written with the tablet upside down the right way up, when
+// sure enough that it was; `label` names the page in what is logged. Typed
+// text is always set the right way up, so pages with any are left be.
+pub fn auto_rotate(document: Document, label: &str, logger: &dyn Fn(LogLevel, String)) -> Document {
+  if !document.text.is_empty() {
+    logger(LogLevel::INFO, format!("{} has typed text, leaving it the way up the text is", label));
+    return document;
+  }
+  return match detect_orientation(&document.layers) {
+    Some((Orientation::UpsideDown, confidence)) if confidence >= MIN_ROTATE_CONFIDENCE => {
+      logger(LogLevel::INFO, format!("{} is upside down, rotating it (confidence {:.2})", label, confidence));
+      Document { layers: rotate_half_turn(&document.layers), ..document }
+    },
+    Some((Orientation::UpsideDown, confidence)) => {
+      logger(LogLevel::INFO, format!("{} may be upside down, leaving it (confidence {:.2})", label, confidence));
+      document
+    },
+    Some((Orientation::Upright, confidence)) => {
+      logger(LogLevel::INFO, format!("{} is upright (confidence {:.2})", label, confidence));
+      document
+    },
+    None => {
+      logger(LogLevel::INFO, format!("too few strokes to tell which way up {} is", label));
+      document
+    }
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::brush::{BrushColor, BrushType};
+  use crate::text::{Paragraph, ParagraphStyle, TextBlock};
+  use crate::Point;
+
+  fn quiet(_: LogLevel, _: String) {}
+
+  // Three lines of six letters, written the right way up and then turned
+  // around
+  fn upside_down() -> Document {
+    let letter = |row: usize, column: usize| {
+      let (x, y) = (100.0 + 60.0 * column as f32, 300.0 + 120.0 * row as f32);
+      let points = (0..8)
+        .map(|index| Point { x: x + 5.0 * index as f32, y: y + 40.0 * (index % 2) as f32, speed: 1.0, direction: 0.0, width: 2.0, pressure: 0.5 })
+        .collect::<Vec<_>>();
+      return Line {
+        brush_type: BrushType::Fineliner,
+        brush_color: BrushColor::Black,
+        padding: 0,
+        brush_size: 2.0,
+        unknown: 0.0,
+        num_points: points.len() as i32,
+        points
+      };
+    };
+    let lines = (0..3).flat_map(|row| (0..6).map(move |column| letter(row, column))).collect::<Vec<_>>();
+    let layers = vec![Layer { num_lines: lines.len() as i32, lines }];
+    return Document { version: 5, layers: rotate_half_turn(&layers), text: Vec::new(), warnings: Vec::new() };
+  }
+
+  #[test]
+  fn turns_upside_down_pages_around() {
+    let document = upside_down();
+    let (orientation, confidence) = detect_orientation(&document.layers).unwrap();
+    assert_eq!(orientation, Orientation::UpsideDown);
+    assert!(confidence >= MIN_ROTATE_CONFIDENCE);
+    let rotated = auto_rotate(document, "page", &quiet);
+    assert_eq!(detect_orientation(&rotated.layers).unwrap().0, Orientation::Upright);
+  }
+
+  #[test]
+  fn leaves_pages_with_typed_text_be() {
+    let text = vec![TextBlock {
+      x: 100.0,
+      y: 200.0,
+      width: 800.0,
+      paragraphs: vec![Paragraph { style: ParagraphStyle::Plain, text: String::from("typed") }]
+    }];
+    let document = Document { text: text.clone(), ..upside_down() };
+    let left = auto_rotate(document.clone(), "page", &quiet);
+    assert_eq!(left.layers, document.layers);
+    assert_eq!(left.text, text);
+  }
+}
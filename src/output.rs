@@ -0,0 +1,220 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use svg::Document as SVGDocument;
+
+use crate::deflate::gzip;
+use crate::device::Device;
+use crate::eps::write_eps;
+use crate::excalidraw::write_excalidraw;
+use crate::gcode::{write_gcode, GcodeOptions};
+use crate::html::{write_html, HtmlPage};
+use crate::json::Value;
+use crate::options::RenderOptions;
+use crate::pdf::write_pdf;
+use crate::png::write_png;
+use crate::raster::rasterize;
+use crate::scene::scene;
+use crate::timelapse::write_timelapse;
+use crate::Document;
+
+// CSS pixels are 1/96 of an inch
+pub const DEFAULT_DPI: f32 = 96.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+  Svg,
+  // gzipped SVG
+  Svgz,
+  Pdf,
+  Png { dpi: f32 },
+  // a time-lapse of the page being drawn, as an animated PNG
+  Apng { dpi: f32 },
+  // the parsed strokes rather than a picture of them
+  Json,
+  // a row per point of the parsed strokes
+  Csv,
+  Eps,
+  // tool paths along the parsed strokes
+  Gcode(GcodeOptions),
+  // a whole notebook in a single page
+  Html,
+  // a scene of freehand drawings to edit further
+  Excalidraw
+}
+
+pub const OUTPUT_FORMATS: [&str; 11] = ["svg", "svgz", "pdf", "png", "apng", "json", "csv", "eps", "gcode", "html", "excalidraw"];
+
+impl OutputFormat {
+  pub fn by_name(name: &str) -> Option<OutputFormat> {
+    return match name.trim().to_lowercase().as_str() {
+      "svg" => Some(OutputFormat::Svg),
+      "svgz" => Some(OutputFormat::Svgz),
+      "pdf" => Some(OutputFormat::Pdf),
+      "png" => Some(OutputFormat::Png { dpi: DEFAULT_DPI }),
+      "apng" => Some(OutputFormat::Apng { dpi: DEFAULT_DPI }),
+      "json" => Some(OutputFormat::Json),
+      "csv" => Some(OutputFormat::Csv),
+      "eps" => Some(OutputFormat::Eps),
+      "gcode" | "nc" => Some(OutputFormat::Gcode(GcodeOptions::default())),
+      "html" | "htm" => Some(OutputFormat::Html),
+      "excalidraw" => Some(OutputFormat::Excalidraw),
+      _ => None
+    };
+  }
+
+  // by a file name's extension, e.g. notes.pdf
+  pub fn from_path(path: &str) -> Option<OutputFormat> {
+    return Path::new(path).extension().and_then(|extension| OutputFormat::by_name(&extension.to_string_lossy()));
+  }
+
+  pub fn extension(&self) -> &'static str {
+    return match self {
+      OutputFormat::Svg => "svg",
+      OutputFormat::Svgz => "svgz",
+      OutputFormat::Pdf => "pdf",
+      OutputFormat::Png { .. } => "png",
+      OutputFormat::Apng { .. } => "apng",
+      OutputFormat::Json => "json",
+      OutputFormat::Csv => "csv",
+      OutputFormat::Eps => "eps",
+      OutputFormat::Gcode(_) => "gcode",
+      OutputFormat::Html => "html",
+      OutputFormat::Excalidraw => "excalidraw"
+    };
+  }
+}
+
+// Layers, lines and points as they were read, in the tablet's
+// coordinates, with the brush of each line and any warnings
+fn document_json(page: &Document) -> Value {
+  let number = |n: f32| Value::Number(n as f64);
+  let (width, height) = Device::detect(&page.layers).canvas();
+  let layers = page.layers.iter()
+    .map(|layer| Value::Object(vec![(String::from("lines"), Value::Array(layer.lines.iter()
+      .map(|line| Value::Object(vec![
+        (String::from("brush"), Value::String(String::from(line.brush_type.name()))),
+        (String::from("color"), Value::String(String::from(line.brush_color.name()))),
+        (String::from("size"), number(line.brush_size)),
+        (String::from("points"), Value::Array(line.points.iter()
+          .map(|point| Value::Object(vec![
+            (String::from("x"), number(point.x)),
+            (String::from("y"), number(point.y)),
+            (String::from("speed"), number(point.speed)),
+            (String::from("direction"), number(point.direction)),
+            (String::from("width"), number(point.width)),
+            (String::from("pressure"), number(point.pressure))
+          ]))
+          .collect()))
+      ]))
+      .collect()))]))
+    .collect();
+  let warnings = page.warnings.iter()
+    .map(|warning| Value::Object(vec![
+      (String::from("code"), Value::String(warning.code.to_string())),
+      (String::from("message"), Value::String(warning.message.clone()))
+    ]))
+    .collect();
+  return Value::Object(vec![
+    (String::from("width"), number(width)),
+    (String::from("height"), number(height)),
+    (String::from("layers"), Value::Array(layers)),
+    (String::from("warnings"), Value::Array(warnings))
+  ]);
+}
+
+// Layers and lines are numbered from 1, as in warnings
+fn document_csv(page: &Document) -> String {
+  let rows = page.layers.iter().enumerate()
+    .flat_map(|(layer_index, layer)| layer.lines.iter().enumerate().map(move |(line_index, line)| (layer_index, line_index, line)))
+    .flat_map(|(layer_index, line_index, line)| line.points.iter().map(move |point| format!(
+      "{},{},{},{},{},{},{},{},{}\n",
+      layer_index + 1, line_index + 1, line.brush_type.name(),
+      point.x, point.y, point.speed, point.direction, point.width, point.pressure
+    )));
+  return std::iter::once(String::from("layer,line,brush,x,y,speed,direction,width,pressure\n")).chain(rows).collect();
+}
+
+// A page as a file of the given format, from what was parsed or from how
+// it was rendered with `options`; time-lapses render it again, a frame at
+// a time
+pub fn encode(page: &Document, document: &SVGDocument, options: &RenderOptions, format: OutputFormat) -> Result<Vec<u8>, String> {
+  return match format {
+    OutputFormat::Svg => Ok(document.to_string().into_bytes()),
+    OutputFormat::Svgz => Ok(gzip(document.to_string().as_bytes())),
+    OutputFormat::Pdf => scene(document).map(|scene| write_pdf(&[scene])),
+    OutputFormat::Png { dpi } => scene(document).map(|scene| write_png(&rasterize(&scene, dpi / DEFAULT_DPI))),
+    OutputFormat::Apng { dpi } => write_timelapse(page, options, dpi / DEFAULT_DPI),
+    OutputFormat::Json => Ok(format!("{}\n", document_json(page)).into_bytes()),
+    OutputFormat::Csv => Ok(document_csv(page).into_bytes()),
+    OutputFormat::Eps => scene(document).map(|scene| write_eps(&scene)),
+    OutputFormat::Gcode(options) => Ok(write_gcode(&page.layers, &options).into_bytes()),
+    OutputFormat::Html => {
+      let page = HtmlPage { number: 1, title: None, svgs: vec![document.to_string()] };
+      Ok(write_html("Page 1", &[page]).into_bytes())
+    },
+    OutputFormat::Excalidraw => Ok(write_excalidraw(&page.layers).into_bytes())
+  };
+}
+
+// Destination for finished outputs. Each call hands over one complete
+// file, so sinks never see partial documents.
+pub trait OutputSink {
+  fn write_output(&mut self, name: &str, contents: &[u8]) -> Result<(), String>;
+
+  // Sets the modification time of an output already written; sinks without
+  // a notion of file times can ignore it.
+  fn set_modified(&mut self, _name: &str, _modified: SystemTime) -> Result<(), String> {
+    return Ok(());
+  }
+}
+
+// Writes to the filesystem via a temporary file in the same directory that
+// is renamed into place only once it has been fully written and synced, so
+// an interrupted run never leaves a truncated file under the real name.
+#[derive(Debug, Default)]
+pub struct FileSink;
+
+impl OutputSink for FileSink {
+  fn write_output(&mut self, name: &str, contents: &[u8]) -> Result<(), String> {
+    let path = Path::new(name);
+    let file_name = path.file_name()
+      .ok_or_else(|| format!("output {} is not a file path", name))?
+      .to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let written = File::create(&temp_path)
+      .and_then(|mut file| {
+        file.write_all(contents)?;
+        return file.sync_all();
+      })
+      .and_then(|_| fs::rename(&temp_path, path));
+    return written.map_err(|why| {
+      let _ = fs::remove_file(&temp_path);
+      return format!("couldn't write {}, {}", name, why);
+    });
+  }
+
+  fn set_modified(&mut self, name: &str, modified: SystemTime) -> Result<(), String> {
+    return OpenOptions::new()
+      .write(true)
+      .open(name)
+      .and_then(|file| file.set_modified(modified))
+      .map_err(|why| format!("couldn't set modification time of {}, {}", name, why));
+  }
+}
+
+// Collects outputs in memory, e.g. for servers or tests
+#[derive(Debug, Default)]
+pub struct MemorySink {
+  pub outputs: Vec<(String, Vec<u8>)>
+}
+
+impl OutputSink for MemorySink {
+  fn write_output(&mut self, name: &str, contents: &[u8]) -> Result<(), String> {
+    self.outputs.push((String::from(name), contents.to_vec()));
+    return Ok(());
+  }
+}
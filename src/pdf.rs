@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use crate::scene::{Anchor, Blend, Item, Label, LineCap, LineJoin, Paint, Scene, Segment, Shape};
+
+// PDF points per CSS pixel
+pub const PT_PER_PX: f32 = 0.75;
+// Helvetica's average advance, in ems, for anchoring labels that don't
+// start at their x
+const HELVETICA_ADVANCE: f32 = 0.556;
+const HELVETICA_BOLD_ADVANCE: f32 = 0.611;
+
+pub fn number(value: f32) -> String {
+  let formatted = format!("{:.3}", value);
+  let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+  return match trimmed {
+    "" | "-" | "-0" => String::from("0"),
+    _ => String::from(trimmed)
+  };
+}
+
+// Latin-1 as octal escapes, which is close enough to the standard
+// fonts' WinAnsiEncoding for the text we write
+pub fn pdf_string(text: &str) -> String {
+  return text.chars()
+    .map(|c| match c {
+      '(' | ')' | '\\' => format!("\\{}", c),
+      ' '..='~' => c.to_string(),
+      _ if (c as u32) < 0x100 => format!("\\{:03o}", c as u32),
+      _ => String::from("?")
+    })
+    .collect();
+}
+
+// Names the transparency and blending states a page uses
+#[derive(Default)]
+struct GraphicsStates {
+  names: HashMap<String, String>
+}
+
+impl GraphicsStates {
+  fn name(&mut self, fill_opacity: f32, stroke_opacity: f32, blend: Blend) -> String {
+    let blend_mode = match blend {
+      Blend::Normal => "Normal",
+      Blend::Multiply => "Multiply",
+      Blend::Screen => "Screen"
+    };
+    let state = format!("<< /ca {} /CA {} /BM /{} >>", number(fill_opacity), number(stroke_opacity), blend_mode);
+    let count = self.names.len();
+    return self.names.entry(state).or_insert_with(|| format!("GS{}", count)).clone();
+  }
+
+  fn resources(&self) -> String {
+    let mut states = self.names.iter().map(|(state, name)| format!("/{} {}", name, state)).collect::<Vec<_>>();
+    states.sort();
+    return states.join(" ");
+  }
+}
+
+fn color(paint: &Paint, operator: &str) -> String {
+  return format!("{} {} {} {}", number(paint.color.red), number(paint.color.green), number(paint.color.blue), operator);
+}
+
+fn shape_operators(shape: &Shape, states: &mut GraphicsStates) -> String {
+  let mut operators = vec![String::from("q")];
+  let fill_opacity = shape.fill.map_or(1.0, |fill| fill.opacity);
+  let stroke_opacity = shape.stroke.as_ref().map_or(1.0, |stroke| stroke.paint.opacity);
+  operators.push(format!("/{} gs", states.name(fill_opacity, stroke_opacity, shape.blend)));
+  if let Some(fill) = &shape.fill {
+    operators.push(color(fill, "rg"));
+  }
+  if let Some(stroke) = &shape.stroke {
+    operators.push(color(&stroke.paint, "RG"));
+    operators.push(format!("{} w", number(stroke.width)));
+    operators.push(format!("{} J", match stroke.cap { LineCap::Butt => 0, LineCap::Round => 1, LineCap::Square => 2 }));
+    operators.push(format!("{} j", match stroke.join { LineJoin::Miter => 0, LineJoin::Round => 1, LineJoin::Bevel => 2 }));
+    if !stroke.dashes.is_empty() {
+      operators.push(format!("[{}] 0 d", stroke.dashes.iter().map(|dash| number(*dash)).collect::<Vec<_>>().join(" ")));
+    }
+  }
+  shape.segments.iter().for_each(|segment| operators.push(match segment {
+    Segment::Move(x, y) => format!("{} {} m", number(*x), number(*y)),
+    Segment::Line(x, y) => format!("{} {} l", number(*x), number(*y)),
+    Segment::Close => String::from("h")
+  }));
+  operators.push(String::from(match (&shape.fill, &shape.stroke) {
+    (Some(_), Some(_)) => "B",
+    (Some(_), None) => "f",
+    (None, Some(_)) => "S",
+    (None, None) => "n"
+  }));
+  operators.push(String::from("Q"));
+  return operators.join("\n");
+}
+
+// Where a label's text starts, set in Helvetica
+pub fn label_start(label: &Label) -> f32 {
+  let advance = if label.bold { HELVETICA_BOLD_ADVANCE } else { HELVETICA_ADVANCE };
+  let width = label.text.chars().count() as f32 * advance * label.size;
+  return match label.anchor {
+    Anchor::Start => label.x,
+    Anchor::Middle => label.x - width / 2.0,
+    Anchor::End => label.x - width
+  };
+}
+
+fn label_operators(label: &Label, states: &mut GraphicsStates) -> String {
+  let x = label_start(label);
+  // glyphs are drawn upright in a page whose y axis points down
+  let [a, b, c, d, _, _] = label.transform.0;
+  let (origin_x, origin_y) = label.transform.apply(x, label.y);
+  return [
+    String::from("q"),
+    format!("/{} gs", states.name(label.paint.opacity, 1.0, Blend::Normal)),
+    color(&label.paint, "rg"),
+    String::from("BT"),
+    // invisible text, such as recognized handwriting, is set but not
+    // painted, so it can still be searched and selected
+    String::from(if label.paint.opacity == 0.0 { "3 Tr" } else { "0 Tr" }),
+    format!("/{} {} Tf", if label.bold { "F2" } else { "F1" }, number(label.size)),
+    format!("{} {} {} {} {} {} Tm", number(a), number(b), number(-c), number(-d), number(origin_x), number(origin_y)),
+    format!("({}) Tj", pdf_string(&label.text)),
+    String::from("ET"),
+    String::from("Q")
+  ].join("\n");
+}
+
+// The page's content stream and the resources it refers to
+pub fn page_contents(scene: &Scene) -> (String, String) {
+  let (width, height) = (scene.width * PT_PER_PX, scene.height * PT_PER_PX);
+  let view_box = &scene.view_box;
+  // fitted and centered, as SVG does by default, with y flipped to point
+  // down like it does in SVG
+  let scale = (width / view_box.width).min(height / view_box.height);
+  let (offset_x, offset_y) = ((width - view_box.width * scale) / 2.0, (height - view_box.height * scale) / 2.0);
+  let mut states = GraphicsStates::default();
+  let mut operators = vec![
+    format!("0 0 {} {} re W n", number(width), number(height)),
+    format!(
+      "{} 0 0 {} {} {} cm",
+      number(scale), number(-scale), number(offset_x - view_box.x * scale), number(height - offset_y + view_box.y * scale)
+    )
+  ];
+  scene.items.iter().for_each(|item| operators.push(match item {
+    Item::Shape(shape) => shape_operators(shape, &mut states),
+    Item::Label(label) => label_operators(label, &mut states)
+  }));
+  let resources = format!(
+    "<< /Font << /F1 {} /F2 {} >> /ExtGState << {} >> >>",
+    "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+    "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>",
+    states.resources()
+  );
+  return (operators.join("\n"), resources);
+}
+
+// A PDF with one page per scene, each the size of the scene's page
+pub fn write_pdf(scenes: &[Scene]) -> Vec<u8> {
+  // objects are numbered from 1: the catalog, the page tree, then a page
+  // and its contents for every scene
+  let page_ids = (0..scenes.len()).map(|index| 3 + index * 2).collect::<Vec<_>>();
+  let mut objects = vec![
+    String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+    format!(
+      "<< /Type /Pages /Kids [{}] /Count {} >>",
+      page_ids.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<_>>().join(" "),
+      scenes.len()
+    )
+  ];
+  scenes.iter().zip(page_ids.iter()).for_each(|(scene, page_id)| {
+    let (contents, resources) = page_contents(scene);
+    objects.push(format!(
+      "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources {} /Contents {} 0 R >>",
+      number(scene.width * PT_PER_PX), number(scene.height * PT_PER_PX), resources, page_id + 1
+    ));
+    objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", contents.len(), contents));
+  });
+
+  let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+  let offsets = objects.iter().enumerate()
+    .map(|(index, object)| {
+      let offset = pdf.len();
+      pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+      return offset;
+    })
+    .collect::<Vec<_>>();
+  let xref = pdf.len();
+  pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+  offsets.iter().for_each(|offset| pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes()));
+  pdf.extend_from_slice(format!(
+    "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref
+  ).as_bytes());
+  return pdf;
+}
@@ -0,0 +1,461 @@
+use svg::Node;
+use svg::node::element::Definitions as SVGDefinitions;
+use svg::node::element::Element as SVGElement;
+use svg::node::element::Filter as SVGFilter;
+use svg::node::element::Path as SVGPath;
+use svg::node::element::Polyline as SVGPolyline;
+use svg::node::element::path::{Command, Data as SVGData};
+
+use crate::brush::{BrushStyle, BrushType};
+use crate::options::{RenderOptions, SpeedStyle, StrokeElement};
+use crate::snap::Shape;
+use crate::{Line, Point};
+
+// A single SVG path before it is built, so that document level passes
+// (such as --optimize) can still inspect and merge what the pens drew.
+#[derive(Debug, Clone)]
+pub struct Stroke {
+  // presentation attributes, in output order
+  pub attributes: Vec<(&'static str, String)>,
+  pub data: SVGData,
+  // the shape it's drawn as instead of its data, if it was snapped to one
+  pub shape: Option<Shape>
+}
+
+impl Stroke {
+  pub fn new(color: &str, width: f32, opacity: f32, style: &BrushStyle, data: SVGData) -> Stroke {
+    let stroke = Stroke {
+      attributes: vec![
+        ("fill", String::from("none")),
+        ("stroke", String::from(color)),
+        ("stroke-width", width.to_string()),
+        ("stroke-opacity", opacity.to_string()),
+        ("stroke-linejoin", String::from(style.linejoin)),
+        ("stroke-linecap", String::from(style.linecap))
+      ],
+      data,
+      shape: None
+    };
+    return match style.blend_mode {
+      Some(blend_mode) => stroke.with_style(&format!("mix-blend-mode:{}", blend_mode)),
+      None => stroke
+    };
+  }
+
+  // A closed shape filled with the ink, rather than a line stroked with it
+  pub fn filled(color: &str, opacity: f32, style: &BrushStyle, data: SVGData) -> Stroke {
+    let outline = Stroke {
+      attributes: vec![
+        ("fill", String::from(color)),
+        ("fill-opacity", opacity.to_string()),
+        ("stroke", String::from("none"))
+      ],
+      data,
+      shape: None
+    };
+    return match style.blend_mode {
+      Some(blend_mode) => outline.with_style(&format!("mix-blend-mode:{}", blend_mode)),
+      None => outline
+    };
+  }
+
+  pub fn with(mut self, name: &'static str, value: String) -> Stroke {
+    self.attributes.push((name, value));
+    return self;
+  }
+
+  // Adds CSS declarations to the style attribute, creating it if needed
+  pub fn add_style(&mut self, declarations: &str) {
+    match self.attributes.iter_mut().find(|(name, _)| *name == "style") {
+      Some((_, style)) => {
+        style.push(';');
+        style.push_str(declarations);
+      },
+      None => self.attributes.push(("style", String::from(declarations)))
+    }
+  }
+
+  // Adds a class to the class attribute, creating it if needed
+  pub fn add_class(&mut self, class: &str) {
+    match self.attributes.iter_mut().find(|(name, _)| *name == "class") {
+      Some((_, classes)) => {
+        classes.push(' ');
+        classes.push_str(class);
+      },
+      None => self.attributes.push(("class", String::from(class)))
+    }
+  }
+
+  pub fn with_style(mut self, declarations: &str) -> Stroke {
+    self.add_style(declarations);
+    return self;
+  }
+
+  pub fn to_path(&self) -> SVGPath {
+    return self.attributes.iter().fold(
+      SVGPath::new(),
+      |acc_path, (name, value)| acc_path.set(*name, value.as_str())
+    ).set("d", self.data.clone());
+  }
+
+  // Polylines can't lift the pen, so data that moves more than once (as
+  // merged by --optimize) becomes one polyline per move
+  pub fn to_polylines(&self) -> Vec<SVGPolyline> {
+    let runs = self.data.iter().fold(Vec::<Vec<String>>::new(), |mut acc_runs, command| {
+      match command {
+        Command::Move(_, p) if p.len() >= 2 => acc_runs.push(vec![format!("{},{}", p[0], p[1])]),
+        Command::Line(_, p) if p.len() >= 2 => match acc_runs.last_mut() {
+          Some(run) => run.push(format!("{},{}", p[0], p[1])),
+          None => acc_runs.push(vec![format!("{},{}", p[0], p[1])])
+        },
+        _ => ()
+      }
+      return acc_runs;
+    });
+    return runs.into_iter()
+      .map(|run| {
+        return self.attributes.iter().fold(
+          SVGPolyline::new(),
+          |acc_polyline, (name, value)| acc_polyline.set(*name, value.as_str())
+        ).set("points", run.join(" "));
+      })
+      .collect();
+  }
+
+  // With its coordinates rounded to `digits` decimal places, if any
+  pub fn rounded(self, digits: Option<u32>) -> Stroke {
+    let factor = match digits {
+      Some(digits) => 10f32.powi(digits as i32),
+      None => return self
+    };
+    let round = |parameters: &[f32]| parameters.iter().map(|value| (value * factor).round() / factor).collect::<Vec<_>>().into();
+    let commands = self.data.iter()
+      .map(|command| match command {
+        Command::Move(position, parameters) => Command::Move(*position, round(parameters)),
+        Command::Line(position, parameters) => Command::Line(*position, round(parameters)),
+        command => command.clone()
+      })
+      .collect::<Vec<_>>();
+    return Stroke { data: SVGData::from(commands), shape: self.shape.map(|shape| shape.rounded(factor)), ..self };
+  }
+
+  // The <line>, <rect>, <circle> or <ellipse> of a snapped stroke
+  fn shape_element(&self, shape: Shape) -> SVGElement {
+    let (name, geometry) = match shape {
+      Shape::Line { from, to } => ("line", vec![("x1", from.0), ("y1", from.1), ("x2", to.0), ("y2", to.1)]),
+      Shape::Rectangle { left, top, width, height } => ("rect", vec![("x", left), ("y", top), ("width", width), ("height", height)]),
+      Shape::Circle { center, radius } => ("circle", vec![("cx", center.0), ("cy", center.1), ("r", radius)]),
+      Shape::Ellipse { center, radius_x, radius_y } => ("ellipse", vec![("cx", center.0), ("cy", center.1), ("rx", radius_x), ("ry", radius_y)])
+    };
+    let mut element = SVGElement::new(name);
+    geometry.into_iter().for_each(|(name, value)| element.assign(name, value));
+    self.attributes.iter().for_each(|(name, value)| element.assign(*name, value.as_str()));
+    return element;
+  }
+
+  pub fn append_to<P: Node>(&self, parent: &mut P, element: StrokeElement) {
+    if let Some(shape) = self.shape {
+      return parent.append(self.shape_element(shape));
+    }
+    match element {
+      StrokeElement::Path => parent.append(self.to_path()),
+      StrokeElement::Polyline => self.to_polylines().into_iter().for_each(|polyline| parent.append(polyline))
+    }
+  }
+}
+
+pub fn points_to_data<'a>(points: impl Iterator<Item = &'a Point>) -> SVGData {
+  return points.enumerate().fold(
+    SVGData::new(),
+    |acc_data, (index, next_point)| {
+      if index == 0 { return acc_data.move_to((next_point.x, next_point.y)); }
+      return acc_data.line_to((next_point.x, next_point.y));
+    }
+  );
+}
+
+// The strokes a line renders as, using the model for its brush
+pub fn line_strokes(line: &Line, options: &RenderOptions) -> Vec<Stroke> {
+  let style = options.brush_style(line.brush_type);
+  let color = options.brush_color(line.brush_type, line.brush_color);
+  if let Some(speed_style) = options.speed_style.filter(|_| line.points.len() > 1) {
+    return speed_mapped(line, &style, color, speed_style);
+  }
+  if options.outline && line.points.len() > 1 {
+    return vec![outline(line, &style, color)];
+  }
+  if options.segment_widths && line.points.len() > 1 {
+    return segment_widths(line, &style, color);
+  }
+  return match line.brush_type {
+    BrushType::Pen | BrushType::BallpointPen2 if line.points.len() > 1 => ballpoint(line, &style, color),
+    BrushType::PencilTilt | BrushType::PencilSharp | BrushType::MechanicalPencil | BrushType::Pencil2
+      if line.points.len() > 1 => pencil(line, &style, color),
+    BrushType::Calligraphy if line.points.len() > 1 => calligraphy(line, &style, color),
+    BrushType::Paintbrush | BrushType::Paintbrush2 if line.points.len() > 1 => paintbrush(line, &style, color),
+    _ => vec![plain(line, &style, color)]
+  };
+}
+
+// A snapped line as its shape, in its brush's color and width
+pub fn shape_stroke(line: &Line, shape: Shape, options: &RenderOptions) -> Stroke {
+  let style = options.brush_style(line.brush_type);
+  let color = options.brush_color(line.brush_type, line.brush_color);
+  return Stroke { shape: Some(shape), ..plain(line, &style, color) };
+}
+
+fn plain(line: &Line, style: &BrushStyle, color: &str) -> Stroke {
+  return Stroke::new(
+    color,
+    line.brush_size * style.width_scale,
+    style.opacity,
+    style,
+    points_to_data(line.points.iter())
+  );
+}
+
+// Ballpoint ink gets wider and darker with pressure and thins out (with
+// visible skips in the ink) when the pen moves quickly. The coefficients
+// follow rmrl's ballpoint model, which was fitted to device exports.
+const STRIATION_SPEED: f32 = 40.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct BallpointInk {
+  width: f32,
+  opacity: f32,
+  striated: bool
+}
+
+fn ballpoint_ink(point: &Point) -> BallpointInk {
+  let width = (0.5 + point.pressure) + point.width - 0.5 * (point.speed / 50.0);
+  let intensity = (0.1 * -(point.speed / 35.0)) + (1.2 * point.pressure) + 0.5;
+  return BallpointInk {
+    // quantized so that neighbouring segments can share a path
+    width: quantize(width.max(0.5), 0.1),
+    opacity: quantize(intensity.clamp(0.0, 1.0), 0.05),
+    striated: point.speed > STRIATION_SPEED
+  };
+}
+
+pub fn quantize(value: f32, step: f32) -> f32 {
+  return (value / step).round() * step;
+}
+
+// Splits a line into runs of consecutive segments that share the same ink,
+// each segment taking the ink of its end point
+fn ink_runs<T: PartialEq>(points: &[Point], ink_at: impl Fn(&Point) -> T) -> Vec<(T, Vec<&Point>)> {
+  return points.windows(2).fold(
+    Vec::<(T, Vec<&Point>)>::new(),
+    |mut acc_runs, pair| {
+      let ink = ink_at(&pair[1]);
+      match acc_runs.last_mut() {
+        Some((run_ink, run_points)) if *run_ink == ink => run_points.push(&pair[1]),
+        _ => acc_runs.push((ink, vec![&pair[0], &pair[1]]))
+      }
+      return acc_runs;
+    }
+  );
+}
+
+fn ballpoint(line: &Line, style: &BrushStyle, color: &str) -> Vec<Stroke> {
+  return ink_runs(&line.points, ballpoint_ink).into_iter()
+    .map(|(ink, run_points)| {
+      let width = ink.width * style.width_scale / 1.5;
+      let stroke = Stroke::new(
+        color,
+        width,
+        ink.opacity * style.opacity,
+        style,
+        points_to_data(run_points.into_iter())
+      );
+      if ink.striated {
+        return stroke.with("stroke-dasharray", format!("{} {}", width * 4.0, width * 0.5));
+      }
+      return stroke;
+    })
+    .collect();
+}
+
+// Graphite only marks the raised tooth of the paper, so light pencil
+// strokes are a faint body speckled with grain that fills in as pressure
+// goes up. Each run is drawn as a translucent body plus a thinner dashed
+// overlay whose gaps close with pressure.
+fn pencil_shade(point: &Point) -> f32 {
+  return quantize((0.3 + 0.7 * point.pressure).clamp(0.0, 1.0), 0.1);
+}
+
+fn pencil(line: &Line, style: &BrushStyle, color: &str) -> Vec<Stroke> {
+  let width = line.brush_size * style.width_scale;
+  let grain = width * 0.35;
+  return ink_runs(&line.points, pencil_shade).into_iter()
+    .flat_map(|(shade, run_points)| {
+      let data = points_to_data(run_points.into_iter());
+      let body = Stroke::new(color, width, style.opacity * shade * 0.6, style, data.clone());
+      let overlay = Stroke::new(color, width * 0.6, style.opacity * shade, style, data)
+        .with("stroke-dasharray", format!("{} {}", grain, grain * (1.5 - shade)));
+      return vec![body, overlay];
+    })
+    .collect();
+}
+
+// A broad nib held at a fixed angle: strokes across the nib's edge get its
+// full width, strokes along it only a hairline. `direction` is the pen's
+// heading in radians as recorded by the tablet.
+const NIB_ANGLE: f32 = std::f32::consts::FRAC_PI_4;
+const HAIRLINE_RATIO: f32 = 0.2;
+
+fn nib_width(point: &Point, full_width: f32) -> f32 {
+  let across = (point.direction - NIB_ANGLE).sin().abs();
+  return quantize(full_width * (HAIRLINE_RATIO + (1.0 - HAIRLINE_RATIO) * across), 0.1).max(0.1);
+}
+
+fn calligraphy(line: &Line, style: &BrushStyle, color: &str) -> Vec<Stroke> {
+  let full_width = line.brush_size * style.width_scale;
+  return ink_runs(&line.points, |point| nib_width(point, full_width)).into_iter()
+    .map(|(width, run_points)| Stroke::new(color, width, style.opacity, style, points_to_data(run_points.into_iter())))
+    .collect();
+}
+
+// Wet paint pools where the brush lingers or is pressed down, spreading
+// wider and darker, and runs thin and pale on quick strokes. The edges
+// feather into the paper, drawn with a slight blur.
+pub const WET_INK_FILTER_ID: &str = "relineate-wet-ink";
+const WET_INK_BLUR: f32 = 1.2;
+// speeds at and above this leave the least paint
+const DRY_SPEED: f32 = 60.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct WetInk {
+  // relative to the brush's width
+  spread: f32,
+  opacity: f32
+}
+
+fn wet_ink(point: &Point) -> WetInk {
+  let dryness = (point.speed / DRY_SPEED).clamp(0.0, 1.0);
+  let pressure = point.pressure.clamp(0.0, 1.0);
+  return WetInk {
+    spread: quantize((0.6 + 0.6 * pressure) * (1.15 - 0.35 * dryness), 0.1),
+    opacity: quantize((0.45 + 0.55 * pressure) * (1.0 - 0.4 * dryness), 0.05)
+  };
+}
+
+// Definitions strokes drawn by `paintbrush` refer to
+pub fn wet_ink_filter() -> SVGDefinitions {
+  let mut blur = SVGElement::new("feGaussianBlur");
+  blur.assign("stdDeviation", WET_INK_BLUR);
+  // the whole canvas rather than each stroke's box, which would clip the
+  // blur of straight strokes
+  return SVGDefinitions::new().add(
+    SVGFilter::new()
+      .set("id", WET_INK_FILTER_ID)
+      .set("filterUnits", "userSpaceOnUse")
+      .add(blur)
+  );
+}
+
+pub fn uses_wet_ink(stroke: &Stroke) -> bool {
+  return stroke.attributes.iter().any(|(name, value)| *name == "filter" && value.contains(WET_INK_FILTER_ID));
+}
+
+fn paintbrush(line: &Line, style: &BrushStyle, color: &str) -> Vec<Stroke> {
+  let full_width = line.brush_size * style.width_scale;
+  return ink_runs(&line.points, wet_ink).into_iter()
+    .map(|(ink, run_points)| {
+      return Stroke::new(color, full_width * ink.spread, style.opacity * ink.opacity, style, points_to_data(run_points.into_iter()))
+        .with("filter", format!("url(#{})", WET_INK_FILTER_ID));
+    })
+    .collect();
+}
+
+// Speeds at and above this get the full "fast" treatment; in practice
+// quick handwriting tops out around here
+const FAST_SPEED: f32 = 80.0;
+const SLOW_RGB: (f32, f32, f32) = (33.0, 102.0, 172.0);
+const FAST_RGB: (f32, f32, f32) = (178.0, 24.0, 43.0);
+
+// 0 for a resting pen to 1 for FAST_SPEED, in tenths
+fn speed_fraction(point: &Point) -> f32 {
+  return quantize((point.speed / FAST_SPEED).clamp(0.0, 1.0), 0.1);
+}
+
+fn speed_color(fraction: f32) -> String {
+  let mix = |slow: f32, fast: f32| (slow + (fast - slow) * fraction).round() as u8;
+  return format!(
+    "#{:02x}{:02x}{:02x}",
+    mix(SLOW_RGB.0, FAST_RGB.0), mix(SLOW_RGB.1, FAST_RGB.1), mix(SLOW_RGB.2, FAST_RGB.2)
+  );
+}
+
+// A constant width stroke split wherever the speed changes noticeably,
+// colored or faded by speed
+fn speed_mapped(line: &Line, style: &BrushStyle, color: &str, speed_style: SpeedStyle) -> Vec<Stroke> {
+  let width = line.brush_size * style.width_scale;
+  return ink_runs(&line.points, speed_fraction).into_iter()
+    .map(|(fraction, run_points)| {
+      let data = points_to_data(run_points.into_iter());
+      return match speed_style {
+        SpeedStyle::Color => Stroke::new(&speed_color(fraction), width, 1.0, style, data),
+        SpeedStyle::Opacity => Stroke::new(color, width, 1.0 - 0.8 * fraction, style, data)
+      };
+    })
+    .collect();
+}
+
+// The width the tablet itself computed for each point, which already takes
+// pressure, tilt and the brush into account. Each run of segments of the
+// same (quantized) width is its own sub-path, each segment taking the
+// average width of its end points.
+fn segment_widths(line: &Line, style: &BrushStyle, color: &str) -> Vec<Stroke> {
+  let point_width = |point: &Point| recorded_width(line, style, point);
+  let runs = line.points.windows(2).fold(Vec::<(f32, Vec<&Point>)>::new(), |mut acc_runs, pair| {
+    let width = quantize((point_width(&pair[0]) + point_width(&pair[1])) / 2.0, 0.1).max(0.1);
+    match acc_runs.last_mut() {
+      Some((run_width, run_points)) if *run_width == width => run_points.push(&pair[1]),
+      _ => acc_runs.push((width, vec![&pair[0], &pair[1]]))
+    }
+    return acc_runs;
+  });
+  return runs.into_iter()
+    .map(|(width, run_points)| Stroke::new(color, width, style.opacity, style, points_to_data(run_points.into_iter())))
+    .collect();
+}
+
+fn recorded_width(line: &Line, style: &BrushStyle, point: &Point) -> f32 {
+  return if point.width > 0.0 { point.width } else { line.brush_size * style.width_scale };
+}
+
+// points on each round cap, between the two sides
+const CAP_STEPS: usize = 4;
+
+// The centerline offset to either side by half the recorded width at each
+// point, along the normal averaged over the segments meeting there, and
+// closed up with round caps: one polygon whose edges follow the pen's
+// actual weight. The sides are walked up one edge and back down the other.
+fn outline(line: &Line, style: &BrushStyle, color: &str) -> Stroke {
+  let points = &line.points;
+  let last = points.len() - 1;
+  let direction = |from: &Point, to: &Point| (to.y - from.y).atan2(to.x - from.x);
+  let headings = (0..=last)
+    .map(|index| {
+      let before = direction(&points[index.saturating_sub(1)], &points[index.max(1).min(last)]);
+      let after = direction(&points[index.min(last - 1)], &points[(index + 1).min(last)]);
+      // the mean of the two angles, without wrapping around at pi
+      return (before.sin() + after.sin()).atan2(before.cos() + after.cos());
+    })
+    .collect::<Vec<_>>();
+  let offset = |index: usize, angle: f32| {
+    let radius = recorded_width(line, style, &points[index]) / 2.0;
+    return (points[index].x + radius * angle.cos(), points[index].y + radius * angle.sin());
+  };
+  let half_turn = std::f32::consts::PI;
+  let left = (0..=last).map(|index| offset(index, headings[index] - half_turn / 2.0));
+  let end_cap = (1..CAP_STEPS).map(|step| offset(last, headings[last] - half_turn / 2.0 + half_turn * step as f32 / CAP_STEPS as f32));
+  let right = (0..=last).rev().map(|index| offset(index, headings[index] + half_turn / 2.0));
+  let start_cap = (1..CAP_STEPS).map(|step| offset(0, headings[0] + half_turn / 2.0 + half_turn * step as f32 / CAP_STEPS as f32));
+  let data = left.chain(end_cap).chain(right).chain(start_cap).enumerate().fold(
+    SVGData::new(),
+    |acc_data, (index, position)| if index == 0 { acc_data.move_to(position) } else { acc_data.line_to(position) }
+  );
+  return Stroke::filled(color, style.opacity, style, data.close());
+}
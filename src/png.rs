@@ -0,0 +1,202 @@
+use crate::deflate::{crc32, deflate};
+use crate::raster::Canvas;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const BYTES_PER_PIXEL: usize = 4;
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = png.len();
+  png.extend_from_slice(kind);
+  png.extend_from_slice(data);
+  let crc = crc32(&png[start..]);
+  png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (a, b) = data.chunks(5552).fold((1u32, 0u32), |(mut a, mut b), block| {
+    block.iter().for_each(|byte| {
+      a += *byte as u32;
+      b += a;
+    });
+    return (a % 65521, b % 65521);
+  });
+  return (b << 16) | a;
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+  let estimate = left as i16 + up as i16 - up_left as i16;
+  let (to_left, to_up, to_up_left) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+  return match (to_left <= to_up && to_left <= to_up_left, to_up <= to_up_left) {
+    (true, _) => left,
+    (false, true) => up,
+    (false, false) => up_left
+  };
+}
+
+// Each row filtered whichever way leaves the smallest bytes, the usual
+// heuristic for what compresses best
+fn filtered_row(row: &[u8], previous: &[u8]) -> Vec<u8> {
+  let left = |index: usize| if index >= BYTES_PER_PIXEL { row[index - BYTES_PER_PIXEL] } else { 0 };
+  let up_left = |index: usize| if index >= BYTES_PER_PIXEL { previous[index - BYTES_PER_PIXEL] } else { 0 };
+  let filters: [(u8, &dyn Fn(usize) -> u8); 4] = [
+    (0, &|_| 0),
+    (1, &left),
+    (2, &|index| previous[index]),
+    (4, &|index| paeth(left(index), previous[index], up_left(index)))
+  ];
+  return filters.iter()
+    .map(|(kind, predict)| {
+      return std::iter::once(*kind)
+        .chain(row.iter().enumerate().map(|(index, byte)| byte.wrapping_sub(predict(index))))
+        .collect::<Vec<_>>();
+    })
+    .min_by_key(|filtered| filtered[1..].iter().map(|byte| (*byte as i8).unsigned_abs() as u32).sum::<u32>())
+    .unwrap_or_default();
+}
+
+// Rows of RGBA pixels as a zlib stream, each row filtered
+fn image_data(pixels: &[u8], width: usize) -> Vec<u8> {
+  let stride = width * BYTES_PER_PIXEL;
+  let blank = vec![0; stride];
+  let rows = pixels.chunks(stride).collect::<Vec<_>>();
+  let raw = rows.iter().enumerate()
+    .flat_map(|(index, row)| filtered_row(row, if index > 0 { rows[index - 1] } else { &blank }))
+    .collect::<Vec<_>>();
+  let mut image_data = vec![0x78, 0x01];
+  image_data.extend(deflate(&raw));
+  image_data.extend_from_slice(&adler32(&raw).to_be_bytes());
+  return image_data;
+}
+
+fn header(width: usize, height: usize) -> Vec<u8> {
+  let mut header = Vec::new();
+  header.extend_from_slice(&(width as u32).to_be_bytes());
+  header.extend_from_slice(&(height as u32).to_be_bytes());
+  // 8 bits per channel, RGBA, then the standard compression, filtering
+  // and no interlacing
+  header.extend_from_slice(&[8, 6, 0, 0, 0]);
+  return header;
+}
+
+// The canvas as an 8-bit RGBA PNG
+pub fn write_png(canvas: &Canvas) -> Vec<u8> {
+  let mut png = SIGNATURE.to_vec();
+  chunk(&mut png, b"IHDR", &header(canvas.width, canvas.height));
+  chunk(&mut png, b"IDAT", &image_data(&canvas.pixels, canvas.width));
+  chunk(&mut png, b"IEND", &[]);
+  return png;
+}
+
+// A frame of an animated PNG, as the rectangle of the picture that changed
+// since the frame before
+struct Frame {
+  x: usize,
+  y: usize,
+  width: usize,
+  height: usize,
+  delay_ms: u32,
+  image_data: Vec<u8>
+}
+
+// Where two canvases of the same size differ, as x, y, width and height
+fn changed_region(previous: &Canvas, next: &Canvas) -> Option<(usize, usize, usize, usize)> {
+  let stride = next.width * BYTES_PER_PIXEL;
+  let changed_row = |row: usize| previous.pixels[row * stride..(row + 1) * stride] != next.pixels[row * stride..(row + 1) * stride];
+  let top = (0..next.height).find(|row| changed_row(*row))?;
+  let bottom = (top..next.height).rev().find(|row| changed_row(*row)).unwrap_or(top);
+  let changed_column = |column: usize| (top..=bottom).any(|row| {
+    let start = row * stride + column * BYTES_PER_PIXEL;
+    return previous.pixels[start..start + BYTES_PER_PIXEL] != next.pixels[start..start + BYTES_PER_PIXEL];
+  });
+  let left = (0..next.width).find(|column| changed_column(*column)).unwrap_or(0);
+  let right = (left..next.width).rev().find(|column| changed_column(*column)).unwrap_or(left);
+  return Some((left, top, right - left + 1, bottom - top + 1));
+}
+
+fn cropped(canvas: &Canvas, (x, y, width, height): (usize, usize, usize, usize)) -> Vec<u8> {
+  let stride = canvas.width * BYTES_PER_PIXEL;
+  return (y..y + height)
+    .flat_map(|row| canvas.pixels[row * stride + x * BYTES_PER_PIXEL..row * stride + (x + width) * BYTES_PER_PIXEL].iter().copied())
+    .collect();
+}
+
+// An animated PNG of the canvases, each shown for its delay in milliseconds
+// and looping forever. Frames are all the size of the first and only store
+// what changed since the one before; one that changes nothing just holds
+// the one before for longer.
+pub fn write_apng(canvases: impl Iterator<Item = Result<(Canvas, u32), String>>) -> Result<Vec<u8>, String> {
+  let mut frames = Vec::<Frame>::new();
+  let mut previous: Option<Canvas> = None;
+  for next in canvases {
+    let (canvas, delay_ms) = next?;
+    let region = match &previous {
+      None => Some((0, 0, canvas.width, canvas.height)),
+      Some(previous) if (previous.width, previous.height) != (canvas.width, canvas.height) => {
+        return Err(String::from("animation frames must all be the same size"));
+      },
+      Some(previous) => changed_region(previous, &canvas)
+    };
+    match (region, frames.last_mut()) {
+      (Some(region), _) => {
+        let (x, y, width, height) = region;
+        frames.push(Frame { x, y, width, height, delay_ms, image_data: image_data(&cropped(&canvas, region), width) });
+      },
+      (None, Some(last)) => last.delay_ms += delay_ms,
+      (None, None) => {}
+    }
+    previous = Some(canvas);
+  }
+  let (width, height) = match frames.first() {
+    Some(first) => (first.width, first.height),
+    None => return Err(String::from("an animation needs at least one frame"))
+  };
+
+  let mut png = SIGNATURE.to_vec();
+  chunk(&mut png, b"IHDR", &header(width, height));
+  // the number of frames, then how many times they play, 0 for forever
+  let mut control = (frames.len() as u32).to_be_bytes().to_vec();
+  control.extend_from_slice(&0u32.to_be_bytes());
+  chunk(&mut png, b"acTL", &control);
+  // frame controls and frame data share one sequence of numbers
+  let mut sequence = 0u32;
+  frames.iter().enumerate().for_each(|(index, frame)| {
+    let mut frame_control = sequence.to_be_bytes().to_vec();
+    [frame.width, frame.height, frame.x, frame.y].iter().for_each(|value| frame_control.extend_from_slice(&(*value as u32).to_be_bytes()));
+    // delays over a u16 of milliseconds are given in hundredths instead
+    let (delay, per_second) = match frame.delay_ms > u16::MAX as u32 {
+      true => ((frame.delay_ms / 10).min(u16::MAX as u32) as u16, 100u16),
+      false => (frame.delay_ms as u16, 1000u16)
+    };
+    frame_control.extend_from_slice(&delay.to_be_bytes());
+    frame_control.extend_from_slice(&per_second.to_be_bytes());
+    // nothing disposed of after a frame, and its pixels replace those under it
+    frame_control.extend_from_slice(&[0, 0]);
+    chunk(&mut png, b"fcTL", &frame_control);
+    sequence += 1;
+    if index == 0 {
+      chunk(&mut png, b"IDAT", &frame.image_data);
+      return;
+    }
+    let mut frame_data = sequence.to_be_bytes().to_vec();
+    frame_data.extend_from_slice(&frame.image_data);
+    chunk(&mut png, b"fdAT", &frame_data);
+    sequence += 1;
+  });
+  chunk(&mut png, b"IEND", &[]);
+  return Ok(png);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn checks_adler32() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"123456789"), 0x091e_01de);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    // past the 5552 bytes summed between reductions
+    assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+  }
+}
@@ -0,0 +1,47 @@
+use crate::device::Device;
+use crate::recognize::is_writing;
+use crate::Layer;
+
+// Unicode braille cells hold 2x4 dots, which makes for a passable page
+// thumbnail in any terminal. Bits are indexed [row][column] within a cell.
+const BRAILLE_BASE: u32 = 0x2800;
+const DOT_BITS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+// The page drawn as `rows` lines of braille, as wide as the page's aspect
+// ratio requires (terminal cells being about twice as tall as wide)
+pub fn braille_preview(layers: &[Layer], rows: usize) -> String {
+  let dots_high = rows * 4;
+  let (width, height) = Device::detect(layers).canvas();
+  let dots_wide = ((dots_high as f32 * width / height) as usize).max(2);
+  let columns = dots_wide.div_ceil(2);
+  let mut cells = vec![0u32; columns * rows];
+  let scale = dots_high as f32 / height;
+
+  let mut plot = |x: f32, y: f32| {
+    if x < 0.0 || y < 0.0 { return; }
+    let (dot_x, dot_y) = ((x * scale) as usize, (y * scale) as usize);
+    if dot_x >= columns * 2 || dot_y >= dots_high { return; }
+    cells[(dot_y / 4) * columns + dot_x / 2] |= DOT_BITS[dot_y % 4][dot_x % 2];
+  };
+
+  layers.iter()
+    .flat_map(|layer| layer.lines.iter())
+    .filter(|line| is_writing(line))
+    .for_each(|line| {
+      line.points.iter().for_each(|point| plot(point.x, point.y));
+      // fill in between points that are more than a dot apart
+      line.points.windows(2).for_each(|pair| {
+        let (dx, dy) = (pair[1].x - pair[0].x, pair[1].y - pair[0].y);
+        let steps = ((dx.abs().max(dy.abs()) * scale) as usize).min(dots_high * 2);
+        (1..steps).for_each(|step| {
+          let t = step as f32 / steps as f32;
+          plot(pair[0].x + dx * t, pair[0].y + dy * t);
+        });
+      });
+    });
+
+  return cells.chunks(columns)
+    .map(|row| row.iter().map(|bits| char::from_u32(BRAILLE_BASE + bits).unwrap_or(' ')).collect::<String>())
+    .collect::<Vec<_>>()
+    .join("\n");
+}
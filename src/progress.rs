@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::events;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Outcome {
+  Succeeded,
+  Failed,
+  Skipped
+}
+
+impl Outcome {
+  pub fn of<T, E>(result: &Result<T, E>) -> Outcome {
+    return match result {
+      Ok(_) => Outcome::Succeeded,
+      Err(_) => Outcome::Failed
+    };
+  }
+}
+
+// Progress through a batch of pages, drawn on stderr, and a count of what
+// became of them. Safe to share between the threads converting pages.
+pub struct Progress {
+  bar: ProgressBar,
+  quiet: bool,
+  json: bool,
+  succeeded: AtomicUsize,
+  failed: AtomicUsize,
+  skipped: AtomicUsize
+}
+
+impl Progress {
+  // `total` pages, or a spinner when how many isn't known up front; with
+  // `json` there's no bar, and the summary is an event
+  pub fn new(total: Option<usize>, quiet: bool, json: bool) -> Progress {
+    let bar = match (quiet || json, total) {
+      (true, _) => ProgressBar::hidden(),
+      (false, Some(total)) => ProgressBar::new(total as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} pages {wide_msg}").unwrap_or_else(|_| ProgressStyle::default_bar())
+      ),
+      (false, None) => ProgressBar::new_spinner().with_style(
+        ProgressStyle::with_template("{spinner} {pos} pages {wide_msg}").unwrap_or_else(|_| ProgressStyle::default_spinner())
+      )
+    };
+    return Progress {
+      bar,
+      quiet,
+      json,
+      succeeded: AtomicUsize::new(0),
+      failed: AtomicUsize::new(0),
+      skipped: AtomicUsize::new(0)
+    };
+  }
+
+  // one page of the total done with, named in the bar
+  pub fn record(&self, label: &str, outcome: Outcome) {
+    let count = match outcome {
+      Outcome::Succeeded => &self.succeeded,
+      Outcome::Failed => &self.failed,
+      Outcome::Skipped => &self.skipped
+    };
+    count.fetch_add(1, Ordering::Relaxed);
+    self.bar.set_message(String::from(label));
+    self.bar.inc(1);
+  }
+
+  // pages left out before the batch started, which aren't in the total
+  pub fn skip(&self, count: usize) {
+    self.skipped.fetch_add(count, Ordering::Relaxed);
+  }
+
+  // clears the bar for a one line summary
+  pub fn finish(&self) {
+    self.bar.finish_and_clear();
+    let (succeeded, failed, skipped) = (
+      self.succeeded.load(Ordering::Relaxed), self.failed.load(Ordering::Relaxed), self.skipped.load(Ordering::Relaxed)
+    );
+    match (self.quiet, self.json) {
+      (true, _) => (),
+      (false, true) => events::summary(succeeded, failed, skipped),
+      (false, false) => eprintln!("{} succeeded, {} failed, {} skipped", succeeded, failed, skipped)
+    }
+  }
+}
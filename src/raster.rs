@@ -0,0 +1,295 @@
+use crate::scene::{Blend, Item, LineCap, Paint, Pen, Scene, Segment, Shape};
+
+// Vertical samples per pixel; horizontal coverage is computed exactly
+const SUBSAMPLES: usize = 4;
+
+// An RGBA image, not premultiplied, row by row from the top
+#[derive(Debug, Clone)]
+pub struct Canvas {
+  pub width: usize,
+  pub height: usize,
+  pub pixels: Vec<u8>
+}
+
+type Polygon = Vec<(f32, f32)>;
+
+fn signed_area(polygon: &Polygon) -> f32 {
+  return polygon.iter().zip(polygon.iter().cycle().skip(1))
+    .map(|((x0, y0), (x1, y1))| x0 * y1 - x1 * y0)
+    .sum::<f32>() / 2.0;
+}
+
+// All pieces of a stroke are wound the same way, so that where they
+// overlap they merge instead of cancelling out
+fn wound(mut polygon: Polygon) -> Polygon {
+  if signed_area(&polygon) < 0.0 {
+    polygon.reverse();
+  }
+  return polygon;
+}
+
+fn circle(x: f32, y: f32, radius: f32) -> Polygon {
+  let steps = ((radius * 2.0).ceil() as usize).clamp(8, 48);
+  return wound((0..steps)
+    .map(|step| {
+      let (sin, cos) = (step as f32 / steps as f32 * std::f32::consts::TAU).sin_cos();
+      return (x + radius * cos, y + radius * sin);
+    })
+    .collect());
+}
+
+// Moves and lines as separate runs of points, and whether each is closed
+fn subpaths(segments: &[Segment]) -> Vec<(Vec<(f32, f32)>, bool)> {
+  return segments.iter().fold(Vec::<(Vec<(f32, f32)>, bool)>::new(), |mut acc, segment| {
+    match segment {
+      Segment::Move(x, y) => acc.push((vec![(*x, *y)], false)),
+      Segment::Line(x, y) => match acc.last_mut() {
+        Some((points, false)) => points.push((*x, *y)),
+        _ => acc.push((vec![(*x, *y)], false))
+      },
+      Segment::Close => if let Some((points, closed)) = acc.last_mut() {
+        if let Some(first) = points.first().copied() {
+          points.push(first);
+        }
+        *closed = true;
+      }
+    }
+    return acc;
+  });
+}
+
+// Splits a run of points into the dashes that are drawn
+fn dashed(points: &[(f32, f32)], dashes: &[f32]) -> Vec<Vec<(f32, f32)>> {
+  let total = dashes.iter().sum::<f32>();
+  if dashes.is_empty() || total <= 0.0 || dashes.iter().any(|dash| *dash < 0.0) {
+    return vec![points.to_vec()];
+  }
+  // an odd list repeats to make it even, as in SVG
+  let pattern = match dashes.len() % 2 {
+    1 => [dashes, dashes].concat(),
+    _ => dashes.to_vec()
+  };
+  let mut runs = Vec::<Vec<(f32, f32)>>::new();
+  let (mut index, mut left) = (0, pattern[0]);
+  points.windows(2).for_each(|pair| {
+    let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+    let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+    let mut done = 0.0;
+    while done < length {
+      let step = left.min(length - done);
+      let at = |distance: f32| {
+        let t = if length > 0.0 { distance / length } else { 0.0 };
+        return (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t);
+      };
+      if index % 2 == 0 {
+        match runs.last_mut() {
+          Some(run) if run.last() == Some(&at(done)) => run.push(at(done + step)),
+          _ => runs.push(vec![at(done), at(done + step)])
+        }
+      }
+      done += step;
+      left -= step;
+      if left <= 0.0 {
+        index = (index + 1) % pattern.len();
+        left = pattern[index];
+      }
+    }
+  });
+  return runs;
+}
+
+// The area a pen covers along a path, as polygons: a quad per segment and
+// discs at the joins and round caps (other joins are drawn round too)
+fn stroke_polygons(segments: &[Segment], pen: &Pen) -> Vec<Polygon> {
+  let half = pen.width / 2.0;
+  return subpaths(segments).into_iter()
+    .flat_map(|(points, closed)| {
+      let runs = match pen.dashes.is_empty() {
+        true => vec![points],
+        false => dashed(&points, &pen.dashes)
+      };
+      return runs.into_iter().map(move |run| (run, closed && pen.dashes.is_empty()));
+    })
+    .flat_map(|(mut run, closed)| {
+      let mut polygons = Vec::new();
+      if pen.cap == LineCap::Square && !closed && run.len() > 1 {
+        // pushed out by half the width at either end
+        let extend = |from: (f32, f32), to: (f32, f32)| {
+          let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt().max(f32::EPSILON);
+          return (to.0 + (to.0 - from.0) / length * half, to.1 + (to.1 - from.1) / length * half);
+        };
+        let last = run.len() - 1;
+        run[0] = extend(run[1], run[0]);
+        run[last] = extend(run[last - 1], run[last]);
+      }
+      run.windows(2).for_each(|pair| {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+        if length == 0.0 { return; }
+        let (nx, ny) = (-(y1 - y0) / length * half, (x1 - x0) / length * half);
+        polygons.push(wound(vec![(x0 + nx, y0 + ny), (x1 + nx, y1 + ny), (x1 - nx, y1 - ny), (x0 - nx, y0 - ny)]));
+      });
+      let joins = match closed {
+        true => &run[..],
+        false => run.get(1..run.len().saturating_sub(1)).unwrap_or(&[])
+      };
+      polygons.extend(joins.iter().map(|(x, y)| circle(*x, *y, half)));
+      if pen.cap == LineCap::Round && !closed {
+        polygons.extend(run.first().iter().chain(run.last().iter()).map(|(x, y)| circle(*x, *y, half)));
+      }
+      return polygons;
+    })
+    .collect();
+}
+
+fn fill_polygons(segments: &[Segment]) -> Vec<Polygon> {
+  return subpaths(segments).into_iter().map(|(points, _)| points).filter(|points| points.len() > 2).collect();
+}
+
+// How much of each pixel in the polygons' bounds is inside them (by the
+// nonzero rule), as (left, top, width, coverage by row)
+fn coverage(polygons: &[Polygon], width: usize, height: usize) -> Option<(usize, usize, usize, Vec<f32>)> {
+  let points = polygons.iter().flatten();
+  let (left, top, right, bottom) = points.fold((f32::MAX, f32::MAX, f32::MIN, f32::MIN), |(l, t, r, b), (x, y)| {
+    return (l.min(*x), t.min(*y), r.max(*x), b.max(*y));
+  });
+  if left > right || right < 0.0 || bottom < 0.0 || left >= width as f32 || top >= height as f32 { return None; }
+  let (left, top) = (left.max(0.0).floor() as usize, top.max(0.0).floor() as usize);
+  let (right, bottom) = ((right.ceil() as usize).min(width), (bottom.ceil() as usize).min(height));
+  if right <= left || bottom <= top { return None; }
+  let span_width = right - left;
+  let edges = polygons.iter()
+    .flat_map(|polygon| polygon.iter().zip(polygon.iter().cycle().skip(1)))
+    .filter(|((_, y0), (_, y1))| y0 != y1)
+    .collect::<Vec<_>>();
+
+  let mut cells = vec![0.0f32; span_width * (bottom - top)];
+  let weight = 1.0 / SUBSAMPLES as f32;
+  let mut crossings = Vec::<(f32, i32)>::new();
+  (top..bottom).for_each(|row| {
+    (0..SUBSAMPLES).for_each(|sample| {
+      let y = row as f32 + (sample as f32 + 0.5) / SUBSAMPLES as f32;
+      crossings.clear();
+      crossings.extend(edges.iter().filter_map(|((x0, y0), (x1, y1))| {
+        let (low, high) = if y0 < y1 { (*y0, *y1) } else { (*y1, *y0) };
+        if y < low || y >= high { return None; }
+        let x = x0 + (y - y0) / (y1 - y0) * (x1 - x0);
+        return Some((x, if y1 > y0 { 1 } else { -1 }));
+      }));
+      crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+      let row_cells = &mut cells[(row - top) * span_width..(row - top + 1) * span_width];
+      crossings.iter().fold((0, 0.0), |(winding, start), (x, direction)| {
+        // the span since the last crossing is inside
+        if winding != 0 {
+          let (from, to) = ((start - left as f32).max(0.0), (x - left as f32).min(span_width as f32));
+          if to > from {
+            let (first, last) = (from.floor() as usize, (to.ceil() as usize).min(span_width));
+            (first..last).for_each(|cell| {
+              let overlap = to.min(cell as f32 + 1.0) - from.max(cell as f32);
+              row_cells[cell] += overlap * weight;
+            });
+          }
+        }
+        return (winding + direction, *x);
+      });
+    });
+  });
+  return Some((left, top, span_width, cells));
+}
+
+fn blend_channel(blend: Blend, backdrop: f32, source: f32) -> f32 {
+  return match blend {
+    Blend::Normal => source,
+    Blend::Multiply => backdrop * source,
+    Blend::Screen => backdrop + source - backdrop * source
+  };
+}
+
+// Premultiplied RGBA in 0 to 1
+struct Surface {
+  width: usize,
+  height: usize,
+  pixels: Vec<[f32; 4]>
+}
+
+impl Surface {
+  fn paint(&mut self, polygons: &[Polygon], paint: &Paint, blend: Blend) {
+    let (left, top, span_width, cells) = match coverage(polygons, self.width, self.height) {
+      Some(coverage) => coverage,
+      None => return
+    };
+    let source = [paint.color.red, paint.color.green, paint.color.blue];
+    cells.iter().enumerate().filter(|(_, cover)| **cover > 0.0).for_each(|(index, cover)| {
+      let (x, y) = (left + index % span_width, top + index / span_width);
+      let pixel = &mut self.pixels[y * self.width + x];
+      let alpha = (cover.min(1.0) * paint.opacity).clamp(0.0, 1.0);
+      let backdrop_alpha = pixel[3];
+      (0..3).for_each(|channel| {
+        let backdrop = if backdrop_alpha > 0.0 { pixel[channel] / backdrop_alpha } else { 0.0 };
+        // the blended color where there is something underneath
+        let color = (1.0 - backdrop_alpha) * source[channel] + backdrop_alpha * blend_channel(blend, backdrop, source[channel]);
+        pixel[channel] = color * alpha + pixel[channel] * (1.0 - alpha);
+      });
+      pixel[3] = alpha + backdrop_alpha * (1.0 - alpha);
+    });
+  }
+
+  fn draw(&mut self, shape: &Shape) {
+    if let Some(fill) = &shape.fill {
+      self.paint(&fill_polygons(&shape.segments), fill, shape.blend);
+    }
+    if let Some(pen) = &shape.stroke {
+      self.paint(&stroke_polygons(&shape.segments, pen), &pen.paint, shape.blend);
+    }
+  }
+}
+
+// Draws the scene's shapes at `scale` device pixels per CSS pixel. Text
+// needs fonts, which this doesn't have, so labels are left out.
+pub fn rasterize(scene: &Scene, scale: f32) -> Canvas {
+  let (width, height) = ((scene.width * scale).round().max(1.0) as usize, (scene.height * scale).round().max(1.0) as usize);
+  let view_box = &scene.view_box;
+  // fitted and centered, as SVG does by default
+  let fit = (width as f32 / view_box.width).min(height as f32 / view_box.height);
+  let (offset_x, offset_y) = ((width as f32 - view_box.width * fit) / 2.0, (height as f32 - view_box.height * fit) / 2.0);
+  let to_device = |x: f32, y: f32| (offset_x + (x - view_box.x) * fit, offset_y + (y - view_box.y) * fit);
+
+  let mut surface = Surface { width, height, pixels: vec![[0.0; 4]; width * height] };
+  scene.items.iter()
+    .filter_map(|item| match item {
+      Item::Shape(shape) => Some(shape),
+      Item::Label(_) => None
+    })
+    .for_each(|shape| {
+      let segments = shape.segments.iter()
+        .map(|segment| match segment {
+          Segment::Move(x, y) => {
+            let (x, y) = to_device(*x, *y);
+            Segment::Move(x, y)
+          },
+          Segment::Line(x, y) => {
+            let (x, y) = to_device(*x, *y);
+            Segment::Line(x, y)
+          },
+          Segment::Close => Segment::Close
+        })
+        .collect();
+      let stroke = shape.stroke.as_ref().map(|pen| Pen {
+        width: pen.width * fit,
+        dashes: pen.dashes.iter().map(|dash| dash * fit).collect(),
+        ..pen.clone()
+      });
+      surface.draw(&Shape { segments, stroke, ..shape.clone() });
+    });
+
+  let pixels = surface.pixels.iter()
+    .flat_map(|[red, green, blue, alpha]| {
+      let channel = |value: f32| {
+        let straight = if *alpha > 0.0 { value / alpha } else { 0.0 };
+        return (straight.clamp(0.0, 1.0) * 255.0).round() as u8;
+      };
+      return [channel(*red), channel(*green), channel(*blue), (alpha.clamp(0.0, 1.0) * 255.0).round() as u8];
+    })
+    .collect();
+  return Canvas { width, height, pixels };
+}
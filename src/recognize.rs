@@ -0,0 +1,216 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use svg::node::element::Group as SVGGroup;
+use svg::node::element::Text as SVGText;
+use svg::node::Text as SVGTextNode;
+
+use crate::brush::BrushType;
+use crate::notebook::Notebook;
+use crate::options::RenderOptions;
+use crate::orientation::canvas_group;
+use crate::watermark::escape_xml;
+use crate::{Layer, Line};
+
+// Turns a set of strokes into text. Recognition engines are large and
+// mostly proprietary, so relineate only defines the hook.
+pub trait Recognizer {
+  fn recognize(&self, lines: &[&Line]) -> Result<String, String>;
+}
+
+// Runs an external command through the shell, writing one stroke per line
+// to its stdin as space separated "x,y" pairs and reading the recognized
+// text from its stdout.
+#[derive(Debug, Clone)]
+pub struct CommandRecognizer {
+  pub command: String
+}
+
+// Handwriting recognition while rendering: each line of writing is run
+// through the recognizer, and what it gives back laid over the writing in
+// the SVG or, for a sidecar, written to a text file beside the output
+#[derive(Debug, Clone)]
+pub struct Ocr {
+  pub recognizer: CommandRecognizer,
+  pub sidecar: bool
+}
+
+pub fn strokes_to_text(lines: &[&Line]) -> String {
+  return lines.iter()
+    .map(|line| {
+      return line.points.iter()
+        .map(|point| format!("{},{}", point.x, point.y))
+        .collect::<Vec<_>>()
+        .join(" ");
+    })
+    .collect::<Vec<_>>()
+    .join("\n");
+}
+
+impl Recognizer for CommandRecognizer {
+  fn recognize(&self, lines: &[&Line]) -> Result<String, String> {
+    let mut child = Command::new("sh")
+      .arg("-c")
+      .arg(&self.command)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .spawn()
+      .map_err(|why| format!("couldn't run {}, {}", self.command, why))?;
+    child.stdin.take()
+      .ok_or_else(|| format!("couldn't write to {}", self.command))?
+      .write_all(strokes_to_text(lines).as_bytes())
+      .map_err(|why| format!("couldn't write to {}, {}", self.command, why))?;
+    let output = child.wait_with_output()
+      .map_err(|why| format!("couldn't run {}, {}", self.command, why))?;
+    if !output.status.success() {
+      return Err(format!("{} failed with {}", self.command, output.status));
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+  }
+}
+
+// Tallest band we'll still consider a single line of handwriting
+const MAX_LINE_HEIGHT: f32 = 150.0;
+
+pub fn is_writing(line: &Line) -> bool {
+  return !matches!(line.brush_type,
+    BrushType::Highlighter | BrushType::Highlighter2 | BrushType::Shader | BrushType::Eraser | BrushType::RubberArea
+      | BrushType::EraseAll | BrushType::SelectionBrush1 | BrushType::SelectionBrush2);
+}
+
+fn vertical_extent(line: &Line) -> (f32, f32) {
+  return line.points.iter().fold((f32::MAX, f32::MIN), |(top, bottom), point| {
+    return (top.min(point.y), bottom.max(point.y));
+  });
+}
+
+// The page's writing as lines, top to bottom: starting from the highest
+// stroke, every stroke that vertically overlaps the band collected so far,
+// then the same again from the highest stroke left.
+pub fn writing_lines(layers: &[Layer]) -> Vec<Vec<&Line>> {
+  let mut lines = layers.iter()
+    .flat_map(|layer| layer.lines.iter())
+    .filter(|line| is_writing(line) && !line.points.is_empty())
+    .map(|line| (vertical_extent(line), line))
+    .filter(|((top, bottom), _)| bottom - top <= MAX_LINE_HEIGHT)
+    .collect::<Vec<_>>();
+  lines.sort_by(|((a, _), _), ((b, _), _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+  let mut writing = Vec::<Vec<&Line>>::new();
+  let mut band = (f32::MIN, f32::MIN);
+  lines.into_iter().for_each(|((top, bottom), line)| {
+    let (band_top, band_bottom) = band;
+    match writing.last_mut() {
+      Some(strokes) if top <= band_bottom => {
+        band = (band_top, band_bottom.max(bottom.min(band_top + MAX_LINE_HEIGHT)));
+        strokes.push(line);
+      },
+      _ => {
+        band = (top, bottom.min(top + MAX_LINE_HEIGHT));
+        writing.push(vec![line]);
+      }
+    }
+  });
+  return writing;
+}
+
+// Strokes making up the topmost line of writing on a page
+pub fn first_writing_line(layers: &[Layer]) -> Vec<&Line> {
+  return writing_lines(layers).into_iter().next().unwrap_or_default();
+}
+
+// A line of writing as recognized, with the bounds of its strokes on the
+// canvas
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecognizedLine {
+  pub text: String,
+  pub left: f32,
+  pub top: f32,
+  pub right: f32,
+  pub bottom: f32
+}
+
+// Each line of the page's writing recognized on its own, leaving out those
+// nothing was recognized in
+pub fn recognize_lines(layers: &[Layer], recognizer: &dyn Recognizer) -> Result<Vec<RecognizedLine>, String> {
+  return writing_lines(layers).into_iter()
+    .map(|strokes| {
+      let text = recognizer.recognize(&strokes)?.split_whitespace().collect::<Vec<_>>().join(" ");
+      return Ok(strokes.iter().flat_map(|line| line.points.iter()).fold(
+        RecognizedLine { text, left: f32::MAX, top: f32::MAX, right: f32::MIN, bottom: f32::MIN },
+        |bounds, point| RecognizedLine {
+          left: bounds.left.min(point.x),
+          top: bounds.top.min(point.y),
+          right: bounds.right.max(point.x),
+          bottom: bounds.bottom.max(point.y),
+          ..bounds
+        }
+      ));
+    })
+    .filter(|line| line.as_ref().map_or(true, |line| !line.text.is_empty()))
+    .collect();
+}
+
+// Recognized text laid over the writing it came from as invisible <text>,
+// one per line and stretched to the line's width, so the writing can be
+// searched and selected in viewers showing the SVG (or a PDF of it)
+pub fn render_recognized(lines: &[RecognizedLine], options: &RenderOptions) -> SVGGroup {
+  return lines.iter().fold(canvas_group(options).set("class", "recognized"), |group, line| {
+    let height = (line.bottom - line.top).max(1.0);
+    return group.add(SVGText::new()
+      .set("x", line.left)
+      .set("y", line.bottom)
+      .set("font-family", "sans-serif")
+      .set("font-size", height)
+      .set("textLength", (line.right - line.left).max(1.0))
+      .set("lengthAdjust", "spacingAndGlyphs")
+      .set("fill", "black")
+      .set("fill-opacity", 0)
+      .add(SVGTextNode::new(escape_xml(&line.text))));
+  });
+}
+
+// Lowercase, dash separated and filesystem safe
+pub fn slugify(text: &str) -> String {
+  return text.split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(|word| word.to_lowercase())
+    .collect::<Vec<_>>()
+    .join("-")
+    .chars()
+    .take(48)
+    .collect::<String>()
+    .trim_end_matches('-')
+    .to_string();
+}
+
+// A page's title from its first line of writing, if anything was recognized
+pub fn page_title(layers: &[Layer], recognizer: &dyn Recognizer) -> Result<Option<String>, String> {
+  let lines = first_writing_line(layers);
+  if lines.is_empty() { return Ok(None); }
+  return recognizer.recognize(&lines).map(|text| {
+    let title = text.lines().next().unwrap_or_default().trim().to_string();
+    return if title.is_empty() { None } else { Some(title) };
+  });
+}
+
+// All of a notebook's writing as Markdown, with a heading per page so that
+// search hits can be traced back to the page they came from
+pub fn transcribe(notebook: &Notebook, recognizer: &dyn Recognizer) -> Result<String, String> {
+  let title = notebook.name.clone().unwrap_or_else(|| notebook.id.clone());
+  return notebook.pages.iter()
+    .map(|page| {
+      let lines = page.layers.iter()
+        .flat_map(|layer| layer.lines.iter())
+        .filter(|line| is_writing(line) && !line.points.is_empty())
+        .collect::<Vec<_>>();
+      let text = match lines.is_empty() {
+        true => String::new(),
+        false => recognizer.recognize(&lines).map_err(|why| format!("page {}: {}", page.number, why))?
+      };
+      let text = if text.trim().is_empty() { String::from("_No writing recognized._") } else { text };
+      return Ok(format!("## Page {}\n\n{}\n", page.number, text.trim()));
+    })
+    .collect::<Result<Vec<_>, String>>()
+    .map(|pages| format!("# {}\n\n{}", title, pages.join("\n")));
+}
@@ -0,0 +1,514 @@
+use std::ops::Range;
+
+use rayon::prelude::*;
+use svg::Document as SVGDocument;
+use svg::Node;
+use svg::node::element::Group as SVGGroup;
+use svg::node::element::Rectangle as SVGRectangle;
+use svg::node::element::Style as SVGStyle;
+use svg::node::element::path::Command;
+use svg::node::element::path::Data as SVGData;
+
+use crate::animate::{animate_strokes, animation_style};
+use crate::device::Device;
+use crate::options::{RenderOptions, StrokeElement, ViewBox};
+use crate::orientation::{rotate_layers, rotate_view_box, turned_canvas};
+use crate::pens::{line_strokes, shape_stroke, uses_wet_ink, wet_ink_filter, Stroke};
+use crate::recognize::{recognize_lines, render_recognized, slugify};
+use crate::simplify::{fit_point_budget, simplify_layers, smooth_layers};
+use crate::snap::{snap_layers, Shape};
+use crate::text::{render_text, TextBlock};
+use crate::{Layer, Line};
+
+pub fn render_svg(layers: &[Layer], text: &[TextBlock], options: &RenderOptions) -> Result<SVGDocument, String>{
+  let device = options.device.unwrap_or_else(|| Device::detect(layers));
+  let canvas = device.canvas();
+  // the default view box is the whole canvas, whichever tablet's it is
+  let picked_view_box = match options.view_box == ViewBox::whole(Device::Remarkable.canvas()) {
+    true => ViewBox::whole(canvas),
+    false => options.view_box
+  };
+  let hides_lines = layers.iter().flat_map(|layer| layer.lines.iter()).any(|line| !options.draws_brush(line.brush_type));
+  // the tablet's number for each layer given
+  let number = |index: usize| options.layer_numbers.get(index).copied().unwrap_or(index + 1);
+  let selected = match options.layers.is_some() || !options.exclude_layers.is_empty() || hides_lines {
+    true => Some(layers.iter().enumerate()
+      .filter(|(index, _)| options.draws_layer(number(*index)))
+      .map(|(_, layer)| {
+        let lines = layer.lines.iter().filter(|line| options.draws_brush(line.brush_type)).cloned().collect::<Vec<_>>();
+        return Layer { num_lines: lines.len() as i32, lines };
+      })
+      .collect::<Vec<_>>()),
+    false => None
+  };
+  // the numbers and names of the layers that are drawn, in order
+  let numbers = (0..layers.len())
+    .map(number)
+    .filter(|number| selected.is_none() || options.draws_layer(*number))
+    .collect::<Vec<_>>();
+  let names = numbers.iter()
+    .map(|number| options.layer_names.get(number - 1).map(String::as_str).filter(|name| !name.trim().is_empty()))
+    .collect::<Vec<_>>();
+  let layers = selected.as_deref().unwrap_or(layers);
+  // recognized as written, before any snapping or simplifying
+  let recognized = match options.ocr.as_ref().filter(|ocr| !ocr.sidecar) {
+    Some(ocr) => recognize_lines(layers, &ocr.recognizer)?,
+    None => Vec::new()
+  };
+  let rotated = Some(options.rotation).filter(|rotation| rotation % 360 != 0).map(|rotation| rotate_layers(layers, canvas, rotation));
+  let layers = rotated.as_deref().unwrap_or(layers);
+  let smoothed = options.smoothing.map(|window| smooth_layers(layers, window));
+  let layers = smoothed.as_deref().unwrap_or(layers);
+  // snapped once turned, so that each shape is where its line is drawn
+  let (snapped, shapes) = match options.snap_shapes {
+    true => {
+      let (snapped, shapes) = snap_layers(layers);
+      (Some(snapped), shapes)
+    },
+    false => (None, Vec::new())
+  };
+  let layers = snapped.as_deref().unwrap_or(layers);
+  let simplified = options.tolerance.map(|tolerance| simplify_layers(layers, tolerance));
+  let layers = simplified.as_deref().unwrap_or(layers);
+  let simplified = options.max_output_points.map(|budget| fit_point_budget(layers, budget).0);
+  let layers = simplified.as_deref().unwrap_or(layers);
+  let view_box = options.crop_to_content
+    .and_then(|margin| content_bounds(layers, margin))
+    .unwrap_or_else(|| rotate_view_box(picked_view_box, canvas, options.rotation));
+  let options = &RenderOptions { view_box, device: Some(device), ..options.clone() };
+  let document = render_page(layers, &numbers, &shapes, &layer_labels(&names), options)?;
+  let document = match text.is_empty() {
+    true => document,
+    false => document.add(render_text(text, options))
+  };
+  let document = match recognized.is_empty() {
+    true => document,
+    false => document.add(render_recognized(&recognized, options))
+  };
+  return Ok(match options.watermark.as_ref().filter(|watermark| watermark.above) {
+    Some(watermark) => document.add(watermark.render(&view_box)),
+    None => document
+  });
+}
+
+// Everything up to the strokes, within options.view_box. `numbers` are the
+// layers' numbers on the tablet. Lines snapped to a shape, as `shapes` has
+// them by layer, are drawn as that shape.
+fn render_page(
+  layers: &[Layer],
+  numbers: &[usize],
+  shapes: &[Vec<Option<Shape>>],
+  labels: &[Option<(String, &str)>],
+  options: &RenderOptions
+) -> Result<SVGDocument, String>{
+  let view_box = options.view_box;
+  let (width, height) = options.dimensions();
+  let document = SVGDocument::new()
+    .set("width", width.to_string())
+    .set("height", height.to_string())
+    .set("viewBox", options.view_box.to_string());
+  let document = match labels.iter().any(Option::is_some) {
+    true => document.set("xmlns:inkscape", INKSCAPE_NAMESPACE),
+    false => document
+  };
+  let document = match &options.background {
+    Some(color) => document.add(
+      SVGRectangle::new()
+        .set("x", view_box.x)
+        .set("y", view_box.y)
+        .set("width", view_box.width)
+        .set("height", view_box.height)
+        .set("fill", color.as_str())
+    ),
+    None => document
+  };
+  let document = match &options.template {
+    Some(template) => document.add(template.render_on(turned_canvas(options.canvas(), options.rotation))),
+    None => document
+  };
+  let document = match options.watermark.as_ref().filter(|watermark| !watermark.above) {
+    Some(watermark) => document.add(watermark.render(&view_box)),
+    None => document
+  };
+  // rendered in parallel, and kept in order
+  let mut strokes = layers.par_iter().enumerate()
+    .map(|(layer_index, layer)| {
+      return layer.lines.par_iter().enumerate()
+        .map(|(index, line)| {
+          let strokes = match shapes.get(layer_index).and_then(|shapes| shapes.get(index)).copied().flatten() {
+            Some(shape) => vec![shape_stroke(line, shape, options)],
+            None => line_strokes(line, options)
+          };
+          return strokes.into_iter().map(|stroke| stroke.rounded(options.precision)).collect();
+        })
+        .collect::<Vec<_>>();
+    })
+    .collect::<Vec<_>>();
+  if let Some(drawn_lines) = options.drawn_lines {
+    strokes.iter_mut().flatten().skip(drawn_lines).for_each(Vec::clear);
+  }
+  if options.data_attributes {
+    label_strokes(&mut strokes, layers, numbers);
+  }
+  if options.css_classes {
+    class_strokes(&mut strokes, layers, numbers);
+  }
+  let document = match &options.stylesheet {
+    Some(stylesheet) => document.add(SVGStyle::new(stylesheet.css())),
+    None => document
+  };
+  let document = match strokes.iter().flatten().flatten().any(uses_wet_ink) {
+    true => document.add(wet_ink_filter()),
+    false => document
+  };
+  let document = match options.animate {
+    true => {
+      animate_strokes(&mut strokes);
+      document.add(animation_style())
+    },
+    false => document
+  };
+  // lines to group together, per layer; a single group of all of a layer's
+  // lines when not grouping
+  let grouped = options.group_strokes || options.group_by_brush;
+  let clusters = layers.iter()
+    .map(|layer| match (options.group_by_brush, options.group_strokes) {
+      (true, _) => brush_clusters(&layer.lines),
+      (false, true) => stroke_clusters(&layer.lines).into_iter().map(|range| Cluster { lines: range.collect(), class: None }).collect(),
+      (false, false) => vec![Cluster { lines: (0..layer.lines.len()).collect(), class: None }]
+    })
+    .collect::<Vec<_>>();
+  if options.optimize {
+    let optimized = strokes.into_iter().zip(clusters.iter())
+      .map(|(layer, layer_clusters)| {
+        return layer_clusters.iter()
+          .map(|cluster| (cluster.class, optimize_strokes(cluster.lines.iter().flat_map(|index| layer[*index].iter()).cloned().collect())))
+          .collect::<Vec<_>>();
+      })
+      .collect::<Vec<_>>();
+    let hoisted = hoistable_attributes(optimized.iter().flatten().flat_map(|(_, strokes)| strokes));
+    // fill="none" is hoisted too; everything else that is drawn sets its
+    // own fill
+    let document = hoisted.iter().fold(
+      document.set("fill", "none"),
+      |acc_svg, (name, value)| acc_svg.set(*name, value.as_str())
+    );
+    if optimized.len() == 1 && labels.iter().all(Option::is_none) {
+      return Ok(append_optimized_layer(document, &optimized[0], &hoisted, grouped, options.element));
+    }
+    return Ok(optimized.iter().zip(labels).fold(document, |acc_svg, (layer_clusters, label)| {
+      return acc_svg.add(labelled(append_optimized_layer(SVGGroup::new(), layer_clusters, &hoisted, grouped, options.element), label));
+    }));
+  }
+  return Ok(strokes.iter().zip(clusters.iter()).zip(labels).fold(
+    document,
+    |acc_svg, ((layer_strokes, layer_clusters), label)| {
+      let layer = layer_clusters.iter().fold(SVGGroup::new(), |acc_group, cluster| {
+        let lines = cluster.lines.iter()
+          .fold(SVGGroup::new(), |acc_cluster, index| render_line(acc_cluster, &layer_strokes[*index], options.element));
+        return match (grouped, cluster.class) {
+          (true, Some(class)) => acc_group.add(lines.set("class", class)),
+          (true, None) => acc_group.add(lines),
+          (false, _) => lines
+        };
+      });
+      return acc_svg.add(labelled(layer, label));
+    })
+  );
+}
+
+const INKSCAPE_NAMESPACE: &str = "http://www.inkscape.org/namespaces/inkscape";
+
+// An id for each named layer, from its name, and the name itself: layer-
+// and the name in lowercase words, numbered from the second of a name on
+fn layer_labels<'a>(names: &[Option<&'a str>]) -> Vec<Option<(String, &'a str)>> {
+  let mut ids = Vec::<String>::new();
+  return names.iter()
+    .map(|name| name.map(|name| {
+      let slug = slugify(name);
+      let base = match slug.is_empty() {
+        true => String::from("layer"),
+        false => format!("layer-{}", slug)
+      };
+      let id = (1..).map(|count| match count {
+        1 => base.clone(),
+        count => format!("{}-{}", base, count)
+      }).find(|id| !ids.contains(id)).unwrap_or(base);
+      ids.push(id.clone());
+      return (id, name);
+    }))
+    .collect();
+}
+
+// A name as an attribute value needing no escapes, which the SVG parser
+// PDF, PNG and EPS output read pages back with doesn't take there
+fn attribute_text(name: &str) -> String {
+  return name.replace('&', "and").chars().filter(|c| !matches!(c, '<' | '>' | '"')).collect();
+}
+
+// A layer's group as an Inkscape layer, if it has a name
+fn labelled(layer: SVGGroup, label: &Option<(String, &str)>) -> SVGGroup {
+  return match label {
+    Some((id, name)) => layer
+      .set("id", id.as_str())
+      .set("inkscape:label", attribute_text(name))
+      .set("inkscape:groupmode", "layer"),
+    None => layer
+  };
+}
+
+// Adds a layer's clusters of optimized strokes to a parent, minus the
+// attributes hoisted to the root; each cluster in a group of its own, with
+// its class if it has one, if `grouped`
+fn append_optimized_layer<P: Node>(
+  mut parent: P,
+  layer_clusters: &[(Option<&'static str>, Vec<Stroke>)],
+  hoisted: &[(&'static str, String)],
+  grouped: bool,
+  element: StrokeElement
+) -> P {
+  layer_clusters.iter().for_each(|(class, cluster)| {
+    let strokes = cluster.iter().map(|stroke| Stroke {
+      attributes: stroke.attributes.iter().filter(|a| !hoisted.contains(a)).cloned().collect(),
+      ..stroke.clone()
+    });
+    if grouped {
+      let mut group = SVGGroup::new();
+      if let Some(class) = class {
+        group.assign("class", *class);
+      }
+      strokes.for_each(|stroke| stroke.append_to(&mut group, element));
+      parent.append(group);
+    } else {
+      strokes.for_each(|stroke| stroke.append_to(&mut parent, element));
+    }
+  });
+  return parent;
+}
+
+// Lines of a layer drawn together, by index, and the class of the group
+// they're drawn in, if any
+struct Cluster {
+  lines: Vec<usize>,
+  class: Option<&'static str>
+}
+
+// A layer's lines by the brush they were drawn with, classed with its name,
+// in the order the brushes were first used
+fn brush_clusters(lines: &[Line]) -> Vec<Cluster> {
+  let mut clusters = Vec::<Cluster>::new();
+  lines.iter().enumerate().for_each(|(index, line)| {
+    let class = Some(line.brush_type.name());
+    match clusters.iter_mut().find(|cluster| cluster.class == class) {
+      Some(cluster) => cluster.lines.push(index),
+      None => clusters.push(Cluster { lines: vec![index], class })
+    }
+  });
+  return clusters;
+}
+
+// Horizontal and vertical gap (in canvas pixels) that still counts as part
+// of the same word or drawing
+const CLUSTER_GAP: f32 = 30.0;
+
+fn line_bounds(line: &Line) -> Option<(f32, f32, f32, f32)> {
+  let reach = line.brush_size * line.brush_type.style().width_scale / 2.0;
+  return line.points.iter().fold(None, |acc: Option<(f32, f32, f32, f32)>, point| {
+    let (min_x, min_y, max_x, max_y) = acc.unwrap_or((f32::MAX, f32::MAX, f32::MIN, f32::MIN));
+    return Some((min_x.min(point.x - reach), min_y.min(point.y - reach), max_x.max(point.x + reach), max_y.max(point.y + reach)));
+  });
+}
+
+// Splits a layer's lines into runs that were drawn one after the other and
+// close together (words, or the parts of a sketch), as ranges of indices.
+// Each line joins the run before it when its bounds come within
+// CLUSTER_GAP of the run's bounds so far.
+pub fn stroke_clusters(lines: &[Line]) -> Vec<Range<usize>> {
+  let mut clusters = Vec::<(Range<usize>, Option<(f32, f32, f32, f32)>)>::new();
+  lines.iter().enumerate().for_each(|(index, line)| {
+    let bounds = line_bounds(line);
+    let near = |a: (f32, f32, f32, f32), b: (f32, f32, f32, f32)| {
+      return a.0 - CLUSTER_GAP <= b.2 && b.0 - CLUSTER_GAP <= a.2 && a.1 - CLUSTER_GAP <= b.3 && b.1 - CLUSTER_GAP <= a.3;
+    };
+    match clusters.last_mut() {
+      Some((range, cluster_bounds)) if match (*cluster_bounds, bounds) {
+        (Some(a), Some(b)) => near(a, b),
+        // lines without points don't affect grouping
+        _ => true
+      } => {
+        range.end = index + 1;
+        *cluster_bounds = match (*cluster_bounds, bounds) {
+          (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))),
+          (a, b) => a.or(b)
+        };
+      },
+      _ => clusters.push((index..index + 1, bounds))
+    }
+  });
+  return clusters.into_iter().map(|(range, _)| range).collect();
+}
+
+// Bounding box of everything drawn, including half of each stroke's
+// width so that thick strokes aren't clipped at the edges
+pub fn content_bounds(layers: &[Layer], margin: f32) -> Option<ViewBox> {
+  return layers.iter()
+    .flat_map(|layer| layer.lines.iter())
+    .flat_map(|line| {
+      let reach = line.brush_size * line.brush_type.style().width_scale / 2.0 + margin;
+      return line.points.iter().map(move |point| (point, reach));
+    })
+    .fold(None, |acc: Option<(f32, f32, f32, f32)>, (point, reach)| {
+      let (min_x, min_y, max_x, max_y) = acc.unwrap_or((f32::MAX, f32::MAX, f32::MIN, f32::MIN));
+      return Some((
+        min_x.min(point.x - reach),
+        min_y.min(point.y - reach),
+        max_x.max(point.x + reach),
+        max_y.max(point.y + reach)
+      ));
+    })
+    .map(|(min_x, min_y, max_x, max_y)| ViewBox {
+      x: min_x,
+      y: min_y,
+      width: (max_x - min_x).max(1.0),
+      height: (max_y - min_y).max(1.0)
+    });
+}
+
+// One path per line, or a group when its pen drew it in several strokes
+fn render_line(mut group: SVGGroup, strokes: &[Stroke], element: StrokeElement) -> SVGGroup {
+  if strokes.len() == 1 {
+    strokes[0].append_to(&mut group, element);
+    return group;
+  }
+  let mut line_group = SVGGroup::new();
+  strokes.iter().for_each(|stroke| stroke.append_to(&mut line_group, element));
+  return group.add(line_group);
+}
+
+// Attribute values that are the SVG defaults anyway
+const REDUNDANT_ATTRIBUTES: [(&str, &str); 3] = [
+  ("stroke-opacity", "1"),
+  ("stroke-linejoin", "miter"),
+  ("stroke-linecap", "butt")
+];
+
+// Drops default and hoisted attributes, then merges runs of consecutive
+// strokes with identical styling into a single path. Translucent strokes
+// are left alone since overlaps within one path don't build up opacity
+// the way overlapping paths do.
+fn optimize_strokes(strokes: Vec<Stroke>) -> Vec<Stroke> {
+  return strokes.into_iter()
+    .map(|stroke| Stroke {
+      attributes: stroke.attributes.into_iter()
+        .filter(|(name, value)| *name != "fill" || value != "none")
+        .filter(|(name, value)| !REDUNDANT_ATTRIBUTES.contains(&(*name, value.as_str())))
+        .collect(),
+      ..stroke
+    })
+    .fold(Vec::<Stroke>::new(), |mut acc_strokes, stroke| {
+      let opaque = !stroke.attributes.iter().any(|(name, _)| *name == "stroke-opacity");
+      match acc_strokes.last_mut() {
+        Some(last) if opaque && last.shape.is_none() && stroke.shape.is_none() && last.attributes == stroke.attributes => {
+          let mut commands = Vec::<Command>::from(last.data.clone());
+          commands.extend(Vec::<Command>::from(stroke.data));
+          last.data = SVGData::from(commands);
+        },
+        _ => acc_strokes.push(stroke)
+      }
+      return acc_strokes;
+    });
+}
+
+// Marks each stroke with the line it was drawn for, and the number of its
+// layer on the tablet. Strokes only merge under --optimize when their
+// labels match, so labels stay accurate.
+fn label_strokes(strokes: &mut [Vec<Vec<Stroke>>], layers: &[Layer], numbers: &[usize]) {
+  strokes.iter_mut().zip(layers.iter().zip(numbers)).for_each(|(layer_strokes, (layer, number))| {
+    layer_strokes.iter_mut().zip(layer.lines.iter()).for_each(|(line_strokes, line)| {
+      line_strokes.iter_mut().for_each(|stroke| {
+        stroke.attributes.push(("data-brush", String::from(line.brush_type.name())));
+        stroke.attributes.push(("data-color", String::from(line.brush_color.name())));
+        stroke.attributes.push(("data-size", line.brush_size.to_string()));
+        stroke.attributes.push(("data-layer", number.to_string()));
+      });
+    });
+  });
+}
+
+// Classes each stroke for its line's brush, color and layer, numbered as
+// on the tablet
+fn class_strokes(strokes: &mut [Vec<Vec<Stroke>>], layers: &[Layer], numbers: &[usize]) {
+  strokes.iter_mut().zip(layers.iter().zip(numbers)).for_each(|(layer_strokes, (layer, number))| {
+    layer_strokes.iter_mut().zip(layer.lines.iter()).for_each(|(line_strokes, line)| {
+      let classes = format!("brush-{} color-{} layer-{}", line.brush_type.name(), line.brush_color.name(), number);
+      line_strokes.iter_mut().for_each(|stroke| stroke.add_class(&classes));
+    });
+  });
+}
+
+// Only attributes that can't change how backgrounds and templates look
+const HOISTABLE_ATTRIBUTES: [&str; 2] = ["stroke-linejoin", "stroke-linecap"];
+
+fn hoistable_attributes<'a>(mut strokes: impl Iterator<Item = &'a Stroke>) -> Vec<(&'static str, String)> {
+  let first = match strokes.next() {
+    Some(first) => first,
+    None => return Vec::new()
+  };
+  let candidates = first.attributes.iter()
+    .filter(|(name, _)| HOISTABLE_ATTRIBUTES.contains(name))
+    .cloned()
+    .collect::<Vec<_>>();
+  return strokes.fold(candidates, |acc, stroke| {
+    return acc.into_iter().filter(|a| stroke.attributes.contains(a)).collect();
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{parse_document, LogLevel};
+
+  const GOOD: &[u8] = include_bytes!("../test_files/good.rm");
+
+  fn quiet(_: LogLevel, _: String) {}
+
+  // good.rm's lines spread over three layers
+  fn three_layers() -> Vec<Layer> {
+    let lines = parse_document(GOOD, &quiet).unwrap().layers.remove(0).lines;
+    return lines.chunks(3).map(|lines| Layer { num_lines: lines.len() as i32, lines: lines.to_vec() }).collect();
+  }
+
+  // the distinct values of an attribute in an SVG, in order
+  fn values(svg: &str, attribute: &str) -> Vec<String> {
+    let mut values = svg.split(&format!("{}=\"", attribute)).skip(1)
+      .map(|rest| rest[..rest.find('"').unwrap()].to_string())
+      .collect::<Vec<_>>();
+    values.dedup();
+    return values;
+  }
+
+  #[test]
+  fn labels_strokes_with_their_layers_on_the_tablet() {
+    let layers = three_layers();
+    let options = RenderOptions::default().with_data_attributes(true);
+    let layer = |options: RenderOptions| values(&render_svg(&layers, &[], &options).unwrap().to_string(), "data-layer");
+    assert_eq!(layer(options.clone()), vec!["1", "2", "3"]);
+    assert_eq!(layer(options.clone().with_layers(vec![3])), vec!["3"]);
+    assert_eq!(layer(options.clone().with_exclude_layers(vec![1])), vec!["2", "3"]);
+    // a layer split off into a page of its own
+    let split = render_svg(&layers[1..2], &[], &options.with_layer_numbers(vec![2])).unwrap().to_string();
+    assert_eq!(values(&split, "data-layer"), vec!["2"]);
+  }
+
+  #[test]
+  fn classes_strokes_with_their_layers_on_the_tablet() {
+    let layers = three_layers();
+    let options = RenderOptions::default().with_css_classes(true).with_exclude_layers(vec![1]);
+    let svg = render_svg(&layers, &[], &options).unwrap().to_string();
+    let mut classes = values(&svg, "class").iter()
+      .filter_map(|classes| classes.split(' ').find(|class| class.starts_with("layer-")).map(String::from))
+      .collect::<Vec<_>>();
+    classes.dedup();
+    assert_eq!(classes, vec!["layer-2", "layer-3"]);
+  }
+}
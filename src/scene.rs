@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+
+use svg::Document as SVGDocument;
+use svg::node::element::path::{Command, Data as SVGData, Position};
+use svg::node::element::tag::Type;
+use svg::parser::{Event, Parser};
+
+use crate::options::{Length, ViewBox};
+
+// A rendered page flattened into plain filled and stroked shapes, for the
+// output formats that aren't SVG. It is read back from the SVG that
+// render_svg produces, so every format gets the same page (templates,
+// watermarks and all) minus effects only SVG has, like blurs.
+#[derive(Debug, Clone)]
+pub struct Scene {
+  // the page size in CSS pixels
+  pub width: f32,
+  pub height: f32,
+  // the region of the canvas the page shows, which shapes are in
+  pub view_box: ViewBox,
+  // in painting order
+  pub items: Vec<Item>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rgb {
+  // 0 to 1
+  pub red: f32,
+  pub green: f32,
+  pub blue: f32
+}
+
+impl Rgb {
+  fn parse(color: &str) -> Option<Rgb> {
+    let hex = |value: &str| u8::from_str_radix(value, 16).ok().map(|v| v as f32 / 255.0);
+    let color = color.trim().to_ascii_lowercase();
+    let color = match color.as_str() {
+      "black" => "#000000",
+      "white" => "#ffffff",
+      "grey" | "gray" => "#808080",
+      "red" => "#ff0000",
+      "green" => "#008000",
+      "blue" => "#0000ff",
+      "yellow" => "#ffff00",
+      other => other
+    };
+    let digits = color.strip_prefix('#')?;
+    return match digits.len() {
+      3 => {
+        let channel = |index: usize| hex(&digits[index..=index].repeat(2));
+        Some(Rgb { red: channel(0)?, green: channel(1)?, blue: channel(2)? })
+      },
+      6 => Some(Rgb { red: hex(&digits[0..2])?, green: hex(&digits[2..4])?, blue: hex(&digits[4..6])? }),
+      _ => None
+    };
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Paint {
+  pub color: Rgb,
+  pub opacity: f32
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineCap {
+  Butt,
+  Round,
+  Square
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineJoin {
+  Miter,
+  Round,
+  Bevel
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pen {
+  pub paint: Paint,
+  pub width: f32,
+  pub cap: LineCap,
+  pub join: LineJoin,
+  // alternating dash and gap lengths, empty for a solid line
+  pub dashes: Vec<f32>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Segment {
+  Move(f32, f32),
+  Line(f32, f32),
+  Close
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Blend {
+  Normal,
+  Multiply,
+  Screen
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shape {
+  pub segments: Vec<Segment>,
+  pub fill: Option<Paint>,
+  pub stroke: Option<Pen>,
+  pub blend: Blend
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Anchor {
+  Start,
+  Middle,
+  End
+}
+
+// A line of text set in a sans-serif face; `transform` maps from the
+// label's own coordinates (x, y and its size) to the canvas
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+  pub text: String,
+  pub x: f32,
+  pub y: f32,
+  pub size: f32,
+  pub bold: bool,
+  pub anchor: Anchor,
+  pub paint: Paint,
+  pub transform: Transform
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+  Shape(Shape),
+  Label(Label)
+}
+
+// An affine transform [a b c d e f], as in SVG's matrix()
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform(pub [f32; 6]);
+
+impl Transform {
+  pub const IDENTITY: Transform = Transform([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+  pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+    let [a, b, c, d, e, f] = self.0;
+    return (a * x + c * y + e, b * x + d * y + f);
+  }
+
+  // `self` applied after `inner`
+  pub fn then(&self, inner: &Transform) -> Transform {
+    let [a, b, c, d, e, f] = self.0;
+    let [g, h, i, j, k, l] = inner.0;
+    return Transform([
+      a * g + c * h, b * g + d * h,
+      a * i + c * j, b * i + d * j,
+      a * k + c * l + e, b * k + d * l + f
+    ]);
+  }
+
+  // how much lengths grow on average, e.g. for stroke widths
+  pub fn scale(&self) -> f32 {
+    let [a, b, c, d, _, _] = self.0;
+    return (a * d - b * c).abs().sqrt();
+  }
+
+  // The transforms render_svg writes: rotate, translate, scale and matrix
+  fn parse(transform: &str) -> Transform {
+    return transform.split(')')
+      .filter_map(|part| {
+        let (name, arguments) = part.split_once('(')?;
+        let values = arguments.split(|c: char| c == ',' || c.is_whitespace())
+          .filter_map(|value| value.parse::<f32>().ok())
+          .collect::<Vec<_>>();
+        let value = |index: usize| values.get(index).copied().unwrap_or(0.0);
+        return match (name.trim(), values.len()) {
+          ("matrix", 6) => Some(Transform([value(0), value(1), value(2), value(3), value(4), value(5)])),
+          ("translate", _) => Some(Transform([1.0, 0.0, 0.0, 1.0, value(0), value(1)])),
+          ("scale", 1) => Some(Transform([value(0), 0.0, 0.0, value(0), 0.0, 0.0])),
+          ("scale", _) => Some(Transform([value(0), 0.0, 0.0, value(1), 0.0, 0.0])),
+          ("rotate", count) => {
+            let (sin, cos) = value(0).to_radians().sin_cos();
+            let rotation = Transform([cos, sin, -sin, cos, 0.0, 0.0]);
+            match count {
+              3 => Some(Transform([1.0, 0.0, 0.0, 1.0, value(1), value(2)])
+                .then(&rotation)
+                .then(&Transform([1.0, 0.0, 0.0, 1.0, -value(1), -value(2)]))),
+              _ => Some(rotation)
+            }
+          },
+          _ => None
+        };
+      })
+      .fold(Transform::IDENTITY, |acc, next| acc.then(&next));
+  }
+}
+
+// Presentation attributes that children inherit, and the group state they
+// sit in
+#[derive(Debug, Clone)]
+struct Context {
+  attributes: HashMap<String, String>,
+  opacity: f32,
+  transform: Transform
+}
+
+const INHERITED: [&str; 12] = [
+  "fill", "fill-opacity", "stroke", "stroke-opacity", "stroke-width", "stroke-linecap", "stroke-linejoin",
+  "stroke-dasharray", "font-size", "font-weight", "text-anchor", "font-family"
+];
+
+// Elements whose contents aren't drawn
+const SKIPPED: [&str; 5] = ["defs", "style", "title", "desc", "image"];
+
+const CIRCLE_SEGMENTS: usize = 24;
+// lines per curve or arc
+const CURVE_SEGMENTS: usize = 16;
+
+impl Context {
+  fn get(&self, name: &str) -> Option<&str> {
+    return self.attributes.get(name).map(|value| value.as_str());
+  }
+
+  fn number(&self, name: &str, default: f32) -> f32 {
+    return self.get(name).and_then(|value| value.trim().parse().ok()).unwrap_or(default);
+  }
+
+  fn paint(&self, color: &str, opacity: &str) -> Option<Paint> {
+    return self.get(color)
+      .filter(|color| *color != "none")
+      .and_then(Rgb::parse)
+      .map(|color| Paint { color, opacity: self.number(opacity, 1.0) * self.opacity });
+  }
+
+  fn shape(&self, segments: Vec<Segment>) -> Shape {
+    let blend = match self.get("style") {
+      Some(style) if style.contains("mix-blend-mode:multiply") => Blend::Multiply,
+      Some(style) if style.contains("mix-blend-mode:screen") => Blend::Screen,
+      _ => Blend::Normal
+    };
+    let scale = self.transform.scale();
+    let stroke = self.paint("stroke", "stroke-opacity").map(|paint| Pen {
+      paint,
+      width: self.number("stroke-width", 1.0) * scale,
+      cap: match self.get("stroke-linecap") {
+        Some("round") => LineCap::Round,
+        Some("square") => LineCap::Square,
+        _ => LineCap::Butt
+      },
+      join: match self.get("stroke-linejoin") {
+        Some("round") => LineJoin::Round,
+        Some("bevel") => LineJoin::Bevel,
+        _ => LineJoin::Miter
+      },
+      dashes: self.get("stroke-dasharray")
+        .map(|dashes| {
+          return dashes.split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(|dash| dash.parse::<f32>().ok())
+            .map(|dash| dash * scale)
+            .collect();
+        })
+        .unwrap_or_default()
+    });
+    let segments = segments.into_iter()
+      .map(|segment| match segment {
+        Segment::Move(x, y) => {
+          let (x, y) = self.transform.apply(x, y);
+          Segment::Move(x, y)
+        },
+        Segment::Line(x, y) => {
+          let (x, y) = self.transform.apply(x, y);
+          Segment::Line(x, y)
+        },
+        Segment::Close => Segment::Close
+      })
+      .collect();
+    return Shape { segments, fill: self.paint("fill", "fill-opacity"), stroke, blend };
+  }
+}
+
+// Where a path's commands have got to, with curves and arcs flattened
+// into lines as they're drawn
+#[derive(Default)]
+struct PathCursor {
+  segments: Vec<Segment>,
+  current: (f32, f32),
+  start: (f32, f32),
+  // the last curve's final control point, which smooth curves reflect
+  cubic_control: Option<(f32, f32)>,
+  quadratic_control: Option<(f32, f32)>
+}
+
+impl PathCursor {
+  fn absolute(&self, position: &Position, x: f32, y: f32) -> (f32, f32) {
+    return match position {
+      Position::Absolute => (x, y),
+      Position::Relative => (self.current.0 + x, self.current.1 + y)
+    };
+  }
+
+  // the current point mirrored through the given control point, or the
+  // current point itself without one
+  fn reflected(&self, control: Option<(f32, f32)>) -> (f32, f32) {
+    let (x, y) = self.current;
+    return control.map_or(self.current, |(control_x, control_y)| (2.0 * x - control_x, 2.0 * y - control_y));
+  }
+
+  fn move_to(&mut self, point: (f32, f32)) {
+    self.segments.push(Segment::Move(point.0, point.1));
+    self.current = point;
+    self.start = point;
+    self.cubic_control = None;
+    self.quadratic_control = None;
+  }
+
+  fn line_to(&mut self, point: (f32, f32)) {
+    self.segments.push(Segment::Line(point.0, point.1));
+    self.current = point;
+    self.cubic_control = None;
+    self.quadratic_control = None;
+  }
+
+  fn close(&mut self) {
+    self.segments.push(Segment::Close);
+    self.current = self.start;
+    self.cubic_control = None;
+    self.quadratic_control = None;
+  }
+
+  fn cubic_to(&mut self, first: (f32, f32), second: (f32, f32), end: (f32, f32)) {
+    let start = self.current;
+    (1..=CURVE_SEGMENTS).for_each(|step| {
+      let t = step as f32 / CURVE_SEGMENTS as f32;
+      let u = 1.0 - t;
+      let weights = [u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t];
+      let along = |a: f32, b: f32, c: f32, d: f32| weights[0] * a + weights[1] * b + weights[2] * c + weights[3] * d;
+      self.line_to((along(start.0, first.0, second.0, end.0), along(start.1, first.1, second.1, end.1)));
+    });
+    self.cubic_control = Some(second);
+  }
+
+  fn quadratic_to(&mut self, control: (f32, f32), end: (f32, f32)) {
+    // the same curve as a cubic one
+    let start = self.current;
+    let toward = |from: (f32, f32)| (from.0 + (control.0 - from.0) * 2.0 / 3.0, from.1 + (control.1 - from.1) * 2.0 / 3.0);
+    self.cubic_to(toward(start), toward(end), end);
+    self.cubic_control = None;
+    self.quadratic_control = Some(control);
+  }
+
+  // As in the SVG spec's implementation notes, from the endpoints to the
+  // ellipse's center and the angles the arc runs between
+  fn arc_to(&mut self, radii: (f32, f32), rotation: f32, large_arc: bool, sweep: bool, end: (f32, f32)) {
+    let start = self.current;
+    let (mut rx, mut ry) = (radii.0.abs(), radii.1.abs());
+    if rx == 0.0 || ry == 0.0 || start == end {
+      return self.line_to(end);
+    }
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let (dx, dy) = ((start.0 - end.0) / 2.0, (start.1 - end.1) / 2.0);
+    let (x, y) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+    // radii too small to reach the end are scaled up until they just do
+    let reach = (x * x) / (rx * rx) + (y * y) / (ry * ry);
+    if reach > 1.0 {
+      rx *= reach.sqrt();
+      ry *= reach.sqrt();
+    }
+    let numerator = (rx * rx * ry * ry - rx * rx * y * y - ry * ry * x * x).max(0.0);
+    let coefficient = (numerator / (rx * rx * y * y + ry * ry * x * x)).sqrt();
+    let coefficient = if large_arc == sweep { -coefficient } else { coefficient };
+    let (center_x, center_y) = (coefficient * rx * y / ry, -coefficient * ry * x / rx);
+    let center = (
+      cos * center_x - sin * center_y + (start.0 + end.0) / 2.0,
+      sin * center_x + cos * center_y + (start.1 + end.1) / 2.0
+    );
+    let first = ((y - center_y) / ry).atan2((x - center_x) / rx);
+    let sweep_angle = ((-y - center_y) / ry).atan2((-x - center_x) / rx) - first;
+    let sweep_angle = match sweep {
+      true if sweep_angle < 0.0 => sweep_angle + std::f32::consts::TAU,
+      false if sweep_angle > 0.0 => sweep_angle - std::f32::consts::TAU,
+      _ => sweep_angle
+    };
+    (1..CURVE_SEGMENTS).for_each(|step| {
+      let (sin_angle, cos_angle) = (first + sweep_angle * step as f32 / CURVE_SEGMENTS as f32).sin_cos();
+      self.line_to((
+        center.0 + rx * cos_angle * cos - ry * sin_angle * sin,
+        center.1 + rx * cos_angle * sin + ry * sin_angle * cos
+      ));
+    });
+    self.line_to(end);
+  }
+
+  fn command(&mut self, command: &Command) {
+    match command {
+      Command::Move(position, p) => p.chunks_exact(2).enumerate().for_each(|(index, p)| {
+        // later pairs are lines from the point moved to
+        let point = self.absolute(position, p[0], p[1]);
+        if index == 0 { self.move_to(point) } else { self.line_to(point) }
+      }),
+      Command::Line(position, p) => p.chunks_exact(2).for_each(|p| {
+        let point = self.absolute(position, p[0], p[1]);
+        self.line_to(point);
+      }),
+      Command::HorizontalLine(position, p) => p.iter().for_each(|x| {
+        let point = (self.absolute(position, *x, 0.0).0, self.current.1);
+        self.line_to(point);
+      }),
+      Command::VerticalLine(position, p) => p.iter().for_each(|y| {
+        let point = (self.current.0, self.absolute(position, 0.0, *y).1);
+        self.line_to(point);
+      }),
+      Command::CubicCurve(position, p) => p.chunks_exact(6).for_each(|p| {
+        let (first, second) = (self.absolute(position, p[0], p[1]), self.absolute(position, p[2], p[3]));
+        let end = self.absolute(position, p[4], p[5]);
+        self.cubic_to(first, second, end);
+      }),
+      Command::SmoothCubicCurve(position, p) => p.chunks_exact(4).for_each(|p| {
+        let first = self.reflected(self.cubic_control);
+        let (second, end) = (self.absolute(position, p[0], p[1]), self.absolute(position, p[2], p[3]));
+        self.cubic_to(first, second, end);
+      }),
+      Command::QuadraticCurve(position, p) => p.chunks_exact(4).for_each(|p| {
+        let (control, end) = (self.absolute(position, p[0], p[1]), self.absolute(position, p[2], p[3]));
+        self.quadratic_to(control, end);
+      }),
+      Command::SmoothQuadraticCurve(position, p) => p.chunks_exact(2).for_each(|p| {
+        let control = self.reflected(self.quadratic_control);
+        let end = self.absolute(position, p[0], p[1]);
+        self.quadratic_to(control, end);
+      }),
+      Command::EllipticalArc(position, p) => p.chunks_exact(7).for_each(|p| {
+        let end = self.absolute(position, p[5], p[6]);
+        self.arc_to((p[0], p[1]), p[2], p[3] != 0.0, p[4] != 0.0, end);
+      }),
+      Command::Close => self.close()
+    }
+  }
+}
+
+fn path_segments(data: &str) -> Vec<Segment> {
+  let data = match SVGData::parse(data) {
+    Ok(data) => data,
+    Err(_) => return Vec::new()
+  };
+  let mut cursor = PathCursor::default();
+  data.iter().for_each(|command| cursor.command(command));
+  return cursor.segments;
+}
+
+fn point_segments(points: &str) -> Vec<Segment> {
+  return points.split_whitespace()
+    .filter_map(|point| {
+      let (x, y) = point.split_once(',')?;
+      return Some((x.parse::<f32>().ok()?, y.parse::<f32>().ok()?));
+    })
+    .enumerate()
+    .map(|(index, (x, y))| if index == 0 { Segment::Move(x, y) } else { Segment::Line(x, y) })
+    .collect();
+}
+
+fn unescape_xml(text: &str) -> String {
+  return text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&");
+}
+
+// Each element's geometry, as the shapes it draws
+fn element_segments(name: &str, context: &Context) -> Option<Vec<Segment>> {
+  let number = |attribute: &str| context.number(attribute, 0.0);
+  return match name {
+    "path" => Some(path_segments(context.get("d").unwrap_or_default())),
+    "polyline" => Some(point_segments(context.get("points").unwrap_or_default())),
+    "polygon" => Some(point_segments(context.get("points").unwrap_or_default()))
+      .map(|segments| segments.into_iter().chain(std::iter::once(Segment::Close)).collect()),
+    "line" => Some(vec![Segment::Move(number("x1"), number("y1")), Segment::Line(number("x2"), number("y2"))]),
+    "rect" => {
+      let (x, y, width, height) = (number("x"), number("y"), number("width"), number("height"));
+      Some(vec![
+        Segment::Move(x, y), Segment::Line(x + width, y), Segment::Line(x + width, y + height),
+        Segment::Line(x, y + height), Segment::Close
+      ])
+    },
+    "circle" | "ellipse" => {
+      let (rx, ry) = match name {
+        "circle" => (number("r"), number("r")),
+        _ => (number("rx"), number("ry"))
+      };
+      let (cx, cy) = (number("cx"), number("cy"));
+      let around = (0..CIRCLE_SEGMENTS).map(|step| {
+        let (sin, cos) = (step as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU).sin_cos();
+        let (x, y) = (cx + rx * cos, cy + ry * sin);
+        return if step == 0 { Segment::Move(x, y) } else { Segment::Line(x, y) };
+      });
+      Some(around.chain(std::iter::once(Segment::Close)).collect())
+    },
+    _ => None
+  };
+}
+
+pub fn scene(document: &SVGDocument) -> Result<Scene, String> {
+  return parse_scene(&document.to_string());
+}
+
+// The same for SVG from anywhere, as far as the shapes go that render_svg
+// writes: paths, polylines, polygons, lines, rects, circles and ellipses
+pub fn parse_scene(source: &str) -> Result<Scene, String> {
+  let mut stack = vec![root_context()];
+  let mut scene = Scene {
+    width: 0.0,
+    height: 0.0,
+    view_box: ViewBox { x: 0.0, y: 0.0, width: 0.0, height: 0.0 },
+    items: Vec::new()
+  };
+  // depth within elements that aren't drawn
+  let mut skipping = 0;
+  // the text element being read, if any
+  let mut label: Option<Label> = None;
+
+  for event in Parser::new(source) {
+    match event {
+      Event::Error(why) => return Err(format!("couldn't read back the SVG, {}", why)),
+      Event::Tag(name, Type::End, _) => {
+        if skipping > 0 {
+          skipping -= 1;
+          continue;
+        }
+        if name == "text" {
+          scene.items.extend(label.take().filter(|label| !label.text.trim().is_empty()).map(Item::Label));
+        }
+        stack.pop();
+      },
+      Event::Tag(name, tag_type, attributes) => {
+        if skipping > 0 || SKIPPED.contains(&name) {
+          if tag_type == Type::Start { skipping += 1; }
+          continue;
+        }
+        let parent = stack.last().cloned().unwrap_or_else(root_context);
+        let mut context = Context {
+          // inherited attributes, plus everything the element sets itself
+          attributes: parent.attributes.iter()
+            .filter(|(name, _)| INHERITED.contains(&name.as_str()))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .chain(attributes.iter().map(|(name, value)| (name.clone(), value.to_string())))
+            .collect(),
+          opacity: parent.opacity,
+          transform: parent.transform
+        };
+        context.opacity *= context.number("opacity", 1.0);
+        if let Some(transform) = context.get("transform") {
+          context.transform = parent.transform.then(&Transform::parse(transform));
+        }
+
+        if name == "svg" {
+          let view_box = context.get("viewBox").and_then(|view_box| ViewBox::parse(view_box).ok());
+          let length = |attribute: &str, default: f32| {
+            return context.get(attribute).and_then(|length| Length::parse(length).ok()).map_or(default, |length| length.to_px());
+          };
+          scene.view_box = view_box.unwrap_or(ViewBox { x: 0.0, y: 0.0, width: length("width", 0.0), height: length("height", 0.0) });
+          scene.width = length("width", scene.view_box.width);
+          scene.height = length("height", scene.view_box.height);
+        } else if name == "text" {
+          label = Some(Label {
+            text: String::new(),
+            x: context.number("x", 0.0),
+            y: context.number("y", 0.0),
+            size: context.number("font-size", 16.0),
+            bold: context.get("font-weight") == Some("bold"),
+            anchor: match context.get("text-anchor") {
+              Some("middle") => Anchor::Middle,
+              Some("end") => Anchor::End,
+              _ => Anchor::Start
+            },
+            paint: context.paint("fill", "fill-opacity")
+              .unwrap_or(Paint { color: Rgb { red: 0.0, green: 0.0, blue: 0.0 }, opacity: 0.0 }),
+            transform: context.transform
+          });
+        } else if let Some(segments) = element_segments(name, &context) {
+          let shape = context.shape(segments);
+          // lines have nothing to fill
+          let shape = match name {
+            "line" => Shape { fill: None, ..shape },
+            _ => shape
+          };
+          scene.items.push(Item::Shape(shape));
+        }
+        if tag_type == Type::Start {
+          stack.push(context);
+        }
+      },
+      Event::Text(text) => {
+        if let Some(label) = label.as_mut() {
+          label.text.push_str(&unescape_xml(text));
+        }
+      },
+      _ => ()
+    }
+  }
+  return Ok(scene);
+}
+
+// SVG's initial values
+fn root_context() -> Context {
+  return Context {
+    attributes: [("fill", "black"), ("stroke", "none")].iter()
+      .map(|(name, value)| (String::from(*name), String::from(*value)))
+      .collect(),
+    opacity: 1.0,
+    transform: Transform::IDENTITY
+  };
+}
@@ -0,0 +1,141 @@
+use crate::{Layer, Line, Point};
+
+// Ramer-Douglas-Peucker over the stroke's centerline. Kept points retain
+// all of their attributes, so pressure/speed driven brushes still work.
+fn keep_points(points: &[Point], tolerance: f32, keep: &mut Vec<bool>, start: usize, end: usize) {
+  if end <= start + 1 { return; }
+  let (a, b) = (&points[start], &points[end]);
+  let (dx, dy) = (b.x - a.x, b.y - a.y);
+  let length = (dx * dx + dy * dy).sqrt();
+  let (farthest, distance) = (start + 1..end)
+    .map(|index| {
+      let p = &points[index];
+      let distance = match length > f32::EPSILON {
+        true => ((p.x - a.x) * dy - (p.y - a.y) * dx).abs() / length,
+        false => ((p.x - a.x).powi(2) + (p.y - a.y).powi(2)).sqrt()
+      };
+      return (index, distance);
+    })
+    .fold((start, 0.0), |best, next| if next.1 > best.1 { next } else { best });
+  if distance > tolerance {
+    keep[farthest] = true;
+    keep_points(points, tolerance, keep, start, farthest);
+    keep_points(points, tolerance, keep, farthest, end);
+  }
+}
+
+pub fn simplify_line(line: &Line, tolerance: f32) -> Line {
+  if line.points.len() < 3 || tolerance <= 0.0 { return line.clone(); }
+  let mut keep = vec![false; line.points.len()];
+  keep[0] = true;
+  keep[line.points.len() - 1] = true;
+  keep_points(&line.points, tolerance, &mut keep, 0, line.points.len() - 1);
+  let points = line.points.iter().zip(keep.iter())
+    .filter(|(_, &kept)| kept)
+    .map(|(point, _)| point.clone())
+    .collect::<Vec<_>>();
+  return Line { num_points: points.len() as i32, points, ..line.clone() };
+}
+
+pub fn simplify_layers(layers: &[Layer], tolerance: f32) -> Vec<Layer> {
+  return layers.iter()
+    .map(|layer| Layer {
+      num_lines: layer.num_lines,
+      lines: layer.lines.iter().map(|line| simplify_line(line, tolerance)).collect()
+    })
+    .collect();
+}
+
+// Each point moved to the average of itself and up to `window` points
+// either side of it, as many on each side, so the ends stay put. Only x
+// and y change, so pressure/speed driven brushes draw as before.
+pub fn smooth_line(line: &Line, window: usize) -> Line {
+  if line.points.len() < 3 || window == 0 { return line.clone(); }
+  let last = line.points.len() - 1;
+  let points = line.points.iter().enumerate()
+    .map(|(index, point)| {
+      let reach = window.min(index).min(last - index);
+      let neighbours = &line.points[index - reach..=index + reach];
+      let count = neighbours.len() as f32;
+      return Point {
+        x: neighbours.iter().map(|p| p.x).sum::<f32>() / count,
+        y: neighbours.iter().map(|p| p.y).sum::<f32>() / count,
+        ..point.clone()
+      };
+    })
+    .collect();
+  return Line { points, ..line.clone() };
+}
+
+pub fn smooth_layers(layers: &[Layer], window: usize) -> Vec<Layer> {
+  return layers.iter()
+    .map(|layer| Layer {
+      num_lines: layer.num_lines,
+      lines: layer.lines.iter().map(|line| smooth_line(line, window)).collect()
+    })
+    .collect();
+}
+
+pub fn count_points(layers: &[Layer]) -> usize {
+  return layers.iter().flat_map(|layer| layer.lines.iter()).map(|line| line.points.len()).sum();
+}
+
+// Beyond this a stroke is reduced to little more than its endpoints anyway
+const MAX_TOLERANCE: f32 = 256.0;
+
+// Finds (by bisection) the smallest tolerance that brings the page under
+// `budget` points and returns the simplified layers with the tolerance
+// used. Every stroke keeps at least its two endpoints, so very small
+// budgets are met as closely as possible rather than exactly.
+pub fn fit_point_budget(layers: &[Layer], budget: usize) -> (Vec<Layer>, f32) {
+  if count_points(layers) <= budget { return (layers.to_vec(), 0.0); }
+  let mut low = 0.0;
+  let mut high = 0.25;
+  while high < MAX_TOLERANCE && count_points(&simplify_layers(layers, high)) > budget {
+    low = high;
+    high *= 2.0;
+  }
+  (0..12).for_each(|_| {
+    let middle = (low + high) / 2.0;
+    if count_points(&simplify_layers(layers, middle)) > budget {
+      low = middle;
+    } else {
+      high = middle;
+    }
+  });
+  return (simplify_layers(layers, high), high);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::brush::{BrushColor, BrushType};
+
+  fn line(points: &[(f32, f32)]) -> Line {
+    let points = points.iter()
+      .map(|(x, y)| Point { x: *x, y: *y, speed: 0.5, direction: 1.0, width: 2.0, pressure: 0.75 })
+      .collect::<Vec<_>>();
+    return Line {
+      brush_type: BrushType::Fineliner,
+      brush_color: BrushColor::Black,
+      padding: 0,
+      brush_size: 2.0,
+      unknown: 0.0,
+      num_points: points.len() as i32,
+      points
+    };
+  }
+
+  #[test]
+  fn smooths_between_the_ends() {
+    let zigzag = line(&[(0.0, 0.0), (1.0, 3.0), (2.0, 0.0), (3.0, 3.0), (4.0, 0.0)]);
+    let smoothed = smooth_line(&zigzag, 1);
+    let coordinates = smoothed.points.iter().map(|point| (point.x, point.y)).collect::<Vec<_>>();
+    assert_eq!(coordinates, vec![(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 1.0), (4.0, 0.0)]);
+    assert!(smoothed.points.iter().all(|point| point.pressure == 0.75 && point.width == 2.0));
+    // a wider window reaches only as far as the nearer end allows
+    let coordinates = smooth_line(&zigzag, 5).points.iter().map(|point| (point.x, point.y)).collect::<Vec<_>>();
+    assert_eq!(coordinates, vec![(0.0, 0.0), (1.0, 1.0), (2.0, 1.2), (3.0, 1.0), (4.0, 0.0)]);
+    assert_eq!(smooth_line(&zigzag, 0), zigzag);
+  }
+}
@@ -0,0 +1,343 @@
+use std::f32::consts::TAU;
+
+use crate::{Layer, Line, Point};
+
+// Strokes smaller than this across, in canvas pixels, are left as they
+// are: dots, ticks and letters aren't shapes
+const MIN_SHAPE_SIZE: f32 = 24.0;
+// How far a stroke may stray from a straight line and still be one, as a
+// fraction of its length
+const LINE_TOLERANCE: f32 = 0.04;
+// How far apart a stroke's ends may be for it to be a closed shape, as a
+// fraction of the size of its bounds
+const CLOSED_TOLERANCE: f32 = 0.2;
+// How far on average a closed stroke may stray from a rectangle or an
+// ellipse and still be one, as a fraction of the shape's half size
+const SHAPE_TOLERANCE: f32 = 0.08;
+// Ellipses with radii closer than this, as a fraction, are circles
+const CIRCLE_TOLERANCE: f32 = 0.1;
+
+// A shape a stroke was drawn as, where it is on the canvas
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shape {
+  Line { from: (f32, f32), to: (f32, f32) },
+  Rectangle { left: f32, top: f32, width: f32, height: f32 },
+  Circle { center: (f32, f32), radius: f32 },
+  Ellipse { center: (f32, f32), radius_x: f32, radius_y: f32 }
+}
+
+impl Shape {
+  // With its coordinates rounded to 1 / `factor`
+  pub fn rounded(self, factor: f32) -> Shape {
+    let round = |value: f32| (value * factor).round() / factor;
+    return match self {
+      Shape::Line { from, to } => Shape::Line { from: (round(from.0), round(from.1)), to: (round(to.0), round(to.1)) },
+      Shape::Rectangle { left, top, width, height } => Shape::Rectangle { left: round(left), top: round(top), width: round(width), height: round(height) },
+      Shape::Circle { center, radius } => Shape::Circle { center: (round(center.0), round(center.1)), radius: round(radius) },
+      Shape::Ellipse { center, radius_x, radius_y } => Shape::Ellipse { center: (round(center.0), round(center.1)), radius_x: round(radius_x), radius_y: round(radius_y) }
+    };
+  }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Bounds {
+  left: f32,
+  top: f32,
+  right: f32,
+  bottom: f32
+}
+
+impl Bounds {
+  fn of(points: &[Point]) -> Bounds {
+    return points.iter().fold(
+      Bounds { left: f32::MAX, top: f32::MAX, right: f32::MIN, bottom: f32::MIN },
+      |bounds, point| Bounds {
+        left: bounds.left.min(point.x),
+        top: bounds.top.min(point.y),
+        right: bounds.right.max(point.x),
+        bottom: bounds.bottom.max(point.y)
+      }
+    );
+  }
+
+  fn center(&self) -> (f32, f32) {
+    return ((self.left + self.right) / 2.0, (self.top + self.bottom) / 2.0);
+  }
+
+  fn half_size(&self) -> (f32, f32) {
+    return ((self.right - self.left) / 2.0, (self.bottom - self.top) / 2.0);
+  }
+}
+
+// How far along the stroke each point is, from 0 at its start to 1 at its
+// end
+fn arc_fractions(points: &[Point]) -> Vec<f32> {
+  let mut travelled = vec![0.0f32];
+  points.windows(2).for_each(|pair| {
+    let last = travelled[travelled.len() - 1];
+    travelled.push(last + (pair[1].x - pair[0].x).hypot(pair[1].y - pair[0].y));
+  });
+  let length = travelled[travelled.len() - 1].max(f32::EPSILON);
+  return travelled.into_iter().map(|distance| distance / length).collect();
+}
+
+fn distance_to_line(point: &Point, start: &Point, end: &Point) -> f32 {
+  let (dx, dy) = (end.x - start.x, end.y - start.y);
+  return ((point.x - start.x) * dy - (point.y - start.y) * dx).abs() / dx.hypot(dy).max(f32::EPSILON);
+}
+
+// twice the area the stroke encloses, positive when drawn clockwise on the
+// canvas (whose y runs down)
+fn signed_area(points: &[Point]) -> f32 {
+  return points.windows(2).map(|pair| pair[0].x * pair[1].y - pair[1].x * pair[0].y).sum();
+}
+
+// The average distance of the points from the rectangle of the bounds, as
+// a fraction of its smaller half size
+fn rectangle_error(points: &[Point], bounds: &Bounds) -> f32 {
+  let (half_width, half_height) = bounds.half_size();
+  let total = points.iter()
+    .map(|point| {
+      return (point.x - bounds.left).abs()
+        .min((point.x - bounds.right).abs())
+        .min((point.y - bounds.top).abs())
+        .min((point.y - bounds.bottom).abs());
+    })
+    .sum::<f32>();
+  return total / points.len() as f32 / half_width.min(half_height).max(f32::EPSILON);
+}
+
+// The average distance of the points from the ellipse in the bounds, in
+// terms of its radii
+fn ellipse_error(points: &[Point], bounds: &Bounds) -> f32 {
+  let (center_x, center_y) = bounds.center();
+  let (radius_x, radius_y) = bounds.half_size();
+  let total = points.iter()
+    .map(|point| (((point.x - center_x) / radius_x).hypot((point.y - center_y) / radius_y) - 1.0).abs())
+    .sum::<f32>();
+  return total / points.len() as f32;
+}
+
+// The shape a stroke was drawn as, if it's near enough to one: a straight
+// line between its ends, or a closed rectangle, circle or ellipse square
+// to the page in its bounds
+pub fn detect_shape(line: &Line) -> Option<Shape> {
+  let points = &line.points;
+  if points.len() < 3 || !line.brush_type.is_ink() {
+    return None;
+  }
+  let bounds = Bounds::of(points);
+  let (half_width, half_height) = bounds.half_size();
+  let size = (half_width * 2.0).hypot(half_height * 2.0);
+  if size < MIN_SHAPE_SIZE {
+    return None;
+  }
+  let (first, last) = (&points[0], &points[points.len() - 1]);
+  let chord = (last.x - first.x).hypot(last.y - first.y);
+  if chord > 0.0 && points.iter().all(|point| distance_to_line(point, first, last) <= chord * LINE_TOLERANCE) {
+    return Some(Shape::Line { from: (first.x, first.y), to: (last.x, last.y) });
+  }
+  if chord > size * CLOSED_TOLERANCE || half_width.min(half_height) * 2.0 < MIN_SHAPE_SIZE {
+    return None;
+  }
+  let (rectangle, ellipse) = (rectangle_error(points, &bounds), ellipse_error(points, &bounds));
+  let rectangle_shape = Shape::Rectangle { left: bounds.left, top: bounds.top, width: half_width * 2.0, height: half_height * 2.0 };
+  return match (rectangle <= SHAPE_TOLERANCE, ellipse <= SHAPE_TOLERANCE) {
+    (true, false) => Some(rectangle_shape),
+    (true, true) if rectangle < ellipse => Some(rectangle_shape),
+    (_, true) if (half_width - half_height).abs() <= half_width.max(half_height) * CIRCLE_TOLERANCE => {
+      Some(Shape::Circle { center: bounds.center(), radius: (half_width + half_height) / 2.0 })
+    },
+    (_, true) => Some(Shape::Ellipse { center: bounds.center(), radius_x: half_width, radius_y: half_height }),
+    (false, false) => None
+  };
+}
+
+// from one corner to the next
+type Side = ((f32, f32), (f32, f32));
+
+// The bounds' rectangle as the sides it's drawn along, from the corner
+// `start` (0 top left, then clockwise), going clockwise or not
+fn rectangle_sides(bounds: &Bounds, start: usize, clockwise: bool) -> Vec<Side> {
+  let corners = [(bounds.left, bounds.top), (bounds.right, bounds.top), (bounds.right, bounds.bottom), (bounds.left, bounds.bottom)];
+  return (0..4)
+    .map(|side| match clockwise {
+      true => (corners[(start + side) % 4], corners[(start + side + 1) % 4]),
+      false => (corners[(start + 4 - side) % 4], corners[(start + 3 - side) % 4])
+    })
+    .collect();
+}
+
+// Which side a point `along` the way round the sides is on, and where
+fn around_rectangle(sides: &[Side], along: f32) -> (usize, (f32, f32)) {
+  let lengths = sides.iter().map(|((x1, y1), (x2, y2))| (x2 - x1).abs() + (y2 - y1).abs()).collect::<Vec<_>>();
+  let mut remaining = along * lengths.iter().sum::<f32>();
+  for (side, (((x1, y1), (x2, y2)), length)) in sides.iter().copied().zip(lengths).enumerate() {
+    if remaining <= length || side == sides.len() - 1 {
+      let fraction = (remaining / length.max(f32::EPSILON)).min(1.0);
+      return (side, (x1 + (x2 - x1) * fraction, y1 + (y2 - y1) * fraction));
+    }
+    remaining -= length;
+  }
+  return (0, sides[0].0);
+}
+
+// The stroke redrawn as the shape, point for point: each point keeps its
+// pressure, width and the rest, and goes as far along the shape as it was
+// along the stroke, so brushes still draw it as they drew the stroke.
+// Rectangles get a point at each corner too.
+pub fn snap_line(line: &Line, shape: Shape) -> Line {
+  let points = &line.points;
+  let fractions = arc_fractions(points);
+  let first = points[0].clone();
+  let (bounds, (radius_x, radius_y)) = match shape {
+    Shape::Line { from, to } => (Bounds { left: from.0, top: from.1, right: to.0, bottom: to.1 }, (0.0, 0.0)),
+    Shape::Rectangle { left, top, width, height } => {
+      (Bounds { left, top, right: left + width, bottom: top + height }, (width / 2.0, height / 2.0))
+    },
+    Shape::Circle { center: (x, y), radius } => (Bounds { left: x - radius, top: y - radius, right: x + radius, bottom: y + radius }, (radius, radius)),
+    Shape::Ellipse { center: (x, y), radius_x, radius_y } => {
+      (Bounds { left: x - radius_x, top: y - radius_y, right: x + radius_x, bottom: y + radius_y }, (radius_x, radius_y))
+    }
+  };
+  let (center_x, center_y) = bounds.center();
+  let clockwise = signed_area(points) >= 0.0;
+  let start_angle = ((first.y - center_y) / radius_y.max(f32::EPSILON)).atan2((first.x - center_x) / radius_x.max(f32::EPSILON));
+  let corners = [(bounds.left, bounds.top), (bounds.right, bounds.top), (bounds.right, bounds.bottom), (bounds.left, bounds.bottom)];
+  let start_corner = (0..4)
+    .min_by(|a, b| {
+      let distance = |corner: usize| (corners[corner].0 - first.x).hypot(corners[corner].1 - first.y);
+      return distance(*a).total_cmp(&distance(*b));
+    })
+    .unwrap_or(0);
+  let sides = rectangle_sides(&bounds, start_corner, clockwise);
+  let mut snapped = Vec::<Point>::with_capacity(points.len() + 4);
+  let mut last_side = 0;
+  points.iter().zip(fractions.iter()).for_each(|(point, along)| {
+    let (x, y) = match shape {
+      Shape::Line { from, to } => (from.0 + (to.0 - from.0) * along, from.1 + (to.1 - from.1) * along),
+      Shape::Rectangle { .. } => {
+        let (side, position) = around_rectangle(&sides, *along);
+        // the corners passed since the last point, so they stay square
+        (last_side..side).for_each(|passed| snapped.push(Point { x: sides[passed].1.0, y: sides[passed].1.1, ..point.clone() }));
+        last_side = side;
+        position
+      },
+      Shape::Circle { .. } | Shape::Ellipse { .. } => {
+        let angle = start_angle + if clockwise { along * TAU } else { -along * TAU };
+        (center_x + radius_x * angle.cos(), center_y + radius_y * angle.sin())
+      }
+    };
+    snapped.push(Point { x, y, ..point.clone() });
+  });
+  return Line { num_points: snapped.len() as i32, points: snapped, ..line.clone() };
+}
+
+// Every stroke near enough to a shape replaced by it, and the shape each
+// line was snapped to, if any, layer by layer
+pub fn snap_layers(layers: &[Layer]) -> (Vec<Layer>, Vec<Vec<Option<Shape>>>) {
+  return layers.iter()
+    .map(|layer| {
+      let shapes = layer.lines.iter().map(detect_shape).collect::<Vec<_>>();
+      let lines = layer.lines.iter().zip(shapes.iter())
+        .map(|(line, shape)| shape.map_or_else(|| line.clone(), |shape| snap_line(line, shape)))
+        .collect();
+      return (Layer { num_lines: layer.num_lines, lines }, shapes);
+    })
+    .unzip();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixture::generate_fixture;
+  use crate::options::RenderOptions;
+  use crate::render_svg;
+  use crate::scene::parse_scene;
+  use crate::Document;
+
+  // A line, a rectangle, a circle and an ellipse, drawn as the tablet
+  // samples them
+  fn shapes() -> Document {
+    let ellipse = (0..=90)
+      .map(|step| step as f32 / 90.0 * TAU)
+      .map(|angle| format!("[{}, {}]", 400.0 + 150.0 * angle.cos(), 1400.0 + 80.0 * angle.sin()))
+      .collect::<Vec<_>>()
+      .join(", ");
+    return generate_fixture(&format!(r#"
+      [[layers]]
+      [[layers.strokes]]
+      shape = "line"
+      from = [100, 100]
+      to = [400, 100]
+
+      [[layers.strokes]]
+      color = "blue"
+      size = "thick"
+      shape = "rectangle"
+      corner = [200, 300]
+      width = 300
+      height = 200
+
+      [[layers.strokes]]
+      color = "red"
+      shape = "circle"
+      center = [702, 936]
+      radius = 200
+
+      [[layers.strokes]]
+      points = [{}]
+    "#, ellipse)).unwrap();
+  }
+
+  fn near(a: f32, b: f32) -> bool {
+    return (a - b).abs() < 1.0;
+  }
+
+  #[test]
+  fn detects_where_shapes_are() {
+    let document = shapes();
+    let detected = document.layers[0].lines.iter().map(detect_shape).collect::<Vec<_>>();
+    assert_eq!(detected[0], Some(Shape::Line { from: (100.0, 100.0), to: (400.0, 100.0) }));
+    assert_eq!(detected[1], Some(Shape::Rectangle { left: 200.0, top: 300.0, width: 300.0, height: 200.0 }));
+    match detected[2] {
+      Some(Shape::Circle { center, radius }) => assert!(near(center.0, 702.0) && near(center.1, 936.0) && near(radius, 200.0)),
+      shape => panic!("expected a circle, got {:?}", shape)
+    }
+    match detected[3] {
+      Some(Shape::Ellipse { center, radius_x, radius_y }) => {
+        assert!(near(center.0, 400.0) && near(center.1, 1400.0) && near(radius_x, 150.0) && near(radius_y, 80.0));
+      },
+      shape => panic!("expected an ellipse, got {:?}", shape)
+    }
+  }
+
+  #[test]
+  fn leaves_scribbles_alone() {
+    let document = generate_fixture(r#"
+      [[layers]]
+      [[layers.strokes]]
+      points = [[100, 100], [180, 140], [120, 220], [260, 160]]
+    "#).unwrap();
+    assert_eq!(detect_shape(&document.layers[0].lines[0]), None);
+  }
+
+  #[test]
+  fn draws_snapped_shapes_as_svg_shapes() {
+    let document = shapes();
+    let options = RenderOptions::default().with_snap_shapes(true);
+    let svg = render_svg(&document, &options).unwrap().to_string();
+    ["<line", "<rect", "<circle", "<ellipse"].iter().for_each(|element| {
+      assert_eq!(svg.matches(element).count(), 1, "not one {} in {}", element, svg);
+    });
+    assert!(!svg.contains("<path"));
+    assert!(svg.contains(r#"x1="100" x2="400" y1="100" y2="100""#));
+    assert!(svg.contains(r#"height="200" stroke"#) && svg.contains(r#"width="300" x="200" y="300""#));
+    let line = &document.layers[0].lines[1];
+    let width = line.brush_size * options.brush_style(line.brush_type).width_scale;
+    assert!(svg.contains(&format!(r#"stroke-width="{}""#, width)), "no {} wide stroke in {}", width, svg);
+    assert!(svg.contains(&format!(r#"stroke="{}""#, line.brush_color.default_rgb())));
+    // and the shapes are read back as such for PDF and PNG output
+    assert_eq!(parse_scene(&svg).unwrap().items.len(), 4);
+  }
+}
@@ -0,0 +1,138 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::brush::{BrushColor, BrushType};
+use crate::json::Value;
+use crate::options::ViewBox;
+use crate::render::content_bounds;
+use crate::Document;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LayerStats {
+  pub strokes: usize,
+  pub points: usize
+}
+
+// What is on a page, for `relineate info`
+#[derive(Debug, Clone)]
+pub struct PageStats {
+  pub version: u32,
+  pub layers: Vec<LayerStats>,
+  // strokes by brush and by color, in the order they're first drawn
+  pub brushes: Vec<(BrushType, usize)>,
+  pub colors: Vec<(BrushColor, usize)>,
+  // where the ink reaches, in the tablet's coordinates; None on a blank page
+  pub bounds: Option<ViewBox>,
+  // the segments between points there are to draw, which is roughly what
+  // rendering takes and how big the output gets
+  pub segments: usize
+}
+
+fn tally<T: PartialEq>(items: impl Iterator<Item = T>) -> Vec<(T, usize)> {
+  let mut counts = Vec::<(T, usize)>::new();
+  items.for_each(|item| match counts.iter_mut().find(|(counted, _)| *counted == item) {
+    Some((_, count)) => *count += 1,
+    None => counts.push((item, 1))
+  });
+  return counts;
+}
+
+pub fn page_stats(page: &Document) -> PageStats {
+  let lines = || page.layers.iter().flat_map(|layer| layer.lines.iter());
+  return PageStats {
+    version: page.version,
+    layers: page.layers.iter()
+      .map(|layer| LayerStats {
+        strokes: layer.lines.len(),
+        points: layer.lines.iter().map(|line| line.points.len()).sum()
+      })
+      .collect(),
+    brushes: tally(lines().map(|line| line.brush_type)),
+    colors: tally(lines().map(|line| line.brush_color)),
+    bounds: content_bounds(&page.layers, 0.0),
+    segments: lines().map(|line| line.points.len().saturating_sub(1)).sum()
+  };
+}
+
+impl PageStats {
+  pub fn strokes(&self) -> usize {
+    return self.layers.iter().map(|layer| layer.strokes).sum();
+  }
+
+  pub fn points(&self) -> usize {
+    return self.layers.iter().map(|layer| layer.points).sum();
+  }
+
+  pub fn to_json(&self) -> Value {
+    let count = |n: usize| Value::Number(n as f64);
+    let counts = |names: Vec<(&str, usize)>| {
+      return Value::Object(names.into_iter().map(|(name, n)| (String::from(name), count(n))).collect());
+    };
+    let bounds = self.bounds.map_or(Value::Null, |bounds| Value::Object(vec![
+      (String::from("x"), Value::Number(bounds.x as f64)),
+      (String::from("y"), Value::Number(bounds.y as f64)),
+      (String::from("width"), Value::Number(bounds.width as f64)),
+      (String::from("height"), Value::Number(bounds.height as f64))
+    ]));
+    return Value::Object(vec![
+      (String::from("version"), count(self.version as usize)),
+      (String::from("layers"), Value::Array(self.layers.iter()
+        .map(|layer| Value::Object(vec![
+          (String::from("strokes"), count(layer.strokes)),
+          (String::from("points"), count(layer.points))
+        ]))
+        .collect())),
+      (String::from("strokes"), count(self.strokes())),
+      (String::from("points"), count(self.points())),
+      (String::from("brushes"), counts(self.brushes.iter().map(|(brush, n)| (brush.name(), *n)).collect())),
+      (String::from("colors"), counts(self.colors.iter().map(|(color, n)| (color.name(), *n)).collect())),
+      (String::from("bounds"), bounds),
+      (String::from("segments"), count(self.segments))
+    ]);
+  }
+}
+
+// Where a converted page came from, as far as is known: a lone .rm file
+// has no notebook, UUIDs or page number
+#[derive(Debug, Default, Clone)]
+pub struct PageOrigin {
+  pub notebook_id: Option<String>,
+  pub notebook_name: Option<String>,
+  pub page_id: Option<String>,
+  pub number: Option<usize>,
+  pub last_modified: Option<SystemTime>,
+  pub layer_names: Vec<String>
+}
+
+// What --sidecar writes beside a converted page, for tools that catalog
+// notes: which notebook and page it is, when the notebook last changed
+// (in milliseconds since the epoch, as .metadata has it), its layers by
+// name and what brushes and colors it's drawn with
+pub fn sidecar_json(page: &Document, origin: &PageOrigin) -> Value {
+  let text = |value: &Option<String>| value.as_ref().map_or(Value::Null, |value| Value::String(value.clone()));
+  let stats = page_stats(page);
+  let stats_json = stats.to_json();
+  let field = |name: &str| stats_json.get(name).cloned().unwrap_or(Value::Null);
+  let last_modified = origin.last_modified
+    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+    .map_or(Value::Null, |since| Value::Number(since.as_millis() as f64));
+  return Value::Object(vec![
+    (String::from("notebook"), Value::Object(vec![
+      (String::from("id"), text(&origin.notebook_id)),
+      (String::from("name"), text(&origin.notebook_name))
+    ])),
+    (String::from("page"), Value::Object(vec![
+      (String::from("id"), text(&origin.page_id)),
+      (String::from("number"), origin.number.map_or(Value::Null, |number| Value::Number(number as f64)))
+    ])),
+    (String::from("last_modified"), last_modified),
+    (String::from("layers"), Value::Array(stats.layers.iter().enumerate()
+      .map(|(index, layer)| Value::Object(vec![
+        (String::from("name"), text(&origin.layer_names.get(index).filter(|name| !name.is_empty()).cloned())),
+        (String::from("strokes"), Value::Number(layer.strokes as f64))
+      ]))
+      .collect())),
+    (String::from("strokes"), field("strokes")),
+    (String::from("brushes"), field("brushes")),
+    (String::from("colors"), field("colors"))
+  ]);
+}
@@ -0,0 +1,209 @@
+use svg::node::element::Circle as SVGCircle;
+use svg::node::element::Group as SVGGroup;
+use svg::node::element::Line as SVGLine;
+use svg::node::element::Rectangle as SVGRectangle;
+use svg::node::element::Text as SVGText;
+use svg::node::Text as SVGTextNode;
+
+use crate::recognize::is_writing;
+use crate::{Layer, X_MAX, Y_MAX};
+
+// Vector approximations of the stock xochitl templates, drawn on the
+// portrait canvas. Spacings are measured off the device's PNG templates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Template {
+  Blank,
+  Lines(f32),
+  Grid(f32),
+  Dots(f32),
+  Checklist,
+  WeeklyPlanner
+}
+
+pub const TEMPLATE_NAMES: [&str; 12] = [
+  "blank",
+  "lines-small", "lines-medium", "lines-large",
+  "grid-small", "grid-medium", "grid-large",
+  "dots-small", "dots-medium", "dots-large",
+  "checklist",
+  "weekly-planner"
+];
+
+const INK: &str = "#c8c8c8";
+const HEADER_HEIGHT: f32 = 156.0;
+
+impl Template {
+  // Accepts our own names as well as the names xochitl writes to
+  // .pagedata, e.g. "P Lines small" or "LS Grid margin large".
+  pub fn by_name(name: &str) -> Option<Template> {
+    let lower = name.trim().to_lowercase().replace('-', " ");
+    let size = |small: f32, medium: f32, large: f32| {
+      if lower.contains("small") || lower.ends_with(" s") { return small; }
+      if lower.contains("large") || lower.ends_with(" l") { return large; }
+      return medium;
+    };
+    if lower.contains("checklist") { return Some(Template::Checklist); }
+    if lower.contains("week") { return Some(Template::WeeklyPlanner); }
+    if lower.contains("lines") { return Some(Template::Lines(size(52.0, 72.0, 100.0))); }
+    if lower.contains("grid") { return Some(Template::Grid(size(44.0, 62.0, 89.0))); }
+    if lower.contains("dots") { return Some(Template::Dots(size(44.0, 62.0, 89.0))); }
+    if lower.contains("blank") { return Some(Template::Blank); }
+    return None;
+  }
+
+  // our name for it, e.g. "lines-small"
+  pub fn name(&self) -> &'static str {
+    return TEMPLATE_NAMES.iter()
+      .find(|name| Template::by_name(name) == Some(*self))
+      .copied()
+      .unwrap_or("custom");
+  }
+
+  pub fn render(&self) -> SVGGroup {
+    return self.render_on((X_MAX as f32, Y_MAX as f32));
+  }
+
+  // Drawn on a canvas of the given width and height instead, e.g. the
+  // landscape one of a turned page
+  pub fn render_on(&self, canvas: (f32, f32)) -> SVGGroup {
+    let group = SVGGroup::new().set("id", "template");
+    return match *self {
+      Template::Blank => group,
+      Template::Lines(spacing) => horizontal_lines(group, canvas, HEADER_HEIGHT, spacing),
+      Template::Grid(spacing) => grid(group, canvas, spacing),
+      Template::Dots(spacing) => dots(group, canvas, spacing),
+      Template::Checklist => checklist(group, canvas),
+      Template::WeeklyPlanner => weekly_planner(group, canvas)
+    };
+  }
+}
+
+// Fewer strokes than this say little about the paper they were written on
+const MIN_INFERENCE_STROKES: usize = 12;
+// Minimum alignment (see `alignment`) before a ruled template is guessed
+const MIN_ALIGNMENT: f32 = 0.6;
+
+// How consistently `values` fall at the same phase of a repeating ruling:
+// the mean resultant length of the phases as angles, 1 when all of them
+// coincide and around 1/sqrt(n) for values unrelated to the ruling.
+fn alignment(values: &[f32], origin: f32, spacing: f32) -> f32 {
+  let (sin, cos) = values.iter().fold((0.0, 0.0), |(sin, cos), value| {
+    let angle = (value - origin) / spacing * std::f32::consts::TAU;
+    return (sin + angle.sin(), cos + angle.cos());
+  });
+  return (sin * sin + cos * cos).sqrt() / values.len() as f32;
+}
+
+// Guesses the template a page was written on from how its strokes line
+// up: handwriting sits on ruled lines, so the bottoms of strokes cluster at
+// one phase of the line spacing, and on grids their left edges do too.
+// Returns the guess with the best alignment found (Blank when nothing
+// reaches MIN_ALIGNMENT), or None with too few strokes to tell. Dots
+// can't be told apart from grids and are reported as grids.
+pub fn infer_template(layers: &[Layer]) -> Option<(Template, f32)> {
+  let extents = layers.iter()
+    .flat_map(|layer| layer.lines.iter())
+    .filter(|line| is_writing(line) && !line.points.is_empty())
+    .map(|line| {
+      return line.points.iter().fold((f32::MAX, f32::MIN), |(left, bottom), point| {
+        return (left.min(point.x), bottom.max(point.y));
+      });
+    })
+    .collect::<Vec<_>>();
+  if extents.len() < MIN_INFERENCE_STROKES { return None; }
+  let lefts = extents.iter().map(|(left, _)| *left).collect::<Vec<_>>();
+  let bottoms = extents.iter().map(|(_, bottom)| *bottom).collect::<Vec<_>>();
+
+  let (template, score) = TEMPLATE_NAMES.iter()
+    .filter_map(|name| Template::by_name(name))
+    .filter_map(|template| {
+      return match template {
+        Template::Lines(spacing) => Some((template, alignment(&bottoms, HEADER_HEIGHT, spacing))),
+        Template::Grid(spacing) => {
+          let origin = (X_MAX as f32 % spacing) / 2.0;
+          Some((template, alignment(&bottoms, origin, spacing).min(alignment(&lefts, origin, spacing))))
+        },
+        _ => None
+      };
+    })
+    .fold((Template::Blank, 0.0), |best, next| if next.1 > best.1 { next } else { best });
+  if score < MIN_ALIGNMENT {
+    return Some((Template::Blank, score));
+  }
+  return Some((template, score));
+}
+
+fn steps(start: f32, end: f32, spacing: f32) -> impl Iterator<Item = f32> {
+  return (0..).map(move |i| start + i as f32 * spacing).take_while(move |v| *v < end);
+}
+
+fn line(x1: f32, y1: f32, x2: f32, y2: f32) -> SVGLine {
+  return SVGLine::new()
+    .set("x1", x1)
+    .set("y1", y1)
+    .set("x2", x2)
+    .set("y2", y2)
+    .set("stroke", INK)
+    .set("stroke-width", 1.5);
+}
+
+fn horizontal_lines(group: SVGGroup, (width, height): (f32, f32), top: f32, spacing: f32) -> SVGGroup {
+  return steps(top, height, spacing)
+    .fold(group, |acc, y| acc.add(line(0.0, y, width, y)));
+}
+
+fn grid(group: SVGGroup, (width, height): (f32, f32), spacing: f32) -> SVGGroup {
+  // centred horizontally like the device's grids
+  let left = (width % spacing) / 2.0;
+  let with_rows = horizontal_lines(group, (width, height), left, spacing);
+  return steps(left, width, spacing)
+    .fold(with_rows, |acc, x| acc.add(line(x, 0.0, x, height)));
+}
+
+fn dots(group: SVGGroup, (width, height): (f32, f32), spacing: f32) -> SVGGroup {
+  let left = (width % spacing) / 2.0;
+  return steps(left, height, spacing)
+    .flat_map(|y| steps(left, width, spacing).map(move |x| (x, y)))
+    .fold(group, |acc, (x, y)| {
+      return acc.add(SVGCircle::new().set("cx", x).set("cy", y).set("r", 2.5).set("fill", INK));
+    });
+}
+
+fn checklist(group: SVGGroup, canvas: (f32, f32)) -> SVGGroup {
+  let spacing = 78.0;
+  let box_size = 32.0;
+  let with_lines = horizontal_lines(group, canvas, HEADER_HEIGHT + spacing, spacing);
+  return steps(HEADER_HEIGHT + spacing, canvas.1, spacing)
+    .fold(with_lines, |acc, y| {
+      return acc.add(
+        SVGRectangle::new()
+          .set("x", 60.0)
+          .set("y", y - box_size - 16.0)
+          .set("width", box_size)
+          .set("height", box_size)
+          .set("fill", "none")
+          .set("stroke", INK)
+          .set("stroke-width", 2)
+      );
+    });
+}
+
+const WEEKDAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+fn weekly_planner(group: SVGGroup, (width, height): (f32, f32)) -> SVGGroup {
+  let row_height = (height - HEADER_HEIGHT) / WEEKDAYS.len() as f32;
+  return WEEKDAYS.iter().enumerate().fold(group, |acc, (index, day)| {
+    let top = HEADER_HEIGHT + index as f32 * row_height;
+    return acc
+      .add(line(60.0, top, width - 60.0, top))
+      .add(
+        SVGText::new()
+          .set("x", 72.0)
+          .set("y", top + 40.0)
+          .set("font-family", "sans-serif")
+          .set("font-size", 26)
+          .set("fill", "#a0a0a0")
+          .add(SVGTextNode::new(*day))
+      );
+  });
+}
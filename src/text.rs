@@ -0,0 +1,158 @@
+use svg::node::element::Group as SVGGroup;
+use svg::node::element::Text as SVGText;
+use svg::node::Text as SVGTextNode;
+
+use crate::brush::BrushColor;
+use crate::options::RenderOptions;
+use crate::orientation::canvas_group;
+use crate::watermark::escape_xml;
+
+// How a paragraph of typed text is set, one of the styles the text tool
+// offers
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParagraphStyle {
+  Plain,
+  Heading,
+  Bold,
+  Bullet,
+  // a bullet one level in
+  Bullet2,
+  Checkbox,
+  CheckboxChecked
+}
+
+impl ParagraphStyle {
+  // from the numbers v6 pages store, plain for any other
+  pub fn from_code(code: u8) -> ParagraphStyle {
+    return match code {
+      2 => ParagraphStyle::Heading,
+      3 => ParagraphStyle::Bold,
+      4 => ParagraphStyle::Bullet,
+      5 => ParagraphStyle::Bullet2,
+      6 => ParagraphStyle::Checkbox,
+      7 => ParagraphStyle::CheckboxChecked,
+      _ => ParagraphStyle::Plain
+    };
+  }
+
+  pub fn name(&self) -> &'static str {
+    return match self {
+      ParagraphStyle::Plain => "plain",
+      ParagraphStyle::Heading => "heading",
+      ParagraphStyle::Bold => "bold",
+      ParagraphStyle::Bullet => "bullet",
+      ParagraphStyle::Bullet2 => "bullet-2",
+      ParagraphStyle::Checkbox => "checkbox",
+      ParagraphStyle::CheckboxChecked => "checkbox-checked"
+    };
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Paragraph {
+  pub style: ParagraphStyle,
+  pub text: String
+}
+
+// Text typed onto a page: where its box starts on the canvas, how wide it
+// is, and what's in it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextBlock {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub paragraphs: Vec<Paragraph>
+}
+
+// Roughly how each style looks on the tablet, in canvas pixels
+struct Setting {
+  font_size: f32,
+  line_height: f32,
+  bold: bool,
+  indent: f32,
+  marker: &'static str
+}
+
+fn setting(style: ParagraphStyle) -> Setting {
+  let plain = Setting { font_size: 32.0, line_height: 48.0, bold: false, indent: 0.0, marker: "" };
+  return match style {
+    ParagraphStyle::Plain => plain,
+    ParagraphStyle::Heading => Setting { font_size: 48.0, line_height: 72.0, bold: true, ..plain },
+    ParagraphStyle::Bold => Setting { bold: true, ..plain },
+    ParagraphStyle::Bullet => Setting { indent: 40.0, marker: "\u{2022}", ..plain },
+    ParagraphStyle::Bullet2 => Setting { indent: 80.0, marker: "\u{25e6}", ..plain },
+    ParagraphStyle::Checkbox => Setting { indent: 48.0, marker: "\u{2610}", ..plain },
+    ParagraphStyle::CheckboxChecked => Setting { indent: 48.0, marker: "\u{2611}", ..plain }
+  };
+}
+
+// roughly: glyphs are about 0.5em wide in running sans-serif text
+const GLYPH_WIDTH: f32 = 0.5;
+
+// `text` broken between words into lines of at most `columns` characters,
+// with words longer than that broken wherever they reach it
+fn wrapped(text: &str, columns: usize) -> Vec<String> {
+  let columns = columns.max(1);
+  let mut lines = Vec::<String>::new();
+  let mut line = String::new();
+  text.split_whitespace().for_each(|word| {
+    let mut word = word.chars().collect::<Vec<_>>();
+    while !word.is_empty() {
+      let length = line.chars().count();
+      let room = match length { 0 => columns, _ => columns.saturating_sub(length + 1) };
+      if word.len() <= room {
+        if length > 0 { line.push(' '); }
+        line.extend(word.drain(..));
+      } else if length > 0 {
+        lines.push(std::mem::take(&mut line));
+      } else {
+        lines.push(word.drain(..columns).collect());
+      }
+    }
+  });
+  if !line.is_empty() || lines.is_empty() {
+    lines.push(line);
+  }
+  return lines;
+}
+
+// Each block's paragraphs as <text> elements, one per line as they wrap
+// to the block's width, turned with the page. Placement and sizes are
+// approximate, as the tablet's fonts aren't known to SVG viewers.
+pub fn render_text(blocks: &[TextBlock], options: &RenderOptions) -> SVGGroup {
+  let group = canvas_group(options);
+  let color = options.stroke_color(BrushColor::Black);
+  return blocks.iter().fold(group.set("class", "text"), |acc_group, block| {
+    let mut baseline = block.y;
+    return block.paragraphs.iter().fold(acc_group, |acc_group, paragraph| {
+      let setting = setting(paragraph.style);
+      let x = block.x + setting.indent;
+      let columns = ((block.width - setting.indent) / (setting.font_size * GLYPH_WIDTH)).floor().max(1.0) as usize;
+      return wrapped(&paragraph.text, columns).into_iter().enumerate().fold(acc_group, |acc_group, (index, line)| {
+        baseline += setting.line_height;
+        let line = match (index, setting.marker) {
+          (0, marker) if !marker.is_empty() => format!("{} {}", marker, line),
+          _ => line
+        };
+        if line.trim().is_empty() {
+          return acc_group;
+        }
+        let text = SVGText::new()
+          .set("x", x)
+          .set("y", baseline)
+          .set("font-family", "sans-serif")
+          .set("font-size", setting.font_size)
+          .set("fill", color)
+          .set("class", paragraph.style.name());
+        let text = match setting.bold {
+          true => text.set("font-weight", "bold"),
+          false => text
+        };
+        return acc_group.add(text.add(SVGTextNode::new(escape_xml(&line))));
+      });
+    });
+  });
+}
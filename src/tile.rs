@@ -0,0 +1,117 @@
+use svg::Document as SVGDocument;
+use svg::node::element::Group as SVGGroup;
+use svg::node::element::Rectangle as SVGRectangle;
+use svg::node::element::Text as SVGText;
+use svg::node::Text as SVGTextNode;
+use svg::parser::Event;
+use svg::Parser;
+
+use crate::html::HtmlPage;
+use crate::options::ViewBox;
+use crate::watermark::escape_xml;
+
+// Space around and between the pages of a sheet, and under each for its
+// label, in canvas pixels
+const GAP: f32 = 48.0;
+const LABEL_HEIGHT: f32 = 64.0;
+const LABEL_SIZE: f32 = 32.0;
+
+// How many pages across and down a sheet holds, from "CxR" as in "4x3"
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tiling {
+  pub columns: usize,
+  pub rows: usize
+}
+
+impl Tiling {
+  pub fn parse(spec: &str) -> Result<Tiling, String> {
+    let invalid = || format!("invalid tiling {}, expected COLUMNSxROWS such as 4x3", spec);
+    let (columns, rows) = spec.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    let count = |value: &str| value.trim().parse::<usize>().ok().filter(|count| *count > 0).ok_or_else(invalid);
+    return Ok(Tiling { columns: count(columns)?, rows: count(rows)? });
+  }
+
+  fn per_sheet(&self) -> usize {
+    return self.columns * self.rows;
+  }
+}
+
+// The view box of a rendered page and everything inside its <svg>
+fn page_contents(svg: &str) -> Result<(ViewBox, &str), String> {
+  let view_box = Parser::new(svg)
+    .find_map(|event| match event {
+      Event::Tag("svg", _, attributes) => Some(attributes.get("viewBox").map(|view_box| view_box.to_string())),
+      _ => None
+    })
+    .flatten()
+    .ok_or_else(|| String::from("a page to tile has no view box"))?;
+  let view_box = ViewBox::parse(&view_box)?;
+  let start = svg.find("<svg").and_then(|start| svg[start..].find('>').map(|end| start + end + 1));
+  let end = svg.rfind("</svg>");
+  return match (start, end) {
+    (Some(start), Some(end)) if start <= end => Ok((view_box, &svg[start..end])),
+    _ => Err(String::from("a page to tile isn't a whole SVG"))
+  };
+}
+
+fn label(page: &HtmlPage) -> String {
+  return match &page.title {
+    Some(title) => format!("Page {}: {}", page.number, title),
+    None => format!("Page {}", page.number)
+  };
+}
+
+// The pages laid out in a grid, `tiling` a sheet, as many sheets as they
+// take. Each page is framed, fitted in a cell as big as the biggest page
+// and labelled underneath; with --split-layers only a page's first
+// rendering is used.
+pub fn tile_pages(pages: &[HtmlPage], tiling: Tiling) -> Result<Vec<SVGDocument>, String> {
+  let contents = pages.iter()
+    .filter_map(|page| page.svgs.first().map(|svg| (page, svg)))
+    .map(|(page, svg)| page_contents(svg).map(|(view_box, contents)| (page, view_box, contents)))
+    .collect::<Result<Vec<_>, String>>()?;
+  let (cell_width, cell_height) = contents.iter()
+    .fold((0.0f32, 0.0f32), |(width, height), (_, view_box, _)| (width.max(view_box.width), height.max(view_box.height)));
+  return Ok(contents.chunks(tiling.per_sheet())
+    .map(|sheet| {
+      let rows = sheet.len().div_ceil(tiling.columns);
+      let width = GAP + tiling.columns as f32 * (cell_width + GAP);
+      let height = GAP + rows as f32 * (cell_height + LABEL_HEIGHT + GAP);
+      let document = SVGDocument::new()
+        .set("viewBox", (0, 0, width, height))
+        .set("width", width)
+        .set("height", height)
+        .add(SVGRectangle::new().set("width", width).set("height", height).set("fill", "white"));
+      return sheet.iter().enumerate().fold(document, |document, (index, (page, view_box, contents))| {
+        let x = GAP + (index % tiling.columns) as f32 * (cell_width + GAP);
+        let y = GAP + (index / tiling.columns) as f32 * (cell_height + LABEL_HEIGHT + GAP);
+        let scale = (cell_width / view_box.width).min(cell_height / view_box.height);
+        // centered in its cell, as SVG fits a view box
+        let (offset_x, offset_y) = ((cell_width - view_box.width * scale) / 2.0, (cell_height - view_box.height * scale) / 2.0);
+        let placed = SVGGroup::new()
+          .set("transform", format!(
+            "translate({} {}) scale({}) translate({} {})",
+            x + offset_x, y + offset_y, scale, -view_box.x, -view_box.y
+          ))
+          .add(SVGTextNode::new(*contents));
+        let frame = SVGRectangle::new()
+          .set("x", x + offset_x)
+          .set("y", y + offset_y)
+          .set("width", view_box.width * scale)
+          .set("height", view_box.height * scale)
+          .set("fill", "none")
+          .set("stroke", "#cccccc")
+          .set("stroke-width", 2);
+        let caption = SVGText::new()
+          .set("x", x + cell_width / 2.0)
+          .set("y", y + cell_height + LABEL_HEIGHT - (LABEL_HEIGHT - LABEL_SIZE) / 2.0)
+          .set("font-family", "sans-serif")
+          .set("font-size", LABEL_SIZE)
+          .set("text-anchor", "middle")
+          .set("fill", "#555555")
+          .add(SVGTextNode::new(escape_xml(&label(page))));
+        return document.add(SVGGroup::new().set("class", "tile").add(placed).add(frame).add(caption));
+      });
+    })
+    .collect());
+}
@@ -0,0 +1,39 @@
+use rayon::prelude::*;
+
+use crate::options::RenderOptions;
+use crate::png::write_apng;
+use crate::raster::rasterize;
+use crate::scene::scene;
+use crate::{render_svg, Document};
+
+// Pages with more lines than this draw several lines a frame
+const MAX_FRAMES: usize = 120;
+const FRAME_DELAY_MS: u32 = 80;
+// how long the finished page is held before the animation starts again
+const FINAL_DELAY_MS: u32 = 3000;
+
+// An animated PNG of the page being drawn line by line in the order it was
+// written, rendered with `options` and rasterized at `scale` device pixels
+// per CSS pixel. Every frame is laid out for the finished page, so crops
+// and view boxes don't jump about as lines are added.
+pub fn write_timelapse(page: &Document, options: &RenderOptions, scale: f32) -> Result<Vec<u8>, String> {
+  let lines = page.layers.iter().enumerate()
+    .filter(|(index, _)| options.draws_layer(index + 1))
+    .flat_map(|(_, layer)| layer.lines.iter())
+    .filter(|line| options.draws_brush(line.brush_type))
+    .count();
+  let frames = lines.clamp(1, MAX_FRAMES);
+  let render_frame = |frame: &usize| {
+    let frame_options = options.clone().with_drawn_lines((lines * frame).div_ceil(frames));
+    let delay_ms = if *frame == frames { FINAL_DELAY_MS } else { FRAME_DELAY_MS };
+    return render_svg(page, &frame_options)
+      .and_then(|svg| scene(&svg))
+      .map(|scene| (rasterize(&scene, scale), delay_ms));
+  };
+  // rendered in parallel a batch at a time, so that only a batch of frames
+  // is ever held at once
+  let numbers = (1..=frames).collect::<Vec<_>>();
+  let canvases = numbers.chunks(rayon::current_num_threads())
+    .flat_map(|batch| batch.par_iter().map(render_frame).collect::<Vec<_>>());
+  return write_apng(canvases);
+}
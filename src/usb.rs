@@ -0,0 +1,144 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::json;
+use crate::xochitl::{DocumentSource, MemoryTree};
+use crate::zip::{is_zip, read_archive};
+
+// Where a tablet plugged in over USB serves its web interface, once it's
+// turned on in the storage settings
+pub const USB_URL: &str = "http://10.11.99.1";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// exporting a long notebook takes the tablet a while
+const READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+// The body of a plain HTTP GET, which is all the web interface needs
+fn get(url: &str) -> Result<Vec<u8>, String> {
+  let rest = url.strip_prefix("http://").ok_or_else(|| format!("{} isn't an http:// URL", url))?;
+  let (host, path) = rest.find('/').map_or((rest, "/"), |slash| (&rest[..slash], &rest[slash..]));
+  let address = match host.contains(':') {
+    true => String::from(host),
+    false => format!("{}:80", host)
+  };
+  let unreachable = |why: std::io::Error| {
+    return format!("couldn't reach the tablet at {}, is it plugged in with the USB web interface on? {}", host, why);
+  };
+  let socket = address.to_socket_addrs().map_err(unreachable)?
+    .next()
+    .ok_or_else(|| format!("couldn't resolve {}", host))?;
+  let mut stream = TcpStream::connect_timeout(&socket, CONNECT_TIMEOUT).map_err(unreachable)?;
+  stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(unreachable)?;
+  write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host).map_err(unreachable)?;
+  let mut response = Vec::new();
+  stream.read_to_end(&mut response).map_err(|why| format!("couldn't read {}, {}", url, why))?;
+
+  let end = response.windows(4).position(|window| window == b"\r\n\r\n")
+    .ok_or_else(|| format!("{} sent a malformed response", url))?;
+  let head = String::from_utf8_lossy(&response[..end]).into_owned();
+  let body = &response[end + 4..];
+  let status = head.lines().next().unwrap_or_default();
+  if status.split(' ').nth(1).is_none_or(|code| !code.starts_with('2')) {
+    return Err(format!("{} answered {}", url, status.split_once(' ').map_or(status, |(_, rest)| rest)));
+  }
+  let chunked = head.lines().any(|line| {
+    return line.to_lowercase().starts_with("transfer-encoding:") && line.to_lowercase().contains("chunked");
+  });
+  return match chunked {
+    true => dechunk(body).ok_or_else(|| format!("{} sent a malformed response", url)),
+    false => Ok(body.to_vec())
+  };
+}
+
+// A chunked body put back together, without any trailers
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+  let mut data = Vec::new();
+  loop {
+    let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+    let size_field = String::from_utf8_lossy(&body[..line_end]).into_owned();
+    let size = usize::from_str_radix(size_field.split(';').next()?.trim(), 16).ok()?;
+    body = &body[line_end + 2..];
+    if size == 0 {
+      return Some(data);
+    }
+    data.extend_from_slice(body.get(..size)?);
+    body = body.get(size + 2..)?;
+  }
+}
+
+// The documents on a tablet as its web interface lists them, a folder at
+// a time, with metadata like xochitl's made up from what it says about
+// each. Documents are only ever downloaded whole, as .rmdoc archives.
+#[derive(Debug, Clone)]
+pub struct UsbTree {
+  url: String,
+  // every document and folder as (UUID, metadata)
+  entries: Vec<(String, String)>
+}
+
+impl UsbTree {
+  pub fn connect(url: &str) -> Result<UsbTree, String> {
+    let url = url.trim_end_matches('/');
+    let mut entries = Vec::new();
+    let mut folders = vec![String::new()];
+    while let Some(folder) = folders.pop() {
+      let listing_url = format!("{}/documents/{}", url, folder);
+      let listing = json::parse(&String::from_utf8_lossy(&get(&listing_url)?))
+        .map_err(|why| format!("couldn't parse the listing from {}, {}", listing_url, why))?;
+      for item in listing.as_array().ok_or_else(|| format!("{} didn't send a list of documents", listing_url))? {
+        let field = |name: &str| item.get(name).and_then(|value| value.as_str()).unwrap_or_default();
+        // sic, the web interface spells it this way
+        let name = Some(field("VissibleName")).filter(|name| !name.is_empty()).unwrap_or_else(|| field("visibleName"));
+        if field("ID").is_empty() {
+          continue;
+        }
+        if field("Type") == "CollectionType" {
+          folders.push(String::from(field("ID")));
+        }
+        let metadata = json::Value::Object(vec![
+          (String::from("visibleName"), json::Value::String(String::from(name))),
+          (String::from("type"), json::Value::String(String::from(field("Type")))),
+          (String::from("parent"), json::Value::String(folder.clone()))
+        ]);
+        entries.push((String::from(field("ID")), metadata.to_string()));
+      }
+    }
+    return Ok(UsbTree { url: String::from(url), entries });
+  }
+
+  // An .rmdoc archive of a document: its .content, .metadata and pages
+  pub fn download(&self, id: &str) -> Result<Vec<u8>, String> {
+    let archive = get(&format!("{}/download/{}/rmdoc", self.url, id))?;
+    if !is_zip(&archive) {
+      return Err(format!("the tablet didn't send {} as an .rmdoc, downloading notebooks needs firmware 3.9 or later", id));
+    }
+    return Ok(archive);
+  }
+
+  // The .content, .pagedata and pages of each document, unpacked from its
+  // archive; the listing already has the metadata
+  pub fn fetch_documents(&self, ids: &[String]) -> Result<MemoryTree, String> {
+    let mut files = Vec::new();
+    for id in ids {
+      let entries = read_archive(&self.download(id)?).map_err(|why| format!("couldn't unpack {}, {}", id, why))?;
+      files.extend(entries.into_iter()
+        .filter(|entry| !entry.name.ends_with(".metadata"))
+        .map(|entry| (entry.name, entry.data)));
+    }
+    return Ok(MemoryTree { files });
+  }
+}
+
+impl DocumentSource for UsbTree {
+  fn list_metadata(&self) -> Result<Vec<(String, String)>, String> {
+    return Ok(self.entries.clone());
+  }
+
+  fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+    return self.entries.iter()
+      .find(|(id, _)| path.strip_suffix(".metadata") == Some(id.as_str()))
+      .map(|(_, metadata)| metadata.clone().into_bytes())
+      .ok_or_else(|| format!("{} can only be downloaded with the rest of its document", path));
+  }
+}
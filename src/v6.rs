@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::annotations::Highlight;
+use crate::device::{Device, EDGE_MARGIN};
+use crate::format::{LineHeader, HEADER_SIZE};
+use crate::text::{Paragraph, ParagraphStyle, TextBlock};
+use crate::Point;
+
+// The layout of v6 pages, which firmware 3.0 and later write. After the
+// header comes a run of blocks, each one thing in the page's scene: a node
+// of its tree, a group (which is what a layer is), a line, typed text and
+// so on. Within a block, values are tagged with their index and how
+// they're stored, so readers can tell what they're reading. Only what is
+// drawn is read, the rest is skipped.
+
+const BLOCK_HEADER_SIZE: usize = 8;
+
+const GLYPH_ITEM_BLOCK: u8 = 0x03;
+const GROUP_ITEM_BLOCK: u8 = 0x04;
+const LINE_ITEM_BLOCK: u8 = 0x05;
+const ROOT_TEXT_BLOCK: u8 = 0x07;
+
+// how a tagged value is stored
+const TAG_BYTE4: u64 = 0x4;
+const TAG_BYTE8: u64 = 0x8;
+const TAG_LENGTH4: u64 = 0xc;
+const TAG_ID: u64 = 0xf;
+
+// Things in the scene are named by the author that made them and a
+// counter of theirs
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+struct CrdtId(u8, u64);
+
+impl CrdtId {
+  fn plus(&self, offset: usize) -> CrdtId {
+    return CrdtId(self.0, self.1 + offset as u64);
+  }
+}
+
+// the start of a sequence, to the left of everything in it
+const START_ID: CrdtId = CrdtId(0, 0);
+// the scene's root group, which layers are in
+const ROOT_ID: CrdtId = CrdtId(0, 1);
+
+// Reads values from a block, or from part of one
+struct Reader<'a> {
+  data: &'a [u8],
+  position: usize
+}
+
+impl<'a> Reader<'a> {
+  fn new(data: &'a [u8]) -> Reader<'a> {
+    return Reader { data, position: 0 };
+  }
+
+  fn remaining(&self) -> usize {
+    return self.data.len() - self.position;
+  }
+
+  fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+    if count > self.remaining() {
+      return Err(format!("it ends early, {} bytes short at byte {}", count - self.remaining(), self.position));
+    }
+    self.position += count;
+    return Ok(&self.data[self.position - count..self.position]);
+  }
+
+  fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+    return self.bytes(N).map(|bytes| <[u8; N]>::try_from(bytes).unwrap_or([0; N]));
+  }
+
+  fn byte(&mut self) -> Result<u8, String> {
+    return self.array::<1>().map(|[byte]| byte);
+  }
+
+  fn u16(&mut self) -> Result<u16, String> {
+    return self.array().map(u16::from_le_bytes);
+  }
+
+  fn u32(&mut self) -> Result<u32, String> {
+    return self.array().map(u32::from_le_bytes);
+  }
+
+  fn f32(&mut self) -> Result<f32, String> {
+    return self.array().map(f32::from_le_bytes);
+  }
+
+  fn f64(&mut self) -> Result<f64, String> {
+    return self.array().map(f64::from_le_bytes);
+  }
+
+  // LEB128, seven bits to a byte, least significant first
+  fn varuint(&mut self) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+      let byte = self.byte()?;
+      value |= u64::from(byte & 0x7f) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    return Err(format!("a number at byte {} runs on too long", self.position));
+  }
+
+  fn crdt_id(&mut self) -> Result<CrdtId, String> {
+    return Ok(CrdtId(self.byte()?, self.varuint()?));
+  }
+
+  fn tag(&mut self, index: u64, kind: u64) -> Result<(), String> {
+    let position = self.position;
+    let tag = self.varuint()?;
+    if tag != index << 4 | kind {
+      return Err(format!("expected tag {:#x} at byte {}, found {:#x}", index << 4 | kind, position, tag));
+    }
+    return Ok(());
+  }
+
+  fn id(&mut self, index: u64) -> Result<CrdtId, String> {
+    self.tag(index, TAG_ID)?;
+    return self.crdt_id();
+  }
+
+  // an int that may be left out, as the tag that comes next tells
+  fn optional_int(&mut self, index: u64) -> Result<Option<u32>, String> {
+    let position = self.position;
+    if self.tag(index, TAG_BYTE4).is_err() {
+      self.position = position;
+      return Ok(None);
+    }
+    return self.u32().map(Some);
+  }
+
+  fn int(&mut self, index: u64) -> Result<u32, String> {
+    self.tag(index, TAG_BYTE4)?;
+    return self.u32();
+  }
+
+  fn float(&mut self, index: u64) -> Result<f32, String> {
+    self.tag(index, TAG_BYTE4)?;
+    return self.f32();
+  }
+
+  fn double(&mut self, index: u64) -> Result<f64, String> {
+    self.tag(index, TAG_BYTE8)?;
+    return self.f64();
+  }
+
+  // a tagged run of values of its own, which is read separately
+  fn subblock(&mut self, index: u64) -> Result<Reader<'a>, String> {
+    self.tag(index, TAG_LENGTH4)?;
+    let length = self.u32()? as usize;
+    return self.bytes(length).map(Reader::new);
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    let length = self.varuint()? as usize;
+    // whether it's all ASCII, which UTF-8 doesn't need telling
+    self.byte()?;
+    return self.bytes(length).map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+  }
+}
+
+// What all of a scene's items start with: the group they're in, their own
+// id and the item they were put after in it. Deleted items are left
+// without a value.
+struct SceneItem<'a> {
+  parent: CrdtId,
+  id: CrdtId,
+  left: CrdtId,
+  value: Option<Reader<'a>>
+}
+
+fn scene_item<'a>(block: &mut Reader<'a>) -> Result<SceneItem<'a>, String> {
+  let parent = block.id(1)?;
+  let id = block.id(2)?;
+  let left = block.id(3)?;
+  // the item it was put before, and how much of it has been deleted
+  block.id(4)?;
+  block.int(5)?;
+  let value = match block.remaining() {
+    0 => None,
+    _ => {
+      let mut value = block.subblock(6)?;
+      // what kind of item it is, which the block's type already says
+      value.byte()?;
+      Some(value)
+    }
+  };
+  return Ok(SceneItem { parent, id, left, value });
+}
+
+// A sequence's items in order: each goes straight after the one it was put
+// after, as far as that's in the sequence yet. That's the order the tablet
+// shows them in for pages edited one change at a time.
+fn ordered<T>(items: Vec<(CrdtId, CrdtId, T)>) -> Vec<T> {
+  let mut sequence = Vec::<(CrdtId, T)>::with_capacity(items.len());
+  items.into_iter().for_each(|(id, left, value)| {
+    // most items are put after the last one, so that's looked at first
+    let index = match left {
+      START_ID => 0,
+      _ => sequence.iter().rposition(|(other, _)| *other == left).map_or(sequence.len(), |index| index + 1)
+    };
+    sequence.insert(index, (id, value));
+  });
+  return sequence.into_iter().map(|(_, value)| value).collect();
+}
+
+// A v6 point, with what v5 points store as floats scaled into integers
+fn point(data: &mut Reader, version: u8) -> Result<Point, String> {
+  let (x, y) = (data.f32()?, data.f32()?);
+  return match version {
+    1 => Ok(Point { x, y, speed: data.f32()?, direction: data.f32()?, width: data.f32()?, pressure: data.f32()? }),
+    _ => {
+      let (speed, width) = (data.u16()?, data.u16()?);
+      let (direction, pressure) = (data.byte()?, data.byte()?);
+      Ok(Point {
+        x,
+        y,
+        speed: f32::from(speed) / 4.0,
+        direction: f32::from(direction) / 255.0 * std::f32::consts::TAU,
+        width: f32::from(width) / 4.0,
+        pressure: f32::from(pressure) / 255.0
+      })
+    }
+  };
+}
+
+// A line item's value: its brush, color and size the way a v5 line header
+// has them, and its points
+fn line(value: &mut Reader, version: u8) -> Result<(LineHeader, Vec<Point>), String> {
+  let brush_type = value.int(1)? as i32;
+  let brush_color = value.int(2)? as i32;
+  let brush_size = value.double(3)? as f32;
+  // how far along an earlier stroke it starts, for lines that were split
+  value.float(4)?;
+  let mut data = value.subblock(5)?;
+  let point_size = if version == 1 { 24 } else { 14 };
+  let points = (0..data.remaining() / point_size).map(|_| point(&mut data, version)).collect::<Result<Vec<_>, _>>()?;
+  let header = LineHeader { brush_type, brush_color, padding: 0, brush_size, unknown: 0.0, num_points: points.len() as i32 };
+  return Ok((header, points));
+}
+
+// A glyph item's value: text highlighted in the page's PDF or EPUB, with
+// the rectangles the tablet covers it with
+fn highlight(value: &mut Reader) -> Result<Highlight, String> {
+  let start = value.optional_int(2)?;
+  let length = value.int(3)?;
+  let color = value.int(4)?;
+  let text = value.subblock(5)?.string()?;
+  let mut data = value.subblock(6)?;
+  let rectangles = (0..data.varuint()?)
+    .map(|_| Ok((data.f64()? as f32, data.f64()? as f32, data.f64()? as f32, data.f64()? as f32)))
+    .collect::<Result<Vec<_>, String>>()?;
+  return Ok(Highlight { start, length, color, text, rectangles });
+}
+
+// A text block's characters, each with its id and the one it was put
+// after
+fn text_items(content: &mut Reader) -> Result<Vec<(CrdtId, CrdtId, Option<char>)>, String> {
+  let mut items = content.subblock(1)?.subblock(1)?;
+  let mut characters = Vec::new();
+  for _ in 0..items.varuint()? {
+    let mut item = items.subblock(0)?;
+    let id = item.id(2)?;
+    let left = item.id(3)?;
+    item.id(4)?;
+    let deleted = item.int(5)? as usize;
+    let text = match item.remaining() {
+      0 => String::new(),
+      _ => item.subblock(6)?.string()?
+    };
+    // deleted characters keep their place, as later ones can be put after
+    // them; so do paragraph styles stored as items
+    let values = match text.is_empty() {
+      true => vec![None; deleted.max(1)],
+      false => text.chars().map(Some).collect()
+    };
+    characters.extend(values.into_iter().enumerate().map(|(index, value)| {
+      let after = if index == 0 { left } else { id.plus(index - 1) };
+      return (id.plus(index), after, value);
+    }));
+  }
+  return Ok(characters);
+}
+
+// The styles of a text block's paragraphs, by the id of the newline that
+// starts each (START_ID for the first)
+fn paragraph_styles(content: &mut Reader) -> Result<HashMap<CrdtId, ParagraphStyle>, String> {
+  let mut formats = content.subblock(2)?.subblock(1)?;
+  let mut styles = HashMap::new();
+  for _ in 0..formats.varuint()? {
+    let character = formats.crdt_id()?;
+    // when it was set
+    formats.id(1)?;
+    let mut format = formats.subblock(2)?;
+    format.byte()?;
+    styles.insert(character, ParagraphStyle::from_code(format.byte()?));
+  }
+  return Ok(styles);
+}
+
+// A page's typed text. Paragraphs are plain where their styles can't be
+// made out.
+fn text_block(block: &mut Reader) -> Result<TextBlock, String> {
+  block.id(1)?;
+  let mut content = block.subblock(2)?;
+  let characters = ordered(text_items(&mut content)?.into_iter().map(|(id, left, value)| (id, left, (id, value))).collect());
+  let styles = paragraph_styles(&mut content).unwrap_or_default();
+  let mut position = block.subblock(3)?;
+  let (x, y) = (position.f64()? as f32, position.f64()? as f32);
+  let width = block.float(4)?;
+  let mut paragraphs = vec![(START_ID, String::new())];
+  for (id, value) in characters {
+    match (value, paragraphs.last_mut()) {
+      (Some('\n'), _) => paragraphs.push((id, String::new())),
+      (Some(character), Some((_, text))) => text.push(character),
+      _ => {}
+    }
+  }
+  let paragraphs = paragraphs.into_iter()
+    .map(|(start, text)| Paragraph { style: styles.get(&start).copied().unwrap_or(ParagraphStyle::Plain), text })
+    .collect();
+  return Ok(TextBlock { x, y, width, paragraphs });
+}
+
+// What was read of a v6 page: its layers' lines, as v5 would have them,
+// its typed text and its highlights. Blocks that couldn't be read are
+// noted in `skipped`.
+pub struct Page {
+  pub layers: Vec<Vec<(LineHeader, Vec<Point>)>>,
+  pub text: Vec<TextBlock>,
+  pub highlights: Vec<Highlight>,
+  pub skipped: Vec<String>
+}
+
+// x is stored from the middle of the canvas rather than its left edge;
+// pages whose strokes reach past the reMarkable's canvas are the Paper
+// Pro's, as Device::detect tells
+fn canvas_middle(layers: &[Vec<(LineHeader, Vec<Point>)>]) -> f32 {
+  let (width, height) = Device::Remarkable.canvas();
+  let beyond = layers.iter().flatten()
+    .flat_map(|(_, points)| points.iter())
+    .any(|point| point.x.abs() > width / 2.0 + EDGE_MARGIN || point.y > height + EDGE_MARGIN);
+  let device = if beyond { Device::PaperPro } else { Device::Remarkable };
+  return device.canvas().0 / 2.0;
+}
+
+pub fn read_page(bytes: &[u8]) -> Result<Page, String> {
+  let mut page = Reader::new(bytes.get(HEADER_SIZE..).unwrap_or_default());
+  let mut layer_ids = Vec::<CrdtId>::new();
+  let mut lines = Vec::<(CrdtId, CrdtId, CrdtId, Option<(LineHeader, Vec<Point>)>)>::new();
+  let mut text = Vec::new();
+  let mut highlights = Vec::new();
+  let mut skipped = Vec::new();
+  while page.remaining() > 0 {
+    let offset = HEADER_SIZE + page.position;
+    let [length, header] = match (page.u32(), page.u32()) {
+      (Ok(length), Ok(header)) => [length, header],
+      _ => return Err(format!("page ends early, at offset {} while reading the header of a block", offset))
+    };
+    let [_, _, version, block_type] = header.to_le_bytes();
+    let mut block = page.bytes(length as usize)
+      .map(Reader::new)
+      .map_err(|_| format!("page ends early, at offset {} while reading a block", offset + BLOCK_HEADER_SIZE))?;
+    let read = match block_type {
+      GROUP_ITEM_BLOCK => scene_item(&mut block).and_then(|SceneItem { parent, value, .. }| {
+        let node = value.map(|mut value| value.id(2)).transpose()?;
+        if let Some(node) = node.filter(|node| parent == ROOT_ID && !layer_ids.contains(node)) {
+          layer_ids.push(node);
+        }
+        return Ok(());
+      }),
+      LINE_ITEM_BLOCK => scene_item(&mut block).and_then(|item| {
+        let value = item.value.map(|mut value| line(&mut value, version)).transpose()?;
+        lines.push((item.parent, item.id, item.left, value));
+        return Ok(());
+      }),
+      ROOT_TEXT_BLOCK => text_block(&mut block).map(|block| text.push(block)),
+      GLYPH_ITEM_BLOCK => scene_item(&mut block).and_then(|item| {
+        if let Some(mut value) = item.value {
+          highlights.push(highlight(&mut value)?);
+        }
+        return Ok(());
+      }),
+      _ => Ok(())
+    };
+    if let Err(why) = read {
+      skipped.push(format!("skipped block {:#04x} at offset {} that couldn't be read, {}", block_type, offset, why));
+    }
+  }
+  // lines in groups that aren't layers, e.g. ones anchored to text, get
+  // layers of their own
+  lines.iter().for_each(|(parent, _, _, _)| if !layer_ids.contains(parent) { layer_ids.push(*parent) });
+  let layers = layer_ids.iter()
+    .map(|layer_id| {
+      let items = lines.iter()
+        .filter(|(parent, _, _, _)| parent == layer_id)
+        .map(|(_, id, left, value)| (*id, *left, value.clone()))
+        .collect::<Vec<_>>();
+      return ordered(items).into_iter().flatten().collect::<Vec<_>>();
+    })
+    .collect::<Vec<_>>();
+  let middle = canvas_middle(&layers);
+  let mut layers = layers;
+  layers.iter_mut().flatten().flat_map(|(_, points)| points.iter_mut()).for_each(|point| point.x += middle);
+  text.iter_mut().for_each(|block| block.x += middle);
+  highlights.iter_mut().flat_map(|highlight| highlight.rectangles.iter_mut()).for_each(|(x, _, _, _)| *x += middle);
+  return Ok(Page { layers, text, highlights, skipped });
+}
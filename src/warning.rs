@@ -0,0 +1,100 @@
+use std::fmt;
+
+use svg::Document as SVGDocument;
+use svg::node::Comment as SVGComment;
+
+// Problems that don't stop a conversion but may make the output differ
+// from what is on the device. Codes are stable across releases so that
+// pipelines can match on them (and --deny them); never renumber, only add.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WarningCode {
+  // a brush id this version doesn't know, drawn as a fineliner
+  UnknownBrush,
+  // data after the last layer
+  TrailingBytes,
+  // points with NaN or infinite coordinates, which are left out
+  DroppedPoints,
+  // a color id this version doesn't know, drawn black
+  UnknownColor,
+  // a block of a v6 page that couldn't be read, left out
+  SkippedBlock
+}
+
+pub const WARNING_CODES: [WarningCode; 5] = [
+  WarningCode::UnknownBrush,
+  WarningCode::TrailingBytes,
+  WarningCode::DroppedPoints,
+  WarningCode::UnknownColor,
+  WarningCode::SkippedBlock
+];
+
+impl WarningCode {
+  pub fn id(&self) -> &'static str {
+    return match self {
+      WarningCode::UnknownBrush => "W001",
+      WarningCode::TrailingBytes => "W002",
+      WarningCode::DroppedPoints => "W003",
+      WarningCode::UnknownColor => "W004",
+      WarningCode::SkippedBlock => "W005"
+    };
+  }
+
+  pub fn by_id(id: &str) -> Option<WarningCode> {
+    let id = id.trim().to_uppercase();
+    return WARNING_CODES.iter().find(|code| code.id() == id).copied();
+  }
+}
+
+impl fmt::Display for WarningCode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.id())
+  }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Warning {
+  pub code: WarningCode,
+  pub message: String
+}
+
+impl Warning {
+  pub fn new(code: WarningCode, message: String) -> Warning {
+    return Warning { code, message };
+  }
+}
+
+impl fmt::Display for Warning {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.code, self.message)
+  }
+}
+
+// "W001,W003" or "all"
+pub fn parse_denied(spec: &str) -> Result<Vec<WarningCode>, String> {
+  if spec.trim().eq_ignore_ascii_case("all") {
+    return Ok(WARNING_CODES.to_vec());
+  }
+  return spec.split(',')
+    .filter(|id| !id.trim().is_empty())
+    .map(|id| WarningCode::by_id(id).ok_or_else(|| format!("unknown warning code {}", id.trim())))
+    .collect();
+}
+
+// Fails on the first warning whose code is denied
+pub fn check_denied(warnings: &[Warning], denied: &[WarningCode]) -> Result<(), String> {
+  return match warnings.iter().find(|warning| denied.contains(&warning.code)) {
+    Some(warning) => Err(format!("{} (denied)", warning)),
+    None => Ok(())
+  };
+}
+
+// Records each warning as a comment in the SVG, so that what went wrong
+// travels with the output
+pub fn annotate_svg(document: SVGDocument, warnings: &[Warning]) -> SVGDocument {
+  return warnings.iter().fold(document, |acc_svg, warning| {
+    // "--" may not appear inside an XML comment
+    return acc_svg.add(SVGComment::new(warning.to_string().replace("--", "- -")));
+  });
+}
@@ -0,0 +1,61 @@
+// Exports for WebAssembly hosts such as a web page, without a binding
+// generator. The host allocates a buffer with `alloc`, copies a page or
+// zipped notebook into it and calls `convert`, which takes the buffer and
+// returns another: a status (0 with the converted text, 1 with an error
+// message) and the text's length, both as little-endian u32s, then the
+// UTF-8 text. Buffers the host gets back are freed with `dealloc`.
+
+use crate::html::{write_html, HtmlPage};
+use crate::options::RenderOptions;
+use crate::convert_bytes;
+
+const HEADER_SIZE: usize = 8;
+
+// An SVG for a single page, or HTML with every page of a notebook
+fn convert_to_text(bytes: &[u8]) -> Result<String, String> {
+  let output = convert_bytes(bytes, &RenderOptions::default())?;
+  if let [page] = &output.pages[..] {
+    return Ok(page.svg.clone());
+  }
+  let pages = output.pages.iter().enumerate()
+    .map(|(index, page)| HtmlPage {
+      // page-003 and so on
+      number: page.name.trim_start_matches("page-").parse().unwrap_or(index + 1),
+      title: page.title.clone(),
+      svgs: vec![page.svg.clone()]
+    })
+    .collect::<Vec<_>>();
+  return Ok(write_html("Notebook", &pages));
+}
+
+fn into_buffer(bytes: Vec<u8>) -> *mut u8 {
+  return Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+}
+
+#[no_mangle]
+pub extern "C" fn alloc(length: usize) -> *mut u8 {
+  return into_buffer(vec![0; length]);
+}
+
+/// # Safety
+/// `buffer` must come from `alloc` or `convert`, with the length it has.
+#[no_mangle]
+pub unsafe extern "C" fn dealloc(buffer: *mut u8, length: usize) {
+  drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer, length)));
+}
+
+/// # Safety
+/// `input` must be a buffer from `alloc` of `length` bytes. It is freed.
+#[no_mangle]
+pub unsafe extern "C" fn convert(input: *mut u8, length: usize) -> *mut u8 {
+  let bytes = Box::from_raw(std::ptr::slice_from_raw_parts_mut(input, length));
+  let (status, text) = match convert_to_text(&bytes) {
+    Ok(text) => (0u32, text),
+    Err(why) => (1u32, why)
+  };
+  let mut output = Vec::with_capacity(HEADER_SIZE + text.len());
+  output.extend_from_slice(&status.to_le_bytes());
+  output.extend_from_slice(&(text.len() as u32).to_le_bytes());
+  output.extend_from_slice(text.as_bytes());
+  return into_buffer(output);
+}
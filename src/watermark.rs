@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use svg::node::element::Group as SVGGroup;
+use svg::node::element::Image as SVGImage;
+use svg::node::element::Text as SVGText;
+use svg::node::Text as SVGTextNode;
+
+use crate::base64;
+use crate::options::ViewBox;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatermarkContent {
+  Text(String),
+  // an SVG or PNG file, as a data: URI
+  Image(String)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WatermarkPosition {
+  // large, across the page at an angle
+  Diagonal,
+  Center,
+  Top,
+  Bottom,
+  TopLeft,
+  TopRight,
+  BottomLeft,
+  BottomRight
+}
+
+pub const WATERMARK_POSITIONS: [&str; 8] = [
+  "diagonal", "center", "top", "bottom", "top-left", "top-right", "bottom-left", "bottom-right"
+];
+
+impl WatermarkPosition {
+  pub fn by_name(name: &str) -> Option<WatermarkPosition> {
+    return match name.trim().to_lowercase().as_str() {
+      "diagonal" => Some(WatermarkPosition::Diagonal),
+      "center" | "centre" => Some(WatermarkPosition::Center),
+      "top" => Some(WatermarkPosition::Top),
+      "bottom" => Some(WatermarkPosition::Bottom),
+      "top-left" => Some(WatermarkPosition::TopLeft),
+      "top-right" => Some(WatermarkPosition::TopRight),
+      "bottom-left" => Some(WatermarkPosition::BottomLeft),
+      "bottom-right" => Some(WatermarkPosition::BottomRight),
+      _ => None
+    };
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+  pub content: WatermarkContent,
+  pub opacity: f32,
+  pub position: WatermarkPosition,
+  // drawn over the strokes rather than under them
+  pub above: bool
+}
+
+impl Watermark {
+  // `spec` names an .svg or .png file to embed, or is the text itself
+  pub fn from_spec(spec: &str, opacity: f32, position: WatermarkPosition, above: bool) -> Result<Watermark, String> {
+    let path = Path::new(spec);
+    let media_type = match path.extension().map(|e| e.to_string_lossy().to_lowercase()).as_deref() {
+      Some("svg") => Some("image/svg+xml"),
+      Some("png") => Some("image/png"),
+      _ => None
+    };
+    let content = match media_type {
+      Some(media_type) if path.is_file() => {
+        let data = std::fs::read(path).map_err(|why| format!("couldn't read watermark {}, {}", spec, why))?;
+        WatermarkContent::Image(format!("data:{};base64,{}", media_type, base64::encode(&data)))
+      },
+      _ => WatermarkContent::Text(String::from(spec))
+    };
+    if !(0.0..=1.0).contains(&opacity) {
+      return Err(format!("invalid watermark opacity {}, expected 0 to 1", opacity));
+    }
+    return Ok(Watermark { content, opacity, position, above });
+  }
+
+  // Laid out relative to the visible region, so it stays on the page when
+  // cropping
+  pub fn render(&self, view_box: &ViewBox) -> SVGGroup {
+    let group = SVGGroup::new().set("id", "watermark").set("opacity", self.opacity);
+    let large = matches!(self.position, WatermarkPosition::Diagonal | WatermarkPosition::Center);
+    // a box to fit the mark into: most of the page, or a small label
+    let (box_width, box_height) = match large {
+      true => (view_box.width * 0.8, view_box.height * 0.3),
+      false => (view_box.width * 0.3, view_box.height * 0.05)
+    };
+    let margin = view_box.width.min(view_box.height) * 0.03;
+    let (left, middle, right) = (view_box.x + margin, view_box.x + view_box.width / 2.0, view_box.x + view_box.width - margin);
+    let (top, center, bottom) = (view_box.y + margin, view_box.y + view_box.height / 2.0, view_box.y + view_box.height - margin);
+    // where the box's anchor point goes, and which point of the box it is
+    // (-1 start, 0 middle, 1 end) horizontally and vertically
+    let (x, y, anchor_x, anchor_y) = match self.position {
+      WatermarkPosition::Diagonal | WatermarkPosition::Center => (middle, center, 0, 0),
+      WatermarkPosition::Top => (middle, top, 0, -1),
+      WatermarkPosition::Bottom => (middle, bottom, 0, 1),
+      WatermarkPosition::TopLeft => (left, top, -1, -1),
+      WatermarkPosition::TopRight => (right, top, 1, -1),
+      WatermarkPosition::BottomLeft => (left, bottom, -1, 1),
+      WatermarkPosition::BottomRight => (right, bottom, 1, 1)
+    };
+    let group = match self.position {
+      // along the page's diagonal
+      WatermarkPosition::Diagonal => group.set("transform", format!(
+        "rotate({} {} {})", -(view_box.height.atan2(view_box.width).to_degrees()), x, y
+      )),
+      _ => group
+    };
+
+    return match &self.content {
+      WatermarkContent::Text(text) => {
+        // roughly: glyphs are about 0.6em wide in a sans-serif face
+        let font_size = (box_width / (text.chars().count().max(1) as f32 * 0.6)).min(box_height);
+        let text_anchor = match anchor_x { -1 => "start", 1 => "end", _ => "middle" };
+        // from the anchor point to the baseline, using a sans-serif
+        // face's typical cap height of about 0.7em
+        let baseline = match anchor_y {
+          -1 => y + font_size * 0.75,
+          1 => y - font_size * 0.2,
+          _ => y + font_size * 0.35
+        };
+        group.add(
+          SVGText::new()
+            .set("x", x)
+            .set("y", baseline)
+            .set("font-family", "sans-serif")
+            .set("font-size", font_size)
+            .set("font-weight", "bold")
+            .set("text-anchor", text_anchor)
+            .set("fill", "#808080")
+            .add(SVGTextNode::new(escape_xml(text)))
+        )
+      },
+      WatermarkContent::Image(href) => group.add(
+        SVGImage::new()
+          .set("x", x - box_width * (anchor_x + 1) as f32 / 2.0)
+          .set("y", y - box_height * (anchor_y + 1) as f32 / 2.0)
+          .set("width", box_width)
+          .set("height", box_height)
+          .set("preserveAspectRatio", match (anchor_x, anchor_y) {
+            (-1, -1) => "xMinYMin meet",
+            (1, -1) => "xMaxYMin meet",
+            (-1, 1) => "xMinYMax meet",
+            (1, 1) => "xMaxYMax meet",
+            (0, -1) => "xMidYMin meet",
+            (0, 1) => "xMidYMax meet",
+            _ => "xMidYMid meet"
+          })
+          .set("href", href.as_str())
+      )
+    };
+  }
+}
+
+pub fn escape_xml(text: &str) -> String {
+  return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
@@ -0,0 +1,405 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+
+use crate::json;
+use crate::notebook::{hidden_layers, is_landscape, last_modified, layer_names, page_ids};
+use crate::{parse_document, Document, LogLevel};
+
+// Where xochitl keeps documents on the tablet
+pub const DEVICE_ROOT: &str = "/home/root/.local/share/remarkable/xochitl";
+
+// A flat xochitl document tree: UUID.metadata and UUID.content files next
+// to a UUID/ directory of page files
+pub trait DocumentSource {
+  // every .metadata file as (UUID, contents)
+  fn list_metadata(&self) -> Result<Vec<(String, String)>, String>;
+  // a file by its path relative to the tree's root
+  fn read(&self, path: &str) -> Result<Vec<u8>, String>;
+
+  fn contains(&self, path: &str) -> bool {
+    return self.read(path).is_ok();
+  }
+
+  // when a file last changed, for sources that know
+  fn modified(&self, _path: &str) -> Option<SystemTime> {
+    return None;
+  }
+}
+
+// A copy of the tree on this machine, e.g. from a backup
+#[derive(Debug, Clone)]
+pub struct LocalTree {
+  pub root: PathBuf
+}
+
+impl DocumentSource for LocalTree {
+  fn list_metadata(&self) -> Result<Vec<(String, String)>, String> {
+    let entries = fs::read_dir(&self.root)
+      .map_err(|why| format!("couldn't list {}, {}", self.root.display(), why))?;
+    return entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| path.extension().is_some_and(|e| e == "metadata"))
+      .map(|path| {
+        let id = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        return fs::read_to_string(&path)
+          .map(|text| (id, text))
+          .map_err(|why| format!("couldn't read {}, {}", path.display(), why));
+      })
+      .collect();
+  }
+
+  fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+    let full_path = self.root.join(path);
+    return fs::read(&full_path).map_err(|why| format!("couldn't read {}, {}", full_path.display(), why));
+  }
+
+  fn contains(&self, path: &str) -> bool {
+    return self.root.join(path).is_file();
+  }
+
+  fn modified(&self, path: &str) -> Option<SystemTime> {
+    return fs::metadata(self.root.join(path)).and_then(|metadata| metadata.modified()).ok();
+  }
+}
+
+// Files held in memory by their paths relative to the root, e.g. as
+// fetched from a tablet
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTree {
+  pub files: Vec<(String, Vec<u8>)>
+}
+
+impl DocumentSource for MemoryTree {
+  fn list_metadata(&self) -> Result<Vec<(String, String)>, String> {
+    return Ok(self.files.iter()
+      .filter(|(path, _)| !path.contains('/'))
+      .filter_map(|(path, contents)| {
+        let id = path.strip_suffix(".metadata")?;
+        return Some((String::from(id), String::from_utf8_lossy(contents).into_owned()));
+      })
+      .collect());
+  }
+
+  fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+    return self.files.iter()
+      .find(|(file, _)| file == path)
+      .map(|(_, contents)| contents.clone())
+      .ok_or_else(|| format!("no {} in the tree", path));
+  }
+
+  fn contains(&self, path: &str) -> bool {
+    return self.files.iter().any(|(file, _)| file == path);
+  }
+}
+
+// The tree on a tablet reachable over SSH (e.g. root@10.11.99.1 over USB),
+// using the system's ssh client so that keys and config just work
+#[derive(Debug, Clone)]
+pub struct SshTree {
+  pub host: String,
+  pub root: String
+}
+
+// separates files when listing in a single round trip
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+// Takes the path, bytes and metadata of the page being drawn
+pub type LivePage<'a> = dyn FnMut(&str, Vec<u8>, Vec<u8>) + 'a;
+
+impl SshTree {
+  fn run(&self, script: &str) -> Result<Vec<u8>, String> {
+    let output = Command::new("ssh")
+      .arg(&self.host)
+      .arg(script)
+      .output()
+      .map_err(|why| format!("couldn't run ssh, {}", why))?;
+    if !output.status.success() {
+      return Err(format!(
+        "ssh {} failed with {}: {}", self.host, output.status, String::from_utf8_lossy(&output.stderr).trim()
+      ));
+    }
+    return Ok(output.stdout);
+  }
+
+  // The .content, .pagedata, pages and page metadata of each document, in
+  // one round trip.
+  // Each file comes back as its path and size on a line each, then its
+  // bytes.
+  pub fn fetch_documents(&self, ids: &[String]) -> Result<MemoryTree, String> {
+    let patterns = ids.iter()
+      .map(|id| {
+        let id = shell_quote(id);
+        return format!("{}.content {}.pagedata {}/*.rm {}/*-metadata.json", id, id, id, id);
+      })
+      .collect::<Vec<_>>()
+      .join(" ");
+    let script = format!(
+      "cd {} && for f in {}; do [ -f \"$f\" ] || continue; printf '%s\\n' \"$f\"; wc -c < \"$f\"; cat \"$f\"; done",
+      shell_quote(&self.root), patterns
+    );
+    let output = self.run(&script)?;
+    let mut files = Vec::new();
+    let mut rest = &output[..];
+    while !rest.is_empty() {
+      let mut line = || {
+        let end = rest.iter().position(|byte| *byte == b'\n').ok_or_else(|| String::from("ssh listing ended early"))?;
+        let line = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+        rest = &rest[end + 1..];
+        return Ok::<String, String>(line);
+      };
+      let path = line()?;
+      let size = line()?.parse::<usize>().map_err(|why| format!("bad size of {} in ssh listing, {}", path, why))?;
+      if rest.len() < size {
+        return Err(format!("ssh listing ended partway through {}", path));
+      }
+      files.push((path, rest[..size].to_vec()));
+      rest = &rest[size..];
+    }
+    return Ok(MemoryTree { files });
+  }
+
+  // Follows the page being drawn on, the most recently changed page of any
+  // document, checking it every `interval` over one ssh connection. Every
+  // version of it goes to `on_page` as its path and bytes, with its
+  // PAGE-metadata.json (empty if it has none), framed as fetch_documents
+  // frames files, until ssh ends. Pages are copied before they're sent so
+  // that xochitl saving partway through can't break the framing.
+  pub fn follow_current_page(&self, interval: Duration, on_page: &mut LivePage) -> Result<(), String> {
+    let script = format!(
+      "cd {} || exit 1; copy=/tmp/relineate-live.$$; trap 'rm -f $copy; exit' EXIT HUP INT PIPE TERM; last=; while :; do \
+      f=$(ls -t */*.rm 2>/dev/null | head -n 1); \
+      if [ -n \"$f\" ] && [ \"$f $(stat -c '%Y %s' \"$f\")\" != \"$last\" ]; then \
+      last=\"$f $(stat -c '%Y %s' \"$f\")\"; cp \"$f\" $copy; printf '%s\\n' \"$f\"; wc -c < $copy; cat $copy; \
+      m=\"${{f%.rm}}-metadata.json\"; if [ -f \"$m\" ]; then cp \"$m\" $copy; else : > $copy; fi; \
+      printf '%s\\n' \"$m\"; wc -c < $copy; cat $copy; \
+      fi; sleep {}; done",
+      shell_quote(&self.root), interval.as_secs().max(1)
+    );
+    let mut child = Command::new("ssh")
+      .arg(&self.host)
+      .arg(script)
+      .stdout(Stdio::piped())
+      .spawn()
+      .map_err(|why| format!("couldn't run ssh, {}", why))?;
+    let mut stdout = BufReader::new(child.stdout.take().ok_or_else(|| String::from("couldn't read from ssh"))?);
+    let line = |stdout: &mut dyn BufRead| {
+      let mut line = String::new();
+      return match stdout.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(String::from(line.trim()))
+      };
+    };
+    let file = |stdout: &mut BufReader<_>, path: &str| {
+      let size = line(stdout).and_then(|size| size.parse::<usize>().ok())
+        .ok_or_else(|| format!("bad size of {} from ssh", path))?;
+      let mut contents = vec![0; size];
+      stdout.read_exact(&mut contents).map_err(|why| format!("ssh ended partway through {}, {}", path, why))?;
+      return Ok::<Vec<u8>, String>(contents);
+    };
+    while let Some(path) = line(&mut stdout) {
+      let page = file(&mut stdout, &path)?;
+      let metadata_path = line(&mut stdout).ok_or_else(|| format!("ssh ended after {}", path))?;
+      let metadata = file(&mut stdout, &metadata_path)?;
+      on_page(&path, page, metadata);
+    }
+    let status = child.wait().map_err(|why| format!("couldn't wait for ssh, {}", why))?;
+    return Err(format!("ssh {} ended with {}", self.host, status));
+  }
+}
+
+// Quotes a value for a POSIX shell
+fn shell_quote(value: &str) -> String {
+  return format!("'{}'", value.replace('\'', "'\\''"));
+}
+
+impl DocumentSource for SshTree {
+  fn list_metadata(&self) -> Result<Vec<(String, String)>, String> {
+    let script = format!(
+      "cd {} && for f in *.metadata; do printf '%s\\n' \"${{f%.metadata}}\"; cat \"$f\"; printf '\\036'; done",
+      shell_quote(&self.root)
+    );
+    let listing = String::from_utf8_lossy(&self.run(&script)?).into_owned();
+    return Ok(listing.split(RECORD_SEPARATOR)
+      .filter_map(|record| {
+        let (id, text) = record.trim_start().split_once('\n')?;
+        return Some((id.trim().to_string(), text.to_string()));
+      })
+      .collect());
+  }
+
+  fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+    return self.run(&format!("cat {}", shell_quote(&format!("{}/{}", self.root, path))));
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct DocumentEntry {
+  pub id: String,
+  pub name: String,
+  // UUID of the containing folder, empty at the top level
+  pub parent: String
+}
+
+// Entries in the tree that aren't deleted or in the trash, either the
+// folders or everything else, sorted by name
+fn list_entries(source: &dyn DocumentSource, folders: bool) -> Result<Vec<DocumentEntry>, String> {
+  let mut entries = source.list_metadata()?.into_iter()
+    .filter_map(|(id, text)| json::parse(&text).ok().map(|metadata| (id, metadata)))
+    .filter(|(_, metadata)| (metadata.get("type").and_then(|t| t.as_str()) == Some("CollectionType")) == folders)
+    .filter(|(_, metadata)| !metadata.get("deleted").and_then(|d| d.as_bool()).unwrap_or(false))
+    .filter(|(_, metadata)| metadata.get("parent").and_then(|p| p.as_str()) != Some("trash"))
+    .map(|(id, metadata)| DocumentEntry {
+      name: metadata.get("visibleName").and_then(|n| n.as_str()).unwrap_or(&id).to_string(),
+      parent: metadata.get("parent").and_then(|p| p.as_str()).unwrap_or_default().to_string(),
+      id
+    })
+    .collect::<Vec<_>>();
+  entries.sort_by_key(|entry| entry.name.to_lowercase());
+  return Ok(entries);
+}
+
+// Notebooks and other documents in the tree (not folders, nor anything
+// deleted or in the trash), sorted by name
+pub fn list_documents(source: &dyn DocumentSource) -> Result<Vec<DocumentEntry>, String> {
+  return list_entries(source, false);
+}
+
+pub fn list_folders(source: &dyn DocumentSource) -> Result<Vec<DocumentEntry>, String> {
+  return list_entries(source, true);
+}
+
+// Names of the folders an entry is in, outermost first
+pub fn folder_names(entry: &DocumentEntry, folders: &[DocumentEntry]) -> Vec<String> {
+  let mut names = Vec::new();
+  let mut parent = entry.parent.as_str();
+  // a folder can't be in itself, however the metadata has it
+  while let Some(folder) = folders.iter().find(|folder| folder.id == parent).filter(|_| names.len() < folders.len()) {
+    names.push(folder.name.clone());
+    parent = folder.parent.as_str();
+  }
+  names.reverse();
+  return names;
+}
+
+// A display name as a file or directory name, with the characters that
+// aren't allowed in one on some systems replaced
+pub fn file_name(name: &str) -> String {
+  let replaced = name.chars()
+    .map(|c| match c {
+      '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+      _ if c.is_control() => '_',
+      _ => c
+    })
+    .collect::<String>();
+  let trimmed = replaced.trim().trim_end_matches('.');
+  return match trimmed {
+    "" => String::from("_"),
+    _ => String::from(trimmed)
+  };
+}
+
+// Template names from a document's .pagedata, in page order, empty for
+// pages that have none
+pub fn document_templates(source: &dyn DocumentSource, id: &str) -> Vec<String> {
+  return source.read(&format!("{}.pagedata", id))
+    .map(|pagedata| String::from_utf8_lossy(&pagedata).lines().map(|line| line.trim().to_string()).collect())
+    .unwrap_or_default();
+}
+
+// The names of a page's layers and the numbers of those hidden, from its
+// PAGE-metadata.json
+pub fn page_layers(source: &dyn DocumentSource, id: &str, page_id: &str) -> (Vec<String>, Vec<usize>) {
+  return source.read(&format!("{}/{}-metadata.json", id, page_id)).ok()
+    .and_then(|metadata| json::parse(&String::from_utf8_lossy(&metadata)).ok())
+    .map_or_else(Default::default, |metadata| (layer_names(&metadata), hidden_layers(&metadata)));
+}
+
+// When a document last changed, as its .metadata says
+pub fn document_modified(source: &dyn DocumentSource, id: &str) -> Option<SystemTime> {
+  return source.read(&format!("{}.metadata", id)).ok()
+    .and_then(|metadata| json::parse(&String::from_utf8_lossy(&metadata)).ok())
+    .and_then(|metadata| last_modified(&metadata));
+}
+
+// Whether a document's .content says it was written in landscape
+pub fn document_landscape(source: &dyn DocumentSource, id: &str) -> bool {
+  return source.read(&format!("{}.content", id)).ok()
+    .and_then(|content| json::parse(&String::from_utf8_lossy(&content)).ok())
+    .is_some_and(|content| is_landscape(&content));
+}
+
+// Page UUIDs of a document, in order
+pub fn document_pages(source: &dyn DocumentSource, id: &str) -> Result<Vec<String>, String> {
+  let content = source.read(&format!("{}.content", id))?;
+  return json::parse(&String::from_utf8_lossy(&content))
+    .map(|content| page_ids(&content))
+    .map_err(|why| format!("couldn't parse {}.content, {}", id, why));
+}
+
+// A page's strokes; pages that were never drawn on have no .rm file
+pub fn read_page(
+  source: &dyn DocumentSource,
+  id: &str,
+  page_id: &str,
+  logger: &dyn Fn(LogLevel, String)
+) -> Result<Option<Document>, String> {
+  return match source.read(&format!("{}/{}.rm", id, page_id)) {
+    Ok(bytes) => parse_document(&bytes, logger).map(Some),
+    Err(why) => {
+      logger(LogLevel::DEBUG, why);
+      Ok(None)
+    }
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::write_rm;
+
+  const GOOD: &[u8] = include_bytes!("../test_files/good.rm");
+
+  fn quiet(_: LogLevel, _: String) {}
+
+  fn notebook() -> MemoryTree {
+    let files = [
+      ("n.metadata", &br#"{"visibleName": "Sketches", "type": "DocumentType", "parent": ""}"#[..]),
+      ("old.metadata", br#"{"visibleName": "Old", "type": "DocumentType", "parent": "trash"}"#),
+      ("n.content", br#"{"pages": ["p1", "p2"]}"#),
+      ("n/p1.rm", GOOD),
+      ("n/p1-metadata.json", br#"{"layers": [{"name": "Sketch"}, {"name": "Notes", "visible": false}]}"#)
+    ];
+    return MemoryTree { files: files.iter().map(|(path, contents)| (String::from(*path), contents.to_vec())).collect() };
+  }
+
+  #[test]
+  fn reads_a_notebook_in_memory() {
+    let tree = notebook();
+    let documents = list_documents(&tree).unwrap();
+    assert_eq!(documents.iter().map(|entry| (entry.id.as_str(), entry.name.as_str())).collect::<Vec<_>>(), vec![("n", "Sketches")]);
+    assert_eq!(document_pages(&tree, "n").unwrap(), vec!["p1", "p2"]);
+    assert_eq!(page_layers(&tree, "n", "p1"), (vec![String::from("Sketch"), String::from("Notes")], vec![2]));
+    assert_eq!(read_page(&tree, "n", "p1", &quiet).unwrap().unwrap().layers, parse_document(GOOD, &quiet).unwrap().layers);
+    // a page that was never drawn on
+    assert!(read_page(&tree, "n", "p2", &quiet).unwrap().is_none());
+  }
+
+  #[test]
+  fn edited_pages_go_back_into_the_tree() {
+    let mut tree = notebook();
+    let mut page = read_page(&tree, "n", "p1", &quiet).unwrap().unwrap();
+    page.layers[0].lines.truncate(3);
+    page.layers[0].num_lines = 3;
+    let mut bytes = Vec::new();
+    write_rm(&page, &mut bytes).unwrap();
+    tree.files.retain(|(path, _)| path != "n/p1.rm");
+    tree.files.push((String::from("n/p1.rm"), bytes));
+    let read = read_page(&tree, "n", "p1", &quiet).unwrap().unwrap();
+    assert_eq!(read.layers, page.layers);
+    assert_eq!(read.layers[0].lines.len(), 3);
+  }
+}
@@ -0,0 +1,373 @@
+use std::io::Read;
+
+use crate::deflate::{crc32, inflate, inflate_prefix, TRUNCATED_STREAM};
+
+// Reads the archives the reMarkable desktop app and the device's USB web
+// interface export, or anything else writes: plain (non-ZIP64) zips with
+// stored or deflated entries.
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+
+pub const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+#[derive(Debug)]
+pub struct ZipEntry {
+  pub name: String,
+  pub data: Vec<u8>
+}
+
+pub fn is_zip(bytes: &[u8]) -> bool {
+  return bytes.starts_with(ZIP_MAGIC);
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, String> {
+  return bytes.get(offset..offset + 2)
+    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    .ok_or_else(|| String::from("truncated zip archive"));
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, String> {
+  return bytes.get(offset..offset + 4)
+    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    .ok_or_else(|| String::from("truncated zip archive"));
+}
+
+pub fn read_archive(bytes: &[u8]) -> Result<Vec<ZipEntry>, String> {
+  let end = (0..bytes.len().saturating_sub(21)).rev()
+    .find(|&offset| u32_at(bytes, offset) == Ok(END_OF_CENTRAL_DIRECTORY))
+    .ok_or_else(|| String::from("zip archive has no central directory"))?;
+  let num_entries = u16_at(bytes, end + 10)? as usize;
+  let directory = u32_at(bytes, end + 16)? as usize;
+  if directory == 0xffff_ffff {
+    return Err(String::from("ZIP64 archives are not supported"));
+  }
+
+  let mut entries = Vec::<ZipEntry>::with_capacity(num_entries);
+  let mut offset = directory;
+  for _ in 0..num_entries {
+    if u32_at(bytes, offset)? != CENTRAL_DIRECTORY_HEADER {
+      return Err(format!("corrupt zip central directory at offset {}", offset));
+    }
+    let method = u16_at(bytes, offset + 10)?;
+    let crc = u32_at(bytes, offset + 16)?;
+    let compressed_size = u32_at(bytes, offset + 20)? as usize;
+    if compressed_size == ZIP64_SIZE as usize || u32_at(bytes, offset + 24)? == ZIP64_SIZE {
+      return Err(String::from("ZIP64 archives are not supported"));
+    }
+    let name_length = u16_at(bytes, offset + 28)? as usize;
+    let extra_length = u16_at(bytes, offset + 30)? as usize;
+    let comment_length = u16_at(bytes, offset + 32)? as usize;
+    let local_header = u32_at(bytes, offset + 42)? as usize;
+    let name = bytes.get(offset + 46..offset + 46 + name_length)
+      .map(|n| String::from_utf8_lossy(n).into_owned())
+      .ok_or_else(|| String::from("truncated zip archive"))?;
+    offset += 46 + name_length + extra_length + comment_length;
+
+    if name.ends_with('/') { continue; }
+    entries.push(ZipEntry {
+      data: read_entry(bytes, &name, local_header, method, compressed_size, crc)?,
+      name
+    });
+  }
+  return Ok(entries);
+}
+
+fn read_entry(
+  bytes: &[u8],
+  name: &str,
+  local_header: usize,
+  method: u16,
+  compressed_size: usize,
+  crc: u32
+) -> Result<Vec<u8>, String> {
+  if u32_at(bytes, local_header)? != LOCAL_FILE_HEADER {
+    return Err(format!("corrupt zip local header for {}", name));
+  }
+  let start = local_header + 30
+    + u16_at(bytes, local_header + 26)? as usize
+    + u16_at(bytes, local_header + 28)? as usize;
+  let raw = bytes.get(start..start + compressed_size)
+    .ok_or_else(|| format!("truncated zip entry {}", name))?;
+  let data = decompress(name, method, raw)?;
+  if crc32(&data) != crc {
+    return Err(format!("checksum mismatch for zip entry {}", name));
+  }
+  return Ok(data);
+}
+
+fn decompress(name: &str, method: u16, raw: &[u8]) -> Result<Vec<u8>, String> {
+  return match method {
+    0 => Ok(raw.to_vec()),
+    8 => inflate(raw).map_err(|why| format!("couldn't inflate {}, {}", name, why)),
+    _ => Err(format!("unsupported compression method {} for {}", method, name))
+  };
+}
+
+// optional before a data descriptor's checksum and sizes
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+// general purpose flag: sizes and checksum follow the data
+const HAS_DATA_DESCRIPTOR: u16 = 0x08;
+// a size too big for 32 bits, given in the ZIP64 extra field instead
+const ZIP64_SIZE: u32 = 0xffff_ffff;
+const ZIP64_EXTRA: u16 = 0x0001;
+const STREAM_CHUNK: usize = 64 * 1024;
+
+// Whether a local header's extra field has a ZIP64 record
+fn has_zip64_extra(extra: &[u8]) -> bool {
+  let mut offset = 0;
+  while let (Ok(id), Ok(size)) = (u16_at(extra, offset), u16_at(extra, offset + 2)) {
+    if id == ZIP64_EXTRA { return true; }
+    offset += 4 + size as usize;
+  }
+  return false;
+}
+
+// Reads an archive front to back through its local headers, e.g. from a
+// pipe, handing over each entry once it's complete so that only one entry
+// is held in memory at a time. The central directory is never needed.
+// Entries written without their sizes, as streaming writers do, end where
+// their deflate stream does, or for stored entries at the first data
+// descriptor that fits them; descriptors may go without their signature.
+pub struct ZipStream<R: Read> {
+  reader: R,
+  // read from the stream but not consumed yet
+  pending: Vec<u8>,
+  done: bool
+}
+
+impl<R: Read> ZipStream<R> {
+  pub fn new(reader: R) -> ZipStream<R> {
+    return ZipStream { reader, pending: Vec::new(), done: false };
+  }
+
+  // Reads until at least `length` bytes are pending; false at the end of
+  // the stream
+  fn fill(&mut self, length: usize) -> Result<bool, String> {
+    while self.pending.len() < length {
+      let mut chunk = vec![0; STREAM_CHUNK.max(length - self.pending.len())];
+      let read = self.reader.read(&mut chunk).map_err(|why| format!("couldn't read zip stream, {}", why))?;
+      if read == 0 { return Ok(false); }
+      self.pending.extend_from_slice(&chunk[..read]);
+    }
+    return Ok(true);
+  }
+
+  fn take(&mut self, length: usize) -> Result<Vec<u8>, String> {
+    if !self.fill(length)? {
+      return Err(String::from("truncated zip stream"));
+    }
+    let rest = self.pending.split_off(length);
+    return Ok(std::mem::replace(&mut self.pending, rest));
+  }
+
+  // The checksum and length of a data descriptor `offset` bytes into
+  // what's pending, with or without its signature, if it's there and
+  // gives `compressed_size`
+  fn descriptor_at(&self, offset: usize, compressed_size: usize) -> Option<(u32, usize)> {
+    let at = |offset: usize| u32_at(&self.pending, offset).ok();
+    if at(offset) == Some(DATA_DESCRIPTOR) && at(offset + 8) == Some(compressed_size as u32) {
+      return Some((at(offset + 4)?, 16)).filter(|_| at(offset + 12).is_some());
+    }
+    if at(offset + 4) == Some(compressed_size as u32) {
+      return Some((at(offset)?, 12)).filter(|_| at(offset + 8).is_some());
+    }
+    return None;
+  }
+
+  // A deflated entry of unknown length, inflated, and the checksum from
+  // the data descriptor right after its deflate stream
+  fn take_deflated(&mut self, name: &str) -> Result<(Vec<u8>, u32), String> {
+    let mut wanted = STREAM_CHUNK;
+    loop {
+      let more = self.fill(wanted)?;
+      match inflate_prefix(&self.pending) {
+        Ok((data, used)) => {
+          self.fill(used + 16)?;
+          let (crc, length) = self.descriptor_at(used, used)
+            .ok_or_else(|| format!("no data descriptor after zip entry {}", name))?;
+          self.take(used + length)?;
+          return Ok((data, crc));
+        },
+        // read twice as much and try again
+        Err(why) if why == TRUNCATED_STREAM && more => wanted = self.pending.len() * 2,
+        Err(why) if why == TRUNCATED_STREAM => return Err(format!("truncated zip stream, in {}", name)),
+        Err(why) => return Err(format!("couldn't inflate {}, {}", name, why))
+      }
+    }
+  }
+
+  // A stored entry of unknown length, up to the first data descriptor
+  // whose size is the bytes before it and whose checksum is theirs
+  fn take_stored(&mut self, name: &str) -> Result<(Vec<u8>, u32), String> {
+    let mut scanned = 0;
+    loop {
+      let found = (scanned..self.pending.len().saturating_sub(11)).find_map(|offset| {
+        return self.descriptor_at(offset, offset)
+          .filter(|(crc, _)| crc32(&self.pending[..offset]) == *crc)
+          .map(|(crc, length)| (offset, crc, length));
+      });
+      if let Some((offset, crc, length)) = found {
+        let data = self.take(offset)?;
+        self.take(length)?;
+        return Ok((data, crc));
+      }
+      scanned = self.pending.len().saturating_sub(15);
+      if !self.fill(self.pending.len() + 1)? {
+        return Err(format!("truncated zip stream, no data descriptor for {}", name));
+      }
+    }
+  }
+
+  fn next_entry(&mut self) -> Result<Option<ZipEntry>, String> {
+    loop {
+      if !self.fill(4)? || u32_at(&self.pending, 0)? != LOCAL_FILE_HEADER {
+        // the central directory, or the end of the stream
+        return Ok(None);
+      }
+      let header = self.take(30)?;
+      let flags = u16_at(&header, 6)?;
+      let method = u16_at(&header, 8)?;
+      let name_length = u16_at(&header, 26)? as usize;
+      let extra_length = u16_at(&header, 28)? as usize;
+      let name = String::from_utf8_lossy(&self.take(name_length)?).into_owned();
+      let extra = self.take(extra_length)?;
+      if u32_at(&header, 18)? == ZIP64_SIZE || u32_at(&header, 22)? == ZIP64_SIZE || has_zip64_extra(&extra) {
+        return Err(format!("ZIP64 archives are not supported, {} is a ZIP64 entry", name));
+      }
+      let (data, crc) = match (flags & HAS_DATA_DESCRIPTOR != 0, method) {
+        (false, _) if name.ends_with('/') => {
+          self.take(u32_at(&header, 18)? as usize)?;
+          continue;
+        },
+        (false, _) => {
+          let raw = self.take(u32_at(&header, 18)? as usize)?;
+          (decompress(&name, method, &raw)?, u32_at(&header, 14)?)
+        },
+        (true, 8) => self.take_deflated(&name)?,
+        (true, 0) => self.take_stored(&name)?,
+        (true, _) => return Err(format!("unsupported compression method {} for {}", method, name))
+      };
+      if name.ends_with('/') { continue; }
+      if crc32(&data) != crc {
+        return Err(format!("checksum mismatch for zip entry {}", name));
+      }
+      return Ok(Some(ZipEntry { name, data }));
+    }
+  }
+}
+
+impl<R: Read> Iterator for ZipStream<R> {
+  type Item = Result<ZipEntry, String>;
+
+  fn next(&mut self) -> Option<Result<ZipEntry, String>> {
+    if self.done { return None; }
+    let entry = self.next_entry().transpose();
+    self.done = !matches!(entry, Some(Ok(_)));
+    return entry;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::deflate::deflate;
+
+  // As Python's zipfile writes to a pipe: every entry with a signed data
+  // descriptor, two deflated and one stored
+  const STREAMED: &[u8] = include_bytes!("../test_files/streamed.zip");
+  // the same with the second entry forced to ZIP64
+  const STREAMED_ZIP64: &[u8] = include_bytes!("../test_files/streamed-zip64.zip");
+
+  // An entry as a streaming writer puts it, sizes after the data in a
+  // descriptor with or without its signature
+  fn streamed_entry(name: &str, method: u16, data: &[u8], signed: bool) -> Vec<u8> {
+    let raw = match method {
+      8 => deflate(data),
+      _ => data.to_vec()
+    };
+    let mut entry = ZIP_MAGIC.to_vec();
+    entry.extend_from_slice(&[20, 0]);
+    entry.extend_from_slice(&HAS_DATA_DESCRIPTOR.to_le_bytes());
+    entry.extend_from_slice(&method.to_le_bytes());
+    entry.extend_from_slice(&[0; 16]);
+    entry.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    entry.extend_from_slice(&[0; 2]);
+    entry.extend_from_slice(name.as_bytes());
+    entry.extend_from_slice(&raw);
+    if signed {
+      entry.extend_from_slice(&DATA_DESCRIPTOR.to_le_bytes());
+    }
+    entry.extend_from_slice(&crc32(data).to_le_bytes());
+    entry.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+    entry.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    return entry;
+  }
+
+  // Hands over a few bytes at a time, as a pipe might
+  struct Trickle<'a>(&'a [u8]);
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+      let length = buffer.len().min(self.0.len()).min(7);
+      buffer[..length].copy_from_slice(&self.0[..length]);
+      self.0 = &self.0[length..];
+      return Ok(length);
+    }
+  }
+
+  fn streamed<R: Read>(reader: R) -> Result<Vec<(String, Vec<u8>)>, String> {
+    return ZipStream::new(reader).map(|entry| entry.map(|entry| (entry.name, entry.data))).collect();
+  }
+
+  fn expected() -> Vec<(String, Vec<u8>)> {
+    return vec![
+      (String::from("n.content"), br#"{"pages": ["p1"]}"#.to_vec()),
+      (String::from("n/p1-metadata.json"), br#"{"layers": [{"name": "Sketch"}]}"#.to_vec()),
+      (String::from("n/stored.txt"), b"kept as is".to_vec())
+    ];
+  }
+
+  #[test]
+  fn streams_signed_descriptors() {
+    assert_eq!(streamed(STREAMED).unwrap(), expected());
+    assert_eq!(streamed(Trickle(STREAMED)).unwrap(), expected());
+    let entries = read_archive(STREAMED).unwrap().into_iter().map(|entry| (entry.name, entry.data)).collect::<Vec<_>>();
+    assert_eq!(entries, expected());
+  }
+
+  #[test]
+  fn streams_unsigned_descriptors() {
+    // stored data with what looks like its descriptor partway through,
+    // sizes and all, but not its checksum
+    let tricky = [b"abcdPK\x07\x08".to_vec(), vec![0xde, 0xad, 0xbe, 0xef], 4u32.to_le_bytes().to_vec(), 4u32.to_le_bytes().to_vec()].concat();
+    let big = (0..200_000u32).map(|n| (n.wrapping_mul(2_654_435_761) >> 24) as u8).collect::<Vec<_>>();
+    let archive = [
+      streamed_entry("a.txt", 8, b"deflated without a signature", false),
+      streamed_entry("b.bin", 0, &tricky, false),
+      streamed_entry("c.bin", 8, &big, false),
+      streamed_entry("d.txt", 0, b"stored with one", true)
+    ].concat();
+    let entries = streamed(Trickle(&archive)).unwrap();
+    assert_eq!(entries.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["a.txt", "b.bin", "c.bin", "d.txt"]);
+    assert_eq!(entries[0].1, b"deflated without a signature");
+    assert_eq!(entries[1].1, tricky);
+    assert_eq!(entries[2].1, big);
+    assert_eq!(entries[3].1, b"stored with one");
+  }
+
+  #[test]
+  fn rejects_zip64() {
+    let error = streamed(STREAMED_ZIP64).unwrap_err();
+    assert_eq!(error, "ZIP64 archives are not supported, n/p1-metadata.json is a ZIP64 entry");
+  }
+
+  #[test]
+  fn rejects_truncated_streams() {
+    let archive = streamed_entry("a.txt", 8, b"cut short", false);
+    assert_eq!(streamed(&archive[..archive.len() - 16]).unwrap_err(), "truncated zip stream, in a.txt");
+    let archive = streamed_entry("b.txt", 0, b"cut short", true);
+    assert_eq!(streamed(&archive[..archive.len() - 4]).unwrap_err(), "truncated zip stream, no data descriptor for b.txt");
+  }
+}